@@ -1,11 +1,92 @@
 use anyhow::{anyhow, Context, Result};
 use std::fs::File;
+use std::io;
 use std::path::Path;
 use std::process::{Command, Stdio};
 
+pub mod native;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Cli,
+    Native,
+}
+
+// Subvolume operations behind a selectable backend: the `btrfs` binary, or
+// ioctls issued directly so hosts without btrfs-progs still work. send,
+// receive, find-new and subvolume details always go through the binary.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Btrfs {
+    backend: Backend,
+}
+
+impl Btrfs {
+    pub fn new(backend: Backend) -> Self {
+        Self { backend }
+    }
+
+    pub fn snapshot_readonly(&self, source: &str, dest: &str) -> Result<()> {
+        match self.backend {
+            Backend::Cli => snapshot_readonly(source, dest),
+            Backend::Native => Ok(native::snapshot(Path::new(source), Path::new(dest), true)?),
+        }
+    }
+
+    pub fn snapshot_writable(&self, source: &str, dest: &str) -> Result<()> {
+        match self.backend {
+            Backend::Cli => snapshot_writable(source, dest),
+            Backend::Native => Ok(native::snapshot(Path::new(source), Path::new(dest), false)?),
+        }
+    }
+
+    pub fn subvolume_delete(&self, path: &str) -> Result<()> {
+        match self.backend {
+            Backend::Cli => subvolume_delete(path),
+            Backend::Native => Ok(native::subvolume_delete(Path::new(path))?),
+        }
+    }
+
+    pub fn subvolume_exists(&self, path: &str) -> Result<bool> {
+        match self.backend {
+            Backend::Cli => subvolume_exists(path),
+            Backend::Native => Ok(native::is_subvolume(Path::new(path))?),
+        }
+    }
+
+    pub fn subvolume_generation(&self, path: &str) -> Result<u64> {
+        match self.backend {
+            Backend::Cli => subvolume_generation(path),
+            Backend::Native => Ok(native::subvolume_generation(Path::new(path))?),
+        }
+    }
+
+    pub fn filesystem_uuid(&self, path: &str) -> Result<String> {
+        match self.backend {
+            Backend::Cli => filesystem_uuid(path),
+            Backend::Native => Ok(native::filesystem_uuid(Path::new(path))?),
+        }
+    }
+
+    pub fn is_btrfs_mount(&self, path: &str) -> Result<bool> {
+        match self.backend {
+            Backend::Cli => is_btrfs_mount(path),
+            Backend::Native => {
+                if !Path::new(path).is_dir() {
+                    return Ok(false);
+                }
+                Ok(native::is_btrfs(Path::new(path))?)
+            }
+        }
+    }
+}
+
+// What btrfs says about its work ("Create a readonly snapshot of ...") goes
+// to stderr, leaving stdout to the command's own output.
 fn run_btrfs(args: &[&str]) -> Result<()> {
     let status = Command::new("btrfs")
         .args(args)
+        .stdout(io::stderr())
         .status()
         .with_context(|| format!("failed to run btrfs {args:?}"))?;
     if !status.success() {
@@ -80,6 +161,99 @@ pub fn subvolume_exists(path: &str) -> Result<bool> {
     }
 }
 
+pub fn subvolume_generation(path: &str) -> Result<u64> {
+    let output = Command::new("btrfs")
+        .args(["subvolume", "show", path])
+        .output()
+        .with_context(|| format!("failed to run btrfs subvolume show on {path}"))?;
+    if !output.status.success() {
+        return Err(anyhow!("btrfs subvolume show failed for {path}"));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Generation:"))
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| anyhow!("generation not found in btrfs subvolume show output for {path}"))
+}
+
+// What `btrfs subvolume show` says about a subvolume beyond its generation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubvolumeDetails {
+    pub uuid: Option<String>,
+    // The subvolume this one is a snapshot of.
+    pub parent_uuid: Option<String>,
+    // Set by btrfs receive once the whole stream has been applied.
+    pub received_uuid: Option<String>,
+    pub creation_time: Option<String>,
+    pub readonly: bool,
+}
+
+pub fn subvolume_details(path: &str) -> Result<SubvolumeDetails> {
+    let output = Command::new("btrfs")
+        .args(["subvolume", "show", path])
+        .output()
+        .with_context(|| format!("failed to run btrfs subvolume show on {path}"))?;
+    if !output.status.success() {
+        return Err(anyhow!("btrfs subvolume show failed for {path}"));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let field = |name: &str| {
+        stdout.lines().find_map(|line| line.trim().strip_prefix(name).map(str::trim))
+    };
+    let uuid = |name: &str| field(name).filter(|value| *value != "-").map(str::to_string);
+    Ok(SubvolumeDetails {
+        uuid: uuid("UUID:"),
+        parent_uuid: uuid("Parent UUID:"),
+        received_uuid: uuid("Received UUID:"),
+        creation_time: field("Creation time:").map(str::to_string),
+        readonly: field("Flags:").is_some_and(|flags| flags.contains("readonly")),
+    })
+}
+
+// The uuid of the btrfs filesystem holding `path`, the same for every
+// subvolume and mount of it.
+pub fn filesystem_uuid(path: &str) -> Result<String> {
+    let output = Command::new("btrfs")
+        .args(["filesystem", "show", path])
+        .output()
+        .with_context(|| format!("failed to run btrfs filesystem show on {path}"))?;
+    if !output.status.success() {
+        return Err(anyhow!("btrfs filesystem show failed for {path}"));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout
+        .lines()
+        .filter(|line| line.starts_with("Label:"))
+        .find_map(|line| line.split_once("uuid:"))
+        .map(|(_, uuid)| uuid.trim().to_string())
+        .ok_or_else(|| anyhow!("uuid not found in btrfs filesystem show output for {path}"))
+}
+
+pub fn changed_bytes_since(path: &str, generation: u64) -> Result<u64> {
+    let output = Command::new("btrfs")
+        .args(["subvolume", "find-new", path, &generation.to_string()])
+        .output()
+        .with_context(|| format!("failed to run btrfs subvolume find-new on {path}"))?;
+    if !output.status.success() {
+        return Err(anyhow!("btrfs subvolume find-new failed for {path}"));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut total: u64 = 0;
+    for line in stdout.lines() {
+        let mut fields = line.split_whitespace();
+        while let Some(field) = fields.next() {
+            if field == "len" {
+                if let Some(len) = fields.next().and_then(|value| value.parse::<u64>().ok()) {
+                    total = total.saturating_add(len);
+                }
+                break;
+            }
+        }
+    }
+    Ok(total)
+}
+
 pub fn is_btrfs_mount(path: &str) -> Result<bool> {
     let stat = std::fs::metadata(path)
         .with_context(|| format!("failed to stat {path}"))?;
@@ -97,6 +271,26 @@ pub fn is_btrfs_mount(path: &str) -> Result<bool> {
     Ok(fs_type == "btrfs")
 }
 
+// Bytes available to unprivileged writers on the filesystem holding `path`.
+pub fn free_bytes(path: &str) -> Result<u64> {
+    let c_path = std::ffi::CString::new(path)
+        .map_err(|_| anyhow!("path contains a NUL byte: {path:?}"))?;
+    // SAFETY: statvfs is plain old data and only written by the call below.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stat is a valid out pointer.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("failed to stat filesystem of {path}"));
+    }
+    Ok(stat.f_bavail.saturating_mul(stat.f_frsize))
+}
+
+// Waits until deleted subvolumes under `path` are cleaned up, so that free
+// space reflects the deletion.
+pub fn subvolume_sync(path: &str) -> Result<()> {
+    run_btrfs(&["subvolume", "sync", path])
+}
+
 pub fn ensure_dir(path: &Path) -> Result<()> {
     std::fs::create_dir_all(path)
         .with_context(|| format!("failed to create directory: {}", path.display()))
@@ -0,0 +1,266 @@
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const BTRFS_SUPER_MAGIC: i64 = 0x9123_683E;
+const BTRFS_FIRST_FREE_OBJECTID: u64 = 256;
+const BTRFS_IOCTL_MAGIC: u64 = 0x94;
+const BTRFS_SUBVOL_RDONLY: u64 = 1 << 1;
+
+#[derive(Debug)]
+pub enum BtrfsError {
+    NotBtrfs(PathBuf),
+    NotSubvolume(PathBuf),
+    InvalidPath(PathBuf),
+    Io {
+        op: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for BtrfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BtrfsError::NotBtrfs(path) => {
+                write!(f, "{} is not on a btrfs filesystem", path.display())
+            }
+            BtrfsError::NotSubvolume(path) => {
+                write!(f, "{} is not a btrfs subvolume", path.display())
+            }
+            BtrfsError::InvalidPath(path) => {
+                write!(f, "invalid subvolume path: {}", path.display())
+            }
+            BtrfsError::Io { op, path, source } => {
+                write!(f, "btrfs {op} failed for {}: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for BtrfsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BtrfsError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// Mirrors struct btrfs_ioctl_vol_args from linux/btrfs.h.
+#[repr(C)]
+#[allow(dead_code)]
+struct VolArgs {
+    fd: i64,
+    name: [u8; 4088],
+}
+
+// Mirrors struct btrfs_ioctl_vol_args_v2 from linux/btrfs.h.
+#[repr(C)]
+#[allow(dead_code)]
+struct VolArgsV2 {
+    fd: i64,
+    transid: u64,
+    flags: u64,
+    unused: [u64; 4],
+    name: [u8; 4040],
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct IoctlTimespec {
+    sec: u64,
+    nsec: u32,
+}
+
+// Mirrors struct btrfs_ioctl_get_subvol_info_args from linux/btrfs.h.
+#[repr(C)]
+#[allow(dead_code)]
+struct GetSubvolInfoArgs {
+    treeid: u64,
+    name: [u8; 256],
+    parent_id: u64,
+    dirid: u64,
+    generation: u64,
+    flags: u64,
+    uuid: [u8; 16],
+    parent_uuid: [u8; 16],
+    received_uuid: [u8; 16],
+    ctransid: u64,
+    otransid: u64,
+    stransid: u64,
+    rtransid: u64,
+    ctime: IoctlTimespec,
+    otime: IoctlTimespec,
+    stime: IoctlTimespec,
+    rtime: IoctlTimespec,
+    reserved: [u64; 8],
+}
+
+// Mirrors struct btrfs_ioctl_fs_info_args from linux/btrfs.h.
+#[repr(C)]
+#[allow(dead_code)]
+struct FsInfoArgs {
+    max_id: u64,
+    num_devices: u64,
+    fsid: [u8; 16],
+    nodesize: u32,
+    sectorsize: u32,
+    clone_alignment: u32,
+    csum_type: u16,
+    csum_size: u16,
+    flags: u64,
+    generation: u64,
+    metadata_uuid: [u8; 16],
+    reserved: [u8; 944],
+}
+
+const _: () = assert!(mem::size_of::<VolArgs>() == 4096);
+const _: () = assert!(mem::size_of::<VolArgsV2>() == 4096);
+const _: () = assert!(mem::size_of::<GetSubvolInfoArgs>() == 504);
+const _: () = assert!(mem::size_of::<FsInfoArgs>() == 1024);
+
+const fn ioc(dir: u64, nr: u64, size: usize) -> u64 {
+    (dir << 30) | ((size as u64) << 16) | (BTRFS_IOCTL_MAGIC << 8) | nr
+}
+
+const BTRFS_IOC_SNAP_DESTROY: u64 = ioc(1, 15, mem::size_of::<VolArgs>());
+const BTRFS_IOC_SNAP_CREATE_V2: u64 = ioc(1, 23, mem::size_of::<VolArgsV2>());
+const BTRFS_IOC_FS_INFO: u64 = ioc(2, 31, mem::size_of::<FsInfoArgs>());
+const BTRFS_IOC_GET_SUBVOL_INFO: u64 = ioc(2, 60, mem::size_of::<GetSubvolInfoArgs>());
+
+pub fn snapshot(source: &Path, dest: &Path, readonly: bool) -> Result<(), BtrfsError> {
+    ensure_subvolume(source)?;
+    let source_dir = open_dir("snapshot", source)?;
+    let (parent, name) = split_parent(dest)?;
+    let parent_dir = open_dir("snapshot", &parent)?;
+
+    // SAFETY: VolArgsV2 is plain old data; all-zero is its "no options" state.
+    let mut args: VolArgsV2 = unsafe { mem::zeroed() };
+    args.fd = source_dir.as_raw_fd() as i64;
+    if readonly {
+        args.flags = BTRFS_SUBVOL_RDONLY;
+    }
+    copy_name(&mut args.name, &name, dest)?;
+    ioctl(&parent_dir, BTRFS_IOC_SNAP_CREATE_V2, &mut args, "snapshot", dest)
+}
+
+pub fn subvolume_delete(path: &Path) -> Result<(), BtrfsError> {
+    ensure_subvolume(path)?;
+    let (parent, name) = split_parent(path)?;
+    let parent_dir = open_dir("delete", &parent)?;
+
+    // SAFETY: VolArgs is plain old data.
+    let mut args: VolArgs = unsafe { mem::zeroed() };
+    copy_name(&mut args.name, &name, path)?;
+    ioctl(&parent_dir, BTRFS_IOC_SNAP_DESTROY, &mut args, "delete", path)
+}
+
+pub fn is_btrfs(path: &Path) -> Result<bool, BtrfsError> {
+    let c_path = c_path(path)?;
+    // SAFETY: statfs only writes into the zeroed buffer we own.
+    let mut stat: libc::statfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io_error("statfs", path, io::Error::last_os_error()));
+    }
+    Ok(stat.f_type as i64 == BTRFS_SUPER_MAGIC)
+}
+
+pub fn is_subvolume(path: &Path) -> Result<bool, BtrfsError> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(io_error("stat", path, err)),
+    };
+    Ok(metadata.is_dir() && metadata.ino() == BTRFS_FIRST_FREE_OBJECTID && is_btrfs(path)?)
+}
+
+pub fn subvolume_generation(path: &Path) -> Result<u64, BtrfsError> {
+    ensure_subvolume(path)?;
+    let dir = open_dir("subvolume info", path)?;
+    // SAFETY: GetSubvolInfoArgs is plain old data filled in by the kernel.
+    let mut args: GetSubvolInfoArgs = unsafe { mem::zeroed() };
+    ioctl(&dir, BTRFS_IOC_GET_SUBVOL_INFO, &mut args, "subvolume info", path)?;
+    Ok(args.generation)
+}
+
+// Formatted like `btrfs filesystem show` prints it.
+pub fn filesystem_uuid(path: &Path) -> Result<String, BtrfsError> {
+    if !is_btrfs(path)? {
+        return Err(BtrfsError::NotBtrfs(path.to_path_buf()));
+    }
+    let dir = open_dir("filesystem info", path)?;
+    // SAFETY: FsInfoArgs is plain old data filled in by the kernel.
+    let mut args: FsInfoArgs = unsafe { mem::zeroed() };
+    ioctl(&dir, BTRFS_IOC_FS_INFO, &mut args, "filesystem info", path)?;
+    let hex: Vec<String> = args.fsid.iter().map(|byte| format!("{byte:02x}")).collect();
+    Ok([&hex[..4], &hex[4..6], &hex[6..8], &hex[8..10], &hex[10..]].map(|g| g.concat()).join("-"))
+}
+
+fn ensure_subvolume(path: &Path) -> Result<(), BtrfsError> {
+    if !is_btrfs(path)? {
+        return Err(BtrfsError::NotBtrfs(path.to_path_buf()));
+    }
+    if !is_subvolume(path)? {
+        return Err(BtrfsError::NotSubvolume(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+fn ioctl<T>(
+    dir: &File,
+    request: u64,
+    args: &mut T,
+    op: &'static str,
+    path: &Path,
+) -> Result<(), BtrfsError> {
+    // SAFETY: `request` encodes the size of T, which matches the kernel's
+    // struct for that ioctl, and `args` outlives the call.
+    let rc = unsafe { libc::ioctl(dir.as_raw_fd(), request as _, args as *mut T) };
+    if rc < 0 {
+        return Err(io_error(op, path, io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+fn open_dir(op: &'static str, path: &Path) -> Result<File, BtrfsError> {
+    File::open(path).map_err(|err| io_error(op, path, err))
+}
+
+fn split_parent(path: &Path) -> Result<(PathBuf, Vec<u8>), BtrfsError> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| BtrfsError::InvalidPath(path.to_path_buf()))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    Ok((parent, name.as_bytes().to_vec()))
+}
+
+fn copy_name(buf: &mut [u8], name: &[u8], path: &Path) -> Result<(), BtrfsError> {
+    if name.is_empty() || name.len() >= buf.len() || name.contains(&0) {
+        return Err(BtrfsError::InvalidPath(path.to_path_buf()));
+    }
+    buf[..name.len()].copy_from_slice(name);
+    Ok(())
+}
+
+fn c_path(path: &Path) -> Result<CString, BtrfsError> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| BtrfsError::InvalidPath(path.to_path_buf()))
+}
+
+fn io_error(op: &'static str, path: &Path, source: io::Error) -> BtrfsError {
+    BtrfsError::Io {
+        op,
+        path: path.to_path_buf(),
+        source,
+    }
+}
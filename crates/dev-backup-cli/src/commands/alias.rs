@@ -0,0 +1,45 @@
+use crate::context::AppContext;
+use crate::output::Table;
+use anyhow::{anyhow, Result};
+use dev_backup_core::alias::is_valid_alias_name;
+
+pub fn alias_set(ctx: &AppContext, name: &str, label: &str) -> Result<()> {
+    if !is_valid_alias_name(name) {
+        return Err(anyhow!(
+            "alias must start with a letter and contain only letters, digits, '-' or '_'"
+        ));
+    }
+    let records = ctx.manifest.read_records()?;
+    let target = ctx.resolve_label(&records, label)?;
+    if !records.iter().any(|record| record.label == target) {
+        return Err(anyhow!("label not found in manifest: {target}"));
+    }
+    match ctx.aliases.set(name, &target)? {
+        Some(previous) if previous != target => {
+            ctx.logger.info(format!("Moved alias {name}: {previous} -> {target}"))
+        }
+        _ => ctx.logger.info(format!("Alias {name} -> {target}")),
+    }
+    Ok(())
+}
+
+pub fn alias_remove(ctx: &AppContext, name: &str) -> Result<()> {
+    match ctx.aliases.remove(name)? {
+        Some(previous) => ctx.logger.info(format!("Removed alias {name} (was {previous})")),
+        None => return Err(anyhow!("alias not found: {name}")),
+    }
+    Ok(())
+}
+
+pub fn alias_list(ctx: &AppContext) -> Result<()> {
+    let mut table = Table::new();
+    for (name, label) in ctx.aliases.read()? {
+        if ctx.json {
+            ctx.emit(&serde_json::json!({ "name": name, "label": label }))?;
+        } else {
+            table.row([name, label]);
+        }
+    }
+    table.print();
+    Ok(())
+}
@@ -0,0 +1,829 @@
+use crate::context::AppContext;
+use crate::generations;
+use crate::label::{auto_parent_label, ensure_label};
+use crate::lock::{self, LockScope};
+use crate::permissions;
+use crate::progress;
+use crate::pipeline::{
+    run_decrypt_pipeline, run_encrypt_pipeline, run_encrypt_stream, run_send_pipeline,
+    run_send_stream, PARTIAL_SUFFIX,
+};
+use anyhow::{anyhow, Context, Result};
+use dev_backup_btrfs as btrfs;
+use dev_backup_core::manifest::{ManifestRecord, ManifestStore, RecordStatus};
+use dev_backup_core::skew::find_clock_skew;
+use dev_backup_storage::artifact::{
+    artifact_filename, parse_artifact_filename, sha256_file, ArtifactInfo, ArtifactType,
+};
+use dev_backup_storage::backend::{ChunkWriter, StorageBackend};
+use dev_backup_storage::header::{open_artifact, payload_sha256, ArtifactHeader};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::io::{self, BufReader, BufWriter, Read};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use tokio::sync::mpsc;
+
+// Builds into `output_dir`, or paths.artifact_staging when it is None.
+pub fn build_artifact(
+    ctx: &AppContext,
+    label: &str,
+    parent: Option<&str>,
+    output_dir: Option<&Path>,
+) -> Result<()> {
+    let dir = output_dir.map_or_else(|| ctx.artifact_staging_dir(), Path::to_path_buf);
+    btrfs::ensure_dir(&dir)?;
+    build_artifact_into(ctx, label, parent, &dir).map(|_| ())
+}
+
+// `artifact build --register`: builds into the staging directory (or
+// `output_dir`) and moves the artifacts into the LS layout with their manifest
+// rows, as `backup-now` does. Built files are removed again if registering
+// them fails.
+pub fn build_and_register_artifact(
+    ctx: &AppContext,
+    label: &str,
+    parent: Option<&str>,
+    output_dir: Option<&Path>,
+) -> Result<()> {
+    let dir = output_dir.map_or_else(|| ctx.artifact_staging_dir(), Path::to_path_buf);
+    btrfs::ensure_dir(&dir)?;
+    let built = build_artifact_into(ctx, label, parent, &dir)?;
+    let paths: Vec<&str> = built.iter().map(|path| path.to_str().unwrap_or_default()).collect();
+    let result = register_artifacts(ctx, &paths, RegisterMode::Move);
+    if result.is_err() {
+        for path in built.iter().filter(|path| path.exists()) {
+            let _ = fs::remove_file(path);
+        }
+    }
+    result
+}
+
+// `parent` as given to `artifact build`; "latest" and `auto` look it up with
+// `auto_parent_label` among the dataset's local snapshots.
+pub fn build_parent(
+    ctx: &AppContext,
+    label: &str,
+    parent: Option<&str>,
+    auto: bool,
+) -> Result<Option<String>> {
+    if !auto && parent != Some("latest") {
+        return Ok(parent.map(str::to_string));
+    }
+    let records = ctx.manifest.read_records()?;
+    let parent = auto_parent_label(&records, label, |candidate| {
+        Path::new(&ctx.snapshot_path(candidate)).exists()
+    });
+    match &parent {
+        Some(parent) => ctx.logger.info(format!("Parent: {}", ctx.snapshot_name(parent))),
+        None => ctx.logger.info(format!(
+            "No manifest label before {label} has a local snapshot; building an anchor"
+        )),
+    }
+    Ok(parent)
+}
+
+// Builds into `dir` (relative paths are taken from the working directory) and
+// returns the artifacts written, the main stream first.
+pub fn build_artifact_into(
+    ctx: &AppContext,
+    label: &str,
+    parent: Option<&str>,
+    dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    ctx.ensure_new_label(label)?;
+    if let Some(parent_label) = parent {
+        ensure_label(parent_label)?;
+    }
+
+    let stage = progress::Stage::begin("build", None);
+    let mut built = vec![build_stream_artifact(ctx, ctx.naming.prefix(), label, parent, dir)?];
+    for part in &ctx.config.split.parts {
+        // A part split off after the parent month has no parent snapshot and
+        // starts its own chain with an anchor.
+        let part_parent =
+            parent.filter(|p| Path::new(&ctx.stream_snapshot_path(part, p)).exists());
+        built.push(build_stream_artifact(ctx, part, label, part_parent, dir)?);
+    }
+    stage.finish();
+    Ok(built)
+}
+
+fn build_stream_artifact(
+    ctx: &AppContext,
+    stream: &str,
+    label: &str,
+    parent: Option<&str>,
+    dir: &Path,
+) -> Result<PathBuf> {
+    let snapshot_path = ctx.stream_snapshot_path(stream, label);
+    if !Path::new(&snapshot_path).exists() {
+        return Err(anyhow!("snapshot not found: {snapshot_path}"));
+    }
+
+    let parent_path = parent.map(|p| ctx.stream_snapshot_path(stream, p));
+    if let Some(ref path) = parent_path {
+        if !Path::new(path).exists() {
+            return Err(anyhow!("parent snapshot not found: {path}"));
+        }
+    }
+
+    let output_path = dir.join(artifact_filename(&ctx.naming, stream, label, parent));
+    let public_key = age_public_key(ctx)?;
+    let seen = generations::check_build(ctx, stream, label, parent)?;
+    progress::item(ctx.naming.name(stream, label));
+
+    run_send_pipeline(
+        &snapshot_path,
+        parent_path.as_deref(),
+        output_path.to_str().unwrap_or_default(),
+        artifact_header(ctx, label, parent),
+        public_key,
+        ctx.age_backend(),
+        ctx.config.compression,
+    )?;
+    generations::record_build(ctx, stream, label, seen)?;
+    ctx.logger
+        .info(format!("Artifact created: {}", output_path.display()));
+    Ok(output_path)
+}
+
+// Streams every artifact of `label` straight to [cloud] (btrfs send, zstd and
+// age feeding a multipart upload) and records it with only an object_key.
+pub async fn stream_artifact(ctx: &AppContext, label: &str, parent: Option<&str>) -> Result<()> {
+    ctx.ensure_new_label(label)?;
+    if let Some(parent_label) = parent {
+        ensure_label(parent_label)?;
+    }
+    let client: Arc<dyn StorageBackend> = Arc::from(ctx.storage().await?);
+
+    let mut streams = vec![(ctx.naming.prefix(), parent)];
+    for part in &ctx.config.split.parts {
+        let part_parent =
+            parent.filter(|p| Path::new(&ctx.stream_snapshot_path(part, p)).exists());
+        streams.push((part.as_str(), part_parent));
+    }
+    let stage = progress::Stage::begin("build", None);
+    for (stream, parent) in streams {
+        progress::item(ctx.naming.name(stream, label));
+        let snapshot_path = ctx.stream_snapshot_path(stream, label);
+        if !Path::new(&snapshot_path).exists() {
+            return Err(anyhow!("snapshot not found: {snapshot_path}"));
+        }
+        let parent_path = parent.map(|p| ctx.stream_snapshot_path(stream, p));
+        if let Some(ref path) = parent_path {
+            if !Path::new(path).exists() {
+                return Err(anyhow!("parent snapshot not found: {path}"));
+            }
+        }
+        let public_key = age_public_key(ctx)?.to_string();
+        let seen = generations::check_build(ctx, stream, label, parent)?;
+        let (backend, compression) = (ctx.age_backend(), ctx.config.compression);
+        let key = stream_to_cloud(ctx, client.clone(), stream, label, parent, move |output| {
+            let parent = parent_path.as_deref();
+            run_send_stream(&snapshot_path, parent, output, &public_key, backend, compression)
+        })
+        .await?;
+        generations::record_build(ctx, stream, label, seen)?;
+        ctx.logger.info(format!("Artifact streamed to {key}"));
+    }
+    stage.finish();
+    Ok(())
+}
+
+// Encrypts and uploads as the bytes are produced; the sha256 and size are
+// taken on the way since there is no file to read them back from.
+async fn stream_to_cloud<F>(
+    ctx: &AppContext,
+    client: Arc<dyn StorageBackend>,
+    stream: &str,
+    label: &str,
+    parent: Option<&str>,
+    produce: F,
+) -> Result<String>
+where
+    F: FnOnce(ChunkWriter) -> Result<ChunkWriter> + Send + 'static,
+{
+    let part = (stream != ctx.naming.prefix()).then_some(stream);
+    let manifest = ctx.manifest_for(part)?;
+    let artifact_type = match parent {
+        Some(_) => ArtifactType::Incremental,
+        None => ArtifactType::Anchor,
+    };
+    let filename = artifact_filename(&ctx.naming, stream, label, parent);
+    let key = format!("{}/{filename}", artifact_dir(ctx.naming.machine(), part, artifact_type));
+    let chunk_size = ctx
+        .config
+        .cloud
+        .as_ref()
+        .map_or(64, |cloud| cloud.multipart_part_mib) as usize
+        * 1024
+        * 1024;
+
+    let (sender, chunks) = mpsc::channel(2);
+    let producer = tokio::task::spawn_blocking(move || -> Result<(String, u64)> {
+        let writer = produce(ChunkWriter::new(sender, chunk_size))?;
+        Ok(writer.finish()?)
+    });
+    let (produced, uploaded) = tokio::join!(producer, client.put_stream(&key, chunks));
+    let (sha256, bytes) = match (produced.context("artifact stream task failed")?, uploaded) {
+        (Ok(summary), Ok(())) => summary,
+        (Err(err), Ok(())) | (Ok(_), Err(err)) => return Err(err),
+        // Whichever side stopped first made the other one fail; a producer
+        // that hit a closed upload only reports the broken pipe.
+        (Err(produce_err), Err(upload_err)) => {
+            let broken_pipe = produce_err.chain().any(|cause| {
+                cause
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe)
+            });
+            return Err(if broken_pipe { upload_err } else { produce_err });
+        }
+    };
+
+    let record = ManifestRecord {
+        ts: ctx.clock.now(),
+        label: label.to_string(),
+        record_type: record_type(artifact_type).to_string(),
+        parent: parent.unwrap_or_default().to_string(),
+        bytes,
+        sha256,
+        local_path: String::new(),
+        object_key: key.clone(),
+        mirror_keys: String::new(),
+        status: RecordStatus::Pushed,
+        machine: ctx.naming.machine().unwrap_or_default().to_string(),
+    };
+    append_records(ctx, manifest, std::slice::from_ref(&record))?;
+    Ok(key)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterMode {
+    Move,
+    Copy,
+    InPlace,
+}
+
+pub fn ingest_artifact(
+    ctx: &AppContext,
+    label: &str,
+    parent: Option<&str>,
+    source: &str,
+    armor: bool,
+) -> Result<()> {
+    ctx.ensure_new_label(label)?;
+    if let Some(parent_label) = parent {
+        ensure_label(parent_label)?;
+    }
+    let public_key = age_public_key(ctx)?;
+
+    let input: Box<dyn Read> = if source == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        let file = File::open(source).with_context(|| format!("failed to open send stream: {source}"))?;
+        Box::new(BufReader::new(file))
+    };
+
+    let staging_dir = ctx.artifact_staging_dir();
+    btrfs::ensure_dir(&staging_dir)?;
+    let staged = staging_dir.join(artifact_filename(&ctx.naming, ctx.naming.prefix(), label, parent));
+    let staged_path = staged.to_str().unwrap_or_default();
+    let result = run_encrypt_pipeline(
+        input,
+        staged_path,
+        artifact_header(ctx, label, parent),
+        public_key,
+        ctx.age_backend(),
+        ctx.config.compression,
+        armor,
+    );
+    if let Err(err) = result {
+        let _ = fs::remove_file(&staged);
+        let name = ctx.snapshot_name(label);
+        return Err(err.context(format!("failed to ingest send stream for {name}")));
+    }
+
+    register_artifact(ctx, staged.to_str().unwrap_or_default(), RegisterMode::Move)
+}
+
+pub async fn ingest_artifact_streaming(
+    ctx: &AppContext,
+    label: &str,
+    parent: Option<&str>,
+    source: &str,
+) -> Result<()> {
+    ctx.ensure_new_label(label)?;
+    if let Some(parent_label) = parent {
+        ensure_label(parent_label)?;
+    }
+    let public_key = age_public_key(ctx)?.to_string();
+    let input: Box<dyn Read + Send> = if source == "-" {
+        Box::new(io::stdin())
+    } else {
+        let file = File::open(source).with_context(|| format!("failed to open send stream: {source}"))?;
+        Box::new(BufReader::new(file))
+    };
+    let client: Arc<dyn StorageBackend> = Arc::from(ctx.storage().await?);
+
+    let (backend, compression) = (ctx.age_backend(), ctx.config.compression);
+    let key = stream_to_cloud(ctx, client, ctx.naming.prefix(), label, parent, move |output| {
+        run_encrypt_stream(input, output, &public_key, backend, compression)
+    })
+    .await
+    .with_context(|| format!("failed to stream send stream for {}", ctx.snapshot_name(label)))?;
+    ctx.logger.info(format!("Artifact streamed to {key}"));
+    Ok(())
+}
+
+pub fn export_artifact(ctx: &AppContext, label: &str, dest: &str) -> Result<()> {
+    let private_key = ctx
+        .config
+        .crypto
+        .as_ref()
+        .and_then(|crypto| crypto.age_private_key_path.as_deref())
+        .ok_or_else(|| anyhow!("age_private_key_path is required in config"))?;
+
+    let index = ctx.manifest.read_index()?;
+    let resolved_label = ctx.resolve_label(index.records(), label)?;
+    let record = index.require(&resolved_label)?;
+    if record.local_path.is_empty() || !Path::new(&record.local_path).exists() {
+        let name = ctx.snapshot_name(&resolved_label);
+        return Err(anyhow!("artifact not available locally for {name}"));
+    }
+
+    if dest == "-" {
+        return run_decrypt_pipeline(
+            &record.local_path,
+            io::stdout().lock(),
+            private_key,
+            ctx.age_backend(),
+        );
+    }
+    let output = File::create(dest).with_context(|| format!("failed to create output: {dest}"))?;
+    let output = BufWriter::new(output);
+    let result = run_decrypt_pipeline(&record.local_path, output, private_key, ctx.age_backend());
+    if let Err(err) = result {
+        let _ = fs::remove_file(dest);
+        return Err(err);
+    }
+    ctx.logger
+        .info(format!("Exported {} send stream to {dest}", ctx.snapshot_name(&resolved_label)));
+    Ok(())
+}
+
+// Prints the v2 header of an artifact, or what its file name says for a
+// headerless v1 one. `verify` also checks the payload against the header.
+pub fn inspect_artifact(ctx: &AppContext, path: &str, verify: bool) -> Result<()> {
+    let (header, mut payload) = open_artifact(path)?;
+    let Some(header) = header else {
+        let mut fields = vec![("format", "v1 (no header)".to_string())];
+        let filename = Path::new(path).file_name().and_then(|v| v.to_str());
+        if let Some(info) = filename.and_then(|name| parse_artifact_filename(&ctx.naming, name)) {
+            fields.push(("label", info.label));
+            fields.push(("parent", info.parent.unwrap_or_else(|| "-".to_string())));
+        }
+        print_fields(ctx, &fields)?;
+        if verify {
+            return Err(anyhow!("{path} has no header to verify against"));
+        }
+        return Ok(());
+    };
+
+    let mut fields = vec![
+        ("format", "v2".to_string()),
+        ("label", header.label),
+        ("parent", header.parent.unwrap_or_else(|| "-".to_string())),
+        ("compression", header.compression),
+        ("created", header.created.format(&Rfc3339)?),
+        ("payload-sha256", header.payload_sha256.clone()),
+    ];
+    let matches = verify
+        .then(|| payload_sha256(&mut payload))
+        .transpose()?
+        .map(|sha256| sha256 == header.payload_sha256);
+    if matches == Some(true) {
+        fields.push(("payload", "ok".to_string()));
+    }
+    print_fields(ctx, &fields)?;
+    if matches == Some(false) {
+        return Err(anyhow!("payload of {path} does not match its header"));
+    }
+    Ok(())
+}
+
+// `name: value` lines, or under --json one object with the same fields.
+fn print_fields(ctx: &AppContext, fields: &[(&str, String)]) -> Result<()> {
+    if ctx.json {
+        let object: serde_json::Map<String, serde_json::Value> = fields
+            .iter()
+            .map(|(name, value)| (name.replace('-', "_"), value.clone().into()))
+            .collect();
+        return ctx.emit(&object);
+    }
+    for (name, value) in fields {
+        println!("{name}: {value}");
+    }
+    Ok(())
+}
+
+fn artifact_header(ctx: &AppContext, label: &str, parent: Option<&str>) -> ArtifactHeader {
+    let compression = format!("zstd-{}", ctx.config.compression.level);
+    ArtifactHeader::new(label, parent, &compression, ctx.clock.now())
+}
+
+fn age_public_key(ctx: &AppContext) -> Result<&str> {
+    ctx.config
+        .crypto
+        .as_ref()
+        .and_then(|crypto| crypto.age_public_key.as_deref())
+        .ok_or_else(|| anyhow!("age_public_key is required in config"))
+}
+
+pub fn register_artifact(ctx: &AppContext, path: &str, mode: RegisterMode) -> Result<()> {
+    register_artifacts(ctx, &[path], mode)
+}
+
+// A file put in place by register: where it came from, and its row.
+type Staged<'a> = (&'a str, ManifestRecord);
+
+// Every name is checked before anything is moved, and the rows go into each
+// manifest in one append, so a batch is either fully registered or not at all:
+// files whose rows never made it into a manifest are put back.
+pub fn register_artifacts(ctx: &AppContext, paths: &[&str], mode: RegisterMode) -> Result<()> {
+    let mut infos = Vec::new();
+    for path in paths {
+        infos.push((*path, parse_register_name(ctx, path)?));
+    }
+    let mut batches: Vec<(Option<String>, Vec<Staged>)> = Vec::new();
+    for (path, info) in infos {
+        let part = (info.stream != ctx.naming.prefix()).then(|| info.stream.clone());
+        let record = match stage_artifact(ctx, path, info, mode) {
+            Ok(Some(record)) => record,
+            Ok(None) => continue,
+            Err(err) => {
+                unstage(ctx, mode, batches.iter().flat_map(|(_, staged)| staged));
+                return Err(err);
+            }
+        };
+        match batches.iter_mut().find(|(batch_part, _)| *batch_part == part) {
+            Some((_, staged)) => staged.push((path, record)),
+            None => batches.push((part, vec![(path, record)])),
+        }
+    }
+    if batches.is_empty() {
+        return Ok(());
+    }
+    for (index, (part, staged)) in batches.iter().enumerate() {
+        let records: Vec<ManifestRecord> =
+            staged.iter().map(|(_, record)| record.clone()).collect();
+        let appended = ctx
+            .manifest_for(part.as_deref())
+            .and_then(|manifest| append_records(ctx, manifest, &records));
+        if let Err(err) = appended {
+            unstage(ctx, mode, batches[index..].iter().flat_map(|(_, staged)| staged));
+            return Err(err);
+        }
+    }
+
+    match paths.len() {
+        1 => ctx.logger.info("Registered artifact and updated manifest."),
+        count => ctx.logger.info(format!("Registered {count} artifacts and updated manifest.")),
+    }
+    Ok(())
+}
+
+// Moves staged files back to where they were registered from, or deletes the
+// copies, so a failed register leaves nothing in the LS layout for
+// `artifact gc` to find.
+fn unstage<'a>(
+    ctx: &AppContext,
+    mode: RegisterMode,
+    staged: impl Iterator<Item = &'a Staged<'a>>,
+) {
+    for (source, record) in staged {
+        let dest = Path::new(&record.local_path);
+        let undone = match mode {
+            RegisterMode::InPlace => continue,
+            RegisterMode::Move => move_artifact(&record.local_path, Path::new(source)),
+            RegisterMode::Copy => fs::remove_file(dest)
+                .with_context(|| format!("failed to remove {}", dest.display())),
+        };
+        if let Err(err) = undone {
+            ctx.logger.warn(format!("could not put back {}: {err:#}", dest.display()));
+        }
+    }
+}
+
+fn parse_register_name(ctx: &AppContext, path: &str) -> Result<ArtifactInfo> {
+    let filename = Path::new(path)
+        .file_name()
+        .and_then(|v| v.to_str())
+        .ok_or_else(|| anyhow!("invalid artifact path: {path}"))?;
+    ensure_complete(path, filename)?;
+    let info = parse_artifact_filename(&ctx.naming, filename).ok_or_else(|| {
+        let expected = ctx.naming.name(ctx.naming.prefix(), "LABEL");
+        let format = ctx.config.naming.label_scheme.format();
+        anyhow!("invalid artifact name: {filename} (expected {expected}.*, label must be {format})")
+    })?;
+    ctx.ensure_new_label(&info.label)?;
+    if let Some(parent) = info.parent.as_deref() {
+        ensure_label(parent)?;
+    }
+    ctx.manifest_for((info.stream != ctx.naming.prefix()).then_some(info.stream.as_str()))?;
+    Ok(info)
+}
+
+// Refuses what an interrupted build leaves: a `.partial` file, or a v2
+// artifact whose header still carries the placeholder payload checksum.
+fn ensure_complete(path: &str, filename: &str) -> Result<()> {
+    let partial = filename.ends_with(PARTIAL_SUFFIX)
+        || matches!(open_artifact(path), Ok((Some(header), _)) if !header.is_finished());
+    if partial {
+        return Err(anyhow!(
+            "{path} is a partial artifact left by an interrupted build; delete it and build again"
+        ));
+    }
+    Ok(())
+}
+
+// Puts the artifact in place and returns its manifest row, or None under
+// --dry-run.
+fn stage_artifact(
+    ctx: &AppContext,
+    path: &str,
+    info: ArtifactInfo,
+    mode: RegisterMode,
+) -> Result<Option<ManifestRecord>> {
+    let part = (info.stream != ctx.naming.prefix()).then_some(info.stream.as_str());
+    let dest_path = if mode == RegisterMode::InPlace {
+        fs::canonicalize(path).with_context(|| format!("artifact not found: {path}"))?
+    } else {
+        let machine = info.machine.as_deref();
+        let dest_dir = ctx.ls_path(&artifact_dir(machine, part, info.artifact_type));
+        let dest_path = dest_dir.join(&info.filename);
+        let verb = if mode == RegisterMode::Move { "move" } else { "copy" };
+        ctx.perform(format!("{verb} {path} to {}", dest_path.display()), || {
+            btrfs::ensure_dir(&dest_dir)?;
+            match mode {
+                RegisterMode::Move => move_artifact(path, &dest_path)?,
+                _ => copy_artifact(path, &dest_path)?,
+            }
+            permissions::apply_file(ctx, "artifacts", &dest_path)
+        })?;
+        dest_path
+    };
+    if ctx.dry_run {
+        let stream = part.unwrap_or(ctx.naming.prefix());
+        ctx.logger
+            .info(format!("Dry run: would add {stream}@{} to the manifest", info.label));
+        return Ok(None);
+    }
+
+    let bytes = dest_path.metadata()?.len();
+    let sha256 = sha256_file(dest_path.to_str().unwrap_or_default())?;
+
+    let record = ManifestRecord {
+        ts: ctx.clock.now(),
+        label: info.label,
+        record_type: record_type(info.artifact_type).to_string(),
+        parent: info.parent.unwrap_or_default(),
+        bytes,
+        sha256,
+        local_path: dest_path.to_string_lossy().to_string(),
+        object_key: String::new(),
+        mirror_keys: String::new(),
+        status: RecordStatus::Registered,
+        machine: info.machine.unwrap_or_default(),
+    };
+    Ok(Some(record))
+}
+
+// Relative to the LS root, which is also the object key prefix on push. Each
+// machine id gets its own tree under artifacts/machines/.
+fn artifact_dir(machine: Option<&str>, part: Option<&str>, artifact_type: ArtifactType) -> String {
+    let kind = match artifact_type {
+        ArtifactType::Anchor => "anchors",
+        ArtifactType::Incremental => "incr",
+    };
+    let root = match machine {
+        Some(machine) => format!("artifacts/machines/{machine}"),
+        None => "artifacts".to_string(),
+    };
+    match part {
+        None => format!("{root}/{kind}"),
+        Some(part) => format!("{root}/parts/{part}/{kind}"),
+    }
+}
+
+fn record_type(artifact_type: ArtifactType) -> &'static str {
+    match artifact_type {
+        ArtifactType::Anchor => "anchor",
+        ArtifactType::Incremental => "incremental",
+    }
+}
+
+fn append_records(
+    ctx: &AppContext,
+    manifest: &ManifestStore,
+    new_records: &[ManifestRecord],
+) -> Result<()> {
+    let Some(last) = new_records.last() else {
+        return Ok(());
+    };
+    manifest.ensure_initialized()?;
+    let mut records = manifest.read_records()?;
+    records.extend_from_slice(new_records);
+    let skew = find_clock_skew(&records, last.ts);
+    for issue in &skew {
+        ctx.logger.warn(format!("clock skew detected: {issue}"));
+    }
+    if !skew.is_empty() {
+        ctx.logger.warn(
+            "manifest timestamps are out of order; run `dev-backup manifest fix-timestamps` to repair",
+        );
+    }
+    match new_records {
+        [record] => manifest.append_record(record),
+        _ => manifest.append_records(new_records),
+    }
+}
+
+// Files under the LS artifacts/ directory that no manifest row of any dataset
+// or split part points at, or whose sha256 differs from their row's. They are
+// only listed unless `quarantine` is set, which moves them to the same path
+// under tmp/quarantine/ rather than deleting them.
+pub fn artifact_gc(ctx: &AppContext, quarantine: bool) -> Result<()> {
+    let ls_root = Path::new(&ctx.config.paths.ls_root);
+    let mut checksums: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    for record in ctx.all_records().context("refusing to collect garbage")? {
+        if !record.local_path.is_empty() {
+            let path = ls_root.join(&record.local_path);
+            checksums.entry(path).or_default().push(record.sha256);
+        }
+    }
+
+    let mut files = Vec::new();
+    let mut pending = vec![ctx.ls_path("artifacts")];
+    while let Some(dir) = pending.pop() {
+        if !dir.exists() {
+            continue;
+        }
+        let entries =
+            fs::read_dir(&dir).with_context(|| format!("failed to read {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+
+    let mut flagged = 0;
+    for path in files {
+        let reason = match checksums.get(&path) {
+            None => "unreferenced",
+            Some(sums) if !sums.contains(&sha256_file(path.to_str().unwrap_or_default())?) => {
+                "checksum mismatch"
+            }
+            Some(_) => continue,
+        };
+        flagged += 1;
+        let relative = path.strip_prefix(ls_root).unwrap_or(&path);
+        if ctx.json {
+            ctx.emit(&serde_json::json!({ "path": relative, "reason": reason }))?;
+        } else {
+            println!("{}\t{reason}", relative.display());
+        }
+        if quarantine {
+            let dest = ctx.ls_path("tmp/quarantine").join(relative);
+            ctx.perform(format!("move {} to {}", path.display(), dest.display()), || {
+                btrfs::ensure_dir(dest.parent().unwrap_or(Path::new("/")))?;
+                fs::rename(&path, &dest)
+                    .with_context(|| format!("failed to move {}", path.display()))
+            })?;
+        }
+    }
+    if flagged > 0 && !quarantine {
+        ctx.logger.info(format!(
+            "{flagged} artifact file(s) flagged; rerun with --quarantine to move them aside"
+        ));
+    } else if flagged > 0 && !ctx.dry_run {
+        let dir = ctx.ls_path("tmp/quarantine");
+        ctx.logger.info(format!("Moved {flagged} artifact file(s) to {}", dir.display()));
+    }
+    Ok(())
+}
+
+pub fn watch_inbox(ctx: &AppContext, dir: &str, interval_secs: u64, once: bool) -> Result<()> {
+    let inbox = Path::new(dir);
+    if !inbox.is_dir() {
+        return Err(anyhow!("inbox directory not found: {dir}"));
+    }
+    ctx.logger.info(format!("Watching {dir} for artifacts"));
+
+    // Size seen on the previous scan; a file is only picked up once its size
+    // stops changing, so in-flight copies are not registered half-written.
+    let mut pending: HashMap<PathBuf, u64> = HashMap::new();
+    let mut rejected: HashMap<PathBuf, u64> = HashMap::new();
+    loop {
+        let mut seen = HashMap::new();
+        for entry in fs::read_dir(inbox).with_context(|| format!("failed to read inbox: {dir}"))? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !entry.file_type()?.is_file() || !name.ends_with(".send.zst.age") {
+                continue;
+            }
+            let size = entry.metadata()?.len();
+            seen.insert(path.clone(), size);
+            if rejected.get(&path) == Some(&size) {
+                continue;
+            }
+            if !once && pending.get(&path) != Some(&size) {
+                continue;
+            }
+            let locks = lock::acquire(ctx, &[LockScope::Manifest])?;
+            let registered = register_inbox_file(ctx, &path);
+            drop(locks);
+            match registered {
+                Ok(()) => {
+                    rejected.remove(&path);
+                }
+                Err(err) => {
+                    ctx.logger.warn(format!("skipping {}: {err:#}", path.display()));
+                    rejected.insert(path.clone(), size);
+                }
+            }
+        }
+        rejected.retain(|path, _| seen.contains_key(path));
+        pending = seen;
+
+        if once {
+            return Ok(());
+        }
+        if ctx.deadline_reached() {
+            ctx.logger.info("Deadline reached; stopping watch");
+            return Ok(());
+        }
+        thread::sleep(Duration::from_secs(interval_secs));
+    }
+}
+
+fn move_artifact(src: &str, dest: &Path) -> Result<()> {
+    match fs::rename(src, dest) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_artifact(src, dest)?;
+            fs::remove_file(src).with_context(|| format!("failed to remove original artifact: {src}"))
+        }
+        Err(err) => Err(err).with_context(|| format!("failed to move artifact to {}", dest.display())),
+    }
+}
+
+fn copy_artifact(src: &str, dest: &Path) -> Result<()> {
+    let expected = sha256_file(src)?;
+    fs::copy(src, dest).with_context(|| format!("failed to copy artifact to {}", dest.display()))?;
+    let actual = sha256_file(dest.to_str().unwrap_or_default())?;
+    if expected != actual {
+        let _ = fs::remove_file(dest);
+        return Err(anyhow!("copy verification failed for {}: sha256 mismatch", dest.display()));
+    }
+    Ok(())
+}
+
+fn register_inbox_file(ctx: &AppContext, path: &Path) -> Result<()> {
+    let filename = path
+        .file_name()
+        .and_then(|v| v.to_str())
+        .ok_or_else(|| anyhow!("invalid artifact path: {}", path.display()))?;
+    parse_artifact_filename(&ctx.naming, filename)
+        .ok_or_else(|| anyhow!("invalid artifact name: {filename}"))?;
+
+    let sidecar = path.with_file_name(format!("{filename}.sha256"));
+    if sidecar.exists() {
+        let contents = fs::read_to_string(&sidecar)
+            .with_context(|| format!("failed to read checksum: {}", sidecar.display()))?;
+        let expected = contents
+            .split_whitespace()
+            .next()
+            .ok_or_else(|| anyhow!("empty checksum file: {}", sidecar.display()))?;
+        let actual = sha256_file(path.to_str().unwrap_or_default())?;
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(anyhow!("sha256 mismatch: expected {expected}, got {actual}"));
+        }
+    }
+
+    register_artifact(ctx, path.to_str().unwrap_or_default(), RegisterMode::Move)?;
+    if sidecar.exists() {
+        fs::remove_file(&sidecar)
+            .with_context(|| format!("failed to remove checksum: {}", sidecar.display()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use crate::commands::artifact::{build_artifact_into, register_artifacts, RegisterMode};
+use crate::commands::restore::{clear_rebaseline, pending_rebaseline};
+use crate::commands::snapshot::create_snapshot;
+use crate::commands::sync::{sync_push, PushScope};
+use crate::context::AppContext;
+use crate::format::{format_bytes, format_duration};
+use crate::label::latest_label_from_records;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_btrfs as btrfs;
+use dev_backup_core::config::Notify;
+use dev_backup_core::manifest::sort_records_by_ts;
+use dev_backup_core::policy::{decide_snapshot_type, PolicyInput, SnapshotDecision};
+use dev_backup_storage::artifact::parse_artifact_filename;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// `backup run --label auto` is `backup-now` without a label.
+pub async fn backup_run(ctx: &AppContext, label: &str) -> Result<()> {
+    backup_now(ctx, (label != "auto").then_some(label)).await
+}
+
+// snapshot -> policy -> build -> register -> push in one go, for a host that
+// is its own LS (ls_root is local). The label defaults to the current month,
+// and a month that is already in the manifest only pushes. A summary is
+// printed at the end and handed to every [notify] channel whether the run
+// worked or not.
+pub async fn backup_now(ctx: &AppContext, label: Option<&str>) -> Result<()> {
+    let started = ctx.clock.now();
+    let mut report = RunReport::default();
+    let result = run_backup(ctx, label, &mut report).await;
+    let elapsed = format_duration(ctx.clock.now() - started);
+    report.summary.push(match &result {
+        Ok(()) => format!("Status: ok in {elapsed}"),
+        Err(err) => format!("Status: failed after {elapsed}: {err:#}"),
+    });
+
+    let text = report.summary.join("\n");
+    ctx.logger.info(format!("Summary:\n{text}"));
+    let error = result.as_ref().err().map(|err| format!("{err:#}")).unwrap_or_default();
+    let bytes = format_bytes(report.bytes);
+    let values = [
+        ("label", report.label.as_str()),
+        ("dataset", ctx.config.paths.dataset.as_str()),
+        ("bytes", bytes.as_str()),
+        ("duration", elapsed.as_str()),
+        ("outcome", if result.is_ok() { "ok" } else { "failed" }),
+        ("error", error.as_str()),
+        ("summary", text.as_str()),
+    ];
+    notify_all(ctx, result.is_ok(), &values);
+    result
+}
+
+// A channel that fails only warns: the notification is never why a run fails.
+pub fn notify_all(ctx: &AppContext, ok: bool, values: &[(&str, &str)]) {
+    for notify in &ctx.config.notify {
+        let sent = notify
+            .message_template()
+            .and_then(|template| send_notification(notify, ok, &template.render(values)));
+        if let Err(err) = sent {
+            ctx.logger
+                .warn(format!("notification via {} failed: {err:#}", notify.display_name()));
+        }
+    }
+}
+
+// What a run did, for the summary and the notification templates.
+#[derive(Default)]
+struct RunReport {
+    summary: Vec<String>,
+    label: String,
+    bytes: u64,
+}
+
+const STEPS: [&str; 5] = ["snapshot", "policy", "build", "register", "push"];
+
+fn step(ctx: &AppContext, name: &str, status: impl AsRef<str>) {
+    let number = STEPS.iter().position(|step| *step == name).unwrap_or_default() + 1;
+    ctx.logger.info(format!("[{number}/{}] {name}: {}", STEPS.len(), status.as_ref()));
+}
+
+async fn run_backup(ctx: &AppContext, label: Option<&str>, report: &mut RunReport) -> Result<()> {
+    // Each step is a command [access] may deny on its own; none of them runs
+    // unless all of them may.
+    let push = ctx.config.cloud.is_some().then_some("sync.push");
+    for command in ["snapshot", "artifact.build", "artifact.register"].into_iter().chain(push) {
+        ctx.config.access.check(command)?;
+    }
+    let label = match label {
+        Some(label) => {
+            ctx.ensure_new_label(label)?;
+            label.to_string()
+        }
+        None => ctx.auto_label(),
+    };
+    report.label = label.clone();
+
+    if !ctx.manifest.records_for_label(&label)?.is_empty() {
+        let name = ctx.snapshot_name(&label);
+        for name in &STEPS[..4] {
+            step(ctx, name, "skipped");
+        }
+        report.summary.push(format!("{name}: already in the manifest, nothing to build"));
+    } else {
+        // Snapshots that exist before the run are not this run's to remove.
+        let fresh: Vec<String> = std::iter::once(ctx.naming.prefix())
+            .chain(ctx.config.split.parts.iter().map(String::as_str))
+            .map(|stream| ctx.stream_snapshot_path(stream, &label))
+            .filter(|path| !Path::new(path).exists())
+            .collect();
+        if let Err(err) = build_and_register(ctx, &label, report) {
+            roll_back(ctx, &label, &fresh, report);
+            return Err(err);
+        }
+    }
+
+    if ctx.config.cloud.is_none() {
+        step(ctx, "push", "skipped, no [cloud] configured");
+        report.summary.push("Push: skipped, no [cloud] configured".to_string());
+        return Ok(());
+    }
+    // The artifacts stay registered, so the next run pushes them.
+    if let Err(err) = sync_push(ctx, PushScope::All).await {
+        step(ctx, "push", "failed");
+        return Err(err);
+    }
+    step(ctx, "push", "done");
+    report.summary.push("Push: done".to_string());
+    Ok(())
+}
+
+fn build_and_register(ctx: &AppContext, label: &str, report: &mut RunReport) -> Result<()> {
+    let records = sort_records_by_ts(ctx.manifest.read_records()?);
+    let decision = if records.is_empty() {
+        SnapshotDecision::Anchor
+    } else {
+        decide_snapshot_type(&records, PolicyInput::at(ctx.clock.as_ref()))?
+    };
+    let mut parent = match decision {
+        SnapshotDecision::Anchor => None,
+        SnapshotDecision::Incremental => Some(latest_label_from_records(&records)?),
+    };
+
+    let name = ctx.snapshot_name(label);
+    if let Err(err) = create_snapshot(ctx, label) {
+        step(ctx, "snapshot", "failed");
+        return Err(err);
+    }
+    step(ctx, "snapshot", format!("{name} taken"));
+    if let Some(missing) = parent
+        .as_deref()
+        .filter(|parent| !Path::new(&ctx.snapshot_path(parent)).exists())
+    {
+        let missing = ctx.snapshot_name(missing);
+        report
+            .summary
+            .push(format!("{missing} snapshot is gone, so this month is an anchor"));
+        parent = None;
+    }
+    let rebaseline = pending_rebaseline(ctx).filter(|restored| {
+        parent.as_ref().is_some_and(|parent| parent != restored)
+    });
+    if let Some(restored) = rebaseline {
+        let restored = ctx.snapshot_name(&restored);
+        report
+            .summary
+            .push(format!("the working tree was restored to {restored}, so this month is an anchor"));
+        parent = None;
+    }
+    let kind = match &parent {
+        Some(parent) => format!("incremental from {parent}"),
+        None => "anchor".to_string(),
+    };
+    step(ctx, "policy", &kind);
+
+    let staging_dir = ctx.artifact_staging_dir();
+    btrfs::ensure_dir(&staging_dir)?;
+    let built = match build_artifact_into(ctx, label, parent.as_deref(), &staging_dir) {
+        Ok(built) => built,
+        Err(err) => {
+            step(ctx, "build", "failed");
+            return Err(err);
+        }
+    };
+    let mut bytes = 0;
+    for path in &built {
+        bytes += fs::metadata(path)
+            .with_context(|| format!("artifact missing: {}", path.display()))?
+            .len();
+    }
+    step(ctx, "build", format!("{} artifact(s), {}", built.len(), format_bytes(bytes)));
+    let paths: Vec<&str> = built.iter().map(|path| path.to_str().unwrap_or_default()).collect();
+    if let Err(err) = register_artifacts(ctx, &paths, RegisterMode::Move) {
+        step(ctx, "register", "failed");
+        return Err(err);
+    }
+    step(ctx, "register", "done");
+    clear_rebaseline(ctx)?;
+    report.summary.push(format!(
+        "{name}: {kind}, {} artifact(s), {}",
+        built.len(),
+        format_bytes(bytes)
+    ));
+    report.bytes = bytes;
+    Ok(())
+}
+
+// Undoes a run that failed before its artifacts were registered: the
+// artifacts it left in the staging directory and the snapshots it took go, so
+// the next run starts over instead of finding half of this one.
+fn roll_back(ctx: &AppContext, label: &str, fresh: &[String], report: &mut RunReport) {
+    let mut removed = Vec::new();
+    let staging_dir = ctx.artifact_staging_dir();
+    for entry in fs::read_dir(&staging_dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let info = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| parse_artifact_filename(&ctx.naming, name));
+        let ours = info.is_some_and(|info| {
+            info.label == label && info.machine.as_deref() == ctx.naming.machine()
+        });
+        if ours && fs::remove_file(&path).is_ok() {
+            removed.push(path.display().to_string());
+        }
+    }
+    for path in fresh.iter().filter(|path| Path::new(path).exists()) {
+        match ctx.btrfs().subvolume_delete(path) {
+            Ok(()) => removed.push(path.clone()),
+            Err(err) => ctx.logger.warn(format!("rollback could not delete {path}: {err:#}")),
+        }
+    }
+    if !removed.is_empty() {
+        report.summary.push(format!("Rolled back: removed {}", removed.join(", ")));
+    }
+}
+
+fn send_notification(notify: &Notify, ok: bool, message: &str) -> Result<()> {
+    let (program, args) = notify
+        .command
+        .split_first()
+        .ok_or_else(|| anyhow!("notify.command is empty"))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .env("DEV_BACKUP_STATUS", if ok { "ok" } else { "failed" })
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run {program}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "{message}").context("failed to write the message")?;
+    }
+    let status = child.wait().with_context(|| format!("failed to wait on {program}"))?;
+    if !status.success() {
+        return Err(anyhow!("{program} exited with {status}"));
+    }
+    Ok(())
+}
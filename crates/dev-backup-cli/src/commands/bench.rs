@@ -0,0 +1,277 @@
+use crate::commands::snapshot::local_snapshot_labels;
+use crate::context::AppContext;
+use crate::format::format_bytes;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_storage::crypto::{encrypt_writer, finish_writer, generate_identity};
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Instant;
+
+const LEVELS: [i32; 5] = [1, 3, 6, 9, 15];
+// Levels within this fraction of the fastest pipeline count as just as fast,
+// and the highest of them wins for its smaller artifacts.
+const TOLERANCE: f64 = 0.05;
+
+#[derive(Debug, Serialize)]
+struct Measurement {
+    stage: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mib_per_second: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ratio: Option<f64>,
+    detail: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ZstdRun {
+    level: i32,
+    threads: u32,
+    rate: f64,
+    ratio: f64,
+}
+
+// Times btrfs send, zstd at several levels and thread counts, age and an
+// upload to [cloud] on a sample of up to `sample_mib`, then recommends the
+// [compression] settings that move the most data per second through all of
+// them. The sample is the start of a send of the newest local snapshot, or
+// made up when there is none.
+pub async fn bench(ctx: &AppContext, sample_mib: u64) -> Result<()> {
+    if sample_mib == 0 {
+        return Err(anyhow!("--sample-mib must be at least 1"));
+    }
+    let limit = sample_mib * 1024 * 1024;
+    let mut measurements = Vec::new();
+
+    let (sample, send_rate) = match send_sample(ctx, limit)? {
+        Some((sample, rate, name)) => {
+            measurements.push(Measurement {
+                stage: "btrfs send".to_string(),
+                mib_per_second: Some(rate),
+                ratio: None,
+                detail: format!("{} of {name}", format_bytes(sample.len() as u64)),
+            });
+            (sample, Some(rate))
+        }
+        None => {
+            measurements.push(Measurement {
+                stage: "btrfs send".to_string(),
+                mib_per_second: None,
+                ratio: None,
+                detail: "skipped, no local snapshot; using a generated sample".to_string(),
+            });
+            (generated_sample(limit as usize), None)
+        }
+    };
+
+    let cores = thread::available_parallelism().map_or(1, |n| n.get() as u32);
+    let mut runs = Vec::new();
+    for level in LEVELS {
+        for threads in [0, cores].into_iter().filter(|t| *t == 0 || cores > 1) {
+            let run = time_zstd(&sample, level, threads)?;
+            measurements.push(Measurement {
+                stage: format!("zstd level {level}, {threads} worker(s)"),
+                mib_per_second: Some(run.rate),
+                ratio: Some(run.ratio),
+                detail: format!("ratio {:.2}", run.ratio),
+            });
+            runs.push(run);
+        }
+    }
+
+    let age_rate = time_age(&sample)?;
+    measurements.push(Measurement {
+        stage: "age".to_string(),
+        mib_per_second: Some(age_rate),
+        ratio: None,
+        detail: "native backend".to_string(),
+    });
+
+    let upload_rate = match &ctx.config.cloud {
+        Some(_) => {
+            let rate = time_upload(ctx, &sample).await?;
+            measurements.push(Measurement {
+                stage: "upload".to_string(),
+                mib_per_second: Some(rate),
+                ratio: None,
+                detail: format!("{} test object", format_bytes(sample.len() as u64)),
+            });
+            Some(rate)
+        }
+        None => {
+            measurements.push(Measurement {
+                stage: "upload".to_string(),
+                mib_per_second: None,
+                ratio: None,
+                detail: "skipped, no [cloud] configured".to_string(),
+            });
+            None
+        }
+    };
+
+    for measurement in &measurements {
+        if ctx.json {
+            ctx.emit(measurement)?;
+            continue;
+        }
+        match measurement.mib_per_second {
+            Some(rate) => ctx.logger.info(format!(
+                "{}: {rate:.1} MiB/s ({})",
+                measurement.stage, measurement.detail
+            )),
+            None => ctx.logger.info(format!("{}: {}", measurement.stage, measurement.detail)),
+        }
+    }
+
+    let best = recommend(&runs, send_rate, age_rate, upload_rate);
+    if ctx.json {
+        ctx.emit(&serde_json::json!({
+            "recommendation": { "level": best.level, "threads": best.threads },
+        }))?;
+        return Ok(());
+    }
+    ctx.logger.info(format!(
+        "Recommended: [compression] level = {}, threads = {} (currently {}, {})",
+        best.level, best.threads, ctx.config.compression.level, ctx.config.compression.threads
+    ));
+    Ok(())
+}
+
+// Compression sits between send and age, and what it saves is what the upload
+// no longer has to carry, so each run is scored by the slowest of the stages
+// in bytes of send stream per second.
+fn recommend(runs: &[ZstdRun], send: Option<f64>, age: f64, upload: Option<f64>) -> ZstdRun {
+    let throughput = |run: &ZstdRun| {
+        let mut rate = run.rate.min(age * run.ratio);
+        if let Some(send) = send {
+            rate = rate.min(send);
+        }
+        if let Some(upload) = upload {
+            rate = rate.min(upload * run.ratio);
+        }
+        rate
+    };
+    let fastest = runs.iter().map(throughput).fold(0.0, f64::max);
+    let mut candidates: Vec<&ZstdRun> = runs
+        .iter()
+        .filter(|run| throughput(run) >= fastest * (1.0 - TOLERANCE))
+        .collect();
+    candidates.sort_by_key(|run| (std::cmp::Reverse(run.level), run.threads));
+    *candidates[0]
+}
+
+fn send_sample(ctx: &AppContext, limit: u64) -> Result<Option<(Vec<u8>, f64, String)>> {
+    let Some(label) = local_snapshot_labels(ctx).ok().and_then(|labels| labels.last().cloned())
+    else {
+        return Ok(None);
+    };
+    let mut child = Command::new("btrfs")
+        .args(["send", &ctx.snapshot_path(&label)])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("failed to start btrfs send")?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("failed to capture btrfs send stdout"))?;
+    let started = Instant::now();
+    let mut sample = Vec::new();
+    let read = stdout.take(limit).read_to_end(&mut sample);
+    let elapsed = started.elapsed().as_secs_f64();
+    // The rest of the stream is not needed.
+    let _ = child.kill();
+    let _ = child.wait();
+    read.context("failed to read btrfs send output")?;
+    if sample.is_empty() {
+        return Err(anyhow!("btrfs send of {} produced no output", ctx.snapshot_name(&label)));
+    }
+    let rate = mib_per_second(sample.len(), elapsed);
+    Ok(Some((sample, rate, ctx.snapshot_name(&label))))
+}
+
+// Half text-like, half incompressible, so the ratios are neither flattering
+// nor useless.
+fn generated_sample(size: usize) -> Vec<u8> {
+    let mut sample = Vec::with_capacity(size);
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut block = 0;
+    while sample.len() < size {
+        if block % 2 == 0 {
+            for _ in 0..4096 / 8 {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                sample.extend_from_slice(&state.to_le_bytes());
+            }
+        } else {
+            let end = sample.len() + 4096;
+            while sample.len() < end {
+                let line = format!("fn block_{block}() -> usize {{ {} }}\n", sample.len() % 1000);
+                sample.extend_from_slice(line.as_bytes());
+            }
+        }
+        block += 1;
+    }
+    sample.truncate(size);
+    sample
+}
+
+fn time_zstd(sample: &[u8], level: i32, threads: u32) -> Result<ZstdRun> {
+    let started = Instant::now();
+    let mut encoder =
+        zstd::Encoder::new(Vec::new(), level).context("failed to start zstd encoder")?;
+    if threads > 0 {
+        encoder
+            .multithread(threads)
+            .context("failed to enable zstd worker threads")?;
+    }
+    encoder.write_all(sample).context("failed to compress sample")?;
+    let compressed = encoder.finish().context("failed to finish zstd stream")?;
+    let elapsed = started.elapsed().as_secs_f64();
+    Ok(ZstdRun {
+        level,
+        threads,
+        rate: mib_per_second(sample.len(), elapsed),
+        ratio: sample.len() as f64 / compressed.len().max(1) as f64,
+    })
+}
+
+// The recipient is a throwaway key: speed does not depend on whose it is.
+fn time_age(sample: &[u8]) -> Result<f64> {
+    let identity = generate_identity();
+    let public_key = identity
+        .lines()
+        .find_map(|line| line.strip_prefix("# public key: "))
+        .ok_or_else(|| anyhow!("generated age identity has no public key"))?;
+    let started = Instant::now();
+    let mut writer = encrypt_writer(public_key, io::sink(), false)?;
+    writer.write_all(sample).context("failed to encrypt sample")?;
+    finish_writer(writer)?;
+    Ok(mib_per_second(sample.len(), started.elapsed().as_secs_f64()))
+}
+
+// Uploads the sample under bench/ and deletes it again.
+async fn time_upload(ctx: &AppContext, sample: &[u8]) -> Result<f64> {
+    let client = ctx.storage().await?;
+    let name = format!("dev-backup-bench-{}.bin", std::process::id());
+    let path = std::env::temp_dir().join(&name);
+    fs::write(&path, sample).with_context(|| format!("failed to write {}", path.display()))?;
+    let key = format!("bench/{name}");
+    let started = Instant::now();
+    let uploaded = client.put(&key, path.to_str().unwrap_or_default()).await;
+    let elapsed = started.elapsed().as_secs_f64();
+    let _ = fs::remove_file(&path);
+    uploaded.with_context(|| format!("failed to upload test object {key}"))?;
+    client
+        .delete(&key)
+        .await
+        .with_context(|| format!("failed to delete test object {key}"))?;
+    Ok(mib_per_second(sample.len(), elapsed))
+}
+
+fn mib_per_second(bytes: usize, seconds: f64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0) / seconds.max(1e-6)
+}
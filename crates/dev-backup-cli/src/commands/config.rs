@@ -0,0 +1,30 @@
+use crate::commands::snapshot;
+use crate::context::AppContext;
+use anyhow::{anyhow, Result};
+use dev_backup_core::config::CloudBackend;
+
+pub fn validate(ctx: &AppContext) -> Result<()> {
+    check(ctx)?;
+    ctx.logger.info(format!("Config OK: {}", ctx.config_path));
+    Ok(())
+}
+
+pub fn check(ctx: &AppContext) -> Result<()> {
+    ctx.config.validate()?;
+    let paths = &ctx.config.paths;
+    let mut roots = vec![(paths.dataset.as_str(), paths.snapshots.as_str())];
+    if !ctx.config.datasets.is_empty() {
+        let datasets = ctx.config.datasets.iter();
+        roots = datasets.map(|d| (d.path.as_str(), d.snapshots.as_str())).collect();
+    }
+    for (dataset, snapshots) in roots {
+        snapshot::check_same_filesystem(ctx, dataset, snapshots)?;
+    }
+    let targets = ctx.config.cloud.iter().chain(ctx.config.mirrors.iter().map(|m| &m.target));
+    for target in targets {
+        if target.backend == CloudBackend::R2 && !cfg!(feature = "cloud") {
+            return Err(anyhow!(crate::context::NO_CLOUD_SUPPORT));
+        }
+    }
+    Ok(())
+}
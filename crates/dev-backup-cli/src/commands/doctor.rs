@@ -0,0 +1,294 @@
+use crate::commands::config;
+use crate::context::AppContext;
+use crate::permissions;
+use anyhow::{anyhow, Result};
+use dev_backup_btrfs as btrfs;
+use dev_backup_core::config::{AgeBackend, Cloud, CloudBackend};
+use serde::Serialize;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+// Older btrfs-progs are not tested against, so doctor warns about them.
+const MIN_BTRFS_PROGS: (u32, u32) = (5, 10);
+const CLOUD_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+struct Finding {
+    check: String,
+    status: Outcome,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fix: Option<String>,
+}
+
+impl Finding {
+    fn ok(check: &str, detail: impl Into<String>) -> Self {
+        Self { check: check.to_string(), status: Outcome::Ok, detail: detail.into(), fix: None }
+    }
+
+    fn warn(check: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self { status: Outcome::Warn, fix: Some(fix.into()), ..Self::ok(check, detail) }
+    }
+
+    fn fail(check: &str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self { status: Outcome::Fail, fix: Some(fix.into()), ..Self::ok(check, detail) }
+    }
+}
+
+// Checks the host and config for what backups and restores need, printing
+// each problem with how to fix it. Only failures make the command fail.
+pub async fn doctor(ctx: &AppContext) -> Result<()> {
+    let mut findings = vec![check_config(ctx)];
+    findings.extend(check_programs(ctx));
+    if find_program("btrfs").is_some() {
+        findings.push(check_btrfs_progs());
+    }
+    let paths = &ctx.config.paths;
+    findings.push(check_btrfs_path("dataset", &paths.dataset));
+    findings.push(check_btrfs_path("snapshots", &paths.snapshots));
+    findings.extend(check_keys(ctx));
+    findings.extend(check_permissions(ctx));
+    findings.extend(check_cloud(ctx).await);
+
+    for finding in &findings {
+        if ctx.json {
+            ctx.emit(finding)?;
+            continue;
+        }
+        let tag = match finding.status {
+            Outcome::Ok => "ok",
+            Outcome::Warn => "WARN",
+            Outcome::Fail => "FAIL",
+        };
+        ctx.logger.info(format!("{tag:<4}  {}: {}", finding.check, finding.detail));
+        if let Some(fix) = &finding.fix {
+            ctx.logger.info(format!("      fix: {fix}"));
+        }
+    }
+    let failed = findings.iter().filter(|f| f.status == Outcome::Fail).count();
+    if failed > 0 {
+        return Err(anyhow!("{failed} check(s) failed"));
+    }
+    let warned = findings.iter().filter(|f| f.status == Outcome::Warn).count();
+    ctx.logger.info(format!("No problems found ({warned} warning(s))"));
+    Ok(())
+}
+
+fn check_config(ctx: &AppContext) -> Finding {
+    match config::check(ctx) {
+        Ok(()) => Finding::ok("config", format!("{} is valid", ctx.config_path)),
+        Err(err) => Finding::fail(
+            "config",
+            format!("{err:#}"),
+            "correct the config (see docs/config.example.toml)",
+        ),
+    }
+}
+
+fn check_programs(ctx: &AppContext) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    // send and receive run the binary even with the native btrfs backend.
+    findings.push(require_program("btrfs", "install btrfs-progs"));
+    findings.push(Finding::ok("zstd", "built in"));
+    if ctx.age_backend() == AgeBackend::External {
+        let fix = "install age, or set crypto.age_backend = \"native\"";
+        findings.push(require_program("age", fix));
+        findings.push(require_program("age-keygen", fix));
+    } else {
+        findings.push(Finding::ok("age", "built in (crypto.age_backend = \"native\")"));
+    }
+
+    let config = &ctx.config;
+    let sftp = config
+        .cloud
+        .iter()
+        .chain(config.mirrors.iter().map(|mirror| &mirror.target))
+        .any(|target| target.backend == CloudBackend::Sftp);
+    if config.remote.is_some() || sftp {
+        findings.push(require_program("ssh", "install the OpenSSH client"));
+    } else {
+        findings.push(Finding::ok("ssh", "not needed without [remote] or an sftp target"));
+    }
+    if sftp {
+        findings.push(require_program("sftp", "install the OpenSSH client"));
+    }
+    let http = config
+        .cloud
+        .iter()
+        .chain(config.mirrors.iter().map(|mirror| &mirror.target))
+        .any(|target| target.backend == CloudBackend::Http);
+    if http {
+        findings.push(require_program("curl", "install curl"));
+    }
+    findings
+}
+
+fn require_program(name: &str, fix: &str) -> Finding {
+    match find_program(name) {
+        Some(path) => Finding::ok(name, path.display().to_string()),
+        None => Finding::fail(name, "not found on PATH", fix),
+    }
+}
+
+fn find_program(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).map(|dir| dir.join(name)).find(|candidate| {
+        candidate
+            .metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    })
+}
+
+fn check_btrfs_progs() -> Finding {
+    let check = "btrfs-progs";
+    let output = match Command::new("btrfs").arg("--version").output() {
+        Ok(output) if output.status.success() => output,
+        _ => return Finding::fail(check, "`btrfs --version` did not run", "install btrfs-progs"),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let Some(version) = stdout.split_whitespace().find_map(|word| word.strip_prefix('v')) else {
+        return Finding::warn(
+            check,
+            format!("unrecognized version: {}", stdout.trim()),
+            "check that `btrfs` on PATH is btrfs-progs",
+        );
+    };
+    let mut numbers = version.split('.').map(|part| part.parse::<u32>().unwrap_or(0));
+    let found = (numbers.next().unwrap_or(0), numbers.next().unwrap_or(0));
+    if found < MIN_BTRFS_PROGS {
+        let (major, minor) = MIN_BTRFS_PROGS;
+        return Finding::warn(
+            check,
+            format!("v{version} is older than v{major}.{minor}"),
+            format!("upgrade btrfs-progs to v{major}.{minor} or later"),
+        );
+    }
+    Finding::ok(check, format!("v{version}"))
+}
+
+fn check_btrfs_path(name: &str, path: &str) -> Finding {
+    let fix = format!("put paths.{name} on a btrfs filesystem");
+    if !Path::new(path).exists() {
+        return Finding::fail(
+            name,
+            format!("{path} does not exist"),
+            format!("create it with `dev-backup init ws`, or correct paths.{name}"),
+        );
+    }
+    match btrfs::is_btrfs_mount(path) {
+        Ok(true) => Finding::ok(name, format!("{path} is on btrfs")),
+        Ok(false) => Finding::fail(name, format!("{path} is not on a btrfs filesystem"), fix),
+        Err(err) => Finding::warn(name, format!("{err:#}"), fix),
+    }
+}
+
+fn check_keys(ctx: &AppContext) -> Vec<Finding> {
+    let Some(crypto) = ctx.config.crypto.as_ref() else {
+        return vec![Finding::warn(
+            "keys",
+            "[crypto] is not configured; artifacts cannot be built or restored",
+            "add a [crypto] section (see docs/config.example.toml)",
+        )];
+    };
+    let Some(private) = crypto.age_private_key_path.as_deref() else {
+        return vec![Finding::ok("keys", "no private key configured on this host")];
+    };
+    let meta = match std::fs::metadata(private) {
+        Ok(meta) => meta,
+        Err(_) => {
+            return vec![Finding::warn(
+                "keys",
+                format!("{private} does not exist; restores on this host cannot decrypt"),
+                "run `dev-backup init ls`, or copy the identity from the LS host",
+            )]
+        }
+    };
+    let mode = meta.permissions().mode() & 0o7777;
+    if mode & 0o077 != 0 {
+        return vec![Finding::fail(
+            "keys",
+            format!("{private} has mode {mode:04o}; group and others can read it"),
+            format!("chmod 600 {private}"),
+        )];
+    }
+    vec![Finding::ok("keys", format!("{private} has mode {mode:04o}"))]
+}
+
+// The same findings as `init ls` would correct; skipped until the LS root exists.
+fn check_permissions(ctx: &AppContext) -> Vec<Finding> {
+    if !Path::new(&ctx.config.paths.ls_root).exists() {
+        return Vec::new();
+    }
+    let fix = "run `dev-backup init ls` to reapply [permissions] to directories, and chmod \
+               files down to the mode shown";
+    match permissions::audit(ctx) {
+        Ok(problems) if problems.is_empty() => {
+            vec![Finding::ok("permissions", "LS directories match [permissions]")]
+        }
+        Ok(problems) => problems
+            .into_iter()
+            .map(|problem| Finding::fail("permissions", problem, fix))
+            .collect(),
+        Err(err) => vec![Finding::fail("permissions", format!("{err:#}"), fix)],
+    }
+}
+
+async fn check_cloud(ctx: &AppContext) -> Vec<Finding> {
+    let targets = ctx.config.cloud.iter().map(|cloud| ("cloud".to_string(), cloud)).chain(
+        ctx.config
+            .mirrors
+            .iter()
+            .map(|mirror| (format!("mirror {}", mirror.name), &mirror.target)),
+    );
+    let mut findings = Vec::new();
+    for (name, target) in targets {
+        findings.push(match tokio::time::timeout(CLOUD_TIMEOUT, probe(ctx, target)).await {
+            Ok(Ok(count)) => Finding::ok(&name, format!("reachable, {count} manifest object(s)")),
+            Ok(Err(err)) => Finding::fail(&name, format!("{err:#}"), cloud_fix(target)),
+            Err(_) => Finding::fail(
+                &name,
+                format!("no answer within {}s", CLOUD_TIMEOUT.as_secs()),
+                cloud_fix(target),
+            ),
+        });
+    }
+    if findings.is_empty() {
+        findings.push(Finding::ok("cloud", "not configured"));
+    }
+    findings
+}
+
+async fn probe(ctx: &AppContext, target: &Cloud) -> Result<usize> {
+    if target.backend == CloudBackend::Local {
+        // A missing root lists as empty, so check that it is there.
+        let root = target.local_root.as_deref().unwrap_or_default();
+        if !Path::new(root).is_dir() {
+            return Err(anyhow!("{root} does not exist"));
+        }
+    }
+    let storage = ctx.storage_for(target).await?;
+    if target.backend == CloudBackend::Http {
+        // Nothing can be listed over http; look for this dataset's manifest.
+        return Ok(usize::from(storage.head(&ctx.manifest_key()).await?.is_some()));
+    }
+    Ok(storage.list(Some("manifests/")).await?.len())
+}
+
+fn cloud_fix(target: &Cloud) -> &'static str {
+    match target.backend {
+        CloudBackend::R2 => "check endpoint, bucket, credentials and https_proxy",
+        CloudBackend::Local => "mount or create local_root",
+        CloudBackend::Sftp => "check that `ssh` to the host works without a prompt",
+        CloudBackend::Http => "check url, https_proxy and ca_bundle_path",
+    }
+}
@@ -0,0 +1,145 @@
+use crate::commands::snapshot;
+use crate::context::{AppContext, Logger};
+use crate::permissions::{self, DIR_CLASSES};
+use anyhow::{anyhow, Context, Result};
+use dev_backup_btrfs as btrfs;
+use dev_backup_core::config::AgeBackend;
+use dev_backup_storage::crypto;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+pub fn init_ls(ctx: &AppContext) -> Result<()> {
+    ctx.config.permissions.validate()?;
+    for (_, dirs) in DIR_CLASSES {
+        for dir in dirs.iter() {
+            btrfs::ensure_dir(&ctx.ls_path(dir))?;
+        }
+    }
+    permissions::apply_dirs(ctx)?;
+    ctx.manifest.ensure_initialized()?;
+    permissions::apply_file(ctx, "manifests", ctx.manifest.path())?;
+    let private_key = ctx.ls_path("keys/ls_dev_backup.key");
+    let public_key = ctx.ls_path("keys/ls_dev_backup.pub");
+    ensure_age_keypair(&private_key, &public_key, ctx.age_backend())?;
+    ctx.logger
+        .info(format!("LS initialized at {}", ctx.config.paths.ls_root));
+    Ok(())
+}
+
+// Starting points for `init --preset`, with {home}, {user} and {config}
+// filled in from the environment and --config.
+pub const PRESETS: [(&str, &str); 3] = [
+    ("laptop", include_str!("../../presets/laptop.toml")),
+    ("homelab", include_str!("../../presets/homelab.toml")),
+    ("team-ls", include_str!("../../presets/team-ls.toml")),
+];
+
+// Runs before any config exists, so it only writes one and never replaces
+// an existing file.
+pub fn write_preset(logger: &Logger, config_path: &str, preset: &str) -> Result<()> {
+    let template = PRESETS
+        .iter()
+        .find(|(name, _)| *name == preset)
+        .map(|(_, template)| *template)
+        .ok_or_else(|| anyhow!("unknown preset {preset}"))?;
+    let path = Path::new(config_path);
+    if path.exists() {
+        return Err(anyhow!("{config_path} already exists; move it aside to write a preset"));
+    }
+    let user = std::env::var("SUDO_USER")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| "root".to_string());
+    let home = std::env::var("HOME").unwrap_or_else(|_| format!("/home/{user}"));
+    let contents = template
+        .replace("{home}", &home)
+        .replace("{user}", &user)
+        .replace("{config}", config_path);
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        btrfs::ensure_dir(parent)?;
+    }
+    fs::write(path, contents).with_context(|| format!("failed to write {config_path}"))?;
+    logger.info(format!(
+        "Wrote the {preset} preset to {config_path}; review its paths, then run `init ls`"
+    ));
+    Ok(())
+}
+
+pub fn init_ws(ctx: &AppContext) -> Result<()> {
+    let paths = &ctx.config.paths;
+    if !ctx.btrfs().is_btrfs_mount(&paths.dataset)? {
+        return Err(anyhow!("dataset path is not on btrfs: {}", paths.dataset));
+    }
+    btrfs::ensure_dir(Path::new(&paths.snapshots))?;
+    snapshot::check_same_filesystem(ctx, &paths.dataset, &paths.snapshots)?;
+    ctx.logger
+        .info(format!("WS initialized. Snapshot root at {}", paths.snapshots));
+    Ok(())
+}
+
+fn ensure_age_keypair(private_path: &Path, public_path: &Path, backend: AgeBackend) -> Result<()> {
+    if !private_path.exists() {
+        match backend {
+            AgeBackend::Native => write_private_key(private_path, &crypto::generate_identity())?,
+            AgeBackend::External => {
+                let status = Command::new("age-keygen")
+                    .args(["-o", private_path.to_str().unwrap_or_default()])
+                    .status()
+                    .context("failed to run age-keygen")?;
+                if !status.success() {
+                    return Err(anyhow!("age-keygen failed"));
+                }
+            }
+        }
+    }
+
+    if !public_path.exists() {
+        let public = match backend {
+            AgeBackend::Native => {
+                let public = crypto::identity_public_key(private_path.to_str().unwrap_or_default())?;
+                format!("{public}\n").into_bytes()
+            }
+            AgeBackend::External => {
+                let output = Command::new("age-keygen")
+                    .args(["-y", private_path.to_str().unwrap_or_default()])
+                    .output()
+                    .context("failed to derive age public key")?;
+                if !output.status.success() {
+                    return Err(anyhow!("age-keygen -y failed"));
+                }
+                output.stdout
+            }
+        };
+        fs::write(public_path, public)
+            .with_context(|| format!("failed to write public key: {}", public_path.display()))?;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let private_perm = fs::Permissions::from_mode(0o600);
+        fs::set_permissions(private_path, private_perm)
+            .with_context(|| format!("failed to set permissions on {}", private_path.display()))?;
+        let public_perm = fs::Permissions::from_mode(0o644);
+        fs::set_permissions(public_path, public_perm)
+            .with_context(|| format!("failed to set permissions on {}", public_path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn write_private_key(path: &Path, secret: &str) -> Result<()> {
+    use std::io::Write;
+    #[cfg(unix)]
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("failed to create private key: {}", path.display()))?;
+    file.write_all(secret.as_bytes())
+        .with_context(|| format!("failed to write private key: {}", path.display()))
+}
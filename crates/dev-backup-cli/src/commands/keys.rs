@@ -0,0 +1,119 @@
+use crate::context::AppContext;
+use anyhow::{anyhow, Result};
+use dev_backup_storage::crypto::{header_stanzas, identity_unwraps};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// Lists, per artifact, the recipient stanzas of its age header and which of
+// the configured and escrowed identities can unwrap it. Artifacts that none of
+// the identities at hand can open are flagged and fail the audit.
+pub fn keys_audit(ctx: &AppContext) -> Result<()> {
+    let identities = available_identities(ctx)?;
+
+    let streams = std::iter::once((ctx.naming.prefix(), &ctx.manifest)).chain(
+        ctx.part_manifests
+            .iter()
+            .map(|(part, manifest)| (part.as_str(), manifest)),
+    );
+    let mut audited = 0;
+    let mut flagged = Vec::new();
+    for (stream, manifest) in streams {
+        if !manifest.path().exists() {
+            continue;
+        }
+        for record in manifest.read_records()? {
+            let name = format!("{stream}@{}", record.label);
+            if record.local_path.is_empty() || !Path::new(&record.local_path).exists() {
+                if ctx.json {
+                    ctx.emit(&json!({ "artifact": name, "status": "absent" }))?;
+                } else {
+                    ctx.logger
+                        .info(format!("{name}\t-\t-\tabsent (no local copy to audit)"));
+                }
+                continue;
+            }
+            let stanzas = summarize_stanzas(&header_stanzas(&record.local_path)?);
+            let mut openers = Vec::new();
+            for (key_name, key_path) in &identities {
+                if identity_unwraps(key_path, &record.local_path)? {
+                    openers.push(key_name.as_str());
+                }
+            }
+            audited += 1;
+            let status = if openers.is_empty() {
+                flagged.push(name.clone());
+                "MISSING KEY"
+            } else {
+                "ok"
+            };
+            if ctx.json {
+                ctx.emit(&json!({
+                    "artifact": name,
+                    "stanzas": stanzas,
+                    "keys": openers,
+                    "status": status,
+                }))?;
+                continue;
+            }
+            let keys = if openers.is_empty() { "-".to_string() } else { openers.join(",") };
+            ctx.logger.info(format!("{name}\t{stanzas}\t{keys}\t{status}"));
+        }
+    }
+
+    if !flagged.is_empty() {
+        return Err(anyhow!(
+            "{} of {audited} artifact(s) can only be decrypted by a key that is not available: {}",
+            flagged.len(),
+            flagged.join(", ")
+        ));
+    }
+    ctx.logger.info(format!(
+        "Audited {audited} artifact(s); each opens with at least one available key"
+    ));
+    Ok(())
+}
+
+fn available_identities(ctx: &AppContext) -> Result<Vec<(String, String)>> {
+    let crypto = ctx
+        .config
+        .crypto
+        .as_ref()
+        .ok_or_else(|| anyhow!("[crypto] is required for keys audit"))?;
+    let configured = crypto
+        .age_private_key_path
+        .iter()
+        .map(|path| ("primary".to_string(), path.clone()));
+    let escrowed = crypto
+        .escrow_identities
+        .iter()
+        .map(|path| (format!("escrow:{path}"), path.clone()));
+
+    let mut identities = Vec::new();
+    for (name, path) in configured.chain(escrowed) {
+        if Path::new(&path).exists() {
+            identities.push((name, path));
+        } else {
+            ctx.logger.warn(format!("{name} identity is not available at {path}"));
+        }
+    }
+    if identities.is_empty() {
+        return Err(anyhow!(
+            "no age identities available; set crypto.age_private_key_path or \
+             crypto.escrow_identities"
+        ));
+    }
+    Ok(identities)
+}
+
+fn summarize_stanzas(stanzas: &[String]) -> String {
+    let mut counts = BTreeMap::new();
+    for tag in stanzas {
+        *counts.entry(tag.as_str()).or_insert(0) += 1;
+    }
+    counts
+        .iter()
+        .map(|(tag, count)| format!("{count}x{tag}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,22 @@
+use crate::context::AppContext;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_storage::crypto::decrypt_reader;
+use std::fs::File;
+use std::io::{self, Write};
+
+// Writes encrypted log files (`[logs] encrypt`) to stdout in the order given.
+// A log cut off by a crash decrypts up to where it stopped, then fails.
+pub fn logs_decrypt(ctx: &AppContext, files: &[String], identity: Option<&str>) -> Result<()> {
+    let identity = identity
+        .or_else(|| ctx.config.crypto.as_ref()?.age_private_key_path.as_deref())
+        .ok_or_else(|| anyhow!("pass --identity, or set crypto.age_private_key_path"))?;
+    let mut stdout = io::stdout().lock();
+    for path in files {
+        let file = File::open(path).with_context(|| format!("failed to open log: {path}"))?;
+        let mut reader = decrypt_reader(identity, file)
+            .with_context(|| format!("failed to decrypt log: {path}"))?;
+        io::copy(&mut reader, &mut stdout)
+            .with_context(|| format!("failed to decrypt log: {path}"))?;
+    }
+    stdout.flush().context("failed to write decrypted log")
+}
@@ -0,0 +1,144 @@
+use crate::commands::restore::resolve_label_from_manifest;
+use crate::context::{AppContext, ALIASES_OBJECT_KEY};
+use crate::format::format_bytes;
+use crate::label::{auto_parent_label, LabelRange};
+use crate::output::Table;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_core::manifest::ManifestRecord;
+use dev_backup_core::naming::NameTemplate;
+use dev_backup_storage::artifact::parse_artifact_filename;
+use serde_json::json;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// With `auto_parent` (or a parent of "latest") the parent is the newest
+// manifest label before `label` that is restored on LS.
+pub fn ls_send(
+    ctx: &AppContext,
+    label: &str,
+    parent: Option<&str>,
+    auto_parent: bool,
+) -> Result<()> {
+    let resolved_label = resolve_label_from_manifest(ctx, label, None)?;
+    let parent = if auto_parent || parent == Some("latest") {
+        let records = ctx.manifest.read_records()?;
+        auto_parent_label(&records, &resolved_label, |candidate| {
+            Path::new(&ctx.restore_snapshot_path(candidate)).exists()
+        })
+    } else {
+        parent
+            .map(|parent_label| resolve_label_from_manifest(ctx, parent_label, None))
+            .transpose()?
+    };
+
+    let snapshot_path = ctx.restore_snapshot_path(&resolved_label);
+    if !Path::new(&snapshot_path).exists() {
+        return Err(anyhow!("snapshot not found on LS: {snapshot_path}"));
+    }
+
+    let parent_path = parent.map(|p| ctx.restore_snapshot_path(&p));
+    if let Some(ref path) = parent_path {
+        if !Path::new(path).exists() {
+            return Err(anyhow!("parent snapshot not found on LS: {path}"));
+        }
+    }
+
+    let mut cmd = Command::new("btrfs");
+    if let Some(parent_path) = parent_path.as_deref() {
+        cmd.args(["send", "-p", parent_path, &snapshot_path]);
+    } else {
+        cmd.args(["send", &snapshot_path]);
+    }
+
+    let status = cmd
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .context("failed to run btrfs send")?;
+    if !status.success() {
+        return Err(anyhow!("btrfs send failed"));
+    }
+    Ok(())
+}
+
+// With a range only artifacts whose label falls inside it are listed; the
+// manifests themselves are left out.
+pub async fn ls_remote(ctx: &AppContext, detail: bool, range: &LabelRange) -> Result<()> {
+    let client = ctx.storage().await?;
+    let mut table = Table::new();
+    if !detail {
+        for object in client.list(None).await? {
+            if range.is_set() && !key_in_range(&ctx.naming, &object.key, range) {
+                continue;
+            }
+            if ctx.json {
+                ctx.emit(&json!({ "key": object.key, "size": object.size }))?;
+            } else {
+                table.row([object.key, format_bytes(object.size)]);
+            }
+        }
+        table.print();
+        return Ok(());
+    }
+
+    let index = ctx.manifest.read_index()?;
+    let mut keys: Vec<(String, Option<&ManifestRecord>)> = Vec::new();
+    if !range.is_set() {
+        keys.push((ctx.manifest_key(), None));
+        keys.push((ALIASES_OBJECT_KEY.to_string(), None));
+    }
+    for record in index.records() {
+        if !range.contains(&record.label) {
+            continue;
+        }
+        if !record.object_key.is_empty() && index.get(&record.label) == Some(record) {
+            keys.push((record.object_key.clone(), Some(record)));
+        }
+    }
+
+    if !ctx.json {
+        table.row(["key", "size", "etag", "storage_class", "last_modified", "status"]);
+    }
+    for (key, record) in keys {
+        let Some(object) = client.head(&key).await? else {
+            if record.is_some() && ctx.json {
+                ctx.emit(&json!({ "key": key, "status": "missing" }))?;
+            } else if record.is_some() {
+                table.row([key.as_str(), "-", "-", "-", "-", "missing"]);
+            }
+            continue;
+        };
+        let status = match record {
+            Some(record) if record.bytes != object.size => {
+                format!("size mismatch: manifest {}", record.bytes)
+            }
+            _ => "ok".to_string(),
+        };
+        if ctx.json {
+            ctx.emit(&json!({
+                "key": key,
+                "size": object.size,
+                "etag": object.etag,
+                "storage_class": object.storage_class.as_deref().unwrap_or("STANDARD"),
+                "last_modified": object.last_modified,
+                "status": status,
+            }))?;
+            continue;
+        }
+        table.row([
+            key,
+            object.size.to_string(),
+            object.etag.unwrap_or_else(|| "-".to_string()),
+            object.storage_class.unwrap_or_else(|| "STANDARD".to_string()),
+            object.last_modified.unwrap_or_else(|| "-".to_string()),
+            status,
+        ]);
+    }
+    table.print();
+    Ok(())
+}
+
+fn key_in_range(naming: &NameTemplate, key: &str, range: &LabelRange) -> bool {
+    let filename = key.rsplit('/').next().unwrap_or(key);
+    parse_artifact_filename(naming, filename).is_some_and(|info| range.contains(&info.label))
+}
@@ -0,0 +1,269 @@
+use crate::context::{manifest_sqlite_key, AppContext, LEGACY_MANIFEST_KEY};
+use anyhow::{anyhow, Context, Result};
+use dev_backup_core::config::ManifestBackend;
+use dev_backup_core::manifest::{ManifestRecord, TSV_SCHEMA_VERSION};
+use dev_backup_core::sqlite::SqliteManifest;
+use dev_backup_core::skew::fix_timestamps;
+use std::fs;
+use time::format_description::well_known::Rfc3339;
+
+pub fn manifest_fix_timestamps(ctx: &AppContext, dry_run: bool) -> Result<()> {
+    let mut records = ctx.manifest.read_records()?;
+    if records.is_empty() {
+        return Err(anyhow!("manifest is empty"));
+    }
+
+    let changes = fix_timestamps(&mut records, ctx.clock.now());
+    if changes.is_empty() {
+        ctx.logger.info("Manifest timestamps are already monotonic.");
+        return Ok(());
+    }
+    for (index, old, new) in &changes {
+        ctx.logger.info(format!(
+            "row {} ({}): {} -> {}",
+            index + 1,
+            records[*index].label,
+            old.format(&Rfc3339)?,
+            new.format(&Rfc3339)?
+        ));
+    }
+    if dry_run {
+        ctx.logger.info(format!("Dry run: {} timestamp(s) would change", changes.len()));
+        return Ok(());
+    }
+
+    let backup = ctx.manifest.path().with_extension("tsv.bak");
+    fs::copy(ctx.manifest.path(), &backup)
+        .with_context(|| format!("failed to back up manifest to {}", backup.display()))?;
+    ctx.manifest.write_records(&records)?;
+    ctx.logger.info(format!(
+        "Fixed {} timestamp(s); previous manifest saved to {}",
+        changes.len(),
+        backup.display()
+    ));
+    Ok(())
+}
+
+// Compares the primary and replica stores row by row; with `repair` the
+// replica is rewritten from the primary when they differ.
+pub fn manifest_check(ctx: &AppContext, repair: bool) -> Result<()> {
+    let replica = ctx
+        .manifest
+        .replica()
+        .ok_or_else(|| anyhow!("no manifest replica is configured; set [manifest] replica"))?;
+    let primary = ctx.manifest.primary();
+    let primary_records = ctx.manifest.read_from(primary)?;
+    let replica_records = ctx
+        .manifest
+        .read_from(replica)
+        .with_context(|| format!("failed to read the {} replica", replica.as_str()))?;
+
+    let differences = diff_records(ctx, &primary_records, &replica_records);
+    if differences.is_empty() {
+        ctx.logger.info(format!(
+            "Manifest stores agree: {} record(s) in {} and {}",
+            primary_records.len(),
+            primary.as_str(),
+            replica.as_str()
+        ));
+        return Ok(());
+    }
+    for difference in &differences {
+        ctx.logger.info(difference);
+    }
+    if repair {
+        let count = ctx.manifest.repair_replica()?;
+        ctx.logger.info(format!(
+            "Rewrote the {} replica from {} ({count} record(s))",
+            replica.as_str(),
+            primary.as_str()
+        ));
+        return Ok(());
+    }
+    Err(anyhow!(
+        "manifest stores differ in {} row(s) ({} primary, {} replica)",
+        differences.len(),
+        primary.as_str(),
+        replica.as_str()
+    ))
+}
+
+// Lists TSV rows that do not parse or validate; with `fix` they move to a
+// quarantine file beside each manifest and the manifest is rewritten without
+// them.
+pub fn manifest_fsck(ctx: &AppContext, fix: bool) -> Result<()> {
+    let streams = std::iter::once(&ctx.manifest).chain(ctx.part_manifests.values());
+    let mut total = 0;
+    for manifest in streams {
+        let rows = if fix {
+            manifest.quarantine_malformed()?
+        } else {
+            manifest.malformed_rows()?
+        };
+        for row in &rows {
+            ctx.logger.info(format!("{}: {row}", manifest.path().display()));
+        }
+        if fix && !rows.is_empty() {
+            ctx.logger.info(format!(
+                "Moved {} row(s) to {}",
+                rows.len(),
+                manifest.quarantine_path().display()
+            ));
+        }
+        total += rows.len();
+    }
+    if total == 0 {
+        ctx.logger.info("No malformed manifest rows");
+    } else if !fix {
+        return Err(anyhow!(
+            "{total} malformed manifest row(s); rerun with --fix to quarantine them"
+        ));
+    }
+    Ok(())
+}
+
+fn diff_records(
+    ctx: &AppContext,
+    primary: &[ManifestRecord],
+    replica: &[ManifestRecord],
+) -> Vec<String> {
+    let name = |record: &ManifestRecord| ctx.snapshot_name(&record.label);
+    let mut differences = Vec::new();
+    for index in 0..primary.len().max(replica.len()) {
+        match (primary.get(index), replica.get(index)) {
+            (Some(a), Some(b)) if a == b => {}
+            (Some(a), Some(b)) => differences.push(format!(
+                "row {}: primary {} ({}) != replica {} ({})",
+                index + 1,
+                name(a),
+                a.sha256,
+                name(b),
+                b.sha256
+            )),
+            (Some(a), None) => {
+                differences.push(format!("row {}: {} missing from replica", index + 1, name(a)))
+            }
+            (None, Some(b)) => {
+                differences.push(format!("row {}: {} only in replica", index + 1, name(b)))
+            }
+            (None, None) => {}
+        }
+    }
+    differences
+}
+
+// Upgrades the TSV manifest to the current schema and, with `--sqlite` or a
+// configured SQLite store, imports it into the database.
+pub fn manifest_migrate(ctx: &AppContext, sqlite: bool) -> Result<()> {
+    migrate_tsv(ctx)?;
+    let configured = ctx.manifest.primary() == ManifestBackend::Sqlite
+        || ctx.manifest.replica() == Some(ManifestBackend::Sqlite);
+    if sqlite || configured {
+        import_sqlite(ctx)?;
+    }
+    Ok(())
+}
+
+// Brings the TSV to the current layout, reading the pre-v2 file when the
+// manifest has not been written yet.
+fn migrate_tsv(ctx: &AppContext) -> Result<()> {
+    let path = ctx.manifest.path();
+    let legacy = ctx.ls_path(LEGACY_MANIFEST_KEY);
+    let source = if ctx.dataset.is_none() && !path.exists() && legacy.exists() {
+        legacy
+    } else {
+        path.to_path_buf()
+    };
+    if ctx.dry_run {
+        match ctx.manifest.tsv_schema()? {
+            _ if source != path => ctx.logger.info(format!(
+                "Dry run: would upgrade {} to {} (schema {TSV_SCHEMA_VERSION})",
+                source.display(),
+                path.display()
+            )),
+            Some(schema) if !schema.is_current() => ctx.logger.info(format!(
+                "Dry run: would upgrade {} from {schema} to schema {TSV_SCHEMA_VERSION}",
+                path.display()
+            )),
+            Some(_) => ctx.logger.info(format!(
+                "{} is already at schema {TSV_SCHEMA_VERSION}",
+                path.display()
+            )),
+            None => {}
+        }
+        return Ok(());
+    }
+    if source == path {
+        let malformed = ctx.manifest.malformed_rows()?;
+        if !malformed.is_empty() {
+            return Err(anyhow!(
+                "{} malformed manifest row(s) would be left behind; run `manifest fsck --fix` first",
+                malformed.len()
+            ));
+        }
+    }
+    match ctx.manifest.upgrade_tsv(&source)? {
+        Some(schema) if source != path => ctx.logger.info(format!(
+            "Upgraded {} ({schema}) to {} (schema {TSV_SCHEMA_VERSION}); the old file is left \
+             in place",
+            source.display(),
+            path.display()
+        )),
+        Some(schema) => ctx.logger.info(format!(
+            "Upgraded {} from {schema} to schema {TSV_SCHEMA_VERSION}",
+            path.display()
+        )),
+        None => ctx.logger.info(format!(
+            "{} is already at schema {TSV_SCHEMA_VERSION}",
+            path.display()
+        )),
+    }
+    Ok(())
+}
+
+// Creates the database at the current schema version. Rerunning it once the
+// two agree is a no-op; a database that already holds other rows is left
+// alone.
+fn import_sqlite(ctx: &AppContext) -> Result<()> {
+    let malformed = ctx.manifest.malformed_rows()?;
+    if !malformed.is_empty() {
+        return Err(anyhow!(
+            "{} malformed manifest row(s) would be left behind; run `manifest fsck --fix` first",
+            malformed.len()
+        ));
+    }
+    let sqlite = SqliteManifest::new(ctx.ls_path(&manifest_sqlite_key(ctx.dataset.as_deref())));
+    if ctx.dry_run {
+        ctx.logger.info(format!(
+            "Dry run: would import the manifest into {}",
+            sqlite.path().display()
+        ));
+        return Ok(());
+    }
+    let records = ctx.manifest.read_from(ManifestBackend::Tsv)?;
+    let existing = sqlite.read_records()?;
+    let path = sqlite.path().display();
+    if !existing.is_empty() {
+        if diff_records(ctx, &records, &existing).is_empty() {
+            ctx.logger.info(format!("{path} already holds the {} record(s)", records.len()));
+            return Ok(());
+        }
+        return Err(anyhow!(
+            "{path} already holds {} different record(s); `manifest check --repair` rewrites \
+             a configured replica",
+            existing.len()
+        ));
+    }
+    sqlite.write_records(&records)?;
+    ctx.logger.info(format!(
+        "Imported {} record(s) into {path} (schema version {})",
+        records.len(),
+        sqlite.schema_version()?
+    ));
+    if ctx.manifest.replica().is_none() {
+        ctx.logger.info(
+            "Set [manifest] primary = \"sqlite\" and replica = \"tsv\" to read from it",
+        );
+    }
+    Ok(())
+}
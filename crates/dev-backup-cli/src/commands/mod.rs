@@ -0,0 +1,18 @@
+pub mod alias;
+pub mod artifact;
+pub mod backup;
+pub mod bench;
+pub mod config;
+pub mod doctor;
+pub mod init;
+pub mod keys;
+pub mod logs;
+pub mod ls;
+pub mod manifest;
+pub mod report;
+pub mod restore;
+pub mod snapshot;
+pub mod status;
+pub mod sync;
+pub mod verify;
+pub mod ws;
@@ -0,0 +1,185 @@
+use crate::context::AppContext;
+use crate::format::format_bytes;
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use dev_backup_core::index::ManifestIndex;
+use dev_backup_core::manifest::ManifestRecord;
+use dev_backup_core::policy::{chain_status, decide_snapshot_type, PolicyInput, SnapshotDecision};
+use std::fmt::Write;
+use std::path::Path;
+use time::format_description::well_known::Rfc3339;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiagramFormat {
+    Ascii,
+    Mermaid,
+}
+
+pub fn report_monthly(ctx: &AppContext, label: &str, diagram: DiagramFormat) -> Result<()> {
+    let index = ManifestIndex::new(ctx.manifest.read_sorted_records()?);
+    if index.is_empty() {
+        return Err(anyhow!("manifest is empty"));
+    }
+    let label = ctx.resolve_label(index.records(), label)?;
+    let report = render_monthly_report(ctx, &index, &label, diagram)?;
+    if ctx.json {
+        return ctx.emit(&serde_json::json!({ "label": label, "report": report }));
+    }
+    print!("{report}");
+    Ok(())
+}
+
+pub fn render_monthly_report(
+    ctx: &AppContext,
+    index: &ManifestIndex,
+    label: &str,
+    diagram: DiagramFormat,
+) -> Result<String> {
+    let records = index.records();
+    let input = PolicyInput::at(ctx.clock.as_ref());
+    let now = input.now;
+    let mut out = String::new();
+    writeln!(out, "dev-backup monthly report: {label}")?;
+    writeln!(out, "Generated: {}", now.format(&Rfc3339)?)?;
+    writeln!(out)?;
+
+    writeln!(out, "New artifacts:")?;
+    let new_records: Vec<&ManifestRecord> = records.iter().filter(|r| r.label == label).collect();
+    if new_records.is_empty() {
+        writeln!(out, "  (none registered for {label})")?;
+    }
+    for record in &new_records {
+        writeln!(
+            out,
+            "  {}  {}  {}  sha256 {}",
+            ctx.snapshot_name(&record.label),
+            describe_type(record),
+            format_bytes(record.bytes),
+            short_hash(&record.sha256)
+        )?;
+    }
+    let total: u64 = records.iter().map(|r| r.bytes).sum();
+    writeln!(out, "  manifest total: {} artifacts, {}", records.len(), format_bytes(total))?;
+    writeln!(out)?;
+
+    let status = chain_status(records, now)?;
+    let decision = decide_snapshot_type(records, input.clone())?;
+    writeln!(out, "Policy:")?;
+    writeln!(
+        out,
+        "  current anchor: {} ({}, {} month(s) old)",
+        ctx.snapshot_name(&status.anchor_label),
+        format_bytes(status.anchor_bytes),
+        status.months_since_anchor
+    )?;
+    writeln!(
+        out,
+        "  incrementals since anchor: {} ({}, {}% of anchor)",
+        status.incremental_count,
+        format_bytes(status.incremental_bytes),
+        status.incremental_bytes.saturating_mul(100) / status.anchor_bytes
+    )?;
+    let next = match decision {
+        SnapshotDecision::Anchor => "anchor",
+        SnapshotDecision::Incremental => "incremental",
+    };
+    writeln!(out, "  next run: {next}")?;
+    let anchor_month =
+        status.anchor_ts.year() as i64 * 12 + status.anchor_ts.month() as i64 - 1
+            + input.max_months_between_anchor;
+    writeln!(
+        out,
+        "  upcoming anchor: by {:04}-{:02} (age limit), or sooner after {} more incremental bytes",
+        anchor_month / 12,
+        anchor_month % 12 + 1,
+        format_bytes(status.anchor_bytes.saturating_sub(status.incremental_bytes))
+    )?;
+    writeln!(out)?;
+
+    let chain = index.chain(label)?;
+    writeln!(out, "Artifact checks:")?;
+    for record in &chain {
+        let name = ctx.snapshot_name(&record.label);
+        writeln!(out, "  {name}  {}", check_artifact(record))?;
+    }
+    writeln!(out)?;
+
+    writeln!(out, "Chain:")?;
+    match diagram {
+        DiagramFormat::Ascii => render_ascii_chain(ctx, &mut out, &chain)?,
+        DiagramFormat::Mermaid => render_mermaid_chain(ctx, &mut out, &chain)?,
+    }
+    Ok(out)
+}
+
+fn describe_type(record: &ManifestRecord) -> String {
+    if record.record_type == "anchor" {
+        "anchor".to_string()
+    } else {
+        format!("incremental from {}", record.parent)
+    }
+}
+
+fn short_hash(hash: &str) -> &str {
+    hash.get(..12).unwrap_or(hash)
+}
+
+fn check_artifact(record: &ManifestRecord) -> String {
+    if record.local_path.is_empty() {
+        if record.object_key.is_empty() {
+            return "no local_path or object_key".to_string();
+        }
+        return format!("cloud only ({})", record.object_key);
+    }
+    match Path::new(&record.local_path).metadata() {
+        Ok(meta) if meta.len() == record.bytes => "ok".to_string(),
+        Ok(meta) => format!("size mismatch: {} on disk, {} in manifest", meta.len(), record.bytes),
+        Err(_) => format!("missing: {}", record.local_path),
+    }
+}
+
+fn render_ascii_chain(
+    ctx: &AppContext,
+    out: &mut String,
+    chain: &[&ManifestRecord],
+) -> Result<()> {
+    for (index, record) in chain.iter().enumerate() {
+        let indent = if index == 0 {
+            "  ".to_string()
+        } else {
+            format!("  {}└─ ", "   ".repeat(index - 1))
+        };
+        let kind = if record.record_type == "anchor" { "anchor" } else { "incr" };
+        let name = ctx.snapshot_name(&record.label);
+        writeln!(out, "{indent}{name} [{kind} {}]", format_bytes(record.bytes))?;
+    }
+    Ok(())
+}
+
+fn render_mermaid_chain(
+    ctx: &AppContext,
+    out: &mut String,
+    chain: &[&ManifestRecord],
+) -> Result<()> {
+    writeln!(out, "```mermaid")?;
+    writeln!(out, "graph LR")?;
+    for record in chain {
+        let kind = if record.record_type == "anchor" { "anchor" } else { "incr" };
+        writeln!(
+            out,
+            "  {}[\"{} ({kind}, {})\"]",
+            node_id(&record.label),
+            ctx.snapshot_name(&record.label),
+            format_bytes(record.bytes)
+        )?;
+    }
+    for pair in chain.windows(2) {
+        writeln!(out, "  {} --> {}", node_id(&pair[0].label), node_id(&pair[1].label))?;
+    }
+    writeln!(out, "```")?;
+    Ok(())
+}
+
+fn node_id(label: &str) -> String {
+    format!("L{}", label.replace(|c: char| !c.is_ascii_alphanumeric(), "_"))
+}
@@ -0,0 +1,728 @@
+use crate::context::AppContext;
+use crate::interrupt;
+use crate::lock;
+use crate::logging::SharedLog;
+use crate::pipeline::{run_receive_pipeline, run_receive_stream, Received};
+use crate::progress;
+use crate::state;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_btrfs as btrfs;
+use dev_backup_core::config::{ReceiveErrors, VerifyReceive};
+use dev_backup_core::index::ManifestIndex;
+use dev_backup_core::manifest::ManifestRecord;
+use dev_backup_storage::artifact::sha256_file;
+use dev_backup_storage::backend::{pump_chunks, ChunkReader, StorageBackend};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+pub fn plan_restore(
+    ctx: &AppContext,
+    label: &str,
+    part: Option<&str>,
+) -> Result<Vec<ManifestRecord>> {
+    plan_into(ctx, label, part, &ctx.restore_snapshot_dir())
+}
+
+// The chain `label` needs on top of what is already received into `restore_dir`.
+fn plan_into(
+    ctx: &AppContext,
+    label: &str,
+    part: Option<&str>,
+    restore_dir: &str,
+) -> Result<Vec<ManifestRecord>> {
+    let stream = part.unwrap_or(ctx.naming.prefix());
+    let index = ctx.manifest_for(part)?.read_index()?;
+    if index.is_empty() {
+        return Err(anyhow!("manifest is empty"));
+    }
+
+    let resolved_label = ctx.resolve_label(index.records(), label)?;
+    let chain = index.chain_until(&resolved_label, |parent| {
+        Path::new(restore_dir).join(ctx.naming.name(stream, parent)).exists()
+    })?;
+    for record in &chain {
+        ensure_one_machine(&index, &record.label)?;
+    }
+    Ok(chain.into_iter().cloned().collect())
+}
+
+// A manifest shared by several workstations has a row per machine for the
+// same label; without --machine there is no telling which chain is meant.
+pub fn ensure_one_machine(index: &ManifestIndex, label: &str) -> Result<()> {
+    let machines = index.machines(label);
+    if machines.len() < 2 {
+        return Ok(());
+    }
+    let names: Vec<&str> = machines
+        .into_iter()
+        .map(|machine| if machine.is_empty() { "(no machine_id)" } else { machine })
+        .collect();
+    Err(anyhow!(
+        "{label} was backed up by more than one machine ({}); pick one with --machine",
+        names.join(", ")
+    ))
+}
+
+pub async fn hydrate_restore(
+    ctx: &AppContext,
+    label: &str,
+    part: Option<&str>,
+    errors: ReceiveErrors,
+    from_cloud: bool,
+) -> Result<()> {
+    let restore_dir = ctx.restore_snapshot_dir();
+    hydrate_into(ctx, label, part, errors, from_cloud, &restore_dir).await
+}
+
+async fn hydrate_into(
+    ctx: &AppContext,
+    label: &str,
+    part: Option<&str>,
+    errors: ReceiveErrors,
+    from_cloud: bool,
+    restore_dir: &str,
+) -> Result<()> {
+    let stream = part.unwrap_or(ctx.naming.prefix());
+    let private_key = ctx
+        .config
+        .crypto
+        .as_ref()
+        .and_then(|crypto| crypto.age_private_key_path.clone())
+        .ok_or_else(|| anyhow!("age_private_key_path is required in config"))?;
+
+    btrfs::ensure_dir(Path::new(restore_dir))?;
+
+    let mut pending = Vec::new();
+    for record in plan_into(ctx, label, part, restore_dir)? {
+        let snapshot_path = format!("{restore_dir}/{}", ctx.naming.name(stream, &record.label));
+        if Path::new(&snapshot_path).exists() {
+            ctx.logger
+                .info(format!("Snapshot already hydrated: {snapshot_path}"));
+            continue;
+        }
+        if record.local_path.is_empty() && record.object_key.is_empty() {
+            return Err(anyhow!("missing local_path for {}", record.label));
+        }
+        pending.push(record);
+    }
+    let needs_cloud = |record: &ManifestRecord| !Path::new(&record.local_path).exists();
+    let client: Option<Arc<dyn StorageBackend>> = match pending.iter().find(|r| needs_cloud(r)) {
+        Some(record) if ctx.config.cloud.is_none() || record.object_key.is_empty() => {
+            return Err(anyhow!("artifact missing: {}", record.local_path));
+        }
+        Some(_) => Some(Arc::from(ctx.storage().await?)),
+        None => None,
+    };
+    let scratch_dir = ctx.ls_path("tmp/hydrate");
+    let budget = ctx.config.recovery.prefetch_budget_mib * 1024 * 1024;
+    let logs_dir = ctx.ls_path("logs");
+    btrfs::ensure_dir(&logs_dir)?;
+    let recipient = ctx.config.log_recipient();
+    let extension = if recipient.is_some() { "log.age" } else { "log" };
+    let log_name = format!("restore-{}.{extension}", ctx.clock.now().unix_timestamp());
+    let log = SharedLog::open(&logs_dir.join(log_name), recipient)?;
+    let log_path = log.path();
+
+    // Artifacts that are only in the cloud are fetched into scratch space, and
+    // the next one is downloaded while the current one is received as long as
+    // both fit in the prefetch budget. With `from_cloud` they are piped
+    // straight into btrfs receive instead and never touch the disk.
+    let mut prefetch: Option<JoinHandle<Result<PathBuf>>> = None;
+    let stage = progress::Stage::begin("hydrate", Some(pending.iter().map(|r| r.bytes).sum()));
+    for (index, record) in pending.iter().enumerate() {
+        let name = ctx.naming.name(stream, &record.label);
+        progress::item(&name);
+        let snapshot_path = format!("{restore_dir}/{name}");
+        // Another hydration may be receiving the parent or this snapshot
+        // itself; wait for the parent to be complete, then for this one.
+        if !record.parent.is_empty() {
+            lock::hydrate_intent(ctx, &ctx.naming.name(stream, &record.parent))?;
+        }
+        let _intent = lock::hydrate_intent(ctx, &name)?;
+        if Path::new(&snapshot_path).exists() {
+            ctx.logger
+                .info(format!("Snapshot hydrated by another run: {snapshot_path}"));
+            if let Some(handle) = prefetch.take() {
+                if let Ok(Ok(path)) = handle.await {
+                    let _ = fs::remove_file(path);
+                }
+            }
+            continue;
+        }
+        // Complete once this iteration is over; an interrupted one is deleted.
+        let _receiving = interrupt::received_subvolume(Path::new(&snapshot_path));
+        if let (true, Some(client)) = (from_cloud && needs_cloud(record), client.as_ref()) {
+            ctx.logger
+                .info(format!("Streaming {stream}@{} from cloud...", record.label));
+            let mut retried = false;
+            let received = loop {
+                let received = receive_from_cloud(
+                    ctx,
+                    client.clone(),
+                    record,
+                    &snapshot_path,
+                    &private_key,
+                    errors,
+                    &log,
+                )
+                .await;
+                let complete = received.and_then(|received| {
+                    let complete = received_complete(ctx, &snapshot_path, &received, retried)?;
+                    Ok(complete.then_some(received))
+                });
+                match complete {
+                    Ok(Some(received)) => break received,
+                    Ok(None) => retried = true,
+                    Err(err) => return Err(discard_failed(ctx, &snapshot_path, err)),
+                }
+            };
+            if received.errors > 0 {
+                ctx.logger.warn(format!(
+                    "btrfs receive reported {} error(s) for {stream}@{}; see {}",
+                    received.errors,
+                    record.label,
+                    log_path.display()
+                ));
+            }
+            continue;
+        }
+        let fetched = match (needs_cloud(record), prefetch.take()) {
+            (false, _) => None,
+            (true, Some(handle)) => Some(handle.await.context("prefetch task failed")??),
+            (true, None) => {
+                let client = client
+                    .clone()
+                    .ok_or_else(|| anyhow!("artifact missing: {}", record.local_path))?;
+                ctx.logger
+                    .info(format!("Downloading {stream}@{}...", record.label));
+                Some(fetch_artifact(client, record, &scratch_dir).await?)
+            }
+        };
+        let input = match &fetched {
+            Some(path) => path.to_string_lossy().to_string(),
+            None => record.local_path.clone(),
+        };
+
+        if let (Some(next), Some(client)) = (pending.get(index + 1), client.as_ref()) {
+            let in_scratch = if fetched.is_some() { record.bytes } else { 0 };
+            if !from_cloud && needs_cloud(next) && in_scratch + next.bytes <= budget {
+                ctx.logger
+                    .info(format!("Prefetching {stream}@{}...", next.label));
+                let (client, next, dir) = (client.clone(), next.clone(), scratch_dir.clone());
+                prefetch = Some(tokio::spawn(async move {
+                    fetch_artifact(client, &next, &dir).await
+                }));
+            }
+        }
+
+        ctx.logger.info(format!("Hydrating {stream}@{}...", record.label));
+        let mut retried = false;
+        let received = loop {
+            let (dir, key, backend) =
+                (restore_dir.to_string(), private_key.clone(), ctx.age_backend());
+            let (input, log) = (input.clone(), log.clone());
+            let received = tokio::task::spawn_blocking(move || {
+                run_receive_pipeline(&input, &dir, &key, backend, errors, &log)
+            })
+            .await
+            .context("receive task failed")?;
+            let complete = received.and_then(|received| {
+                let complete = received_complete(ctx, &snapshot_path, &received, retried)?;
+                Ok(complete.then_some(received))
+            });
+            match complete {
+                Ok(Some(received)) => break Ok(received),
+                Ok(None) => retried = true,
+                Err(err) => break Err(err),
+            }
+        };
+        if let Some(path) = &fetched {
+            let _ = fs::remove_file(path);
+        }
+        match received {
+            Ok(received) if received.errors == 0 => {}
+            Ok(received) => ctx.logger.warn(format!(
+                "btrfs receive reported {} error(s) for {stream}@{}; see {}",
+                received.errors,
+                record.label,
+                log_path.display()
+            )),
+            Err(err) => {
+                if let Some(handle) = prefetch.take() {
+                    handle.abort();
+                }
+                return Err(discard_failed(ctx, &snapshot_path, err));
+            }
+        }
+    }
+    stage.finish();
+    Ok(())
+}
+
+// A receive that failed part way leaves a broken subvolume at its target,
+// which a rerun would take for one hydrated by another run. Nothing else can
+// have put it there while the hydrate intent is held.
+fn discard_failed(ctx: &AppContext, snapshot_path: &str, err: anyhow::Error) -> anyhow::Error {
+    if let Err(cleanup) = discard_received(ctx, Path::new(snapshot_path)) {
+        ctx.logger.warn(format!("failed to clean up the partial receive: {cleanup:#}"));
+    }
+    err
+}
+
+// Deletes what a receive left at `path`: a subvolume, or the plain directory
+// of one that had not got that far.
+pub fn discard_received(ctx: &AppContext, path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let name = path.to_str().unwrap_or_default();
+    let btrfs = ctx.btrfs();
+    if btrfs.subvolume_exists(name)? {
+        btrfs.subvolume_delete(name)
+    } else {
+        fs::remove_dir_all(path)
+            .with_context(|| format!("failed to remove partial receive: {}", path.display()))
+    }
+}
+
+// Whether a receive that exited 0 left a complete subvolume at
+// `snapshot_path`, as [btrfs] verify_receive asks. An incomplete one is
+// deleted, and false asks for one more receive unless that was the retry.
+fn received_complete(
+    ctx: &AppContext,
+    snapshot_path: &str,
+    received: &Received,
+    retried: bool,
+) -> Result<bool> {
+    let mode = ctx.config.btrfs.verify_receive;
+    if mode == VerifyReceive::Off {
+        return Ok(true);
+    }
+    let problem = match btrfs::subvolume_details(snapshot_path) {
+        Err(_) => "no subvolume was created".to_string(),
+        Ok(details) => match (details.received_uuid, &received.stream_uuid) {
+            (None, _) => "its received_uuid is not set".to_string(),
+            (Some(uuid), Some(expected)) if uuid != *expected => {
+                format!("its received_uuid {uuid} does not match the stream's {expected}")
+            }
+            _ => return Ok(true),
+        },
+    };
+    if Path::new(snapshot_path).exists() {
+        ctx.btrfs().subvolume_delete(snapshot_path)?;
+    }
+    if mode == VerifyReceive::Retry && !retried {
+        ctx.logger.warn(format!(
+            "{snapshot_path} is incomplete after btrfs receive ({problem}); receiving it again"
+        ));
+        return Ok(false);
+    }
+    Err(anyhow!("{snapshot_path} is incomplete after btrfs receive: {problem}"))
+}
+
+async fn receive_from_cloud(
+    ctx: &AppContext,
+    client: Arc<dyn StorageBackend>,
+    record: &ManifestRecord,
+    snapshot_path: &str,
+    private_key: &str,
+    errors: ReceiveErrors,
+    log: &SharedLog,
+) -> Result<Received> {
+    let object = client.get_stream(&record.object_key).await?;
+    let (sender, chunks) = mpsc::channel(2);
+    let dir = Path::new(snapshot_path)
+        .parent()
+        .map(|dir| dir.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (key, backend) = (private_key.to_string(), ctx.age_backend());
+    let (source, log) = (record.object_key.clone(), log.clone());
+    let receiver = tokio::task::spawn_blocking(move || {
+        run_receive_stream(ChunkReader::new(chunks), &source, &dir, &key, backend, errors, &log)
+    });
+    let (received, pumped) = tokio::join!(receiver, pump_chunks(object, sender));
+    let (reported, (sha256, _)) = match (received.context("receive task failed")?, pumped) {
+        (Ok(reported), Ok(summary)) => (reported, summary),
+        (Err(err), Ok(_)) | (Ok(_), Err(err)) => return Err(err),
+        // A download that broke off ends the receive early too; report the
+        // download error rather than the truncated stream.
+        (Err(receive_err), Err(download_err)) => {
+            let truncated = receive_err.chain().any(|cause| {
+                cause
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|err| err.kind() == io::ErrorKind::UnexpectedEof)
+            });
+            return Err(if truncated { download_err } else { receive_err });
+        }
+    };
+    // The checksum is only known once the whole object has gone through, so a
+    // mismatch undoes a receive that already happened.
+    if sha256 != record.sha256 {
+        if Path::new(snapshot_path).exists() {
+            ctx.btrfs().subvolume_delete(snapshot_path)?;
+        }
+        return Err(anyhow!("checksum mismatch for {}", record.object_key));
+    }
+    Ok(reported)
+}
+
+async fn fetch_artifact(
+    client: Arc<dyn StorageBackend>,
+    record: &ManifestRecord,
+    scratch_dir: &Path,
+) -> Result<PathBuf> {
+    btrfs::ensure_dir(scratch_dir)?;
+    let file_name = Path::new(&record.object_key)
+        .file_name()
+        .ok_or_else(|| anyhow!("invalid object_key for {}", record.label))?;
+    // Named per process, as another hydration may be fetching it too.
+    let mut file_name = file_name.to_os_string();
+    file_name.push(format!(".{}", std::process::id()));
+    let path = scratch_dir.join(file_name);
+    client
+        .get(&record.object_key, path.to_str().unwrap_or_default())
+        .await?;
+    if sha256_file(path.to_str().unwrap_or_default())? != record.sha256 {
+        let _ = fs::remove_file(&path);
+        return Err(anyhow!("checksum mismatch for {}", record.object_key));
+    }
+    Ok(path)
+}
+
+// Receives the whole chain of `label` into a fresh directory under `scratch`,
+// optionally hashes `sample` files of the result against the live dataset,
+// and removes everything it received again, whether or not it succeeded.
+// Files that changed since the backup show up as differing, so the sample only
+// warns; a receive that fails is what fails the test.
+pub async fn test_restore(
+    ctx: &AppContext,
+    label: &str,
+    part: Option<&str>,
+    scratch: Option<&str>,
+    sample: usize,
+) -> Result<()> {
+    let stream = part.unwrap_or(ctx.naming.prefix());
+    let index = ctx.manifest_for(part)?.read_index()?;
+    let resolved = ctx.resolve_label(index.records(), label)?;
+    index.require(&resolved)?;
+    let scratch = match scratch {
+        Some(path) => PathBuf::from(path),
+        None => ctx.ls_path("tmp/restore-test"),
+    };
+    let test_dir = scratch.join(ctx.clock.now().unix_timestamp().to_string());
+    let test_dir = test_dir.to_string_lossy().to_string();
+    ctx.logger
+        .info(format!("Test-restoring {stream}@{resolved} into {test_dir}"));
+
+    let result = async {
+        hydrate_into(ctx, &resolved, part, ReceiveErrors::Abort, false, &test_dir).await?;
+        let snapshot = Path::new(&test_dir).join(ctx.naming.name(stream, &resolved));
+        if !snapshot.exists() {
+            return Err(anyhow!("receive did not produce {}", snapshot.display()));
+        }
+        if sample > 0 {
+            let live = match part {
+                Some(part) => ctx.part_dataset_path(part),
+                None => ctx.config.paths.dataset.clone(),
+            };
+            compare_sample(ctx, &snapshot, Path::new(&live), sample)?;
+        }
+        Ok(())
+    }
+    .await;
+
+    let cleaned = remove_test_dir(ctx, &test_dir);
+    result?;
+    cleaned?;
+    ctx.logger
+        .info(format!("Restore test passed for {stream}@{resolved}"));
+    Ok(())
+}
+
+fn compare_sample(ctx: &AppContext, snapshot: &Path, live: &Path, sample: usize) -> Result<()> {
+    let mut files = Vec::new();
+    collect_files(snapshot, Path::new(""), &mut files)?;
+    files.sort();
+    let step = (files.len() / sample).max(1);
+    let (mut matched, mut differ, mut missing) = (0, 0, 0);
+    for relative in files.iter().step_by(step).take(sample) {
+        let live_path = live.join(relative);
+        if !live_path.is_file() {
+            missing += 1;
+            continue;
+        }
+        let restored = sha256_file(&snapshot.join(relative).to_string_lossy())?;
+        if restored == sha256_file(&live_path.to_string_lossy())? {
+            matched += 1;
+        } else {
+            differ += 1;
+            ctx.logger
+                .warn(format!("{} differs from the live dataset", relative.display()));
+        }
+    }
+    ctx.logger.info(format!(
+        "Sampled {} file(s): {matched} match, {differ} differ, {missing} not in the live dataset",
+        matched + differ + missing
+    ));
+    Ok(())
+}
+
+fn collect_files(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let dir = root.join(relative);
+    for entry in fs::read_dir(&dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let entry = entry.with_context(|| format!("failed to read {}", dir.display()))?;
+        let path = relative.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn remove_test_dir(ctx: &AppContext, test_dir: &str) -> Result<()> {
+    if !Path::new(test_dir).exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(test_dir).with_context(|| format!("failed to read {test_dir}"))? {
+        let path = entry?.path().to_string_lossy().to_string();
+        if ctx.btrfs().subvolume_exists(&path)? {
+            ctx.btrfs().subvolume_delete(&path)?;
+        }
+    }
+    fs::remove_dir_all(test_dir).with_context(|| format!("failed to remove {test_dir}"))
+}
+
+pub fn apply_restore(ctx: &AppContext, label: &str, part: Option<&str>) -> Result<()> {
+    let resolved_label = resolve_label_from_manifest(ctx, label, part)?;
+    let Some(part) = part else {
+        let restore_snapshot = ctx.restore_snapshot_path(&resolved_label);
+        if !Path::new(&restore_snapshot).exists() {
+            return Err(anyhow!("restore snapshot missing: {restore_snapshot}"));
+        }
+        return replace_worktree(ctx, &restore_snapshot, &resolved_label);
+    };
+    let restore_snapshot = ctx.stream_restore_snapshot_path(part, &resolved_label);
+    if !Path::new(&restore_snapshot).exists() {
+        return Err(anyhow!("restore snapshot missing: {restore_snapshot}"));
+    }
+    replace_subvolume(ctx, &restore_snapshot, &ctx.part_dataset_path(part))?;
+    if !ctx.dry_run {
+        ctx.logger
+            .info(format!("Split part {part} updated to {part}@{resolved_label}"));
+        mark_rebaseline(ctx, &resolved_label)?;
+    }
+    Ok(())
+}
+
+// Beside the snapshots, like .queue: holds the label a restore rewrote the
+// working tree to. Unless that label is the next incremental's parent anyway,
+// the next `ws run-month` or `backup run` builds an anchor so the chain
+// restarts from the restored tree.
+const REBASELINE_MARKER: &str = ".rebaseline";
+
+fn rebaseline_marker(ctx: &AppContext) -> PathBuf {
+    Path::new(&ctx.config.paths.snapshots).join(REBASELINE_MARKER)
+}
+
+fn mark_rebaseline(ctx: &AppContext, label: &str) -> Result<()> {
+    state::write(ctx, &rebaseline_marker(ctx), &format!("{label}\n"))
+}
+
+// The label the working tree was last restored to, until the next backup.
+pub fn pending_rebaseline(ctx: &AppContext) -> Option<String> {
+    let contents = state::read(ctx, &rebaseline_marker(ctx)).ok()??;
+    Some(contents.trim().to_string())
+}
+
+pub fn clear_rebaseline(ctx: &AppContext) -> Result<()> {
+    state::remove(&rebaseline_marker(ctx))
+}
+
+pub fn resolve_label_from_manifest(
+    ctx: &AppContext,
+    label: &str,
+    part: Option<&str>,
+) -> Result<String> {
+    let records = ctx.manifest_for(part)?.read_records()?;
+    if records.is_empty() {
+        return Err(anyhow!("manifest is empty"));
+    }
+    ctx.resolve_label(&records, label)
+}
+
+// The dataset is switched blue/green: the new tree is snapshotted beside it as
+// `<dataset>.new` while the old one is still live, then two renames put it in
+// place and keep the old tree as `<dataset>.old` until `restore confirm`
+// deletes it or `restore switch-back` swaps it back in.
+pub fn replace_worktree(ctx: &AppContext, snapshot_path: &str, label: &str) -> Result<()> {
+    let dataset = &ctx.config.paths.dataset;
+    let staged = stage_subvolume(ctx, snapshot_path, dataset)?;
+    let set_aside = set_aside_parts(ctx)?;
+    swap_in(ctx, &staged, dataset)?;
+    put_back_parts(ctx, set_aside)?;
+    if !ctx.dry_run {
+        ctx.logger.info(format!("Working tree updated to {}", ctx.snapshot_name(label)));
+        ctx.logger.info(format!(
+            "The previous tree is kept at {dataset}.old until `dev-backup restore confirm`"
+        ));
+        mark_rebaseline(ctx, label)?;
+    }
+    Ok(())
+}
+
+// Puts the tree kept by the last apply back in place, keeping the one it
+// replaces as `.old` in turn.
+pub fn switch_back(ctx: &AppContext, part: Option<&str>) -> Result<()> {
+    let target = match part {
+        Some(part) => {
+            ctx.manifest_for(Some(part))?;
+            ctx.part_dataset_path(part)
+        }
+        None => ctx.config.paths.dataset.clone(),
+    };
+    let old = format!("{target}.old");
+    if !Path::new(&old).exists() {
+        return Err(anyhow!("nothing to switch back to: {old} does not exist"));
+    }
+    let set_aside = if part.is_none() { set_aside_parts(ctx)? } else { Vec::new() };
+    let swapping = format!("{target}.new");
+    if Path::new(&swapping).exists() {
+        return Err(anyhow!("{swapping} is in the way; remove it and run switch-back again"));
+    }
+    ctx.perform(format!("move {old} to {swapping}"), || rename(&old, &swapping))?;
+    swap_in(ctx, &swapping, &target)?;
+    put_back_parts(ctx, set_aside)?;
+    if !ctx.dry_run {
+        ctx.logger.info(format!("Switched {target} back; the tree it replaced is at {old}"));
+    }
+    Ok(())
+}
+
+// Deletes the tree the last apply (or switch-back) kept as `.old`.
+pub fn confirm_apply(ctx: &AppContext, part: Option<&str>) -> Result<()> {
+    let target = match part {
+        Some(part) => {
+            ctx.manifest_for(Some(part))?;
+            ctx.part_dataset_path(part)
+        }
+        None => ctx.config.paths.dataset.clone(),
+    };
+    let old = format!("{target}.old");
+    if !Path::new(&old).exists() {
+        ctx.logger.info(format!("Nothing to confirm: {old} does not exist"));
+        return Ok(());
+    }
+    ctx.perform(format!("delete {old}"), || {
+        if ctx.btrfs().subvolume_exists(&old)? {
+            ctx.btrfs().subvolume_delete(&old)
+        } else {
+            fs::remove_dir_all(&old).with_context(|| format!("failed to remove {old}"))
+        }
+    })?;
+    if !ctx.dry_run {
+        ctx.logger.info(format!("Deleted {old}"));
+    }
+    Ok(())
+}
+
+// Split parts are nested subvolumes with their own chains; a snapshot of the
+// dataset leaves only empty directories in their place, so the live parts are
+// set aside and moved back once the dataset is replaced.
+fn set_aside_parts(ctx: &AppContext) -> Result<Vec<(String, String)>> {
+    let dataset = &ctx.config.paths.dataset;
+    let mut set_aside = Vec::new();
+    for part in &ctx.config.split.parts {
+        let part_path = ctx.part_dataset_path(part);
+        if !Path::new(&part_path).exists() || !ctx.btrfs().subvolume_exists(&part_path)? {
+            continue;
+        }
+        let aside = format!("{dataset}_part_{part}");
+        ctx.perform(format!("move split part {part_path} to {aside}"), || {
+            fs::rename(&part_path, &aside)
+                .with_context(|| format!("failed to move split part {part} to {aside}"))
+        })?;
+        set_aside.push((part_path, aside));
+    }
+    Ok(set_aside)
+}
+
+fn put_back_parts(ctx: &AppContext, set_aside: Vec<(String, String)>) -> Result<()> {
+    for (part_path, aside) in set_aside {
+        ctx.perform(format!("move split part {aside} back to {part_path}"), || {
+            let placeholder = Path::new(&part_path);
+            if placeholder.is_dir() {
+                fs::remove_dir(placeholder)
+                    .with_context(|| format!("split part placeholder is not empty: {part_path}"))?;
+            }
+            fs::rename(&aside, &part_path)
+                .with_context(|| format!("failed to move split part back to {part_path}"))
+        })?;
+    }
+    Ok(())
+}
+
+fn replace_subvolume(ctx: &AppContext, snapshot_path: &str, target: &str) -> Result<()> {
+    let staged = stage_subvolume(ctx, snapshot_path, target)?;
+    swap_in(ctx, &staged, target)
+}
+
+// Snapshots `snapshot_path` to `<target>.new`. A `.new` subvolume left by an
+// interrupted apply is replaced; an `.old` tree nobody has confirmed yet
+// stops the apply rather than being lost.
+fn stage_subvolume(ctx: &AppContext, snapshot_path: &str, target: &str) -> Result<String> {
+    ensure_nothing_kept(target)?;
+    let staged = format!("{target}.new");
+    if Path::new(&staged).exists() {
+        if !ctx.btrfs().subvolume_exists(&staged)? {
+            return Err(anyhow!("{staged} is in the way and is not a subvolume"));
+        }
+        ctx.perform(format!("delete leftover subvolume {staged}"), || {
+            ctx.btrfs().subvolume_delete(&staged)
+        })?;
+    }
+    ctx.perform(format!("snapshot {snapshot_path} to {staged}"), || {
+        ctx.btrfs().snapshot_writable(snapshot_path, &staged)
+    })?;
+    Ok(staged)
+}
+
+// A tree can only be replaced once the one the last apply kept as `.old` is
+// confirmed away or switched back to.
+pub fn ensure_nothing_kept(target: &str) -> Result<()> {
+    let old = format!("{target}.old");
+    if Path::new(&old).exists() {
+        return Err(anyhow!(
+            "{old} is still kept from the last apply; delete it with `dev-backup restore \
+             confirm` or go back to it with `dev-backup restore switch-back` first"
+        ));
+    }
+    Ok(())
+}
+
+// The two renames that switch trees; the target path is missing only between
+// them. If the second fails the first is undone.
+fn swap_in(ctx: &AppContext, staged: &str, target: &str) -> Result<()> {
+    let old = format!("{target}.old");
+    let had_target = Path::new(target).exists();
+    if had_target {
+        ctx.perform(format!("move {target} to {old}"), || rename(target, &old))?;
+    }
+    ctx.perform(format!("move {staged} to {target}"), || {
+        let result = rename(staged, target);
+        if result.is_err() && had_target {
+            let _ = fs::rename(&old, target);
+        }
+        result
+    })
+}
+
+fn rename(from: &str, to: &str) -> Result<()> {
+    fs::rename(from, to).with_context(|| format!("failed to move {from} to {to}"))
+}
@@ -0,0 +1,330 @@
+use crate::commands::ws::planned_parent;
+use crate::context::AppContext;
+use crate::format::format_bytes;
+use crate::label::{ensure_label, LabelRange};
+use crate::output::Table;
+use crate::progress;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_btrfs as btrfs;
+use dev_backup_core::config::ChurnAction;
+use dev_backup_core::sets::SnapshotSet;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Without a label (`--auto`) the label is derived from the current date.
+pub fn snapshot(ctx: &AppContext, label: Option<&str>, adopt: Option<&str>) -> Result<()> {
+    let label = label.map_or_else(|| ctx.auto_label(), str::to_string);
+    ctx.ensure_new_label(&label)?;
+    if let Some(source) = adopt {
+        adopt_snapshot(ctx, &label, source)?;
+    }
+    create_snapshot(ctx, &label)
+}
+
+// Moves a read-only snapshot of the dataset that another tool (snapper,
+// btrbk) already took into place as `label`'s snapshot, so the artifact is
+// built from it instead of from a second snapshot of the same data. The
+// other tool no longer manages it afterwards.
+pub fn adopt_snapshot(ctx: &AppContext, label: &str, source: &str) -> Result<()> {
+    if !ctx.config.split.parts.is_empty() {
+        return Err(anyhow!(
+            "--adopt cannot be used with split.parts, whose snapshots would be from \
+             another point in time"
+        ));
+    }
+    let dest = ctx.snapshot_path(label);
+    if Path::new(&dest).exists() {
+        return Err(anyhow!("{dest} already exists; nothing to adopt {source} as"));
+    }
+    let details = btrfs::subvolume_details(source)
+        .with_context(|| format!("{source} must be a btrfs subvolume"))?;
+    if !details.readonly {
+        return Err(anyhow!("{source} is not a read-only snapshot"));
+    }
+    let dataset = &ctx.config.paths.dataset;
+    let source_uuid = btrfs::subvolume_details(dataset)?.uuid;
+    if details.parent_uuid.is_none() || details.parent_uuid != source_uuid {
+        return Err(anyhow!("{source} is not a snapshot of {dataset}"));
+    }
+    fs::rename(source, &dest).with_context(|| {
+        format!(
+            "failed to move {source} to {dest}; it must be on the same filesystem as {}",
+            ctx.config.paths.snapshots
+        )
+    })?;
+    ctx.logger.info(format!("Adopted snapshot {source} as {dest}"));
+    Ok(())
+}
+
+// Deletes the snapshot of `label` and its split parts. The parent of the next
+// planned incremental is only deleted with `force`: without it the next run
+// has nothing to send an incremental from.
+pub async fn snapshot_delete(ctx: &AppContext, label: &str, force: bool) -> Result<()> {
+    ensure_label(label)?;
+    let path = ctx.snapshot_path(label);
+    if !Path::new(&path).exists() {
+        return Err(anyhow!("snapshot not found: {path}"));
+    }
+    let parent = planned_parent(ctx)
+        .await
+        .context("the manifest is needed to tell which snapshot is the next parent")?;
+    if parent.as_deref() == Some(label) {
+        let name = ctx.snapshot_name(label);
+        if !force {
+            return Err(anyhow!(
+                "{name} is the parent of the next planned incremental; \
+                 pass --force to delete it anyway"
+            ));
+        }
+        ctx.logger.warn(format!(
+            "deleting {name}, the parent of the next planned incremental"
+        ));
+    }
+    let streams = std::iter::once(ctx.naming.prefix())
+        .chain(ctx.config.split.parts.iter().map(String::as_str));
+    for stream in streams {
+        let path = ctx.stream_snapshot_path(stream, label);
+        if Path::new(&path).exists() {
+            ctx.perform(format!("delete snapshot {path}"), || {
+                ctx.btrfs().subvolume_delete(&path)
+            })?;
+            if !ctx.dry_run {
+                ctx.logger.info(format!("Deleted snapshot {path}"));
+            }
+        }
+    }
+    Ok(())
+}
+
+// One row per snapshot of the dataset: its label, creation time and
+// read-only flag from `btrfs subvolume show` ("-" and "?" when that cannot
+// tell), and the type of its manifest row, if it has one.
+pub fn snapshot_list(ctx: &AppContext, range: &LabelRange) -> Result<()> {
+    let records = if ctx.manifest.path().exists() {
+        ctx.manifest.read_records()?
+    } else {
+        Vec::new()
+    };
+    let mut table = Table::new();
+    for label in local_snapshot_labels(ctx)?.into_iter().filter(|label| range.contains(label)) {
+        let details = btrfs::subvolume_details(&ctx.snapshot_path(&label)).ok();
+        let created = details.as_ref().and_then(|details| details.creation_time.clone());
+        let readonly = details.as_ref().map(|details| details.readonly);
+        let artifact = records.iter().find(|record| record.label == label);
+        let artifact = artifact.map(|record| record.record_type.as_str());
+        if ctx.json {
+            ctx.emit(&json!({
+                "label": label,
+                "snapshot": ctx.snapshot_name(&label),
+                "created": created,
+                "readonly": readonly,
+                "artifact": artifact,
+            }))?;
+            continue;
+        }
+        let readonly = match readonly {
+            Some(true) => "ro",
+            Some(false) => "rw",
+            None => "?",
+        };
+        let created = created.as_deref().unwrap_or("-");
+        table.row([label.as_str(), created, readonly, artifact.unwrap_or("-")]);
+    }
+    table.print();
+    Ok(())
+}
+
+// Labels of the dataset's own snapshots, oldest first.
+pub fn local_snapshot_labels(ctx: &AppContext) -> Result<Vec<String>> {
+    let snapshots = &ctx.config.paths.snapshots;
+    let mut labels = Vec::new();
+    for entry in fs::read_dir(snapshots)
+        .with_context(|| format!("failed to read snapshot root: {snapshots}"))?
+    {
+        let name = entry?.file_name();
+        let Some((stream, label)) = name.to_str().and_then(|name| ctx.naming.parse(name)) else {
+            continue;
+        };
+        if stream == ctx.naming.prefix() {
+            labels.push(label);
+        }
+    }
+    labels.sort();
+    Ok(labels)
+}
+
+// Snapshots every [[dataset]] under one label and records them as a set, so a
+// restore of that label brings all of them back to the same point in time.
+// The snapshots are taken back to back once every dataset is quiet, and a set
+// is all or nothing: if one fails, the ones taken by this run are deleted.
+pub fn snapshot_set(ctx: &AppContext, label: &str) -> Result<()> {
+    ctx.ensure_new_label(label)?;
+    if ctx.config.datasets.is_empty() {
+        return Err(anyhow!("snapshot sets need [[dataset]] entries in the config"));
+    }
+    if ctx.sets.find(label)?.is_some() {
+        ctx.logger.info(format!("Snapshot set {label} is already recorded"));
+        return Ok(());
+    }
+    let names: Vec<String> = ctx.config.datasets.iter().map(|d| d.name.clone()).collect();
+    let members = names
+        .iter()
+        .map(|name| ctx.with_dataset(name))
+        .collect::<Result<Vec<_>>>()?;
+    for member in &members {
+        let path = member.snapshot_path(label);
+        if Path::new(&path).exists() {
+            return Err(anyhow!(
+                "{path} already exists and may be from another point in time; \
+                 delete it or pick another label"
+            ));
+        }
+        ensure_disk_floor(member)?;
+        wait_for_quiet_dataset(member)?;
+    }
+
+    let mut taken: Vec<String> = Vec::new();
+    for member in &members {
+        let path = member.snapshot_path(label);
+        if let Err(err) = member.btrfs().snapshot_readonly(&member.config.paths.dataset, &path) {
+            for taken_path in &taken {
+                if let Err(cleanup) = ctx.btrfs().subvolume_delete(taken_path) {
+                    ctx.logger.warn(format!("failed to delete {taken_path}: {cleanup:#}"));
+                }
+            }
+            return Err(err.context(format!("snapshot set {label} was not taken")));
+        }
+        ctx.logger.info(format!("Created snapshot {path}"));
+        taken.push(path);
+    }
+    ctx.sets.append(&SnapshotSet {
+        ts: ctx.clock.now(),
+        label: label.to_string(),
+        datasets: names.clone(),
+    })?;
+    ctx.logger.info(format!("Recorded snapshot set {label}: {}", names.join(", ")));
+    Ok(())
+}
+
+pub fn create_snapshot(ctx: &AppContext, label: &str) -> Result<()> {
+    let stage = progress::Stage::begin("snapshot", None);
+    let snapshot_path = ctx.snapshot_path(label);
+    if Path::new(&snapshot_path).exists() {
+        ctx.logger
+            .info(format!("Snapshot already exists: {snapshot_path}"));
+    } else {
+        ensure_disk_floor(ctx)?;
+        wait_for_quiet_dataset(ctx)?;
+        let dataset = &ctx.config.paths.dataset;
+        if let Err(err) = ctx.btrfs().snapshot_readonly(dataset, &snapshot_path) {
+            // btrfs only reports EXDEV, so explain what it means here.
+            check_same_filesystem(ctx, dataset, &ctx.config.paths.snapshots)?;
+            return Err(err);
+        }
+        ctx.logger.info(format!("Created snapshot {snapshot_path}"));
+    }
+
+    for part in &ctx.config.split.parts {
+        let part_snapshot = ctx.stream_snapshot_path(part, label);
+        if Path::new(&part_snapshot).exists() {
+            continue;
+        }
+        let source = ctx.part_dataset_path(part);
+        ctx.btrfs()
+            .snapshot_readonly(&source, &part_snapshot)
+            .with_context(|| format!("split part {part} must be a subvolume at {source}"))?;
+        ctx.logger.info(format!("Created snapshot {part_snapshot}"));
+    }
+    stage.finish();
+    Ok(())
+}
+
+// btrfs snapshots stay on the filesystem of their source, so a snapshot root
+// on another btrfs filesystem (a second disk, a separately formatted
+// partition) can never hold them. A root that does not exist yet is judged by
+// the directory it would be created in. Paths whose filesystem cannot be read
+// (not btrfs) are left to the other checks.
+pub fn check_same_filesystem(ctx: &AppContext, dataset: &str, snapshots: &str) -> Result<()> {
+    let existing = Path::new(snapshots).ancestors().find(|path| path.exists());
+    let Some(existing) = existing.and_then(Path::to_str) else {
+        return Ok(());
+    };
+    let btrfs = ctx.btrfs();
+    let (Ok(source), Ok(target)) = (btrfs.filesystem_uuid(dataset), btrfs.filesystem_uuid(existing))
+    else {
+        return Ok(());
+    };
+    if source != target {
+        return Err(anyhow!(
+            "snapshot root {snapshots} is on btrfs filesystem {target}, but dataset {dataset} \
+             is on {source}; btrfs cannot snapshot across filesystems, so move the snapshot \
+             root onto the dataset's filesystem (e.g. a directory beside the dataset) and let \
+             the LS keep the copies elsewhere"
+        ));
+    }
+    Ok(())
+}
+
+// Refuses to snapshot once the snapshots filesystem is below disk.floor_free_mib,
+// so that backups never fill the disk they are protecting.
+fn ensure_disk_floor(ctx: &AppContext) -> Result<()> {
+    let Some(floor) = ctx.config.disk.as_ref().and_then(|disk| disk.floor_free_mib) else {
+        return Ok(());
+    };
+    let snapshots = &ctx.config.paths.snapshots;
+    let free = btrfs::free_bytes(snapshots)?;
+    if free < floor * 1024 * 1024 {
+        return Err(anyhow!(
+            "only {} free on {snapshots}, below disk.floor_free_mib ({floor} MiB); \
+             refusing to take a snapshot (see `ws maintain`)",
+            format_bytes(free)
+        ));
+    }
+    Ok(())
+}
+
+pub fn wait_for_quiet_dataset(ctx: &AppContext) -> Result<()> {
+    let churn = match ctx.config.churn.as_ref() {
+        Some(churn) => churn,
+        None => return Ok(()),
+    };
+    let dataset = &ctx.config.paths.dataset;
+    let probe = Duration::from_secs(churn.probe_seconds);
+    let started = Instant::now();
+    loop {
+        let generation = ctx.btrfs().subvolume_generation(dataset)?;
+        thread::sleep(probe);
+        let changed = btrfs::changed_bytes_since(dataset, generation)?;
+        if changed <= churn.max_changed_bytes {
+            return Ok(());
+        }
+
+        let message = format!(
+            "dataset {dataset} changed {} in {}s (limit {})",
+            format_bytes(changed),
+            churn.probe_seconds,
+            format_bytes(churn.max_changed_bytes)
+        );
+        match churn.action {
+            ChurnAction::Warn => {
+                ctx.logger.warn(format!("{message}; snapshot may be inconsistent"));
+                return Ok(());
+            }
+            ChurnAction::Delay => {
+                if started.elapsed() >= Duration::from_secs(churn.max_wait_seconds)
+                    || ctx.deadline_reached()
+                {
+                    ctx.logger.warn(format!(
+                        "{message}; waited {}s, snapshotting anyway",
+                        started.elapsed().as_secs()
+                    ));
+                    return Ok(());
+                }
+                ctx.logger.info(format!("{message}; delaying snapshot"));
+            }
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::commands::sync::read_last_push;
+use crate::context::AppContext;
+use crate::format::{format_bytes, format_duration};
+use anyhow::Result;
+use dev_backup_core::deadline::parse_duration;
+use dev_backup_core::manifest::RecordStatus;
+use dev_backup_core::recovery::estimate_restore;
+use serde_json::json;
+use std::collections::BTreeMap;
+use time::format_description::well_known::Rfc3339;
+use time::Duration;
+
+pub fn status(ctx: &AppContext) -> Result<()> {
+    let index = ctx.manifest.read_index()?;
+    if index.is_empty() {
+        if ctx.json {
+            return ctx.emit(&json!({ "latest": null }));
+        }
+        ctx.logger.info("Manifest is empty; nothing to restore yet");
+        return Ok(());
+    }
+    let label = ctx.resolve_label(index.records(), "latest")?;
+    let chain = index.chain(&label)?;
+    let recovery = &ctx.config.recovery;
+    let estimate = estimate_restore(&chain, recovery);
+
+    // Rows that a later row for the same label replaced are left out.
+    let current: Vec<_> = index
+        .records()
+        .iter()
+        .filter(|record| index.get(&record.label) == Some(*record))
+        .collect();
+    let mut counts = Vec::new();
+    for status in RecordStatus::ALL {
+        let count = current.iter().filter(|record| record.status == status).count();
+        if count > 0 {
+            counts.push((status.as_str(), count));
+        }
+    }
+    let anchor = &chain[0].label;
+    let depth = chain.len() - 1;
+    let bytes_of = |kind: &str| -> u64 {
+        current.iter().filter(|record| record.record_type == kind).map(|r| r.bytes).sum()
+    };
+    let (anchor_bytes, incremental_bytes) = (bytes_of("anchor"), bytes_of("incremental"));
+    let unpushed: Vec<&str> = current
+        .iter()
+        .filter(|record| record.object_key.is_empty())
+        .map(|record| record.label.as_str())
+        .collect();
+    let last_push = read_last_push(ctx)?;
+    let push_age = last_push.map(|pushed| ctx.clock.now() - pushed);
+    let objective = recovery.objective.as_deref().map(parse_duration).transpose()?;
+    if ctx.json {
+        return ctx.emit(&json!({
+            "latest": label,
+            "anchor": anchor,
+            "chain_depth": depth,
+            "artifacts": counts.into_iter().collect::<BTreeMap<_, _>>(),
+            "anchor_bytes": anchor_bytes,
+            "incremental_bytes": incremental_bytes,
+            "unpushed": unpushed,
+            "last_push": last_push.map(|pushed| pushed.format(&Rfc3339)).transpose()?,
+            "last_push_age_seconds": push_age.map(|age| age.whole_seconds()),
+            "chain_artifacts": chain.len(),
+            "chain_bytes": estimate.bytes,
+            "rto_seconds": estimate.total().whole_seconds(),
+            "objective_seconds": objective.map(|objective| objective.whole_seconds()),
+            "objective_met": objective.map(|objective| estimate.total() <= objective),
+        }));
+    }
+
+    ctx.logger.info(format!("Latest: {}", ctx.snapshot_name(&label)));
+    ctx.logger.info(format!("Chain depth: {depth} incremental(s) since anchor {anchor}"));
+    let counts: Vec<String> =
+        counts.iter().map(|(status, count)| format!("{count} {status}")).collect();
+    ctx.logger.info(format!("Artifacts: {}", counts.join(", ")));
+    ctx.logger.info(format!(
+        "Artifact bytes: {} (anchors {}, incrementals {})",
+        format_bytes(anchor_bytes + incremental_bytes),
+        format_bytes(anchor_bytes),
+        format_bytes(incremental_bytes)
+    ));
+    if unpushed.is_empty() {
+        ctx.logger.info("Not pushed: none");
+    } else {
+        ctx.logger.info(format!("Not pushed: {}", unpushed.join(", ")));
+    }
+    ctx.logger.info(match (last_push, push_age) {
+        (Some(pushed), Some(age)) => {
+            format!("Last push: {} ({} ago)", pushed.format(&Rfc3339)?, format_age(age))
+        }
+        _ => "Last push: never".to_string(),
+    });
+    ctx.logger.info(format!(
+        "Restore chain: {} artifact(s), {}",
+        chain.len(),
+        format_bytes(estimate.bytes)
+    ));
+    ctx.logger.info(format!(
+        "Estimated RTO: {} (download {} at {} Mbit/s, decrypt {}, receive {})",
+        format_duration(estimate.total()),
+        format_duration(estimate.download),
+        recovery.download_mbps,
+        format_duration(estimate.decrypt),
+        format_duration(estimate.receive)
+    ));
+    if let Some(objective) = objective {
+        if estimate.total() > objective {
+            ctx.logger.warn(format!(
+                "estimated RTO {} exceeds the recovery objective of {}; consider a new anchor",
+                format_duration(estimate.total()),
+                format_duration(objective)
+            ));
+        } else {
+            ctx.logger.info(format!(
+                "Recovery objective: {} (met)",
+                format_duration(objective)
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Coarser than format_duration: pushes are days apart.
+fn format_age(age: Duration) -> String {
+    match age.whole_days() {
+        0 => format_duration(age),
+        days => format!("{days}d"),
+    }
+}
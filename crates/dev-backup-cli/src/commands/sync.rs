@@ -0,0 +1,706 @@
+use crate::commands::restore::ensure_one_machine;
+use crate::context::{
+    manifest_checksum_key, part_manifest_key, AppContext, ALIASES_OBJECT_KEY, SETS_OBJECT_KEY,
+};
+use crate::format::format_bytes;
+use crate::label::{resolve_label_input, LabelRange};
+use crate::output::Table;
+use crate::progress;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_btrfs as btrfs;
+use dev_backup_core::alias::AliasStore;
+use dev_backup_core::manifest::{
+    remote_only_records, ManifestRecord, ManifestStore, RecordStatus,
+};
+use dev_backup_storage::artifact::sha256_file;
+use dev_backup_storage::backend::StorageBackend;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+// When a push last reached [cloud] in full; shown by `status`.
+const LAST_PUSH_FILE: &str = "manifests/last_push";
+
+// Rounds of merge-and-upload before a manifest push gives up on a target that
+// keeps changing underneath it.
+const MANIFEST_PUT_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushScope {
+    All,
+    ArtifactsOnly,
+    ManifestOnly,
+}
+
+// [cloud] is pushed first and any failure there aborts the run. Mirrors are
+// pushed afterwards; one that fails is reported but does not stop the others.
+// Manifests go out last so every target receives the keys of all targets;
+// `scope` can hold either half back.
+pub async fn sync_push(ctx: &AppContext, scope: PushScope) -> Result<()> {
+    let artifacts = scope != PushScope::ManifestOnly;
+    let mut targets = vec![(None, ctx.storage().await?)];
+    let mut remaining = 0;
+    if artifacts {
+        remaining += push_all_artifacts(ctx, targets[0].1.as_ref(), None).await?;
+    }
+
+    let mut failed = Vec::new();
+    for mirror in &ctx.config.mirrors {
+        let name = mirror.name.as_str();
+        let pushed = match ctx.storage_for(&mirror.target).await {
+            Ok(client) if artifacts => push_all_artifacts(ctx, client.as_ref(), Some(name))
+                .await
+                .map(|count| (client, count)),
+            Ok(client) => Ok((client, 0)),
+            Err(err) => Err(err),
+        };
+        match pushed {
+            Ok((client, count)) => {
+                remaining += count;
+                targets.push((Some(name), client));
+            }
+            Err(err) => {
+                ctx.logger.warn(format!("mirror {name}: {err:#}"));
+                failed.push(name);
+            }
+        }
+    }
+
+    if scope == PushScope::ManifestOnly {
+        let unpushed = unpushed_artifacts(ctx)?;
+        if unpushed > 0 {
+            ctx.logger.warn(format!(
+                "publishing a manifest with {unpushed} artifact(s) that are not pushed yet"
+            ));
+        }
+    }
+    let manifest_targets = if scope == PushScope::ArtifactsOnly { &[][..] } else { &targets[..] };
+    for (mirror, client) in manifest_targets {
+        if let Err(err) = push_manifests(ctx, client.as_ref()).await {
+            let Some(name) = mirror else {
+                return Err(err);
+            };
+            ctx.logger.warn(format!("mirror {name}: {err:#}"));
+            failed.push(name);
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(anyhow!("sync push failed for mirror(s): {}", failed.join(", ")));
+    }
+    if remaining > 0 {
+        ctx.logger.warn(format!(
+            "deadline reached with {remaining} artifact(s) left to upload; rerun to resume"
+        ));
+        return Ok(());
+    }
+    if !ctx.dry_run {
+        write_last_push(ctx)?;
+        ctx.logger.info(match scope {
+            PushScope::All => "Sync push complete",
+            PushScope::ArtifactsOnly => "Sync push complete; manifests were held back",
+            PushScope::ManifestOnly => "Sync push complete; only manifests were published",
+        });
+    }
+    Ok(())
+}
+
+async fn push_all_artifacts(
+    ctx: &AppContext,
+    client: &dyn StorageBackend,
+    mirror: Option<&str>,
+) -> Result<usize> {
+    let mut remaining = push_artifacts(ctx, client, &ctx.manifest, mirror).await?;
+    for part in &ctx.config.split.parts {
+        let manifest = ctx.manifest_for(Some(part))?;
+        if manifest.path().exists() {
+            remaining += push_artifacts(ctx, client, manifest, mirror).await?;
+        }
+    }
+    Ok(remaining)
+}
+
+// Kept per dataset when [[dataset]] is configured, since each is pushed on
+// its own.
+fn last_push_path(ctx: &AppContext) -> PathBuf {
+    match &ctx.dataset {
+        Some(name) => ctx.ls_path(&format!("manifests/datasets/{name}.last_push")),
+        None => ctx.ls_path(LAST_PUSH_FILE),
+    }
+}
+
+pub fn read_last_push(ctx: &AppContext) -> Result<Option<OffsetDateTime>> {
+    let path = last_push_path(ctx);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let pushed = OffsetDateTime::parse(contents.trim(), &Rfc3339)
+        .with_context(|| format!("invalid timestamp in {}", path.display()))?;
+    Ok(Some(pushed))
+}
+
+fn write_last_push(ctx: &AppContext) -> Result<()> {
+    let path = last_push_path(ctx);
+    btrfs::ensure_dir(path.parent().unwrap_or(Path::new("/")))?;
+    fs::write(&path, format!("{}\n", ctx.clock.now().format(&Rfc3339)?))
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn unpushed_artifacts(ctx: &AppContext) -> Result<usize> {
+    let mut count = 0;
+    let parts = ctx.config.split.parts.iter().map(|part| ctx.manifest_for(Some(part)));
+    for manifest in std::iter::once(Ok(&ctx.manifest)).chain(parts) {
+        let manifest = manifest?;
+        if manifest.path().exists() {
+            count += manifest
+                .read_records()?
+                .iter()
+                .filter(|record| record.status < RecordStatus::Pushed)
+                .count();
+        }
+    }
+    Ok(count)
+}
+
+async fn push_manifests(ctx: &AppContext, client: &dyn StorageBackend) -> Result<()> {
+    let mut manifests = vec![(ctx.manifest_key(), ctx.naming.prefix(), &ctx.manifest)];
+    for part in &ctx.config.split.parts {
+        let manifest = ctx.manifest_for(Some(part))?;
+        if manifest.path().exists() {
+            manifests.push((part_manifest_key(part), part.as_str(), manifest));
+        }
+    }
+    for (key, stream, manifest) in manifests {
+        if ctx.dry_run {
+            ctx.logger
+                .info(format!("Dry run: would upload {} as {key}", manifest.path().display()));
+            continue;
+        }
+        publish_manifest(ctx, client, &key, stream, manifest).await?;
+    }
+    let mut uploads = Vec::new();
+    if ctx.aliases.path().exists() {
+        uploads.push((ALIASES_OBJECT_KEY.to_string(), ctx.aliases.path()));
+    }
+    if ctx.sets.path().exists() {
+        uploads.push((SETS_OBJECT_KEY.to_string(), ctx.sets.path()));
+    }
+    for (key, path) in uploads {
+        if ctx.dry_run {
+            ctx.logger
+                .info(format!("Dry run: would upload {} as {key}", path.display()));
+            continue;
+        }
+        client.put(&key, path.to_str().unwrap_or_default()).await?;
+    }
+    Ok(())
+}
+
+// Download, merge, conditional upload: rows another host published under
+// `key` since this one last looked are appended to the local manifest first,
+// and the upload only lands if `key` has not changed again in the meantime,
+// so concurrent pushes cannot drop each other's rows.
+async fn publish_manifest(
+    ctx: &AppContext,
+    client: &dyn StorageBackend,
+    key: &str,
+    stream: &str,
+    manifest: &ManifestStore,
+) -> Result<()> {
+    let path = manifest.path().to_str().unwrap_or_default();
+    for attempt in 1..=MANIFEST_PUT_ATTEMPTS {
+        let seen = client.head(key).await?;
+        if seen.is_some() {
+            merge_remote_manifest(ctx, client, key, stream, manifest).await?;
+        }
+        let sha256 = sha256_file(path)?;
+        if client.put_if_unchanged(key, path, seen.as_ref()).await? {
+            return put_manifest_checksum(ctx, client, key, &sha256).await;
+        }
+        ctx.logger.warn(format!(
+            "{key} changed during the push (attempt {attempt} of {MANIFEST_PUT_ATTEMPTS}); \
+             merging again"
+        ));
+    }
+    Err(anyhow!("{key} kept changing during the push; rerun `dev-backup sync push`"))
+}
+
+async fn put_manifest_checksum(
+    ctx: &AppContext,
+    client: &dyn StorageBackend,
+    key: &str,
+    sha256: &str,
+) -> Result<()> {
+    let tmp_dir = ctx.ls_path("tmp");
+    btrfs::ensure_dir(&tmp_dir)?;
+    let checksum_key = manifest_checksum_key(key);
+    let tmp_path = tmp_dir.join(checksum_key.replace('/', "_"));
+    let filename = key.rsplit('/').next().unwrap_or(key);
+    fs::write(&tmp_path, format!("{sha256}  {filename}\n"))
+        .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+    let result = client.put(&checksum_key, tmp_path.to_str().unwrap_or_default()).await;
+    let _ = fs::remove_file(&tmp_path);
+    result
+}
+
+// Downloads the manifest at `key` to `dest` and checks it against its
+// checksum object. Manifests pushed before checksums existed have none and
+// are taken as they are.
+// The aliases in the LS root, over those pushed to `client`: a host that
+// only pulls, such as a workstation, has none of its own.
+pub async fn read_aliases(
+    ctx: &AppContext,
+    client: Option<&dyn StorageBackend>,
+) -> Result<BTreeMap<String, String>> {
+    let mut aliases = BTreeMap::new();
+    if let Some(client) = client {
+        if client.head(ALIASES_OBJECT_KEY).await?.is_some() {
+            let tmp_path = std::env::temp_dir().join(format!(
+                "dev-backup-aliases-{}.tsv",
+                std::process::id()
+            ));
+            let dest = tmp_path.to_str().unwrap_or_default();
+            let remote = match client.get(ALIASES_OBJECT_KEY, dest).await {
+                Ok(()) => AliasStore::new(&tmp_path).read(),
+                Err(err) => Err(err),
+            };
+            let _ = fs::remove_file(&tmp_path);
+            aliases = remote.with_context(|| format!("failed to read {ALIASES_OBJECT_KEY}"))?;
+        }
+    }
+    aliases.extend(ctx.aliases.read()?);
+    Ok(aliases)
+}
+
+pub async fn get_manifest(client: &dyn StorageBackend, key: &str, dest: &Path) -> Result<()> {
+    let dest_str = dest.to_str().unwrap_or_default();
+    client.get(key, dest_str).await?;
+    let checksum_key = manifest_checksum_key(key);
+    if client.head(&checksum_key).await?.is_none() {
+        return Ok(());
+    }
+    let mut checksum_path = dest.as_os_str().to_os_string();
+    checksum_path.push(".sha256");
+    let checksum_path = PathBuf::from(checksum_path);
+    client.get(&checksum_key, checksum_path.to_str().unwrap_or_default()).await?;
+    let checksum = fs::read_to_string(&checksum_path);
+    let _ = fs::remove_file(&checksum_path);
+    let checksum = checksum.with_context(|| format!("failed to read {checksum_key}"))?;
+    let expected = checksum.split_whitespace().next().unwrap_or_default();
+    let actual = sha256_file(dest_str)?;
+    if actual != expected {
+        let _ = fs::remove_file(dest);
+        return Err(anyhow!(
+            "downloaded {key} does not match {checksum_key} (sha256 {actual}, expected \
+             {expected}); the download was truncated or corrupted, or a push was under way, \
+             so rerun it"
+        ));
+    }
+    Ok(())
+}
+
+async fn merge_remote_manifest(
+    ctx: &AppContext,
+    client: &dyn StorageBackend,
+    key: &str,
+    stream: &str,
+    manifest: &ManifestStore,
+) -> Result<()> {
+    let tmp_dir = ctx.ls_path("tmp");
+    btrfs::ensure_dir(&tmp_dir)?;
+    let name = key.replace('/', "_");
+    let tmp_path = tmp_dir.join(format!("{name}.remote"));
+    get_manifest(client, key, &tmp_path).await?;
+    let remote = ManifestStore::new(&tmp_path).read_records();
+    let _ = fs::remove_file(&tmp_path);
+    let remote = remote.with_context(|| format!("failed to read {key} from the bucket"))?;
+    let local = manifest.read_all_records()?;
+    let missing = remote_only_records(&local, &remote, &ctx.naming, stream)
+        .with_context(|| format!("refusing to overwrite {key}"))?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+    manifest.append_records(&missing)?;
+    let labels: Vec<&str> = missing.iter().map(|record| record.label.as_str()).collect();
+    ctx.logger.info(format!(
+        "Merged {} row(s) from {key} that another host pushed: {}",
+        missing.len(),
+        labels.join(", ")
+    ));
+    Ok(())
+}
+
+// Uploads the artifacts `mirror` (or [cloud] when `None`) does not have yet.
+async fn push_artifacts(
+    ctx: &AppContext,
+    client: &dyn StorageBackend,
+    manifest: &ManifestStore,
+    mirror: Option<&str>,
+) -> Result<usize> {
+    let mut records = manifest.read_records()?;
+    let size = |record: &ManifestRecord| fs::metadata(&record.local_path).map_or(0, |m| m.len());
+    let total = records.iter().filter(|r| target_key(r, mirror).is_none()).map(size).sum();
+    let stage = progress::Stage::begin("sync", Some(total));
+
+    // The manifest is rewritten after every upload so a run cut short by the
+    // deadline resumes with the artifacts that are not pushed yet.
+    let mut remaining = 0;
+    for index in 0..records.len() {
+        let record = &records[index];
+        if target_key(record, mirror).is_some() {
+            continue;
+        }
+        if ctx.deadline_reached() {
+            remaining += 1;
+            continue;
+        }
+        if record.local_path.is_empty() && !record.object_key.is_empty() {
+            ctx.logger.warn(format!(
+                "{}: streamed to [cloud] without a local copy, so it is not mirrored",
+                record.label
+            ));
+            continue;
+        }
+        if record.local_path.is_empty() {
+            return Err(anyhow!("missing local_path for {}", record.label));
+        }
+        let local_path = Path::new(&record.local_path);
+        if !local_path.exists() {
+            return Err(anyhow!("artifact missing: {}", record.local_path));
+        }
+        let object_key = build_object_key(&ctx.config.paths.ls_root, local_path);
+        if ctx.dry_run {
+            ctx.logger
+                .info(format!("Dry run: would upload {} as {object_key}", record.local_path));
+            continue;
+        }
+        progress::item(&object_key);
+        client
+            .put(&object_key, local_path.to_str().unwrap_or_default())
+            .await?;
+        progress::advance(size(record));
+        match mirror {
+            Some(name) => records[index].set_mirror_key(name, &object_key),
+            None => {
+                records[index].object_key = object_key;
+                records[index].advance(RecordStatus::Pushed)?;
+            }
+        }
+        manifest.write_records(&records)?;
+    }
+    stage.finish();
+    Ok(remaining)
+}
+
+// Pulls the chain of `label`, or with a range the chains of every manifest
+// label inside it; artifacts shared between chains are fetched once.
+pub async fn sync_pull(
+    ctx: &AppContext,
+    label: Option<&str>,
+    range: &LabelRange,
+    dest: Option<&str>,
+    part: Option<&str>,
+    mirror: Option<&str>,
+) -> Result<()> {
+    let client = match mirror {
+        Some(name) => {
+            let target = ctx
+                .config
+                .mirrors
+                .iter()
+                .find(|candidate| candidate.name == name)
+                .ok_or_else(|| anyhow!("no mirror named {name:?} is configured"))?;
+            ctx.storage_for(&target.target).await?
+        }
+        None => ctx.storage().await?,
+    };
+
+    let dest_dir = dest.unwrap_or("/tmp/dev-backup-cloud-pull");
+    btrfs::ensure_dir(Path::new(dest_dir))?;
+
+    let (manifest_key, manifest_name) = match part {
+        Some(part) => {
+            ctx.manifest_for(Some(part))?;
+            (part_manifest_key(part), format!("{part}.tsv"))
+        }
+        None => {
+            let key = ctx.manifest_key();
+            let name = key.rsplit('/').next().unwrap_or_default().to_string();
+            (key, name)
+        }
+    };
+    let manifest_path = Path::new(dest_dir).join(manifest_name);
+    get_manifest(client.as_ref(), &manifest_key, &manifest_path).await?;
+
+    let store = ManifestStore::new(&manifest_path).with_machine(ctx.naming.machine());
+    let index = store.read_index()?;
+    if index.is_empty() {
+        return Err(anyhow!("downloaded manifest is empty"));
+    }
+
+    let labels = if range.is_set() {
+        let labels = range.labels_in(index.records());
+        if labels.is_empty() {
+            return Err(anyhow!("no labels in the manifest from {range}"));
+        }
+        labels
+    } else {
+        let label = label.ok_or_else(|| anyhow!("a label or --from/--to is required"))?;
+        let aliases = read_aliases(ctx, Some(client.as_ref())).await?;
+        vec![resolve_label_input(index.records(), &aliases, label)?]
+    };
+
+    let mut plan: Vec<&ManifestRecord> = Vec::new();
+    for label in &labels {
+        for record in index.chain(label)? {
+            ensure_one_machine(&index, &record.label)?;
+            if !plan.contains(&record) {
+                plan.push(record);
+            }
+        }
+    }
+    for record in plan {
+        let object_key = target_key(record, mirror)
+            .ok_or_else(|| anyhow!("missing object_key for {}", record.label))?;
+        let dest_path = Path::new(dest_dir).join(object_key);
+        if dest_path.exists()
+            && sha256_file(dest_path.to_str().unwrap_or_default())? == record.sha256
+        {
+            continue;
+        }
+        if ctx.deadline_reached() {
+            ctx.logger.warn(format!(
+                "deadline reached before downloading {}@{}; rerun to resume",
+                part.unwrap_or(ctx.naming.prefix()),
+                record.label
+            ));
+            return Ok(());
+        }
+        if let Some(parent) = dest_path.parent() {
+            btrfs::ensure_dir(parent)?;
+        }
+        client
+            .get(object_key, dest_path.to_str().unwrap_or_default())
+            .await?;
+    }
+
+    ctx.logger.info(format!("Sync pull complete into {dest_dir}"));
+    Ok(())
+}
+
+fn target_key<'a>(record: &'a ManifestRecord, mirror: Option<&str>) -> Option<&'a str> {
+    match mirror {
+        Some(name) => record.mirror_key(name),
+        None => Some(record.object_key.as_str()).filter(|_| record.status >= RecordStatus::Pushed),
+    }
+}
+
+pub fn build_object_key(ls_root: &str, local_path: &Path) -> String {
+    let root = Path::new(ls_root);
+    let key = local_path
+        .strip_prefix(root)
+        .unwrap_or(local_path)
+        .to_string_lossy()
+        .to_string();
+    key.trim_start_matches('/').to_string()
+}
+
+// Objects under artifacts/ in [cloud] that no manifest row of any dataset or
+// split part points at any more, such as anchors left behind by manual
+// cleanups. The manifests in [cloud] count as well as the local ones, since
+// other machines sharing the bucket push rows this host has not merged yet.
+// They are only listed unless `delete` is set.
+pub async fn sync_gc(ctx: &AppContext, delete: bool) -> Result<()> {
+    let mut referenced: HashSet<String> = ctx
+        .all_records()
+        .context("refusing to collect garbage")?
+        .into_iter()
+        .map(|record| record.object_key)
+        .collect();
+    let client = ctx.storage().await?;
+    referenced.extend(
+        remote_object_keys(ctx, client.as_ref())
+            .await
+            .context("refusing to collect garbage")?,
+    );
+    let orphans: Vec<_> = client
+        .list(Some("artifacts/"))
+        .await?
+        .into_iter()
+        .filter(|object| !referenced.contains(&object.key))
+        .collect();
+    let bytes = format_bytes(orphans.iter().map(|object| object.size).sum());
+    let mut table = Table::new();
+    for object in &orphans {
+        if ctx.json {
+            ctx.emit(&json!({ "key": object.key, "size": object.size }))?;
+        } else {
+            table.row([object.key.clone(), format_bytes(object.size)]);
+        }
+    }
+    table.print();
+    if !delete {
+        if !orphans.is_empty() {
+            ctx.logger.info(format!(
+                "{} unreferenced object(s), {bytes}; rerun with --delete to remove them",
+                orphans.len()
+            ));
+        }
+        return Ok(());
+    }
+    for object in &orphans {
+        if ctx.dry_run {
+            ctx.logger.info(format!("Dry run: would delete {}", object.key));
+            continue;
+        }
+        client.delete(&object.key).await?;
+    }
+    if !ctx.dry_run {
+        ctx.logger.info(format!("Deleted {} unreferenced object(s), {bytes}", orphans.len()));
+    }
+    Ok(())
+}
+
+// The object keys named by every manifest in [cloud]: the dataset manifests
+// and their split parts, whichever machine pushed them.
+async fn remote_object_keys(ctx: &AppContext, client: &dyn StorageBackend) -> Result<Vec<String>> {
+    let tmp_dir = ctx.ls_path("tmp");
+    btrfs::ensure_dir(&tmp_dir)?;
+    let mut keys = Vec::new();
+    for object in client.list(Some("manifests/")).await? {
+        let key = object.key;
+        if !key.ends_with(".tsv") || key == ALIASES_OBJECT_KEY || key == SETS_OBJECT_KEY {
+            continue;
+        }
+        let tmp_path = tmp_dir.join(format!("{}.gc", key.replace('/', "_")));
+        let records = match get_manifest(client, &key, &tmp_path).await {
+            Ok(()) => ManifestStore::new(&tmp_path).read_all_records(),
+            Err(err) => Err(err),
+        };
+        let _ = fs::remove_file(&tmp_path);
+        let records = records.with_context(|| format!("failed to read {key} from the bucket"))?;
+        keys.extend(records.into_iter().map(|record| record.object_key));
+    }
+    Ok(keys)
+}
+
+// A manifest row that does not match [cloud] or the LS.
+struct Mismatch {
+    label: String,
+    problem: &'static str,
+    detail: String,
+}
+
+// Cross-checks the manifest rows of this dataset and its split parts against
+// [cloud] and the LS in both directions. With `repair`, rows whose artifact is
+// already in [cloud] under the key a push would use, at the recorded size, get
+// that object_key and are marked pushed; everything else is only reported.
+pub async fn sync_reconcile(ctx: &AppContext, repair: bool) -> Result<()> {
+    let client = ctx.storage().await?;
+    let remote: HashMap<String, u64> = client
+        .list(Some("artifacts/"))
+        .await?
+        .into_iter()
+        .map(|object| (object.key, object.size))
+        .collect();
+    let mut remaining = 0;
+    let mut repaired = 0;
+    for manifest in std::iter::once(&ctx.manifest).chain(ctx.part_manifests.values()) {
+        if !manifest.path().exists() {
+            continue;
+        }
+        let mut records = manifest.read_records()?;
+        let mut changed = false;
+        for record in &mut records {
+            let (mismatches, key) = reconcile_record(ctx, record, &remote);
+            for mismatch in &mismatches {
+                if ctx.json {
+                    ctx.emit(&json!({
+                        "label": mismatch.label,
+                        "problem": mismatch.problem,
+                        "detail": mismatch.detail,
+                    }))?;
+                } else {
+                    println!("{}\t{}\t{}", mismatch.label, mismatch.problem, mismatch.detail);
+                }
+            }
+            remaining += mismatches.len();
+            let Some(key) = key.filter(|_| repair) else {
+                continue;
+            };
+            remaining -= 1;
+            if ctx.dry_run {
+                ctx.logger.info(format!("Dry run: would record {key} for {}", record.label));
+                continue;
+            }
+            record.object_key = key;
+            record.advance(RecordStatus::Pushed)?;
+            changed = true;
+            repaired += 1;
+        }
+        if changed {
+            manifest.write_records(&records)?;
+        }
+    }
+    if repaired > 0 {
+        ctx.logger.info(format!("Recorded object_key for {repaired} artifact(s)"));
+    }
+    if remaining > 0 {
+        return Err(anyhow!("{remaining} mismatch(es) between the manifest, [cloud] and the LS"));
+    }
+    ctx.logger.info("Manifest, [cloud] and the LS agree");
+    Ok(())
+}
+
+// Also returns the key to record when the row's only problem is an upload the
+// manifest does not know about.
+fn reconcile_record(
+    ctx: &AppContext,
+    record: &ManifestRecord,
+    remote: &HashMap<String, u64>,
+) -> (Vec<Mismatch>, Option<String>) {
+    let mut mismatches = Vec::new();
+    let mut found = |problem, detail| {
+        mismatches.push(Mismatch { label: record.label.clone(), problem, detail })
+    };
+    if !record.local_path.is_empty() {
+        match fs::metadata(&record.local_path) {
+            Err(_) => found("missing local file", record.local_path.clone()),
+            Ok(meta) if meta.len() != record.bytes => {
+                let (path, size) = (&record.local_path, meta.len());
+                let detail = format!("{path} is {size} bytes, manifest says {}", record.bytes);
+                found("size mismatch", detail)
+            }
+            Ok(_) => {}
+        }
+    }
+    let mut unrecorded = None;
+    let key = if record.object_key.is_empty() {
+        if record.local_path.is_empty() || record.status < RecordStatus::Built {
+            return (mismatches, None);
+        }
+        build_object_key(&ctx.config.paths.ls_root, Path::new(&record.local_path))
+    } else {
+        record.object_key.clone()
+    };
+    match remote.get(&key) {
+        None if record.status >= RecordStatus::Pushed => found("missing upload", key),
+        None => {}
+        Some(&size) if size != record.bytes => found(
+            "size mismatch",
+            format!("{key} is {size} bytes in [cloud], manifest says {}", record.bytes),
+        ),
+        Some(_) if record.object_key.is_empty() => {
+            found("unrecorded upload", key.clone());
+            unrecorded = Some(key);
+        }
+        Some(_) => {}
+    }
+    let repairable = mismatches.len() == 1 && unrecorded.is_some();
+    (mismatches, unrecorded.filter(|_| repairable))
+}
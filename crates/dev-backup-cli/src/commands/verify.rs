@@ -0,0 +1,173 @@
+use crate::context::AppContext;
+use crate::label::LabelRange;
+use crate::pipeline::run_decrypt_pipeline;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use dev_backup_core::manifest::{ManifestRecord, ReadMode, RecordStatus};
+use dev_backup_storage::artifact::sha256_file;
+use dev_backup_storage::backend::StorageBackend;
+use std::io;
+use std::path::Path;
+
+// Re-hashes local artifacts against the manifest and, with `cloud`, checks
+// that each uploaded object exists at the recorded size. Object stores do not
+// keep a sha256 of their own, so the cloud side is a size check only. `deep`
+// also decrypts and decodes each local artifact, discarding the output, to
+// catch damage a matching checksum was computed over. Artifacts whose cloud
+// copy checked out are marked `verified` in the manifest. Malformed manifest
+// rows fail the run rather than being skipped.
+pub async fn verify(
+    ctx: &AppContext,
+    label: Option<&str>,
+    range: &LabelRange,
+    part: Option<&str>,
+    cloud: bool,
+    deep: bool,
+) -> Result<()> {
+    let private_key = if deep {
+        let key = ctx.config.crypto.as_ref().and_then(|crypto| crypto.age_private_key_path.clone());
+        Some(key.ok_or_else(|| anyhow!("age_private_key_path is required for verify --deep"))?)
+    } else {
+        None
+    };
+    let mut targets = Vec::new();
+    match label {
+        None if range.is_set() => {
+            let stream = part.unwrap_or(ctx.naming.prefix());
+            let index = ctx.manifest_for(part)?.read_index_with(ReadMode::Strict)?;
+            let labels = range.labels_in(index.records());
+            if labels.is_empty() {
+                return Err(anyhow!("no labels in the manifest from {range}"));
+            }
+            for label in &labels {
+                targets.push((stream.to_string(), index.require(label)?.clone()));
+            }
+        }
+        Some(label) => {
+            let stream = part.unwrap_or(ctx.naming.prefix());
+            let index = ctx.manifest_for(part)?.read_index_with(ReadMode::Strict)?;
+            let resolved = ctx.resolve_label(index.records(), label)?;
+            targets.push((stream.to_string(), index.require(&resolved)?.clone()));
+        }
+        None => {
+            let streams = std::iter::once((ctx.naming.prefix(), &ctx.manifest)).chain(
+                ctx.part_manifests
+                    .iter()
+                    .map(|(part, manifest)| (part.as_str(), manifest)),
+            );
+            for (stream, manifest) in streams {
+                if !manifest.path().exists() {
+                    continue;
+                }
+                let index = manifest.read_index_with(ReadMode::Strict)?;
+                for record in index.records() {
+                    if index.get(&record.label) == Some(record) {
+                        targets.push((stream.to_string(), record.clone()));
+                    }
+                }
+            }
+        }
+    }
+    let client = if cloud { Some(ctx.storage().await?) } else { None };
+
+    let mut failed = Vec::new();
+    let mut verified = Vec::new();
+    for (stream, record) in &targets {
+        let name = format!("{stream}@{}", record.label);
+        let local = verify_local(ctx, record, private_key.as_deref())?;
+        let remote = match &client {
+            Some(client) => verify_cloud(client.as_ref(), record).await?,
+            None => "-".to_string(),
+        };
+        let passed = !local.starts_with("FAIL") && !remote.starts_with("FAIL");
+        if !passed {
+            failed.push(name.clone());
+        } else if client.is_some() && record.status >= RecordStatus::Pushed {
+            verified.push((stream, record));
+        }
+        if ctx.json {
+            ctx.emit(&VerifyLine { stream, label: &record.label, local, cloud: remote, passed })?;
+        } else {
+            let status = if passed { "pass" } else { "FAIL" };
+            ctx.logger.info(format!("{name}\t{local}\t{remote}\t{status}"));
+        }
+    }
+    mark_verified(ctx, &verified)?;
+
+    if !failed.is_empty() {
+        return Err(anyhow!(
+            "{} of {} artifact(s) failed verification: {}",
+            failed.len(),
+            targets.len(),
+            failed.join(", ")
+        ));
+    }
+    ctx.logger
+        .info(format!("Verified {} artifact(s)", targets.len()));
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct VerifyLine<'a> {
+    stream: &'a str,
+    label: &'a str,
+    local: String,
+    cloud: String,
+    passed: bool,
+}
+
+fn mark_verified(ctx: &AppContext, verified: &[(&String, &ManifestRecord)]) -> Result<()> {
+    let mut streams: Vec<&str> = verified.iter().map(|(stream, _)| stream.as_str()).collect();
+    streams.dedup();
+    for stream in streams {
+        let manifest = ctx.manifest_for((stream != ctx.naming.prefix()).then_some(stream))?;
+        let mut records = manifest.read_records()?;
+        let mut changed = false;
+        for record in &mut records {
+            if record.status != RecordStatus::Verified
+                && verified.iter().any(|(name, target)| *name == stream && *target == record)
+            {
+                record.advance(RecordStatus::Verified)?;
+                changed = true;
+            }
+        }
+        if changed {
+            manifest.write_records(&records)?;
+        }
+    }
+    Ok(())
+}
+
+fn verify_local(
+    ctx: &AppContext,
+    record: &ManifestRecord,
+    private_key: Option<&str>,
+) -> Result<String> {
+    if record.local_path.is_empty() || !Path::new(&record.local_path).exists() {
+        return Ok("local absent".to_string());
+    }
+    if sha256_file(&record.local_path)? != record.sha256 {
+        return Ok("FAIL: local sha256 mismatch".to_string());
+    }
+    let Some(private_key) = private_key else {
+        return Ok("local ok".to_string());
+    };
+    let backend = ctx.age_backend();
+    Ok(match run_decrypt_pipeline(&record.local_path, io::sink(), private_key, backend) {
+        Ok(()) => "local ok (decoded)".to_string(),
+        Err(err) => format!("FAIL: local does not decode: {err:#}"),
+    })
+}
+
+async fn verify_cloud(client: &dyn StorageBackend, record: &ManifestRecord) -> Result<String> {
+    if record.status < RecordStatus::Pushed {
+        return Ok("cloud not uploaded".to_string());
+    }
+    Ok(match client.head(&record.object_key).await? {
+        None => "FAIL: cloud object missing".to_string(),
+        Some(object) if object.size != record.bytes => {
+            format!("FAIL: cloud size {} != {}", object.size, record.bytes)
+        }
+        Some(_) => "cloud ok".to_string(),
+    })
+}
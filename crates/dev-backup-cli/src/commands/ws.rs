@@ -0,0 +1,708 @@
+use crate::commands::artifact::{build_artifact_into, register_artifacts, RegisterMode};
+use crate::commands::backup::notify_all;
+use crate::commands::restore::{
+    clear_rebaseline, discard_received, ensure_nothing_kept, pending_rebaseline, replace_worktree,
+};
+use crate::commands::snapshot::{adopt_snapshot, create_snapshot, local_snapshot_labels};
+use crate::commands::sync::{get_manifest, read_aliases, sync_push, PushScope};
+use crate::context::AppContext;
+use crate::format::format_bytes;
+use crate::interrupt;
+use crate::label::{
+    ensure_label, find_latest_local_snapshot_label, latest_label_from_records,
+    resolve_label_input,
+};
+use crate::remote::RemoteTarget;
+use crate::state;
+use crate::watchdog::{Stage, Watchdog};
+use crate::worktree;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_btrfs as btrfs;
+use dev_backup_core::manifest::{
+    records_from_tsv, records_to_tsv, sort_records_by_ts, ManifestRecord, ManifestStore,
+    RecordStatus,
+};
+use dev_backup_core::policy::{decide_snapshot_type, PolicyInput, SnapshotDecision};
+use dev_backup_storage::artifact::{parse_artifact_filename, sha256_file};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+// Beside the snapshots, like .staging: artifacts built by `ws run-month` wait
+// here, each with a built row in queue.tsv, until they reach the LS. While
+// the build runs its label has a single pending row instead.
+const QUEUE_DIR: &str = ".queue";
+
+// Without a label (`--auto`) the label is derived from the current date. The
+// artifacts are queued and then shipped; when the LS cannot be reached they
+// stay queued for `ws flush-queue`, and the run still succeeds.
+pub async fn ws_run_month(
+    ctx: &AppContext,
+    label: Option<&str>,
+    adopt: Option<&str>,
+) -> Result<()> {
+    let label = &label.map_or_else(|| ctx.auto_label(), str::to_string);
+    ctx.ensure_new_label(label)?;
+    ctx.config.access.check("snapshot")?;
+    ctx.config.access.check("artifact.build")?;
+    // A target that does not resolve only fails the flush, which keeps the
+    // artifacts queued.
+    if let Ok(target) = RemoteTarget::resolve(ctx, None, None) {
+        check_flush_permitted(ctx, &target)?;
+    }
+    let queued = read_queue(ctx)?;
+    if queued.iter().any(|record| record.label == *label && record.status == RecordStatus::Built) {
+        ctx.logger.info(format!("{} is already queued", ctx.snapshot_name(label)));
+        return flush_or_keep(ctx).await;
+    }
+    let parent_label = run_month_parent(ctx).await?;
+
+    // Each step is skipped or redone on the next run, so stopping between
+    // them at the deadline is safe.
+    if ctx.deadline_reached() {
+        let name = ctx.snapshot_name(label);
+        ctx.logger.warn(format!("deadline reached before snapshot {name}; rerun to resume"));
+        return Ok(());
+    }
+    if let Some(source) = adopt {
+        adopt_snapshot(ctx, label, source)?;
+    }
+    create_snapshot(ctx, label)?;
+    if ctx.deadline_reached() {
+        ctx.logger.warn(format!(
+            "deadline reached before building artifact {}; rerun to resume",
+            ctx.snapshot_name(label)
+        ));
+        return Ok(());
+    }
+    let queue_dir = queue_dir(ctx);
+    btrfs::ensure_dir(&queue_dir)?;
+    enqueue_pending(ctx, label, parent_label.as_deref())?;
+    let built = match build_artifact_into(ctx, label, parent_label.as_deref(), &queue_dir) {
+        Ok(built) => built,
+        Err(err) => {
+            drop_queued(ctx, label)?;
+            return Err(err);
+        }
+    };
+    enqueue(ctx, label, &built)?;
+    clear_rebaseline(ctx)?;
+
+    match parent_label {
+        Some(parent) => ctx
+            .logger
+            .info(format!("Run-month complete: incremental from {parent}")),
+        None => ctx.logger.info("Run-month complete: anchor"),
+    }
+    flush_or_keep(ctx).await
+}
+
+async fn flush_or_keep(ctx: &AppContext) -> Result<()> {
+    if let Err(err) = ws_flush_queue(ctx, None, None).await {
+        ctx.logger.warn(format!(
+            "{err:#}; the artifacts stay queued, run `dev-backup ws flush-queue` once the LS \
+             is reachable"
+        ));
+    }
+    Ok(())
+}
+
+// Ships the queued artifacts to the LS one label at a time, oldest first, so
+// an incremental never lands before its parent. Each label is registered
+// there and dropped from the queue before the next one goes; the first
+// failure stops the flush and leaves it and everything after it queued. A
+// `sync push` follows once the queue is empty.
+pub async fn ws_flush_queue(
+    ctx: &AppContext,
+    ls_host: Option<String>,
+    ls_user: Option<String>,
+) -> Result<()> {
+    let mut remaining = sort_records_by_ts(read_queue(ctx)?);
+    if remaining.is_empty() {
+        ctx.logger.info("Nothing queued");
+        return Ok(());
+    }
+    let target = RemoteTarget::resolve(ctx, ls_host, ls_user)?;
+    check_flush_permitted(ctx, &target)?;
+    while let Some(label) = remaining.first().map(|record| record.label.clone()) {
+        let (batch, rest): (Vec<_>, Vec<_>) =
+            remaining.into_iter().partition(|record| record.label == label);
+        if batch.iter().any(|record| record.status == RecordStatus::Pending) {
+            return Err(anyhow!(
+                "the build of {} did not finish; run `dev-backup ws run-month {label}` again",
+                ctx.snapshot_name(&label)
+            ));
+        }
+        let mut paths = Vec::new();
+        for record in &batch {
+            let sha256 = sha256_file(&record.local_path)?;
+            if sha256 != record.sha256 {
+                return Err(anyhow!(
+                    "queued artifact {} changed since it was built (sha256 {sha256}, expected {})",
+                    record.local_path,
+                    record.sha256
+                ));
+            }
+            paths.push(PathBuf::from(&record.local_path));
+        }
+        if target.is_local() {
+            let paths: Vec<&str> =
+                paths.iter().map(|path| path.to_str().unwrap_or_default()).collect();
+            register_artifacts(ctx, &paths, RegisterMode::Move)?;
+        } else {
+            ship_to_ls(ctx, &target, &paths)?;
+            for path in &paths {
+                fs::remove_file(path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+            }
+        }
+        write_queue(ctx, &rest)?;
+        ctx.logger.info(format!("Shipped {} to the LS", ctx.snapshot_name(&label)));
+        remaining = rest;
+    }
+
+    if target.is_local() {
+        if ctx.config.cloud.is_none() {
+            ctx.logger.info("Push: skipped, no [cloud] configured");
+            return Ok(());
+        }
+        return sync_push(ctx, PushScope::All).await;
+    }
+    let status = target
+        .remote_command(&remote_args(ctx, &["sync", "push"]))
+        .status()
+        .context("failed to run remote sync push")?;
+    if !status.success() {
+        return Err(anyhow!(
+            "remote sync push failed ({status}); the artifacts are registered on the LS, \
+             run `dev-backup sync push` there"
+        ));
+    }
+    Ok(())
+}
+
+// A flush into a local LS registers and pushes here, so [access] on this
+// host must allow both; a remote LS checks its own.
+fn check_flush_permitted(ctx: &AppContext, target: &RemoteTarget) -> Result<()> {
+    if !target.is_local() {
+        return Ok(());
+    }
+    ctx.config.access.check("artifact.register")?;
+    if ctx.config.cloud.is_some() {
+        ctx.config.access.check("sync.push")?;
+    }
+    Ok(())
+}
+
+fn queue_dir(ctx: &AppContext) -> PathBuf {
+    Path::new(&ctx.config.paths.snapshots).join(QUEUE_DIR)
+}
+
+// queue.tsv has the manifest's layout, and is kept through the state module
+// like the rest of the WS-local state.
+fn queue_path(ctx: &AppContext) -> PathBuf {
+    queue_dir(ctx).join("queue.tsv")
+}
+
+fn read_queue(ctx: &AppContext) -> Result<Vec<ManifestRecord>> {
+    let path = queue_path(ctx);
+    match state::read(ctx, &path)? {
+        Some(contents) => records_from_tsv(&contents)
+            .with_context(|| format!("invalid queue: {}", path.display())),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn write_queue(ctx: &AppContext, records: &[ManifestRecord]) -> Result<()> {
+    state::write(ctx, &queue_path(ctx), &records_to_tsv(records)?)
+}
+
+// Marks `label` as being built, replacing whatever an earlier, interrupted
+// build of it left queued.
+fn enqueue_pending(ctx: &AppContext, label: &str, parent: Option<&str>) -> Result<()> {
+    let record = ManifestRecord {
+        ts: ctx.clock.now(),
+        label: label.to_string(),
+        record_type: if parent.is_some() { "incremental" } else { "anchor" }.to_string(),
+        parent: parent.unwrap_or_default().to_string(),
+        bytes: 0,
+        sha256: String::new(),
+        local_path: String::new(),
+        object_key: String::new(),
+        mirror_keys: String::new(),
+        status: RecordStatus::Pending,
+        machine: ctx.naming.machine().unwrap_or_default().to_string(),
+    };
+    record.validate().context("refusing to queue artifact")?;
+    let mut queued = read_queue(ctx)?;
+    queued.retain(|queued| queued.label != label);
+    queued.push(record);
+    write_queue(ctx, &queued)
+}
+
+fn drop_queued(ctx: &AppContext, label: &str) -> Result<()> {
+    let mut queued = read_queue(ctx)?;
+    queued.retain(|record| record.label != label);
+    write_queue(ctx, &queued)
+}
+
+// Replaces the pending row of `label` with what `artifact register` will be
+// given, so the next run can plan its parent from the queue while the LS is
+// out of reach.
+fn enqueue(ctx: &AppContext, label: &str, built: &[PathBuf]) -> Result<()> {
+    let mut records = Vec::new();
+    for path in built {
+        let local_path = fs::canonicalize(path)
+            .with_context(|| format!("artifact missing: {}", path.display()))?;
+        let filename = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let info = parse_artifact_filename(&ctx.naming, filename)
+            .ok_or_else(|| anyhow!("invalid artifact name: {filename}"))?;
+        let local_path = local_path.to_string_lossy().to_string();
+        records.push(ManifestRecord {
+            ts: ctx.clock.now(),
+            label: info.label,
+            record_type: if info.parent.is_some() { "incremental" } else { "anchor" }.to_string(),
+            parent: info.parent.unwrap_or_default(),
+            bytes: fs::metadata(&local_path)?.len(),
+            sha256: sha256_file(&local_path)?,
+            local_path,
+            object_key: String::new(),
+            mirror_keys: String::new(),
+            status: RecordStatus::Built,
+            machine: info.machine.unwrap_or_default(),
+        });
+    }
+    for record in &records {
+        record.validate().context("refusing to queue artifact")?;
+    }
+    let mut queued = read_queue(ctx)?;
+    queued.retain(|record| record.label != label);
+    queued.extend(records);
+    write_queue(ctx, &queued)
+}
+
+// The built rows of the dataset's own stream, as the manifest will hold them.
+fn queued_records(ctx: &AppContext) -> Result<Vec<ManifestRecord>> {
+    let records = read_queue(ctx)?;
+    Ok(records
+        .into_iter()
+        .filter(|record| record.status == RecordStatus::Built)
+        .filter(|record| {
+            let filename = Path::new(&record.local_path).file_name().and_then(|name| name.to_str());
+            filename
+                .and_then(|name| parse_artifact_filename(&ctx.naming, name))
+                .is_some_and(|info| info.stream == ctx.naming.prefix())
+        })
+        .collect())
+}
+
+// Copies the artifacts into the LS's tmp directory over ssh and registers
+// them there in one batch.
+fn ship_to_ls(ctx: &AppContext, target: &RemoteTarget, paths: &[PathBuf]) -> Result<()> {
+    let tmp_dir = Path::new(&ctx.config.paths.ls_root).join("tmp");
+    let mut remote_paths = Vec::new();
+    for path in paths {
+        let name = path.file_name().unwrap_or_default();
+        let dest = tmp_dir.join(name).to_string_lossy().to_string();
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        let status = target
+            .remote_command(&["sh", "-c", "mkdir -p \"${1%/*}\" && cat > \"$1\"", "sh", &dest])
+            .stdin(Stdio::from(file))
+            .status()
+            .context("failed to run ssh")?;
+        if !status.success() {
+            return Err(anyhow!("copying {} to the LS failed ({status})", path.display()));
+        }
+        remote_paths.push(dest);
+    }
+    let mut args = vec!["artifact", "register"];
+    args.extend(remote_paths.iter().map(String::as_str));
+    let status = target
+        .remote_command(&remote_args(ctx, &args))
+        .status()
+        .context("failed to run remote artifact register")?;
+    if !status.success() {
+        return Err(anyhow!("remote artifact register failed ({status})"));
+    }
+    Ok(())
+}
+
+// `dev-backup` on the LS with its system config and this dataset. Its stdout
+// is ours, so under --json it answers in JSON as well.
+fn remote_args<'a>(ctx: &'a AppContext, args: &[&'a str]) -> Vec<&'a str> {
+    let mut all = vec!["dev-backup", "--config", "/etc/dev-backup/config.toml"];
+    if let Some(dataset) = &ctx.dataset {
+        all.extend(["--dataset", dataset.as_str()]);
+    }
+    if ctx.json {
+        all.push("--json");
+    }
+    all.extend(args);
+    all
+}
+
+// Frees space on the snapshots filesystem once it is below
+// disk.critical_free_mib by deleting the oldest local snapshots. The newest
+// local snapshot and the manifest's latest label are kept, since the next
+// incremental is sent from one of them. [notify] hears about every prune.
+pub async fn ws_maintain(ctx: &AppContext) -> Result<()> {
+    let critical = ctx
+        .config
+        .disk
+        .as_ref()
+        .and_then(|disk| disk.critical_free_mib)
+        .ok_or_else(|| anyhow!("disk.critical_free_mib must be set for ws maintain"))?;
+    let critical = critical * 1024 * 1024;
+    let snapshots = &ctx.config.paths.snapshots;
+    let mut free = btrfs::free_bytes(snapshots)?;
+    if free >= critical {
+        ctx.logger.info(format!("{} free on {snapshots}; nothing to prune", format_bytes(free)));
+        return Ok(());
+    }
+    ctx.logger.warn(format!(
+        "only {} free on {snapshots}, below disk.critical_free_mib; pruning old snapshots",
+        format_bytes(free)
+    ));
+
+    let labels = local_snapshot_labels(ctx)?;
+    let mut keep: Vec<String> = labels.last().cloned().into_iter().collect();
+    let records = fetch_manifest_records_for_ws(ctx)
+        .await
+        .context("the manifest is needed to tell which snapshot is the next parent")?;
+    if !records.is_empty() {
+        keep.push(latest_label_from_records(&records)?);
+    }
+    keep.extend(queued_records(ctx)?.into_iter().map(|record| record.label));
+
+    let mut pruned = Vec::new();
+    for label in labels.iter().filter(|label| !keep.contains(label)) {
+        if free >= critical {
+            break;
+        }
+        let streams = std::iter::once(ctx.naming.prefix())
+            .chain(ctx.config.split.parts.iter().map(String::as_str));
+        for stream in streams {
+            let path = ctx.stream_snapshot_path(stream, label);
+            if Path::new(&path).exists() {
+                ctx.perform(format!("delete snapshot {path}"), || {
+                    ctx.btrfs().subvolume_delete(&path)
+                })?;
+            }
+        }
+        pruned.push(label.as_str());
+        if !ctx.dry_run {
+            // btrfs frees a deleted subvolume's extents in the background.
+            btrfs::subvolume_sync(snapshots)?;
+            free = btrfs::free_bytes(snapshots)?;
+        }
+    }
+    if ctx.dry_run {
+        return Ok(());
+    }
+
+    let ok = free >= critical;
+    let summary = format!(
+        "Pruned {} snapshot(s) on {snapshots}: {}; {} free",
+        pruned.len(),
+        if pruned.is_empty() { "-".to_string() } else { pruned.join(", ") },
+        format_bytes(free)
+    );
+    ctx.logger.info(&summary);
+    let error = if ok {
+        String::new()
+    } else {
+        "still below disk.critical_free_mib; only parent snapshots are left".to_string()
+    };
+    let labels = pruned.join(",");
+    let bytes = format_bytes(free);
+    let values = [
+        ("label", labels.as_str()),
+        ("dataset", ctx.config.paths.dataset.as_str()),
+        ("bytes", bytes.as_str()),
+        ("outcome", if ok { "ok" } else { "failed" }),
+        ("error", error.as_str()),
+        ("summary", summary.as_str()),
+    ];
+    notify_all(ctx, ok, &values);
+    if !ok {
+        return Err(anyhow!("only {bytes} free on {snapshots} after pruning: {error}"));
+    }
+    Ok(())
+}
+
+pub async fn ws_request(
+    ctx: &AppContext,
+    label: &str,
+    parent: Option<&str>,
+    auto_parent: bool,
+    ls_host: Option<String>,
+    ls_user: Option<String>,
+    force: bool,
+) -> Result<()> {
+    let cfg = &ctx.config;
+    let resolved_label = resolve_label_for_ws_request(ctx, label).await?;
+    // Checked before anything is received, so a refusal costs nothing and
+    // never leaves a received snapshot that no rerun would apply.
+    worktree::ensure_clean(ctx, force)?;
+    ensure_nothing_kept(&cfg.paths.dataset)?;
+    let auto_parent = auto_parent || parent == Some("latest");
+    let mut parent_label = parent.filter(|_| !auto_parent).map(|value| value.to_string());
+    if let Some(ref label) = parent_label {
+        ensure_label(label)?;
+    } else if auto_parent {
+        parent_label = find_latest_local_snapshot_label(
+            &ctx.naming,
+            &cfg.paths.snapshots,
+            &resolved_label,
+        )?;
+    }
+
+    btrfs::ensure_dir(Path::new(&cfg.paths.snapshots))?;
+    let target = RemoteTarget::resolve(ctx, ls_host, ls_user)?;
+
+    let snapshot_path = ctx.snapshot_path(&resolved_label);
+    if Path::new(&snapshot_path).exists() {
+        return Err(anyhow!("snapshot already exists: {snapshot_path}"));
+    }
+
+    // Receive next to the real snapshots but out of their namespace, so a
+    // failed or interrupted receive never leaves a half-written dev@ subvolume
+    // where restore and --auto-parent would pick it up.
+    // Each request receives into its own directory there, so a stream that
+    // names some other subvolume is still found and discarded on failure.
+    let name = ctx.naming.name(ctx.naming.prefix(), &resolved_label);
+    let receive_dir = Path::new(&cfg.paths.snapshots).join(".staging").join(&name);
+    discard_staging(ctx, &receive_dir)?;
+    btrfs::ensure_dir(&receive_dir)?;
+    let staged = receive_dir.join(&name);
+    let _receiving = interrupt::received_subvolume(&staged);
+
+    let received = receive_from_ls(
+        ctx,
+        &target,
+        &resolved_label,
+        parent_label.as_deref(),
+        &receive_dir,
+    )
+    .and_then(|()| verify_staged(ctx, &staged));
+    if let Err(err) = received {
+        if let Err(cleanup) = discard_staging(ctx, &receive_dir) {
+            ctx.logger.warn(format!("failed to clean up staging: {cleanup:#}"));
+        }
+        return Err(err);
+    }
+    fs::rename(&staged, &snapshot_path)
+        .with_context(|| format!("failed to promote {} to {snapshot_path}", staged.display()))?;
+    discard_staging(ctx, &receive_dir)?;
+
+    replace_worktree(ctx, &snapshot_path, &resolved_label)
+}
+
+fn receive_from_ls(
+    ctx: &AppContext,
+    target: &RemoteTarget,
+    label: &str,
+    parent: Option<&str>,
+    dest_dir: &Path,
+) -> Result<()> {
+    let mut send_child = if target.is_local() {
+        spawn_local_ls_send(ctx, label, parent)?
+    } else {
+        spawn_remote_ls_send(ctx, target, label, parent)?
+    };
+
+    let send_stdout = send_child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("failed to capture ls send stdout"))?;
+
+    // The stream is copied through here rather than handed to receive, so
+    // the watchdog sees it move and can tell which side stalled.
+    let watchdog = Watchdog::start();
+    let send = watchdog.add(Stage::Send, "ls send", send_child);
+    let mut recv_child = Command::new("btrfs")
+        .arg("receive")
+        .arg(dest_dir)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context("failed to start btrfs receive")?;
+    let recv_stdin = recv_child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("failed to capture btrfs receive stdin"))?;
+    let recv = watchdog.add(Stage::Receive, "btrfs receive", recv_child);
+
+    let mut reader = watchdog.reader("ls send", send_stdout);
+    let mut writer = watchdog.writer("btrfs receive", recv_stdin);
+    let copied = io::copy(&mut reader, &mut writer);
+    drop(writer);
+    drop(reader);
+    let recv_status = watchdog.wait(recv)?;
+    let send_status = watchdog.wait(send)?;
+
+    watchdog.check()?;
+    if !send_status.success() {
+        return Err(anyhow!("ls send failed"));
+    }
+    if !recv_status.success() {
+        return Err(anyhow!("btrfs receive failed"));
+    }
+    copied.context("failed to pass the stream to btrfs receive")?;
+    Ok(())
+}
+
+fn discard_staging(ctx: &AppContext, dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("failed to read directory: {}", dir.display()))?
+    {
+        discard_received(ctx, &entry?.path())?;
+    }
+    fs::remove_dir(dir).with_context(|| format!("failed to remove {}", dir.display()))
+}
+
+fn verify_staged(ctx: &AppContext, staged: &Path) -> Result<()> {
+    let path = staged.to_str().unwrap_or_default();
+    if !staged.exists() || !ctx.btrfs().subvolume_exists(path)? {
+        return Err(anyhow!("received snapshot missing: {}", staged.display()));
+    }
+    Ok(())
+}
+
+async fn resolve_label_for_ws_request(ctx: &AppContext, label: &str) -> Result<String> {
+    if label != "latest" {
+        let client = match &ctx.config.cloud {
+            Some(_) => Some(ctx.storage().await?),
+            None => None,
+        };
+        let aliases = read_aliases(ctx, client.as_deref()).await?;
+        return resolve_label_input(&[], &aliases, label);
+    }
+    let records = fetch_manifest_records_for_ws(ctx).await?;
+    if records.is_empty() {
+        return Err(anyhow!("manifest unavailable to resolve latest label"));
+    }
+    latest_label_from_records(&records)
+}
+
+// Overrides the program run for a local `ls send`, e.g. with a stub that
+// writes a canned stream in tests.
+pub const LS_SEND_PROGRAM_ENV: &str = "DEV_BACKUP_LS_SEND";
+
+fn spawn_local_ls_send(ctx: &AppContext, label: &str, parent: Option<&str>) -> Result<Child> {
+    let program = std::env::var(LS_SEND_PROGRAM_ENV).unwrap_or_else(|_| "dev-backup".to_string());
+    let mut cmd = Command::new(&program);
+    cmd.args(["--config", &ctx.config_path]);
+    if let Some(dataset) = &ctx.dataset {
+        cmd.args(["--dataset", dataset]);
+    }
+    cmd.args(["ls", "send", label]);
+    if let Some(parent_label) = parent {
+        cmd.arg(parent_label);
+    }
+    let child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("failed to spawn local ls send ({program})"))?;
+    Ok(child)
+}
+
+fn spawn_remote_ls_send(
+    ctx: &AppContext,
+    target: &RemoteTarget,
+    label: &str,
+    parent: Option<&str>,
+) -> Result<Child> {
+    ensure_label(label)?;
+    let mut args = remote_args(ctx, &["ls", "send", label]);
+    if let Some(parent_label) = parent {
+        ensure_label(parent_label)?;
+        args.push(parent_label);
+    }
+    let child = target
+        .remote_command(&args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context("failed to spawn remote ls send")?;
+    Ok(child)
+}
+
+// The parent of the next `ws run-month`: the latest label of the manifest and
+// the queue, or None when the policy calls for an anchor. Without the
+// manifest, a queued chain is continued from its newest label.
+// An anchor when a restore rewrote the working tree to anything but the
+// planned parent, or when the planned parent has no local snapshot to send
+// from; otherwise the planned parent.
+async fn run_month_parent(ctx: &AppContext) -> Result<Option<String>> {
+    let parent = planned_parent(ctx).await?;
+    if let Some(restored) = pending_rebaseline(ctx).filter(|_| parent.is_some()) {
+        if parent.as_deref() != Some(restored.as_str()) {
+            ctx.logger.info(format!(
+                "The working tree was restored to {}; building an anchor",
+                ctx.snapshot_name(&restored)
+            ));
+            return Ok(None);
+        }
+    }
+    if let Some(missing) = parent
+        .as_deref()
+        .filter(|parent| !Path::new(&ctx.snapshot_path(parent)).exists())
+    {
+        ctx.logger.warn(format!(
+            "parent snapshot {} is gone; building an anchor",
+            ctx.snapshot_name(missing)
+        ));
+        return Ok(None);
+    }
+    Ok(parent)
+}
+
+pub async fn planned_parent(ctx: &AppContext) -> Result<Option<String>> {
+    let queued = sort_records_by_ts(queued_records(ctx)?);
+    let mut records = match fetch_manifest_records_for_ws(ctx).await {
+        Ok(records) => records,
+        Err(err) if !queued.is_empty() => {
+            ctx.logger.warn(format!(
+                "manifest unavailable ({err:#}); continuing the queued chain"
+            ));
+            return Ok(queued.last().map(|record| record.label.clone()));
+        }
+        Err(err) => return Err(err),
+    };
+    records.extend(queued);
+    let records = sort_records_by_ts(records);
+    if records.is_empty() {
+        return Ok(None);
+    }
+    match decide_snapshot_type(&records, PolicyInput::at(ctx.clock.as_ref()))? {
+        SnapshotDecision::Anchor => Ok(None),
+        SnapshotDecision::Incremental => Ok(Some(latest_label_from_records(&records)?)),
+    }
+}
+
+pub async fn fetch_manifest_records_for_ws(ctx: &AppContext) -> Result<Vec<ManifestRecord>> {
+    if ctx.manifest.path().exists() {
+        return ctx.manifest.read_records();
+    }
+
+    if ctx.config.cloud.is_none() {
+        return Ok(Vec::new());
+    }
+    let client = ctx.storage().await?;
+
+    let tmp_path = std::env::temp_dir().join(format!(
+        "dev-backup-manifest-{}.tsv",
+        ctx.clock.now().unix_timestamp()
+    ));
+    get_manifest(client.as_ref(), &ctx.manifest_key(), &tmp_path).await?;
+
+    let store = ManifestStore::new(&tmp_path).with_machine(ctx.naming.machine());
+    store.read_records()
+}
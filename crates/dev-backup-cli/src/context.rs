@@ -0,0 +1,411 @@
+use anyhow::{anyhow, Context, Result};
+use dev_backup_core::alias::AliasStore;
+use dev_backup_core::clock::{Clock, SystemClock};
+use dev_backup_btrfs as btrfs;
+use dev_backup_core::config::{
+    AgeBackend, BtrfsBackend, Cloud, CloudBackend, Config, ManifestBackend, SftpUrl,
+};
+use dev_backup_core::deadline::Deadline;
+use dev_backup_core::manifest::{ManifestRecord, ManifestStore};
+use dev_backup_core::naming::NameTemplate;
+use dev_backup_core::sets::SnapshotSetStore;
+use dev_backup_storage::backend::StorageBackend;
+#[cfg(feature = "cloud")]
+use dev_backup_storage::cloud::{R2Client, R2Config};
+use dev_backup_storage::http::{HttpBackend, HttpConfig};
+use dev_backup_storage::local::LocalBackend;
+use dev_backup_storage::sftp::{SftpBackend, SftpConfig};
+use crate::label::resolve_label_input;
+use crate::remote::multiplex_options;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const MANIFEST_OBJECT_KEY: &str = "manifests/snapshots_v2.tsv";
+pub const ALIASES_OBJECT_KEY: &str = "manifests/aliases.tsv";
+pub const SETS_OBJECT_KEY: &str = "manifests/sets.tsv";
+pub const MANIFEST_SQLITE_PATH: &str = "manifests/snapshots_v2.sqlite";
+// Where manifests lived before snapshots_v2; `manifest migrate` upgrades it.
+pub const LEGACY_MANIFEST_KEY: &str = "manifests/snapshots_v1.tsv";
+
+// Uploaded beside every manifest: its sha256 in `sha256sum` format, so a
+// truncated or corrupted download is caught before its rows are trusted.
+pub fn manifest_checksum_key(key: &str) -> String {
+    format!("{key}.sha256")
+}
+
+pub fn part_manifest_key(part: &str) -> String {
+    format!("manifests/parts/{part}.tsv")
+}
+
+pub fn dataset_manifest_key(dataset: &str) -> String {
+    format!("manifests/datasets/{dataset}.tsv")
+}
+
+// Where the SQLite copy of a manifest lives, relative to the LS root, whether
+// or not [manifest] uses it yet.
+pub fn manifest_sqlite_key(dataset: Option<&str>) -> String {
+    match dataset {
+        Some(name) => format!("manifests/datasets/{name}.sqlite"),
+        None => MANIFEST_SQLITE_PATH.to_string(),
+    }
+}
+
+pub struct AppContext {
+    pub config_path: String,
+    pub config: Config,
+    // The selected [[dataset]], or None for a config with a single paths.dataset.
+    pub dataset: Option<String>,
+    pub naming: NameTemplate,
+    pub manifest: ManifestStore,
+    pub part_manifests: BTreeMap<String, ManifestStore>,
+    pub aliases: AliasStore,
+    pub sets: SnapshotSetStore,
+    pub logger: Logger,
+    pub clock: Arc<dyn Clock>,
+    pub deadline: Option<Deadline>,
+    pub dry_run: bool,
+    pub json: bool,
+}
+
+impl AppContext {
+    pub fn load(config_path: &str) -> Result<Self> {
+        let config =
+            Config::load(config_path).with_context(|| format!("config required at {config_path}"))?;
+        config.manifest.validate()?;
+        Self::new(config_path, config)
+    }
+
+    // With [[dataset]] entries the first one is selected until `with_dataset`
+    // picks another.
+    pub fn new(config_path: &str, config: Config) -> Result<Self> {
+        let first = config.datasets.first().map(|dataset| dataset.name.clone());
+        Self::build(config_path, config, first)
+    }
+
+    // A context for another [[dataset]], keeping the clock, deadline and flags.
+    pub fn with_dataset(&self, name: &str) -> Result<Self> {
+        let ctx = Self::build(&self.config_path, self.config.clone(), Some(name.to_string()))?;
+        Ok(self.carry_over(ctx))
+    }
+
+    // A context that reads (and names artifacts) as workstation `machine`,
+    // for `--machine` on an LS or bucket that several of them share.
+    pub fn with_machine(&self, machine: &str) -> Result<Self> {
+        let mut config = self.config.clone();
+        config.naming.machine_id = Some(machine.to_string());
+        let ctx = Self::build(&self.config_path, config, self.dataset.clone())?;
+        Ok(self.carry_over(ctx))
+    }
+
+    fn carry_over(&self, ctx: Self) -> Self {
+        Self {
+            clock: self.clock.clone(),
+            deadline: self.deadline,
+            dry_run: self.dry_run,
+            json: self.json,
+            ..ctx
+        }
+    }
+
+    fn build(config_path: &str, mut config: Config, dataset: Option<String>) -> Result<Self> {
+        config.validate_datasets()?;
+        if let Some(name) = &dataset {
+            config.select_dataset(name)?;
+        }
+        let naming = config.naming.template()?;
+        config.split.validate(naming.prefix())?;
+        let ls_root = Path::new(&config.paths.ls_root);
+        let manifest_path = match &dataset {
+            Some(name) => dataset_manifest_key(name),
+            None => MANIFEST_OBJECT_KEY.to_string(),
+        };
+        let sqlite_path = manifest_sqlite_key(dataset.as_deref());
+        let machine = naming.machine();
+        let mut manifest = ManifestStore::new(ls_root.join(manifest_path)).with_machine(machine);
+        let stores = config.manifest;
+        if stores.primary == ManifestBackend::Sqlite || stores.replica.is_some() {
+            manifest = manifest.with_sqlite(ls_root.join(sqlite_path), stores.primary);
+        }
+        let part_manifests = config
+            .split
+            .parts
+            .iter()
+            .map(|part| {
+                let store = ManifestStore::new(ls_root.join(part_manifest_key(part)));
+                (part.clone(), store.with_machine(machine))
+            })
+            .collect();
+        let aliases = AliasStore::new(Path::new(&config.paths.ls_root).join(ALIASES_OBJECT_KEY));
+        let sets = SnapshotSetStore::new(ls_root.join(SETS_OBJECT_KEY));
+        Ok(Self {
+            config_path: config_path.to_string(),
+            config,
+            dataset,
+            naming,
+            manifest,
+            part_manifests,
+            aliases,
+            sets,
+            logger: Logger,
+            clock: Arc::new(SystemClock),
+            deadline: None,
+            dry_run: false,
+            json: false,
+        })
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Deadline) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    // Under --json every stdout line is a JSON object: commands emit their
+    // results, and log messages become `{"level": ..., "message": ...}`.
+    pub fn with_json(mut self) -> Self {
+        self.json = true;
+        self
+    }
+
+    pub fn emit(&self, value: &impl Serialize) -> Result<()> {
+        println!("{}", serde_json::to_string(value).context("failed to encode JSON output")?);
+        Ok(())
+    }
+
+    // Runs `action`, or under --dry-run only says that it would have.
+    pub fn perform(
+        &self,
+        what: impl AsRef<str>,
+        action: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        if self.dry_run {
+            self.logger.info(format!("Dry run: would {}", what.as_ref()));
+            return Ok(());
+        }
+        action()
+    }
+
+    pub fn deadline_reached(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| deadline.reached(self.clock.now()))
+    }
+
+    pub fn resolve_label(&self, records: &[ManifestRecord], label: &str) -> Result<String> {
+        let aliases = self.aliases.read()?;
+        resolve_label_input(records, &aliases, label)
+    }
+
+    pub fn age_backend(&self) -> AgeBackend {
+        self.config
+            .crypto
+            .as_ref()
+            .map(|crypto| crypto.age_backend)
+            .unwrap_or_default()
+    }
+
+    pub fn btrfs(&self) -> btrfs::Btrfs {
+        btrfs::Btrfs::new(match self.config.btrfs.backend {
+            BtrfsBackend::Cli => btrfs::Backend::Cli,
+            BtrfsBackend::Native => btrfs::Backend::Native,
+        })
+    }
+
+    // Object key of the manifest, also its path relative to the LS root.
+    pub fn manifest_key(&self) -> String {
+        self.dataset.as_deref().map_or(MANIFEST_OBJECT_KEY.to_string(), dataset_manifest_key)
+    }
+
+    pub fn ls_path(&self, relative: &str) -> PathBuf {
+        Path::new(&self.config.paths.ls_root).join(relative)
+    }
+
+    // paths.artifact_staging, or the LS tmp directory.
+    pub fn artifact_staging_dir(&self) -> PathBuf {
+        match &self.config.paths.artifact_staging {
+            Some(staging) => PathBuf::from(staging),
+            None => self.ls_path("tmp"),
+        }
+    }
+
+    // `part` is None for the dataset itself, or one of split.parts.
+    pub fn manifest_for(&self, part: Option<&str>) -> Result<&ManifestStore> {
+        match part {
+            None => Ok(&self.manifest),
+            Some(part) => self
+                .part_manifests
+                .get(part)
+                .ok_or_else(|| anyhow!("{part:?} is not listed in split.parts")),
+        }
+    }
+
+    // The rows of every [[dataset]]'s manifest (or the single dataset's) and
+    // of their split parts, for commands that look at the whole LS or bucket.
+    // Rows of every machine count, including ones written before machine ids,
+    // whatever naming.machine_id says. A dataset without a manifest is an
+    // error: all of its artifacts would look unreferenced.
+    pub fn all_records(&self) -> Result<Vec<ManifestRecord>> {
+        let datasets = self
+            .config
+            .datasets
+            .iter()
+            .map(|dataset| self.with_dataset(&dataset.name))
+            .collect::<Result<Vec<_>>>()?;
+        let members: Vec<&AppContext> =
+            if datasets.is_empty() { vec![self] } else { datasets.iter().collect() };
+        let mut records = Vec::new();
+        for member in members {
+            if !member.manifest.path().exists() {
+                return Err(anyhow!("no manifest at {}", member.manifest.path().display()));
+            }
+            let parts =
+                member.config.split.parts.iter().map(|part| member.manifest_for(Some(part)));
+            for manifest in std::iter::once(Ok(&member.manifest)).chain(parts) {
+                let manifest = manifest?;
+                if manifest.path().exists() {
+                    records.extend(manifest.read_all_records()?);
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    pub fn part_dataset_path(&self, part: &str) -> String {
+        format!("{}/{}", self.config.paths.dataset, part)
+    }
+
+    // New labels (snapshots, artifacts, backup runs) follow naming.label_scheme;
+    // ones that already exist only have to be valid under some scheme.
+    pub fn ensure_new_label(&self, label: &str) -> Result<()> {
+        let scheme = self.config.naming.label_scheme;
+        if !scheme.accepts(label) {
+            return Err(anyhow!("label must be {}", scheme.format()));
+        }
+        Ok(())
+    }
+
+    // The label for now under naming.label_scheme, for `--auto` and
+    // `backup-now` without a label.
+    pub fn auto_label(&self) -> String {
+        self.config.naming.label_scheme.label_at(self.clock.now())
+    }
+
+    // `dev@2024-01` under the default naming.
+    pub fn snapshot_name(&self, label: &str) -> String {
+        self.naming.name(self.naming.prefix(), label)
+    }
+
+    pub fn snapshot_path(&self, label: &str) -> String {
+        self.stream_snapshot_path(self.naming.prefix(), label)
+    }
+
+    pub fn stream_snapshot_path(&self, stream: &str, label: &str) -> String {
+        format!("{}/{}", self.config.paths.snapshots, self.naming.name(stream, label))
+    }
+
+    pub fn restore_snapshot_dir(&self) -> String {
+        format!("{}/restore/snapshots", self.config.paths.ls_root)
+    }
+
+    pub fn restore_snapshot_path(&self, label: &str) -> String {
+        self.stream_restore_snapshot_path(self.naming.prefix(), label)
+    }
+
+    pub fn stream_restore_snapshot_path(&self, stream: &str, label: &str) -> String {
+        format!("{}/{}", self.restore_snapshot_dir(), self.naming.name(stream, label))
+    }
+
+    pub async fn storage(&self) -> Result<Box<dyn StorageBackend>> {
+        let cloud = self
+            .config
+            .cloud
+            .as_ref()
+            .ok_or_else(|| anyhow!("cloud config is required"))?;
+        self.storage_for(cloud).await
+    }
+
+    pub async fn storage_for(&self, cloud: &Cloud) -> Result<Box<dyn StorageBackend>> {
+        cloud.validate()?;
+        match cloud.backend {
+            CloudBackend::R2 => {}
+            CloudBackend::Local => {
+                let root = cloud.local_root.as_deref().unwrap_or_default();
+                return Ok(Box::new(LocalBackend::new(root)));
+            }
+            CloudBackend::Sftp => {
+                let url = SftpUrl::parse(cloud.url.as_deref().unwrap_or_default())?;
+                let mut ssh_options = match self.config.remote.as_ref() {
+                    Some(remote) if !remote.ssh_multiplex => Vec::new(),
+                    Some(remote) => multiplex_options(remote.control_persist_seconds),
+                    None => multiplex_options(60),
+                };
+                ssh_options.extend(cloud.ssh_options.iter().cloned());
+                return Ok(Box::new(SftpBackend::new(SftpConfig {
+                    user: url.user,
+                    host: url.host,
+                    port: url.port,
+                    root: url.path,
+                    ssh_options,
+                })));
+            }
+            CloudBackend::Http => {
+                return Ok(Box::new(HttpBackend::new(HttpConfig {
+                    url: cloud.url.clone().unwrap_or_default(),
+                    https_proxy: cloud.https_proxy.clone(),
+                    ca_bundle_path: cloud.ca_bundle_path.clone(),
+                })));
+            }
+        }
+        self.r2_storage(cloud).await
+    }
+
+    #[cfg(feature = "cloud")]
+    async fn r2_storage(&self, cloud: &Cloud) -> Result<Box<dyn StorageBackend>> {
+        let client = R2Client::new(R2Config {
+            endpoint: cloud.endpoint.clone(),
+            bucket: cloud.bucket.clone(),
+            access_key: cloud.access_key.clone(),
+            secret_key: cloud.secret_key.clone(),
+            https_proxy: cloud.https_proxy.clone(),
+            ca_bundle_path: cloud.ca_bundle_path.clone(),
+            multipart_threshold: cloud.multipart_threshold_mib * 1024 * 1024,
+            multipart_part_size: cloud.multipart_part_mib * 1024 * 1024,
+            resume_dir: self.ls_path("tmp/uploads"),
+        })
+        .await?;
+        Ok(Box::new(client))
+    }
+
+    #[cfg(not(feature = "cloud"))]
+    async fn r2_storage(&self, _cloud: &Cloud) -> Result<Box<dyn StorageBackend>> {
+        Err(anyhow!(NO_CLOUD_SUPPORT))
+    }
+}
+
+pub const NO_CLOUD_SUPPORT: &str =
+    "dev-backup was built without cloud support; the r2 backend needs the `cloud` feature";
+
+// Progress and warnings go out as tracing events; `logging::init` decides
+// where they are printed and whether they also reach the log file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Logger;
+
+impl Logger {
+    pub fn info(&self, message: impl AsRef<str>) {
+        tracing::info!("{}", message.as_ref());
+    }
+
+    pub fn warn(&self, message: impl AsRef<str>) {
+        tracing::warn!("{}", message.as_ref());
+    }
+}
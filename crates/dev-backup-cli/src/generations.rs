@@ -0,0 +1,145 @@
+use crate::context::AppContext;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_btrfs as btrfs;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const GENERATIONS_FILE: &str = "manifests/generations.tsv";
+const HEADER: &str = "snapshot\tsource_generation\tsnapshot_generation";
+
+// btrfs generations read when a snapshot was built: its source subvolume's
+// and its own. A snapshot whose generation has changed since was deleted and
+// taken again under the same label, so its artifacts no longer describe it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Generations {
+    pub source: u64,
+    pub snapshot: u64,
+}
+
+// Kept per dataset when [[dataset]] is configured, like the last push time.
+fn generations_path(ctx: &AppContext) -> PathBuf {
+    match &ctx.dataset {
+        Some(name) => ctx.ls_path(&format!("manifests/datasets/{name}.generations.tsv")),
+        None => ctx.ls_path(GENERATIONS_FILE),
+    }
+}
+
+// Keyed by snapshot name, so split parts have rows of their own.
+pub fn read_generations(ctx: &AppContext) -> Result<BTreeMap<String, Generations>> {
+    let path = generations_path(ctx);
+    let mut generations = BTreeMap::new();
+    if !path.exists() {
+        return Ok(generations);
+    }
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    for (index, line) in contents.lines().enumerate().skip(1) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let parsed = match fields.as_slice() {
+            [name, source, snapshot] => source
+                .parse()
+                .ok()
+                .zip(snapshot.parse().ok())
+                .map(|(source, snapshot)| (name.to_string(), Generations { source, snapshot })),
+            _ => None,
+        };
+        let (name, row) = parsed
+            .ok_or_else(|| anyhow!("malformed row {} in {}", index + 1, path.display()))?;
+        generations.insert(name, row);
+    }
+    Ok(generations)
+}
+
+fn write_generations(ctx: &AppContext, generations: &BTreeMap<String, Generations>) -> Result<()> {
+    let path = generations_path(ctx);
+    btrfs::ensure_dir(path.parent().unwrap_or(Path::new("/")))?;
+    let mut body = format!("{HEADER}\n");
+    for (name, row) in generations {
+        body.push_str(&format!("{name}\t{}\t{}\n", row.source, row.snapshot));
+    }
+    fs::write(&path, body).with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn source_path(ctx: &AppContext, stream: &str) -> String {
+    if stream == ctx.naming.prefix() {
+        ctx.config.paths.dataset.clone()
+    } else {
+        ctx.part_dataset_path(stream)
+    }
+}
+
+// None when either generation cannot be read (not on btrfs), in which case
+// builds are neither checked nor recorded.
+fn current_generations(ctx: &AppContext, stream: &str, label: &str) -> Option<Generations> {
+    let btrfs = ctx.btrfs();
+    let source = btrfs.subvolume_generation(&source_path(ctx, stream)).ok()?;
+    let snapshot = btrfs.subvolume_generation(&ctx.stream_snapshot_path(stream, label)).ok()?;
+    Some(Generations { source, snapshot })
+}
+
+// Run before `stream`'s artifact of `label` is sent. Fails when the snapshot
+// or its parent was recreated after being built, and warns when the dataset
+// has not advanced since the parent was built. The generations to record
+// once the artifact is written are returned.
+pub fn check_build(
+    ctx: &AppContext,
+    stream: &str,
+    label: &str,
+    parent: Option<&str>,
+) -> Result<Option<Generations>> {
+    let Some(current) = current_generations(ctx, stream, label) else {
+        return Ok(None);
+    };
+    let recorded = read_generations(ctx)?;
+    let name = ctx.naming.name(stream, label);
+    if let Some(seen) = recorded.get(&name).filter(|seen| seen.snapshot != current.snapshot) {
+        return Err(anyhow!(
+            "snapshot {name} was recreated since it was built (generation {}, now {}); \
+             artifacts already built from it no longer match it, so take a new label",
+            seen.snapshot,
+            current.snapshot
+        ));
+    }
+    let Some(parent) = parent else {
+        return Ok(Some(current));
+    };
+    let parent_name = ctx.naming.name(stream, parent);
+    let Some(seen) = recorded.get(&parent_name) else {
+        return Ok(Some(current));
+    };
+    let parent_path = ctx.stream_snapshot_path(stream, parent);
+    if let Ok(generation) = ctx.btrfs().subvolume_generation(&parent_path) {
+        if generation != seen.snapshot {
+            return Err(anyhow!(
+                "parent snapshot {parent_name} was recreated since it was built \
+                 (generation {}, now {generation}); an incremental from it would not \
+                 apply on top of its artifact",
+                seen.snapshot
+            ));
+        }
+    }
+    if current.source <= seen.source {
+        ctx.logger.warn(format!(
+            "{} has not changed since {parent_name} was built (generation {}); \
+             the incremental will carry nothing new",
+            source_path(ctx, stream),
+            seen.source
+        ));
+    }
+    Ok(Some(current))
+}
+
+pub fn record_build(
+    ctx: &AppContext,
+    stream: &str,
+    label: &str,
+    generations: Option<Generations>,
+) -> Result<()> {
+    let Some(generations) = generations else {
+        return Ok(());
+    };
+    let mut recorded = read_generations(ctx)?;
+    recorded.insert(ctx.naming.name(stream, label), generations);
+    write_generations(ctx, &recorded)
+}
@@ -0,0 +1,109 @@
+use crate::{logging, watchdog};
+use anyhow::{Context, Result};
+use dev_backup_btrfs as btrfs;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use tokio::signal::unix::{signal, SignalKind};
+
+// What a command has half-written while it runs: artifacts still under their
+// .partial name and subvolumes btrfs receive is still filling. Each stays
+// registered until its guard is dropped, which the code that made it does
+// once it is complete or cleaned up the normal way.
+static PENDING: Mutex<Vec<(u64, Leftover)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+enum Leftover {
+    File(PathBuf),
+    Subvolume(PathBuf),
+}
+
+#[must_use]
+pub struct Pending(u64);
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        with_pending(|pending| pending.retain(|(id, _)| *id != self.0));
+    }
+}
+
+pub fn partial_file(path: &Path) -> Pending {
+    register(Leftover::File(path.to_path_buf()))
+}
+
+pub fn received_subvolume(path: &Path) -> Pending {
+    register(Leftover::Subvolume(path.to_path_buf()))
+}
+
+fn register(leftover: Leftover) -> Pending {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    with_pending(|pending| pending.push((id, leftover)));
+    Pending(id)
+}
+
+fn with_pending<T>(update: impl FnOnce(&mut Vec<(u64, Leftover)>) -> T) -> T {
+    update(&mut PENDING.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+// On SIGINT or SIGTERM: stops the pipelines' subprocesses, removes whatever
+// is still registered and exits with 128 + the signal number. The command
+// itself is usually blocked in a pipeline, so this runs on its own task.
+pub fn install() -> Result<()> {
+    let mut interrupt =
+        signal(SignalKind::interrupt()).context("failed to install the SIGINT handler")?;
+    let mut terminate =
+        signal(SignalKind::terminate()).context("failed to install the SIGTERM handler")?;
+    tokio::spawn(async move {
+        let code = tokio::select! {
+            _ = interrupt.recv() => 130,
+            _ = terminate.recv() => 143,
+        };
+        clean_up();
+        logging::finish();
+        std::process::exit(code);
+    });
+    Ok(())
+}
+
+fn clean_up() {
+    // Receive has to be gone before its subvolume can be deleted.
+    watchdog::kill_all();
+    tracing::warn!("interrupted");
+    let leftovers: Vec<Leftover> =
+        with_pending(|pending| pending.drain(..).map(|(_, leftover)| leftover).collect());
+    for leftover in leftovers {
+        let (path, removed) = match &leftover {
+            Leftover::File(path) => (path, remove_file(path)),
+            Leftover::Subvolume(path) => (path, remove_subvolume(path)),
+        };
+        match removed {
+            Ok(true) => tracing::warn!("removed incomplete {}", path.display()),
+            Ok(false) => {}
+            Err(err) => tracing::warn!("could not remove {}: {err:#}", path.display()),
+        }
+    }
+}
+
+fn remove_file(path: &Path) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    fs::remove_file(path).with_context(|| format!("failed to remove {}", path.display()))?;
+    Ok(true)
+}
+
+// A receive that had not created its subvolume yet may have left a plain
+// directory, or nothing.
+fn remove_subvolume(path: &Path) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let name = path.to_string_lossy();
+    if btrfs::subvolume_exists(&name).unwrap_or(false) {
+        btrfs::subvolume_delete(&name)?;
+    } else {
+        fs::remove_dir_all(path).with_context(|| format!("failed to remove {}", path.display()))?;
+    }
+    Ok(true)
+}
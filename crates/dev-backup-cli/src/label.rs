@@ -0,0 +1,160 @@
+use anyhow::{anyhow, Context, Result};
+pub use dev_backup_core::manifest::is_valid_label;
+use dev_backup_core::manifest::ManifestRecord;
+use dev_backup_core::naming::{LabelScheme, NameTemplate};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub fn ensure_label(label: &str) -> Result<()> {
+    if !is_valid_label(label) {
+        return Err(anyhow!("label must be YYYY-MM, YYYY-MM-DD, YYYY-Www or YYYY-MM-DD-tag"));
+    }
+    Ok(())
+}
+
+// Inclusive bounds from `--from`/`--to`; either end may be left open. Both
+// ends take one scheme, the configured one when it fits, and only labels under
+// that scheme fall inside, since string order is time order only within one.
+#[derive(Debug, Clone, Default)]
+pub struct LabelRange {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    scheme: Option<LabelScheme>,
+}
+
+impl LabelRange {
+    pub fn new(configured: LabelScheme, from: Option<String>, to: Option<String>) -> Result<Self> {
+        let from_scheme = from.as_deref().map(|label| scheme_of(configured, label)).transpose()?;
+        let to_scheme = to.as_deref().map(|label| scheme_of(configured, label)).transpose()?;
+        if let (Some(from), Some(to)) = (&from, &to) {
+            if from_scheme != to_scheme {
+                return Err(anyhow!(
+                    "--from {from} and --to {to} use different label schemes ({} and {})",
+                    from_scheme.unwrap().format(),
+                    to_scheme.unwrap().format()
+                ));
+            }
+            if from > to {
+                return Err(anyhow!("--from {from} is after --to {to}"));
+            }
+        }
+        Ok(Self { from, to, scheme: from_scheme.or(to_scheme) })
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.from.is_some() || self.to.is_some()
+    }
+
+    pub fn contains(&self, label: &str) -> bool {
+        self.scheme.is_none_or(|scheme| scheme.accepts(label))
+            && self.from.as_deref().is_none_or(|from| label >= from)
+            && self.to.as_deref().is_none_or(|to| label <= to)
+    }
+
+    // Distinct labels of `records` inside the range, oldest first.
+    pub fn labels_in(&self, records: &[ManifestRecord]) -> Vec<String> {
+        let mut labels: Vec<String> = records
+            .iter()
+            .filter(|record| self.contains(&record.label))
+            .map(|record| record.label.clone())
+            .collect();
+        labels.sort();
+        labels.dedup();
+        labels
+    }
+}
+
+// The scheme a range end is read under: the configured one when the label fits
+// it, otherwise the first that does, for ranges over labels from before a switch.
+fn scheme_of(configured: LabelScheme, label: &str) -> Result<LabelScheme> {
+    ensure_label(label)?;
+    if configured.accepts(label) {
+        return Ok(configured);
+    }
+    Ok(LabelScheme::ALL.into_iter().find(|scheme| scheme.accepts(label)).unwrap_or(configured))
+}
+
+impl std::fmt::Display for LabelRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let from = self.from.as_deref().unwrap_or("the first label");
+        let to = self.to.as_deref().unwrap_or("the last label");
+        write!(f, "{from} to {to}")
+    }
+}
+
+pub fn resolve_latest_label(records: &[ManifestRecord]) -> Option<String> {
+    let mut best: Option<&ManifestRecord> = None;
+    for record in records {
+        if best.is_none_or(|best| record.ts > best.ts) {
+            best = Some(record);
+        }
+    }
+    best.map(|record| record.label.clone())
+}
+
+pub fn latest_label_from_records(records: &[ManifestRecord]) -> Result<String> {
+    resolve_latest_label(records)
+        .ok_or_else(|| anyhow!("no label found in manifest"))
+}
+
+pub fn resolve_label_input(
+    records: &[ManifestRecord],
+    aliases: &BTreeMap<String, String>,
+    label: &str,
+) -> Result<String> {
+    if label == "latest" {
+        return latest_label_from_records(records);
+    }
+    if let Some(target) = aliases.get(label) {
+        ensure_label(target)?;
+        return Ok(target.clone());
+    }
+    ensure_label(label)?;
+    Ok(label.to_string())
+}
+
+// The parent for `--auto-parent`, or a parent given as "latest": the newest
+// manifest label before `label` that `on_hand` has a snapshot for, so the new
+// incremental extends the chain restores follow. None means an anchor.
+pub fn auto_parent_label(
+    records: &[ManifestRecord],
+    label: &str,
+    on_hand: impl Fn(&str) -> bool,
+) -> Option<String> {
+    records
+        .iter()
+        .map(|record| record.label.as_str())
+        .filter(|candidate| *candidate < label && on_hand(candidate))
+        .max()
+        .map(str::to_string)
+}
+
+pub fn find_latest_local_snapshot_label(
+    naming: &NameTemplate,
+    snapshots_root: &str,
+    exclude_label: &str,
+) -> Result<Option<String>> {
+    let mut candidates = Vec::new();
+    if !Path::new(snapshots_root).exists() {
+        return Ok(None);
+    }
+    for entry in fs::read_dir(snapshots_root)
+        .with_context(|| format!("failed to read snapshot root: {snapshots_root}"))?
+    {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(value) => value,
+            None => continue,
+        };
+        if let Some((stream, label)) = naming.parse(name) {
+            if stream != naming.prefix() || label == exclude_label {
+                continue;
+            }
+            candidates.push(label);
+        }
+    }
+    candidates.sort();
+    Ok(candidates.pop())
+}
@@ -0,0 +1,16 @@
+pub mod commands;
+pub mod context;
+pub mod format;
+pub mod generations;
+pub mod interrupt;
+pub mod label;
+pub mod lock;
+pub mod logging;
+pub mod output;
+pub mod permissions;
+pub mod pipeline;
+pub mod progress;
+pub mod remote;
+pub mod state;
+pub mod watchdog;
+pub mod worktree;
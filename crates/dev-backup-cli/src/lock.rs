@@ -0,0 +1,102 @@
+use crate::context::AppContext;
+use crate::permissions;
+use anyhow::{Context, Result};
+use dev_backup_btrfs as btrfs;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::Path;
+
+// What a lock guards. Commands take them in this order, so two commands that
+// both need the dataset and the manifest cannot wait on each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockScope {
+    // The dataset's snapshots and the btrfs sends made from them.
+    Dataset,
+    // The dataset's manifest and those of its split parts.
+    Manifest,
+}
+
+// An flock(2) on a file under locks/, released when dropped (or when the
+// process dies, so a killed run never leaves a stale lock behind).
+pub struct Lock {
+    _file: File,
+}
+
+// Takes the locks in `scopes` for the current dataset, waiting for another
+// run that holds one. Only an LS initialized with `init ls` has locks/; a
+// workstation that is not also the LS runs unlocked.
+pub fn acquire(ctx: &AppContext, scopes: &[LockScope]) -> Result<Vec<Lock>> {
+    let dir = ctx.ls_path("locks");
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut locks = Vec::new();
+    for scope in scopes {
+        let kind = match scope {
+            LockScope::Dataset => "dataset",
+            LockScope::Manifest => "manifest",
+        };
+        let path = dir.join(format!("{}.{kind}.lock", ctx.naming.prefix()));
+        let file = lock_file(ctx, &path, || format!("{} held by another run", path.display()))?;
+        locks.push(Lock { _file: file });
+    }
+    Ok(locks)
+}
+
+// `acquire` for each [[dataset]] in config order, for commands such as the gcs
+// and `snapshot-set` that act on all of them at once.
+pub fn acquire_every_dataset(ctx: &AppContext, scopes: &[LockScope]) -> Result<Vec<Lock>> {
+    if ctx.config.datasets.is_empty() || scopes.is_empty() {
+        return acquire(ctx, scopes);
+    }
+    let mut locks = Vec::new();
+    for dataset in &ctx.config.datasets {
+        locks.extend(acquire(&ctx.with_dataset(&dataset.name)?, scopes)?);
+    }
+    Ok(locks)
+}
+
+// Held while snapshot `name` is received into the restore directory, so two
+// hydrations of overlapping chains never feed the same label at once. The
+// intent file names the pid receiving it for whoever waits.
+pub fn hydrate_intent(ctx: &AppContext, name: &str) -> Result<Option<Lock>> {
+    let dir = ctx.ls_path("locks");
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let dir = dir.join("hydrate");
+    btrfs::ensure_dir(&dir)?;
+    let path = dir.join(format!("{name}.lock"));
+    let mut file = lock_file(ctx, &path, || {
+        let holder = fs::read_to_string(&path).unwrap_or_default();
+        match holder.trim() {
+            "" => format!("{name}, which another run is receiving"),
+            pid => format!("{name}, which pid {pid} is receiving"),
+        }
+    })?;
+    file.set_len(0)
+        .and_then(|()| writeln!(file, "{}", std::process::id()))
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(Some(Lock { _file: file }))
+}
+
+fn lock_file(ctx: &AppContext, path: &Path, holder: impl FnOnce() -> String) -> Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open lock {}", path.display()))?;
+    permissions::apply_file(ctx, "locks", path)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            ctx.logger.info(format!("Waiting for {}", holder()));
+            file.lock().with_context(|| format!("failed to lock {}", path.display()))?;
+        }
+        Err(TryLockError::Error(err)) => {
+            return Err(err).with_context(|| format!("failed to lock {}", path.display()));
+        }
+    }
+    Ok(file)
+}
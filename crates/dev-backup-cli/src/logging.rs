@@ -0,0 +1,327 @@
+use crate::output::{self, Style};
+use anyhow::{Context, Result};
+use dev_backup_storage::crypto::{encrypt_writer, finish_writer, AgeWriter};
+use serde_json::json;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriter};
+use tracing_subscriber::layer::{Context as LayerContext, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, Registry};
+
+// Overrides --verbose/--quiet for both the console and the log file, e.g.
+// DEV_BACKUP_LOG=dev_backup=trace,aws_sdk_s3=debug.
+pub const FILTER_ENV: &str = "DEV_BACKUP_LOG";
+
+const KEEP_LOG_FILES: usize = 30;
+
+// The encrypted log of this run, which has to be finished before exit for
+// its last lines to be readable.
+static RUN_LOG: OnceLock<SharedLog> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+// Installs the global subscriber: events from our crates go to the console in
+// the formats the commands have always printed (info on stdout, warnings and
+// errors on stderr, one JSON object per line under --json), and to a log file
+// under `logs_dir` that rotates daily, when that directory exists. With a
+// `recipient` each run writes its own age file there instead, since an age
+// stream cannot be appended to.
+pub fn init(
+    verbosity: Verbosity,
+    json: bool,
+    logs_dir: Option<&Path>,
+    recipient: Option<&str>,
+) -> Result<()> {
+    let console_level = match verbosity {
+        Verbosity::Quiet => LevelFilter::WARN,
+        Verbosity::Normal => LevelFilter::INFO,
+        Verbosity::Verbose => LevelFilter::DEBUG,
+    };
+    let file_level = console_level.max(LevelFilter::INFO);
+
+    let console = ConsoleLayer { json }.with_filter(filter(console_level)?);
+    // A log file that cannot be opened (say, a WS user without access to the
+    // LS logs) only warns; the command still runs.
+    let mut file_error = None;
+    let mut writer = None;
+    if let Some(dir) = logs_dir.filter(|dir| dir.is_dir()) {
+        let opened = match recipient {
+            Some(recipient) => open_run_log(dir, recipient).map(BoxMakeWriter::new),
+            None => RollingFileAppender::builder()
+                .rotation(Rotation::DAILY)
+                .filename_prefix("dev-backup")
+                .filename_suffix("log")
+                .max_log_files(KEEP_LOG_FILES)
+                .build(dir)
+                .map(BoxMakeWriter::new)
+                .with_context(|| dir.display().to_string()),
+        };
+        match opened {
+            Ok(opened) => {
+                restrict_log_files(dir);
+                writer = Some(opened);
+            }
+            Err(err) => file_error = Some(format!("{err:#}")),
+        }
+    }
+    let file = match writer {
+        Some(writer) => {
+            let layer = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_target(false)
+                .with_writer(writer);
+            Some(layer.with_filter(filter(file_level)?))
+        }
+        None => None,
+    };
+    Registry::default()
+        .with(console)
+        .with(file)
+        .try_init()
+        .context("failed to install the logger")?;
+    if let Some(err) = file_error {
+        tracing::warn!("not writing a log file: {err}");
+    }
+    Ok(())
+}
+
+// Finishes the encrypted run log, if there is one; the process exits after.
+pub fn finish() {
+    if let Some(log) = RUN_LOG.get() {
+        if let Err(err) = log.finish() {
+            eprintln!("warning: {err:#}");
+        }
+    }
+}
+
+fn open_run_log(dir: &Path, recipient: &str) -> Result<SharedLog> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let name = format!("dev-backup.{}-{}.log.age", now.as_secs(), std::process::id());
+    let log = SharedLog::open(&dir.join(name), Some(recipient))?;
+    prune_encrypted_logs(dir, now);
+    Ok(RUN_LOG.get_or_init(|| log).clone())
+}
+
+// Encrypted run logs are one file per run, so they are kept by age rather
+// than by count.
+fn prune_encrypted_logs(dir: &Path, now: Duration) {
+    let keep = Duration::from_secs(KEEP_LOG_FILES as u64 * 24 * 60 * 60);
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(stamp) = name.strip_prefix("dev-backup.").and_then(|rest| rest.split_once('-'))
+        else {
+            continue;
+        };
+        let old = stamp.0.parse::<u64>().is_ok_and(|secs| secs + keep.as_secs() < now.as_secs());
+        if name.ends_with(".log.age") && old {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+// A log file written from several threads (the tracing layer, or each receive
+// of a restore). Plain logs are appended to; with a recipient the file is a
+// new age stream that is only complete once `finish` runs or the last handle
+// is dropped.
+#[derive(Clone)]
+pub struct SharedLog {
+    path: PathBuf,
+    file: Arc<Mutex<Option<LogFile>>>,
+}
+
+impl SharedLog {
+    pub fn open(path: &Path, recipient: Option<&str>) -> Result<Self> {
+        let context = || format!("failed to open log: {}", path.display());
+        let mut options = OpenOptions::new();
+        let file = match recipient {
+            None => {
+                let file = options.create(true).append(true).open(path).with_context(context)?;
+                LogFile::Plain(file)
+            }
+            Some(recipient) => {
+                let file = options.create_new(true).write(true).open(path).with_context(context)?;
+                match encrypt_writer(recipient, file, false) {
+                    Ok(writer) => LogFile::Encrypted(Some(writer)),
+                    Err(err) => {
+                        let _ = fs::remove_file(path);
+                        return Err(err);
+                    }
+                }
+            }
+        };
+        Ok(Self { path: path.to_path_buf(), file: Arc::new(Mutex::new(Some(file))) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Later writes are dropped.
+    pub fn finish(&self) -> Result<()> {
+        let file = self.file.lock().unwrap_or_else(PoisonError::into_inner).take();
+        match file {
+            Some(file) => file
+                .close()
+                .with_context(|| format!("failed to finish log: {}", self.path.display())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for SharedLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        match file.as_mut() {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        file.as_mut().map_or(Ok(()), Write::flush)
+    }
+}
+
+impl<'a> MakeWriter<'a> for SharedLog {
+    type Writer = SharedLog;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+enum LogFile {
+    Plain(File),
+    Encrypted(Option<AgeWriter<File>>),
+}
+
+impl LogFile {
+    fn close(mut self) -> Result<()> {
+        match &mut self {
+            Self::Plain(file) => file.flush().map_err(Into::into),
+            Self::Encrypted(writer) => match writer.take() {
+                Some(writer) => finish_writer(writer).map(drop),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.write(buf),
+            Self::Encrypted(Some(writer)) => writer.write(buf),
+            Self::Encrypted(None) => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(file) => file.flush(),
+            Self::Encrypted(Some(writer)) => writer.flush(),
+            Self::Encrypted(None) => Ok(()),
+        }
+    }
+}
+
+// A restore that returns early still leaves a readable log.
+impl Drop for LogFile {
+    fn drop(&mut self) {
+        if let Self::Encrypted(writer) = self {
+            if let Some(writer) = writer.take() {
+                let _ = finish_writer(writer);
+            }
+        }
+    }
+}
+
+// The appender creates files under the umask; cap them at the directory's own
+// mode, which is what `[permissions]` and `doctor` expect of files in it.
+// Best effort: another user's log files are left alone.
+fn restrict_log_files(dir: &Path) {
+    let Ok(dir_meta) = fs::metadata(dir) else { return };
+    let allowed = dir_meta.permissions().mode() & 0o666;
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let is_log = entry.file_name().to_string_lossy().starts_with("dev-backup.");
+        let Ok(meta) = entry.metadata() else { continue };
+        let mode = meta.permissions().mode() & 0o7777;
+        if is_log && meta.is_file() && mode & !allowed != 0 {
+            let _ = fs::set_permissions(entry.path(), fs::Permissions::from_mode(mode & allowed));
+        }
+    }
+}
+
+// Dependencies (the AWS SDK in particular) are chatty, so by default only
+// their warnings get through.
+fn filter(level: LevelFilter) -> Result<EnvFilter> {
+    let directives = std::env::var(FILTER_ENV)
+        .unwrap_or_else(|_| format!("warn,dev_backup={level}"));
+    EnvFilter::try_new(&directives)
+        .with_context(|| format!("invalid {FILTER_ENV}: {directives}"))
+}
+
+struct ConsoleLayer {
+    json: bool,
+}
+
+impl<S: Subscriber> Layer<S> for ConsoleLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        let message = message.0;
+        let level = *event.metadata().level();
+        if self.json {
+            let name = level.as_str().to_ascii_lowercase();
+            let line = json!({"level": name, "message": message});
+            if level == Level::INFO {
+                println!("{line}");
+            } else {
+                eprintln!("{line}");
+            }
+            return;
+        }
+        // Only the prefix is coloured, and never in the log file.
+        let prefix = |text: &str, style| {
+            if output::stderr_color() {
+                output::paint(text, style)
+            } else {
+                text.to_string()
+            }
+        };
+        match level {
+            Level::INFO => println!("{message}"),
+            Level::WARN => eprintln!("{} {message}", prefix("warning:", Style::Warning)),
+            Level::ERROR => eprintln!("{} {message}", prefix("error:", Style::Error)),
+            _ => eprintln!("debug: {message}"),
+        }
+    }
+}
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{value:?}");
+        }
+    }
+}
@@ -1,39 +1,199 @@
 use anyhow::{anyhow, Context, Result};
-use clap::{Parser, Subcommand, ValueEnum};
-use dev_backup_btrfs as btrfs;
-use dev_backup_core::config::Config;
-use dev_backup_core::manifest::{ManifestRecord, ManifestStore};
-use dev_backup_core::policy::{decide_snapshot_type, PolicyInput, SnapshotDecision};
-use dev_backup_storage::artifact::{parse_artifact_filename, sha256_file, ArtifactType};
-use dev_backup_storage::cloud::{R2Client, R2Config};
-use std::collections::HashMap;
-use std::fs;
-use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use clap::{ArgMatches, ColorChoice, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use dev_backup::commands::{
+    alias, artifact, backup, bench, config, doctor, init, keys, logs, ls, manifest, report, restore,
+    snapshot, status, sync, verify, ws,
+};
+use dev_backup::context::{AppContext, Logger};
+use dev_backup::interrupt;
+use dev_backup::label::LabelRange;
+use dev_backup::lock::{self, LockScope};
+use dev_backup::logging::{self, Verbosity};
+use dev_backup::output;
+use dev_backup::progress;
+use dev_backup::watchdog;
+use dev_backup_core::clock::FixedClock;
+use dev_backup_core::config::{Config, ReceiveErrors};
+use dev_backup_core::deadline::Deadline;
+use std::path::Path;
+use std::sync::Arc;
+use time::UtcOffset;
+use tracing::Instrument;
+
+const DRY_RUN_COMMANDS: [&str; 12] = [
+    "restore.apply",
+    "restore.switch-back",
+    "restore.confirm",
+    "snapshot.delete",
+    "artifact.register",
+    "artifact.gc",
+    "sync.push",
+    "sync.gc",
+    "sync.reconcile",
+    "manifest.fix-timestamps",
+    "manifest.migrate",
+    "ws.maintain",
+];
+
+// With [[dataset]] entries and no --dataset, these run once per dataset, the
+// dataset-free ones run once, and anything else asks for --dataset.
+const PER_DATASET_COMMANDS: [&str; 16] = [
+    "init",
+    "snapshot",
+    "snapshot.list",
+    "status",
+    "doctor",
+    "backup-now",
+    "backup.run",
+    "artifact.build",
+    "sync.push",
+    "sync.reconcile",
+    "verify",
+    "keys.audit",
+    "manifest.fsck",
+    "manifest.migrate",
+    "ws.maintain",
+    "ws.flush-queue",
+];
+
+// Held for the whole command, so overlapping cron or timer runs queue up
+// instead of racing on btrfs send or rewriting the manifest under each other.
+// Every command that writes a manifest takes its lock; `artifact watch` runs
+// indefinitely, so it takes it for each file it registers instead. The
+// dataset-free commands here take their locks for every [[dataset]].
+const LOCKED_COMMANDS: [(&str, &[LockScope]); 26] = [
+    ("snapshot", &[LockScope::Dataset]),
+    ("snapshot.delete", &[LockScope::Dataset]),
+    ("snapshot-set", &[LockScope::Dataset]),
+    ("ws.maintain", &[LockScope::Dataset]),
+    ("restore.hydrate", &[LockScope::Dataset]),
+    ("restore.apply", &[LockScope::Dataset]),
+    ("restore.switch-back", &[LockScope::Dataset]),
+    ("restore.confirm", &[LockScope::Dataset]),
+    ("restore.test", &[LockScope::Dataset]),
+    ("artifact.build", &[LockScope::Dataset, LockScope::Manifest]),
+    ("artifact.register", &[LockScope::Manifest]),
+    ("artifact.ingest", &[LockScope::Manifest]),
+    ("sync.push", &[LockScope::Manifest]),
+    ("sync.pull", &[LockScope::Manifest]),
+    ("sync.reconcile", &[LockScope::Manifest]),
+    ("verify", &[LockScope::Manifest]),
+    ("manifest.fix-timestamps", &[LockScope::Manifest]),
+    ("manifest.check", &[LockScope::Manifest]),
+    ("manifest.fsck", &[LockScope::Manifest]),
+    ("manifest.migrate", &[LockScope::Manifest]),
+    ("artifact.gc", &[LockScope::Manifest]),
+    ("sync.gc", &[LockScope::Manifest]),
+    ("backup-now", &[LockScope::Dataset, LockScope::Manifest]),
+    ("backup.run", &[LockScope::Dataset, LockScope::Manifest]),
+    ("ws.run-month", &[LockScope::Dataset, LockScope::Manifest]),
+    ("ws.flush-queue", &[LockScope::Manifest]),
+];
+
+const DATASET_FREE_COMMANDS: [&str; 8] = [
+    "config.validate",
+    "snapshot-set",
+    "alias.set",
+    "alias.remove",
+    "alias.list",
+    "logs.decrypt",
+    "artifact.gc",
+    "sync.gc",
+];
 
 #[derive(Parser)]
 #[command(name = "dev-backup", version, about = "Btrfs backup and restore tooling")]
 struct Cli {
     #[arg(long, default_value = "/etc/dev-backup/config.toml")]
     config: String,
+    #[arg(long, global = true, value_parser = parse_now)]
+    now: Option<FixedClock>,
+    #[arg(long, global = true, conflicts_with = "max_runtime")]
+    deadline: Option<String>,
+    #[arg(long, global = true)]
+    max_runtime: Option<String>,
+    #[arg(long, global = true)]
+    dry_run: bool,
+    #[arg(long, global = true)]
+    json: bool,
+    #[arg(long, global = true)]
+    no_color: bool,
+    #[arg(long, global = true)]
+    dataset: Option<String>,
+    #[arg(long, global = true)]
+    progress_fd: Option<i32>,
+    #[arg(long, short, global = true, conflicts_with = "quiet")]
+    verbose: bool,
+    #[arg(long, short, global = true)]
+    quiet: bool,
     #[command(subcommand)]
     command: CliCommand,
 }
 
-#[derive(Subcommand)]
+#[derive(Clone, Subcommand)]
 enum CliCommand {
     Init {
-        #[arg(value_enum)]
-        target: InitTarget,
+        #[arg(value_enum, required_unless_present = "preset")]
+        target: Option<InitTarget>,
+        #[arg(long, value_enum, conflicts_with = "target")]
+        preset: Option<Preset>,
     },
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Snapshot {
+        #[command(subcommand)]
+        action: Option<SnapshotCommand>,
+        #[arg(required_unless_present = "auto")]
+        label: Option<String>,
+        #[arg(long, conflicts_with = "label")]
+        auto: bool,
+        #[arg(long)]
+        adopt: Option<String>,
+    },
+    SnapshotSet {
         label: String,
     },
+    Status,
+    Doctor,
+    Bench {
+        #[arg(long, default_value_t = 64)]
+        sample_mib: u64,
+    },
+    BackupNow {
+        #[arg(long)]
+        label: Option<String>,
+    },
+    Backup {
+        #[command(subcommand)]
+        action: BackupCommand,
+    },
     Artifact {
         #[command(subcommand)]
         action: ArtifactCommand,
     },
+    Alias {
+        #[command(subcommand)]
+        action: AliasCommand,
+    },
+    Manifest {
+        #[command(subcommand)]
+        action: ManifestCommand,
+    },
+    Keys {
+        #[command(subcommand)]
+        action: KeysCommand,
+    },
+    Logs {
+        #[command(subcommand)]
+        action: LogsCommand,
+    },
+    Report {
+        #[command(subcommand)]
+        action: ReportCommand,
+    },
     Restore {
         #[command(subcommand)]
         action: RestoreCommand,
@@ -50,6 +210,22 @@ enum CliCommand {
         #[command(subcommand)]
         action: LsCommand,
     },
+    Verify {
+        #[arg(required_unless_present_any = ["all", "from", "to"])]
+        label: Option<String>,
+        #[arg(long, conflicts_with_all = ["label", "from", "to"])]
+        all: bool,
+        #[arg(long, conflicts_with = "label")]
+        from: Option<String>,
+        #[arg(long, conflicts_with = "label")]
+        to: Option<String>,
+        #[arg(long, conflicts_with = "all")]
+        part: Option<String>,
+        #[arg(long)]
+        cloud: bool,
+        #[arg(long)]
+        deep: bool,
+    },
 }
 
 #[derive(Clone, Copy, ValueEnum)]
@@ -58,1012 +234,703 @@ enum InitTarget {
     Ws,
 }
 
-#[derive(Subcommand)]
+#[derive(Clone, Copy, ValueEnum)]
+enum Preset {
+    Laptop,
+    Homelab,
+    TeamLs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ReceiveErrorsArg {
+    Abort,
+    Continue,
+}
+
+#[derive(Clone, Subcommand)]
+enum ConfigCommand {
+    Validate,
+}
+
+#[derive(Clone, Subcommand)]
 enum ArtifactCommand {
     Build {
         label: String,
         parent: Option<String>,
+        #[arg(long, conflicts_with = "parent")]
+        auto_parent: bool,
+        #[arg(long)]
+        stream: bool,
+        #[arg(long, conflicts_with = "stream")]
+        register: bool,
+        #[arg(long, conflicts_with = "stream")]
+        output_dir: Option<String>,
     },
     Register {
-        path: String,
+        #[arg(required = true)]
+        paths: Vec<String>,
+        #[arg(long, conflicts_with = "in_place")]
+        copy: bool,
+        #[arg(long)]
+        in_place: bool,
     },
-}
-
-#[derive(Subcommand)]
-enum RestoreCommand {
-    Plan { label: String },
-    Hydrate { label: String },
-    Apply { label: String },
-}
-
-#[derive(Subcommand)]
-enum SyncCommand {
-    Push,
-    Pull { label: String, dest: Option<String> },
-}
-
-#[derive(Subcommand)]
-enum WsCommand {
-    RunMonth { label: String },
-    Request {
+    Ingest {
+        #[arg(long)]
         label: String,
+        #[arg(long)]
         parent: Option<String>,
         #[arg(long)]
-        auto_parent: bool,
+        armor: bool,
+        #[arg(long, conflicts_with = "armor")]
+        stream: bool,
+        source: String,
+    },
+    Inspect {
+        path: String,
         #[arg(long)]
-        ls_host: Option<String>,
+        verify: bool,
+    },
+    Export {
+        label: String,
+        #[arg(default_value = "-")]
+        dest: String,
+    },
+    Watch {
+        dir: String,
+        #[arg(long, default_value_t = 10)]
+        interval: u64,
         #[arg(long)]
-        ls_user: Option<String>,
+        once: bool,
+    },
+    Gc {
+        #[arg(long)]
+        quarantine: bool,
     },
 }
 
-#[derive(Subcommand)]
-enum LsCommand {
-    Send { label: String, parent: Option<String> },
-}
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
-    match cli.command {
-        CliCommand::Init { target } => init(&cli.config, target),
-        CliCommand::Snapshot { label } => snapshot(&cli.config, &label),
-        CliCommand::Artifact { action } => artifact(&cli.config, action),
-        CliCommand::Restore { action } => restore(&cli.config, action),
-        CliCommand::Sync { action } => sync(&cli.config, action).await,
-        CliCommand::Ws { action } => ws(&cli.config, action).await,
-        CliCommand::Ls { action } => ls(&cli.config, action),
-    }
+#[derive(Clone, Subcommand)]
+enum SnapshotCommand {
+    List {
+        #[arg(long)]
+        from: Option<String>,
+        #[arg(long)]
+        to: Option<String>,
+    },
+    Delete {
+        label: String,
+        #[arg(long)]
+        force: bool,
+    },
 }
 
-fn load_config(path: &str) -> Result<Config> {
-    Config::load(path).with_context(|| format!("config required at {path}"))
+#[derive(Clone, Subcommand)]
+enum AliasCommand {
+    Set { name: String, label: String },
+    Remove { name: String },
+    List,
 }
 
-fn init(config_path: &str, target: InitTarget) -> Result<()> {
-    let cfg = load_config(config_path)?;
-    match target {
-        InitTarget::Ls => {
-            let base = PathBuf::from(&cfg.paths.ls_root);
-            let dirs = [
-                base.join("artifacts/anchors"),
-                base.join("artifacts/incr"),
-                base.join("manifests"),
-                base.join("keys"),
-                base.join("restore/snapshots"),
-                base.join("tmp"),
-                base.join("logs"),
-                base.join("locks"),
-            ];
-            for dir in dirs {
-                btrfs::ensure_dir(&dir)?;
-            }
-            let manifest_path = base.join("manifests/snapshots_v2.tsv");
-            let store = ManifestStore::new(&manifest_path);
-            store.ensure_initialized()?;
-            let private_key = base.join("keys/ls_dev_backup.key");
-            let public_key = base.join("keys/ls_dev_backup.pub");
-            ensure_age_keypair(&private_key, &public_key)?;
-            println!("LS initialized at {}", base.display());
-        }
-        InitTarget::Ws => {
-            if !btrfs::is_btrfs_mount(&cfg.paths.dataset)? {
-                return Err(anyhow!("dataset path is not on btrfs: {}", cfg.paths.dataset));
-            }
-            btrfs::ensure_dir(Path::new(&cfg.paths.snapshots))?;
-            println!("WS initialized. Snapshot root at {}", cfg.paths.snapshots);
-        }
-    }
-    Ok(())
-}
-
-fn snapshot(config_path: &str, label: &str) -> Result<()> {
-    let cfg = load_config(config_path)?;
-    ensure_label(label)?;
-    let snapshot_path = format!("{}/dev@{}", cfg.paths.snapshots, label);
-    if Path::new(&snapshot_path).exists() {
-        println!("Snapshot already exists: {snapshot_path}");
-        return Ok(());
-    }
-    btrfs::snapshot_readonly(&cfg.paths.dataset, &snapshot_path)?;
-    println!("Created snapshot {snapshot_path}");
-    Ok(())
+#[derive(Clone, Subcommand)]
+enum LogsCommand {
+    Decrypt {
+        #[arg(required = true)]
+        files: Vec<String>,
+        #[arg(long)]
+        identity: Option<String>,
+    },
 }
 
-fn artifact(config_path: &str, action: ArtifactCommand) -> Result<()> {
-    let cfg = load_config(config_path)?;
-    match action {
-        ArtifactCommand::Build { label, parent } => build_artifact(&cfg, &label, parent.as_deref()),
-        ArtifactCommand::Register { path } => register_artifact(&cfg, &path),
-    }
+#[derive(Clone, Subcommand)]
+enum ManifestCommand {
+    FixTimestamps,
+    Check {
+        #[arg(long)]
+        repair: bool,
+    },
+    Fsck {
+        #[arg(long)]
+        fix: bool,
+    },
+    Migrate {
+        #[arg(long)]
+        sqlite: bool,
+    },
 }
 
-fn build_artifact(cfg: &Config, label: &str, parent: Option<&str>) -> Result<()> {
-    ensure_label(label)?;
-    if let Some(parent_label) = parent {
-        ensure_label(parent_label)?;
-    }
-
-    let snapshot_path = format!("{}/dev@{}", cfg.paths.snapshots, label);
-    if !Path::new(&snapshot_path).exists() {
-        return Err(anyhow!("snapshot not found: {snapshot_path}"));
-    }
-
-    let parent_path = parent.map(|p| format!("{}/dev@{}", cfg.paths.snapshots, p));
-    if let Some(ref path) = parent_path {
-        if !Path::new(path).exists() {
-            return Err(anyhow!("parent snapshot not found: {path}"));
-        }
-    }
-
-    let output_name = if let Some(parent_label) = parent {
-        format!("dev@{label}.incr.from_{parent_label}.send.zst.age")
-    } else {
-        format!("dev@{label}.full.send.zst.age")
-    };
-
-    let public_key = cfg
-        .crypto
-        .as_ref()
-        .and_then(|crypto| crypto.age_public_key.as_deref())
-        .ok_or_else(|| anyhow!("age_public_key is required in config"))?;
-
-    run_send_pipeline(&snapshot_path, parent_path.as_deref(), &output_name, public_key)?;
-    println!("Artifact created: {output_name}");
-    Ok(())
+#[derive(Clone, Subcommand)]
+enum ReportCommand {
+    Monthly {
+        #[arg(default_value = "latest")]
+        label: String,
+        #[arg(long, value_enum, default_value_t = report::DiagramFormat::Ascii)]
+        diagram: report::DiagramFormat,
+    },
 }
 
-fn register_artifact(cfg: &Config, path: &str) -> Result<()> {
-    let filename = Path::new(path)
-        .file_name()
-        .and_then(|v| v.to_str())
-        .ok_or_else(|| anyhow!("invalid artifact path: {path}"))?;
-    let info = parse_artifact_filename(filename)
-        .ok_or_else(|| anyhow!("invalid artifact name: {filename}"))?;
-
-    let dest_dir = match info.artifact_type {
-        ArtifactType::Anchor => Path::new(&cfg.paths.ls_root).join("artifacts/anchors"),
-        ArtifactType::Incremental => Path::new(&cfg.paths.ls_root).join("artifacts/incr"),
-    };
-    btrfs::ensure_dir(&dest_dir)?;
-
-    let dest_path = dest_dir.join(&info.filename);
-    fs::rename(path, &dest_path)
-        .with_context(|| format!("failed to move artifact to {}", dest_path.display()))?;
-
-    let bytes = dest_path.metadata()?.len();
-    let sha256 = sha256_file(dest_path.to_str().unwrap_or_default())?;
-
-    let record = ManifestRecord {
-        ts: OffsetDateTime::now_utc().format(&Rfc3339)?,
-        label: info.label,
-        record_type: match info.artifact_type {
-            ArtifactType::Anchor => "anchor".to_string(),
-            ArtifactType::Incremental => "incremental".to_string(),
-        },
-        parent: info.parent.unwrap_or_default(),
-        bytes,
-        sha256,
-        local_path: dest_path.to_string_lossy().to_string(),
-        object_key: String::new(),
-    };
-
-    let manifest_path = Path::new(&cfg.paths.ls_root).join("manifests/snapshots_v2.tsv");
-    let store = ManifestStore::new(&manifest_path);
-    store.ensure_initialized()?;
-    store.append_record(&record)?;
-
-    println!("Registered artifact and updated manifest.");
-    Ok(())
+#[derive(Clone, Subcommand)]
+enum BackupCommand {
+    Run {
+        #[arg(long, default_value = "auto")]
+        label: String,
+    },
 }
 
-fn restore(config_path: &str, action: RestoreCommand) -> Result<()> {
-    let cfg = load_config(config_path)?;
-    match action {
-        RestoreCommand::Plan { label } => {
-            let plan = plan_restore(&cfg, &label)?;
-            for record in plan {
-                println!("{}", record.local_path);
-            }
-            Ok(())
-        }
-        RestoreCommand::Hydrate { label } => hydrate_restore(&cfg, &label),
-        RestoreCommand::Apply { label } => apply_restore(&cfg, &label),
-    }
+#[derive(Clone, Subcommand)]
+enum KeysCommand {
+    Audit,
 }
 
-fn plan_restore(cfg: &Config, label: &str) -> Result<Vec<ManifestRecord>> {
-    let manifest_path = Path::new(&cfg.paths.ls_root).join("manifests/snapshots_v2.tsv");
-    let store = ManifestStore::new(&manifest_path);
-    let records = store.read_records()?;
-    if records.is_empty() {
-        return Err(anyhow!("manifest is empty"));
-    }
-
-    let resolved_label = resolve_label_input(&records, label)?;
-    let mut latest_by_label: HashMap<String, ManifestRecord> = HashMap::new();
-    for record in records {
-        latest_by_label.insert(record.label.clone(), record);
-    }
-
-    let mut chain = Vec::new();
-    let mut current = resolved_label;
-    loop {
-        let record = latest_by_label
-            .get(&current)
-            .ok_or_else(|| anyhow!("label not found in manifest: {current}"))?
-            .clone();
-        chain.push(record.clone());
-
-        if record.record_type == "anchor" {
-            break;
-        }
-
-        if record.parent.is_empty() {
-            return Err(anyhow!("incremental record missing parent for {current}"));
-        }
-
-        let parent_snapshot = format!(
-            "{}/restore/snapshots/dev@{}",
-            cfg.paths.ls_root, record.parent
-        );
-        if Path::new(&parent_snapshot).exists() {
-            break;
-        }
-
-        current = record.parent.clone();
-    }
-
-    chain.reverse();
-    Ok(chain)
+#[derive(Clone, Subcommand)]
+enum RestoreCommand {
+    Plan {
+        label: String,
+        #[arg(long)]
+        part: Option<String>,
+        #[arg(long)]
+        machine: Option<String>,
+    },
+    Hydrate {
+        label: String,
+        #[arg(long)]
+        part: Option<String>,
+        #[arg(long, value_enum)]
+        receive_errors: Option<ReceiveErrorsArg>,
+        #[arg(long)]
+        from_cloud: bool,
+    },
+    Apply {
+        label: String,
+        #[arg(long)]
+        part: Option<String>,
+    },
+    SwitchBack {
+        #[arg(long)]
+        part: Option<String>,
+    },
+    Confirm {
+        #[arg(long)]
+        part: Option<String>,
+    },
+    Test {
+        #[arg(default_value = "latest")]
+        label: String,
+        #[arg(long)]
+        part: Option<String>,
+        #[arg(long)]
+        scratch: Option<String>,
+        #[arg(long, default_value_t = 0)]
+        sample: usize,
+    },
 }
 
-fn hydrate_restore(cfg: &Config, label: &str) -> Result<()> {
-    let private_key = cfg
-        .crypto
-        .as_ref()
-        .and_then(|crypto| crypto.age_private_key_path.as_deref())
-        .ok_or_else(|| anyhow!("age_private_key_path is required in config"))?;
-
-    let restore_dir = format!("{}/restore/snapshots", cfg.paths.ls_root);
-    btrfs::ensure_dir(Path::new(&restore_dir))?;
-
-    let plan = plan_restore(cfg, label)?;
-    for record in plan {
-        let snapshot_path = format!("{restore_dir}/dev@{}", record.label);
-        if Path::new(&snapshot_path).exists() {
-            println!("Snapshot already hydrated: {snapshot_path}");
-            continue;
+impl RestoreCommand {
+    fn label(&self) -> Option<&str> {
+        match self {
+            RestoreCommand::Plan { label, .. }
+            | RestoreCommand::Hydrate { label, .. }
+            | RestoreCommand::Apply { label, .. }
+            | RestoreCommand::Test { label, .. } => Some(label),
+            RestoreCommand::SwitchBack { .. } | RestoreCommand::Confirm { .. } => None,
         }
-        if record.local_path.is_empty() {
-            return Err(anyhow!("missing local_path for {}", record.label));
-        }
-        if !Path::new(&record.local_path).exists() {
-            return Err(anyhow!("artifact missing: {}", record.local_path));
-        }
-        println!("Hydrating dev@{}...", record.label);
-        run_receive_pipeline(&record.local_path, &restore_dir, private_key)?;
     }
-    Ok(())
 }
 
-fn apply_restore(cfg: &Config, label: &str) -> Result<()> {
-    let resolved_label = resolve_label_from_manifest(cfg, label)?;
-    let restore_snapshot = format!(
-        "{}/restore/snapshots/dev@{}",
-        cfg.paths.ls_root, resolved_label
-    );
-    if !Path::new(&restore_snapshot).exists() {
-        return Err(anyhow!("restore snapshot missing: {restore_snapshot}"));
-    }
-
-    let worktree = Path::new(&cfg.paths.dataset);
-    if worktree.exists() {
-        if btrfs::subvolume_exists(worktree.to_str().unwrap_or_default())? {
-            btrfs::subvolume_delete(worktree.to_str().unwrap_or_default())?;
-        } else {
-            let backup_name = format!(
-                "{}_backup_{}",
-                cfg.paths.dataset,
-                OffsetDateTime::now_utc().unix_timestamp()
-            );
-            fs::rename(worktree, &backup_name)
-                .with_context(|| format!("failed to move existing worktree to {backup_name}"))?;
-        }
-    }
-
-    btrfs::snapshot_writable(&restore_snapshot, worktree.to_str().unwrap_or_default())?;
-    println!("Working tree updated to dev@{resolved_label}");
-    Ok(())
+#[derive(Clone, Subcommand)]
+enum SyncCommand {
+    Push {
+        #[arg(long, conflicts_with = "artifacts_only")]
+        manifest_only: bool,
+        #[arg(long)]
+        artifacts_only: bool,
+    },
+    Pull {
+        label: Option<String>,
+        dest: Option<String>,
+        #[arg(long)]
+        from: Option<String>,
+        #[arg(long)]
+        to: Option<String>,
+        #[arg(long)]
+        part: Option<String>,
+        #[arg(long)]
+        mirror: Option<String>,
+        #[arg(long)]
+        machine: Option<String>,
+    },
+    Gc {
+        #[arg(long)]
+        delete: bool,
+    },
+    Reconcile {
+        #[arg(long)]
+        repair: bool,
+    },
 }
 
-async fn sync(config_path: &str, action: SyncCommand) -> Result<()> {
-    let cfg = load_config(config_path)?;
-    match action {
-        SyncCommand::Push => sync_push(&cfg).await,
-        SyncCommand::Pull { label, dest } => sync_pull(&cfg, &label, dest.as_deref()).await,
-    }
+#[derive(Clone, Subcommand)]
+enum WsCommand {
+    RunMonth {
+        #[arg(required_unless_present = "auto")]
+        label: Option<String>,
+        #[arg(long, conflicts_with = "label")]
+        auto: bool,
+        #[arg(long)]
+        adopt: Option<String>,
+    },
+    Maintain,
+    FlushQueue {
+        #[arg(long)]
+        ls_host: Option<String>,
+        #[arg(long)]
+        ls_user: Option<String>,
+    },
+    Request {
+        label: String,
+        parent: Option<String>,
+        #[arg(long)]
+        auto_parent: bool,
+        #[arg(long)]
+        ls_host: Option<String>,
+        #[arg(long)]
+        ls_user: Option<String>,
+        #[arg(long)]
+        force: bool,
+    },
 }
 
-async fn sync_push(cfg: &Config) -> Result<()> {
-    let cloud = cfg
-        .cloud
-        .as_ref()
-        .ok_or_else(|| anyhow!("cloud config is required"))?;
-    let client = R2Client::new(R2Config {
-        endpoint: cloud.endpoint.clone(),
-        bucket: cloud.bucket.clone(),
-        access_key: cloud.access_key.clone(),
-        secret_key: cloud.secret_key.clone(),
-    })
-    .await?;
-
-    let manifest_path = Path::new(&cfg.paths.ls_root).join("manifests/snapshots_v2.tsv");
-    let store = ManifestStore::new(&manifest_path);
-    let mut records = store.read_records()?;
-
-    let mut changed = false;
-    for record in &mut records {
-        if !record.object_key.is_empty() {
-            continue;
-        }
-        if record.local_path.is_empty() {
-            return Err(anyhow!("missing local_path for {}", record.label));
-        }
-        let local_path = Path::new(&record.local_path);
-        if !local_path.exists() {
-            return Err(anyhow!("artifact missing: {}", record.local_path));
-        }
-        let object_key = build_object_key(&cfg.paths.ls_root, local_path);
-        client
-            .upload_object(&object_key, local_path.to_str().unwrap_or_default())
-            .await?;
-        record.object_key = object_key;
-        changed = true;
-    }
-
-    if changed {
-        store.write_records(&records)?;
-    }
-
-    client
-        .upload_object(
-            "manifests/snapshots_v2.tsv",
-            manifest_path.to_str().unwrap_or_default(),
-        )
-        .await?;
-    println!("Sync push complete");
-    Ok(())
+#[derive(Clone, Subcommand)]
+enum LsCommand {
+    Send {
+        label: String,
+        parent: Option<String>,
+        #[arg(long, conflicts_with = "parent")]
+        auto_parent: bool,
+    },
+    Remote {
+        #[arg(long)]
+        detail: bool,
+        #[arg(long)]
+        from: Option<String>,
+        #[arg(long)]
+        to: Option<String>,
+    },
 }
 
-async fn sync_pull(cfg: &Config, label: &str, dest: Option<&str>) -> Result<()> {
-    let cloud = cfg
-        .cloud
-        .as_ref()
-        .ok_or_else(|| anyhow!("cloud config is required"))?;
-    let client = R2Client::new(R2Config {
-        endpoint: cloud.endpoint.clone(),
-        bucket: cloud.bucket.clone(),
-        access_key: cloud.access_key.clone(),
-        secret_key: cloud.secret_key.clone(),
-    })
-    .await?;
-
-    let dest_dir = dest.unwrap_or("/tmp/dev-backup-cloud-pull");
-    btrfs::ensure_dir(Path::new(dest_dir))?;
-
-    let manifest_path = Path::new(dest_dir).join("snapshots_v2.tsv");
-    client
-        .download_object(
-            "manifests/snapshots_v2.tsv",
-            manifest_path.to_str().unwrap_or_default(),
-        )
-        .await?;
-
-    let store = ManifestStore::new(&manifest_path);
-    let records = store.read_records()?;
-    if records.is_empty() {
-        return Err(anyhow!("downloaded manifest is empty"));
-    }
-
-    let resolved_label = if label == "latest" {
-        resolve_latest_label(&records)?.ok_or_else(|| anyhow!("no label found"))?
+fn main() -> Result<()> {
+    // Looked up before the runtime starts its threads: the time crate will
+    // not read the local offset in a process that has several.
+    let local_offset = UtcOffset::current_local_offset().ok();
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("failed to start the runtime")?
+        .block_on(run_main(local_offset))
+}
+
+async fn run_main(local_offset: Option<UtcOffset>) -> Result<()> {
+    // Read ahead of parsing so that clap's own help and errors honour it too.
+    let no_color = std::env::args_os().any(|arg| arg == "--no-color");
+    let color = if output::init(no_color) { ColorChoice::Auto } else { ColorChoice::Never };
+    let matches = Cli::command().color(color).get_matches();
+    let command_path = subcommand_path(&matches);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let verbosity = if cli.quiet {
+        Verbosity::Quiet
+    } else if cli.verbose {
+        Verbosity::Verbose
     } else {
-        label.to_string()
+        Verbosity::Normal
     };
-
-    let plan = plan_chain_from_records(&records, &resolved_label)?;
-    for record in plan {
-        if record.object_key.is_empty() {
-            return Err(anyhow!("missing object_key for {}", record.label));
+    // Read ls_root ahead of the context so that a context that fails to load
+    // is logged too.
+    let config = Config::load(&cli.config).ok();
+    let logs_dir = config.as_ref().map(|config| Path::new(&config.paths.ls_root).join("logs"));
+    let recipient = config.as_ref().and_then(Config::log_recipient);
+    logging::init(verbosity, cli.json, logs_dir.as_deref(), recipient)?;
+    progress::init(cli.progress_fd)?;
+    interrupt::install()?;
+
+    let span = tracing::info_span!("command", name = %command_path.replace('.', " "));
+    let result = run(cli, &command_path, local_offset).instrument(span.clone()).await;
+    span.in_scope(|| {
+        if let Err(err) = &result {
+            tracing::error!("{err:#}");
         }
-        let dest_path = Path::new(dest_dir).join(&record.object_key);
-        if let Some(parent) = dest_path.parent() {
-            btrfs::ensure_dir(parent)?;
+        if let Some(summary) = progress::summary_line() {
+            tracing::info!("{summary}");
         }
-        client
-            .download_object(&record.object_key, dest_path.to_str().unwrap_or_default())
-            .await?;
+    });
+    logging::finish();
+    if result.is_err() {
+        std::process::exit(1);
     }
-
-    println!("Sync pull complete into {dest_dir}");
     Ok(())
 }
 
-fn plan_chain_from_records(records: &[ManifestRecord], label: &str) -> Result<Vec<ManifestRecord>> {
-    let mut latest_by_label: HashMap<String, ManifestRecord> = HashMap::new();
-    for record in records {
-        latest_by_label.insert(record.label.clone(), record.clone());
-    }
-
-    let mut chain = Vec::new();
-    let mut current = label.to_string();
-    loop {
-        let record = latest_by_label
-            .get(&current)
-            .ok_or_else(|| anyhow!("label not found in manifest: {current}"))?
-            .clone();
-        chain.push(record.clone());
-
-        if record.record_type == "anchor" {
-            break;
-        }
-        if record.parent.is_empty() {
-            return Err(anyhow!("incremental record missing parent for {current}"));
-        }
-        current = record.parent.clone();
-    }
-
-    chain.reverse();
-    Ok(chain)
-}
-
-fn resolve_latest_label(records: &[ManifestRecord]) -> Result<Option<String>> {
-    let mut best: Option<(OffsetDateTime, String)> = None;
-    for record in records {
-        let ts = OffsetDateTime::parse(&record.ts, &Rfc3339)
-            .with_context(|| format!("invalid timestamp: {}", record.ts))?;
-        match &best {
-            None => best = Some((ts, record.label.clone())),
-            Some((best_ts, _)) if ts > *best_ts => best = Some((ts, record.label.clone())),
-            _ => {}
+async fn run(cli: Cli, command_path: &str, local_offset: Option<UtcOffset>) -> Result<()> {
+    if let CliCommand::Init { preset: Some(preset), .. } = &cli.command {
+        let name = preset.to_possible_value().map(|value| value.get_name().to_string());
+        return init::write_preset(&Logger, &cli.config, &name.unwrap_or_default());
+    }
+    tracing::debug!("loading config {}", cli.config);
+    let mut ctx = AppContext::load(&cli.config)?;
+    ctx.config.access.validate(&command_paths(&Cli::command(), ""))?;
+    ctx.config.access.check(command_path)?;
+    watchdog::init(&ctx.config.timeouts)?;
+    if let Some(clock) = cli.now {
+        ctx = ctx.with_clock(Arc::new(clock));
+    }
+    let now = ctx.clock.now();
+    if let Some(value) = cli.deadline.as_deref() {
+        ctx = ctx.with_deadline(Deadline::parse_time(value, now, local_offset)?);
+    } else if let Some(value) = cli.max_runtime.as_deref() {
+        ctx = ctx.with_deadline(Deadline::after(value, now)?);
+    }
+    if cli.dry_run {
+        // Anything else would run for real, which is worse than refusing.
+        if !DRY_RUN_COMMANDS.contains(&command_path) {
+            return Err(anyhow!(
+                "--dry-run is only supported by: {}",
+                DRY_RUN_COMMANDS.join(", ").replace('.', " ")
+            ));
         }
+        ctx = ctx.with_dry_run();
     }
-    Ok(best.map(|(_, label)| label))
-}
-
-fn resolve_label_input(records: &[ManifestRecord], label: &str) -> Result<String> {
-    if label == "latest" {
-        return resolve_latest_label(records)?
-            .ok_or_else(|| anyhow!("no label found in manifest"));
-    }
-    ensure_label(label)?;
-    Ok(label.to_string())
-}
-
-fn resolve_label_from_manifest(cfg: &Config, label: &str) -> Result<String> {
-    let manifest_path = Path::new(&cfg.paths.ls_root).join("manifests/snapshots_v2.tsv");
-    let store = ManifestStore::new(&manifest_path);
-    let records = store.read_records()?;
-    if records.is_empty() {
-        return Err(anyhow!("manifest is empty"));
-    }
-    resolve_label_input(&records, label)
-}
-
-fn build_object_key(ls_root: &str, local_path: &Path) -> String {
-    let root = Path::new(ls_root);
-    let key = local_path
-        .strip_prefix(root)
-        .unwrap_or(local_path)
-        .to_string_lossy()
-        .to_string();
-    key.trim_start_matches('/').to_string()
-}
-
-async fn ws(config_path: &str, action: WsCommand) -> Result<()> {
-    let cfg = load_config(config_path)?;
-    match action {
-        WsCommand::RunMonth { label } => ws_run_month(&cfg, &label).await,
-        WsCommand::Request {
-            label,
-            parent,
-            auto_parent,
-            ls_host,
-            ls_user,
-        } => ws_request(
-            &cfg,
-            config_path,
-            &label,
-            parent.as_deref(),
-            auto_parent,
-            ls_host,
-            ls_user,
-        )
-        .await,
-    }
-}
-
-fn ls(config_path: &str, action: LsCommand) -> Result<()> {
-    let cfg = load_config(config_path)?;
-    match action {
-        LsCommand::Send { label, parent } => ls_send(&cfg, &label, parent.as_deref()),
+    if cli.json {
+        ctx = ctx.with_json();
     }
-}
 
-fn ls_send(cfg: &Config, label: &str, parent: Option<&str>) -> Result<()> {
-    let resolved_label = resolve_label_from_manifest(cfg, label)?;
-    if let Some(parent_label) = parent {
-        ensure_label(parent_label)?;
-    }
-
-    let snapshot_dir = format!("{}/restore/snapshots", cfg.paths.ls_root);
-    let snapshot_path = format!("{snapshot_dir}/dev@{resolved_label}");
-    if !Path::new(&snapshot_path).exists() {
-        return Err(anyhow!("snapshot not found on LS: {snapshot_path}"));
-    }
-
-    let parent_path = parent.map(|p| format!("{snapshot_dir}/dev@{p}"));
-    if let Some(ref path) = parent_path {
-        if !Path::new(path).exists() {
-            return Err(anyhow!("parent snapshot not found on LS: {path}"));
+    let names: Vec<String> = ctx.config.datasets.iter().map(|d| d.name.clone()).collect();
+    if let Some(name) = cli.dataset.as_deref() {
+        if names.is_empty() {
+            return Err(anyhow!("--dataset needs [[dataset]] entries in the config"));
         }
+        return dispatch(&ctx.with_dataset(name)?, command_path, cli.command).await;
     }
-
-    let mut cmd = Command::new("btrfs");
-    if let Some(parent_path) = parent_path.as_deref() {
-        cmd.args(["send", "-p", parent_path, &snapshot_path]);
-    } else {
-        cmd.args(["send", &snapshot_path]);
-    }
-
-    let status = cmd
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()
-        .context("failed to run btrfs send")?;
-    if !status.success() {
-        return Err(anyhow!("btrfs send failed"));
-    }
-    Ok(())
-}
-
-fn ensure_label(label: &str) -> Result<()> {
-    if !is_valid_label(label) {
-        return Err(anyhow!("label must be YYYY-MM"));
+    if names.is_empty() || DATASET_FREE_COMMANDS.contains(&command_path) {
+        return dispatch(&ctx, command_path, cli.command).await;
     }
-    Ok(())
-}
-
-fn ensure_age_keypair(private_path: &Path, public_path: &Path) -> Result<()> {
-    if !private_path.exists() {
-        let status = Command::new("age-keygen")
-            .args(["-o", private_path.to_str().unwrap_or_default()])
-            .status()
-            .context("failed to run age-keygen")?;
-        if !status.success() {
-            return Err(anyhow!("age-keygen failed"));
+    // A restore of a snapshot set's label covers every dataset in the set and
+    // stops at the first one that fails.
+    if let CliCommand::Restore { action } = &cli.command {
+        let set = match action.label() {
+            Some(label) => ctx.sets.find(label)?,
+            None => None,
+        };
+        if let Some(set) = set {
+            for name in &set.datasets {
+                let ctx = ctx.with_dataset(name)?;
+                if !ctx.json {
+                    ctx.logger.info(format!("Dataset {name}:"));
+                }
+                dispatch(&ctx, command_path, cli.command.clone())
+                    .await
+                    .with_context(|| format!("dataset {name}"))?;
+            }
+            return Ok(());
         }
     }
-
-    if !public_path.exists() {
-        let output = Command::new("age-keygen")
-            .args(["-y", private_path.to_str().unwrap_or_default()])
-            .output()
-            .context("failed to derive age public key")?;
-        if !output.status.success() {
-            return Err(anyhow!("age-keygen -y failed"));
+    if !PER_DATASET_COMMANDS.contains(&command_path) {
+        let hint = if command_path.starts_with("restore.") {
+            ", or the label of a snapshot set"
+        } else {
+            ""
+        };
+        return Err(anyhow!(
+            "{} needs --dataset (one of: {}){hint}",
+            command_path.replace('.', " "),
+            names.join(", ")
+        ));
+    }
+    // One failing dataset does not stop the others from being backed up.
+    let mut failed = Vec::new();
+    for name in &names {
+        let ctx = ctx.with_dataset(name)?;
+        if !ctx.json {
+            ctx.logger.info(format!("Dataset {name}:"));
+        }
+        if let Err(err) = dispatch(&ctx, command_path, cli.command.clone()).await {
+            tracing::error!("dataset {name}: {err:#}");
+            failed.push(name.as_str());
         }
-        fs::write(public_path, output.stdout)
-            .with_context(|| format!("failed to write public key: {}", public_path.display()))?;
-    }
-
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let private_perm = fs::Permissions::from_mode(0o600);
-        fs::set_permissions(private_path, private_perm)
-            .with_context(|| format!("failed to set permissions on {}", private_path.display()))?;
-        let public_perm = fs::Permissions::from_mode(0o644);
-        fs::set_permissions(public_path, public_perm)
-            .with_context(|| format!("failed to set permissions on {}", public_path.display()))?;
-    }
-
-    Ok(())
-}
-
-fn is_valid_label(label: &str) -> bool {
-    let mut parts = label.split('-');
-    let year = match parts.next() {
-        Some(value) => value,
-        None => return false,
-    };
-    let month = match parts.next() {
-        Some(value) => value,
-        None => return false,
-    };
-    if parts.next().is_some() || year.len() != 4 || month.len() != 2 {
-        return false;
-    }
-    if !year.chars().all(|c| c.is_ascii_digit()) || !month.chars().all(|c| c.is_ascii_digit()) {
-        return false;
     }
-    true
-}
-
-async fn ws_run_month(cfg: &Config, label: &str) -> Result<()> {
-    ensure_label(label)?;
-    let records = fetch_manifest_records_for_ws(cfg).await?;
-    let sorted_records = sort_records_by_ts(&records)?;
-
-    let decision = if sorted_records.is_empty() {
-        SnapshotDecision::Anchor
-    } else {
-        decide_snapshot_type(&sorted_records, PolicyInput::default())?
-    };
-
-    let parent_label = match decision {
-        SnapshotDecision::Anchor => None,
-        SnapshotDecision::Incremental => Some(latest_label_from_records(&sorted_records)?),
-    };
-
-    snapshot_from_cfg(cfg, label)?;
-    build_artifact(cfg, label, parent_label.as_deref())?;
-
-    match parent_label {
-        Some(parent) => println!("Run-month complete: incremental from {parent}"),
-        None => println!("Run-month complete: anchor"),
+    if !failed.is_empty() {
+        return Err(anyhow!("failed for dataset(s): {}", failed.join(", ")));
     }
     Ok(())
 }
 
-async fn ws_request(
-    cfg: &Config,
-    config_path: &str,
-    label: &str,
-    parent: Option<&str>,
-    auto_parent: bool,
-    ls_host: Option<String>,
-    ls_user: Option<String>,
-) -> Result<()> {
-    let resolved_label = resolve_label_for_ws_request(cfg, label).await?;
-    let mut parent_label = parent.map(|value| value.to_string());
-    if let Some(ref label) = parent_label {
-        ensure_label(label)?;
-    } else if auto_parent {
-        parent_label = find_latest_local_snapshot_label(&cfg.paths.snapshots, &resolved_label)?;
-    }
-
-    btrfs::ensure_dir(Path::new(&cfg.paths.snapshots))?;
-    let (host, user) = resolve_remote_target(cfg, ls_host, ls_user);
-
-    let mut send_child = if is_local_host(&host) {
-        spawn_local_ls_send(config_path, &resolved_label, parent_label.as_deref())?
+async fn dispatch(ctx: &AppContext, command_path: &str, command: CliCommand) -> Result<()> {
+    let scopes = LOCKED_COMMANDS.iter().find(|(path, _)| *path == command_path);
+    let scopes = scopes.map_or(&[][..], |(_, scopes)| scopes);
+    let _locks = if DATASET_FREE_COMMANDS.contains(&command_path) {
+        lock::acquire_every_dataset(ctx, scopes)?
     } else {
-        spawn_remote_ls_send(&user, &host, &resolved_label, parent_label.as_deref())?
-    };
-
-    let send_stdout = send_child
-        .stdout
-        .take()
-        .ok_or_else(|| anyhow!("failed to capture ls send stdout"))?;
-
-    let mut recv_child = Command::new("btrfs")
-        .args(["receive", &cfg.paths.snapshots])
-        .stdin(Stdio::from(send_stdout))
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("failed to start btrfs receive")?;
-
-    let recv_status = recv_child.wait().context("failed to wait on btrfs receive")?;
-    let send_status = send_child.wait().context("failed to wait on ls send")?;
-
-    if !send_status.success() {
-        return Err(anyhow!("ls send failed"));
-    }
-    if !recv_status.success() {
-        return Err(anyhow!("btrfs receive failed"));
-    }
-
-    let snapshot_path = format!("{}/dev@{}", cfg.paths.snapshots, resolved_label);
-    if !Path::new(&snapshot_path).exists() {
-        return Err(anyhow!("received snapshot missing: {snapshot_path}"));
-    }
-
-    update_worktree_from_snapshot(cfg, &snapshot_path, &resolved_label)?;
-    Ok(())
-}
-
-async fn resolve_label_for_ws_request(cfg: &Config, label: &str) -> Result<String> {
-    if label != "latest" {
-        ensure_label(label)?;
-        return Ok(label.to_string());
-    }
-    let records = fetch_manifest_records_for_ws(cfg).await?;
-    if records.is_empty() {
-        return Err(anyhow!("manifest unavailable to resolve latest label"));
-    }
-    resolve_latest_label(&records)?
-        .ok_or_else(|| anyhow!("no label found in manifest"))
-}
-
-fn resolve_remote_target(
-    cfg: &Config,
-    ls_host: Option<String>,
-    ls_user: Option<String>,
-) -> (String, String) {
-    let default_user = std::env::var("USER").unwrap_or_else(|_| "chuck".to_string());
-    let host = ls_host
-        .or_else(|| cfg.remote.as_ref().and_then(|remote| remote.ls_host.clone()))
-        .unwrap_or_else(|| "localhost".to_string());
-    let user = ls_user
-        .or_else(|| cfg.remote.as_ref().and_then(|remote| remote.ls_user.clone()))
-        .unwrap_or(default_user);
-    (host, user)
-}
-
-fn is_local_host(host: &str) -> bool {
-    host == "localhost" || host == "127.0.0.1"
-}
-
-fn spawn_local_ls_send(config_path: &str, label: &str, parent: Option<&str>) -> Result<std::process::Child> {
-    let mut cmd = Command::new("dev-backup");
-    cmd.args(["--config", config_path, "ls", "send", label]);
-    if let Some(parent_label) = parent {
-        cmd.arg(parent_label);
-    }
-    let child = cmd
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("failed to spawn local ls send")?;
-    Ok(child)
-}
-
-fn spawn_remote_ls_send(
-    user: &str,
-    host: &str,
-    label: &str,
-    parent: Option<&str>,
-) -> Result<std::process::Child> {
-    let target = format!("{user}@{host}");
-    let mut cmd = Command::new("ssh");
-    cmd.arg(target)
-        .arg("dev-backup")
-        .arg("--config")
-        .arg("/etc/dev-backup/config.toml")
-        .arg("ls")
-        .arg("send")
-        .arg(label);
-    if let Some(parent_label) = parent {
-        cmd.arg(parent_label);
-    }
-    let child = cmd
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("failed to spawn remote ls send")?;
-    Ok(child)
-}
-
-fn snapshot_from_cfg(cfg: &Config, label: &str) -> Result<()> {
-    let snapshot_path = format!("{}/dev@{}", cfg.paths.snapshots, label);
-    if Path::new(&snapshot_path).exists() {
-        println!("Snapshot already exists: {snapshot_path}");
-        return Ok(());
-    }
-    btrfs::snapshot_readonly(&cfg.paths.dataset, &snapshot_path)?;
-    println!("Created snapshot {snapshot_path}");
-    Ok(())
-}
-
-async fn fetch_manifest_records_for_ws(cfg: &Config) -> Result<Vec<ManifestRecord>> {
-    let local_manifest = Path::new(&cfg.paths.ls_root).join("manifests/snapshots_v2.tsv");
-    if local_manifest.exists() {
-        let store = ManifestStore::new(&local_manifest);
-        return store.read_records();
-    }
-
-    let cloud = match cfg.cloud.as_ref() {
-        Some(cloud) => cloud,
-        None => return Ok(Vec::new()),
+        lock::acquire(ctx, scopes)?
     };
-
-    let client = R2Client::new(R2Config {
-        endpoint: cloud.endpoint.clone(),
-        bucket: cloud.bucket.clone(),
-        access_key: cloud.access_key.clone(),
-        secret_key: cloud.secret_key.clone(),
-    })
-    .await?;
-
-    let tmp_path = std::env::temp_dir().join(format!(
-        "dev-backup-manifest-{}.tsv",
-        OffsetDateTime::now_utc().unix_timestamp()
-    ));
-    client
-        .download_object(
-            "manifests/snapshots_v2.tsv",
-            tmp_path.to_str().unwrap_or_default(),
-        )
-        .await?;
-
-    let store = ManifestStore::new(&tmp_path);
-    store.read_records()
-}
-
-fn sort_records_by_ts(records: &[ManifestRecord]) -> Result<Vec<ManifestRecord>> {
-    let mut parsed = Vec::with_capacity(records.len());
-    for record in records {
-        let ts = OffsetDateTime::parse(&record.ts, &Rfc3339)
-            .with_context(|| format!("invalid timestamp: {}", record.ts))?;
-        parsed.push((ts, record.clone()));
-    }
-    parsed.sort_by_key(|(ts, _)| *ts);
-    Ok(parsed.into_iter().map(|(_, record)| record).collect())
-}
-
-fn latest_label_from_records(records: &[ManifestRecord]) -> Result<String> {
-    resolve_latest_label(records)?
-        .ok_or_else(|| anyhow!("no label found in manifest"))
-}
-
-fn find_latest_local_snapshot_label(
-    snapshots_root: &str,
-    exclude_label: &str,
-) -> Result<Option<String>> {
-    let mut candidates = Vec::new();
-    if !Path::new(snapshots_root).exists() {
-        return Ok(None);
-    }
-    for entry in fs::read_dir(snapshots_root)
-        .with_context(|| format!("failed to read snapshot root: {snapshots_root}"))?
-    {
-        let entry = entry?;
-        let name = entry.file_name();
-        let name = match name.to_str() {
-            Some(value) => value,
-            None => continue,
-        };
-        if let Some(label) = name.strip_prefix("dev@") {
-            if label == exclude_label {
-                continue;
+    match command {
+        CliCommand::Init { target, .. } => match target {
+            Some(InitTarget::Ls) => init::init_ls(ctx),
+            Some(InitTarget::Ws) => init::init_ws(ctx),
+            None => Err(anyhow!("init needs ls or ws")),
+        },
+        CliCommand::Config { action } => match action {
+            ConfigCommand::Validate => config::validate(ctx),
+        },
+        CliCommand::Snapshot { action: Some(SnapshotCommand::List { from, to }), .. } => {
+            let range = LabelRange::new(ctx.config.naming.label_scheme, from, to)?;
+            snapshot::snapshot_list(ctx, &range)
+        }
+        CliCommand::Snapshot { action: Some(SnapshotCommand::Delete { label, force }), .. } => {
+            snapshot::snapshot_delete(ctx, &label, force).await
+        }
+        CliCommand::Snapshot { label, adopt, .. } => {
+            snapshot::snapshot(ctx, label.as_deref(), adopt.as_deref())
+        }
+        CliCommand::SnapshotSet { label } => snapshot::snapshot_set(ctx, &label),
+        CliCommand::Status => status::status(ctx),
+        CliCommand::Doctor => doctor::doctor(ctx).await,
+        CliCommand::Bench { sample_mib } => bench::bench(ctx, sample_mib).await,
+        CliCommand::BackupNow { label } => backup::backup_now(ctx, label.as_deref()).await,
+        CliCommand::Backup { action } => match action {
+            BackupCommand::Run { label } => backup::backup_run(ctx, &label).await,
+        },
+        CliCommand::Artifact { action } => match action {
+            ArtifactCommand::Build {
+                label,
+                parent,
+                auto_parent,
+                stream,
+                register,
+                output_dir,
+            } => {
+                let parent = artifact::build_parent(ctx, &label, parent.as_deref(), auto_parent)?;
+                let output_dir = output_dir.as_deref().map(Path::new);
+                if stream {
+                    artifact::stream_artifact(ctx, &label, parent.as_deref()).await
+                } else if register {
+                    artifact::build_and_register_artifact(ctx, &label, parent.as_deref(), output_dir)
+                } else {
+                    artifact::build_artifact(ctx, &label, parent.as_deref(), output_dir)
+                }
+            }
+            ArtifactCommand::Register {
+                paths,
+                copy,
+                in_place,
+            } => {
+                let mode = if in_place {
+                    artifact::RegisterMode::InPlace
+                } else if copy {
+                    artifact::RegisterMode::Copy
+                } else {
+                    artifact::RegisterMode::Move
+                };
+                let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+                artifact::register_artifacts(ctx, &paths, mode)
             }
-            if is_valid_label(label) {
-                candidates.push(label.to_string());
+            ArtifactCommand::Ingest {
+                label,
+                parent,
+                stream: true,
+                source,
+                ..
+            } => {
+                artifact::ingest_artifact_streaming(ctx, &label, parent.as_deref(), &source).await
             }
+            ArtifactCommand::Ingest {
+                label,
+                parent,
+                armor,
+                source,
+                ..
+            } => artifact::ingest_artifact(ctx, &label, parent.as_deref(), &source, armor),
+            ArtifactCommand::Inspect { path, verify } => {
+                artifact::inspect_artifact(ctx, &path, verify)
+            }
+            ArtifactCommand::Export { label, dest } => artifact::export_artifact(ctx, &label, &dest),
+            ArtifactCommand::Watch {
+                dir,
+                interval,
+                once,
+            } => artifact::watch_inbox(ctx, &dir, interval, once),
+            ArtifactCommand::Gc { quarantine } => artifact::artifact_gc(ctx, quarantine),
+        },
+        CliCommand::Alias { action } => match action {
+            AliasCommand::Set { name, label } => alias::alias_set(ctx, &name, &label),
+            AliasCommand::Remove { name } => alias::alias_remove(ctx, &name),
+            AliasCommand::List => alias::alias_list(ctx),
+        },
+        CliCommand::Manifest { action } => match action {
+            ManifestCommand::FixTimestamps => {
+                manifest::manifest_fix_timestamps(ctx, ctx.dry_run)
+            }
+            ManifestCommand::Check { repair } => manifest::manifest_check(ctx, repair),
+            ManifestCommand::Fsck { fix } => manifest::manifest_fsck(ctx, fix),
+            ManifestCommand::Migrate { sqlite } => manifest::manifest_migrate(ctx, sqlite),
+        },
+        CliCommand::Logs { action } => match action {
+            LogsCommand::Decrypt { files, identity } => {
+                logs::logs_decrypt(ctx, &files, identity.as_deref())
+            }
+        },
+        CliCommand::Report { action } => match action {
+            ReportCommand::Monthly { label, diagram } => {
+                report::report_monthly(ctx, &label, diagram)
+            }
+        },
+        CliCommand::Keys { action } => match action {
+            KeysCommand::Audit => keys::keys_audit(ctx),
+        },
+        CliCommand::Restore { action } => match action {
+            RestoreCommand::Plan { label, part, machine } => {
+                let scoped = machine.map(|name| ctx.with_machine(&name)).transpose()?;
+                let ctx = scoped.as_ref().unwrap_or(ctx);
+                for record in restore::plan_restore(ctx, &label, part.as_deref())? {
+                    if ctx.json {
+                        ctx.emit(&record)?;
+                    } else {
+                        println!("{}", record.local_path);
+                    }
+                }
+                Ok(())
+            }
+            RestoreCommand::Hydrate {
+                label,
+                part,
+                receive_errors,
+                from_cloud,
+            } => {
+                let errors = match receive_errors {
+                    Some(ReceiveErrorsArg::Abort) => ReceiveErrors::Abort,
+                    Some(ReceiveErrorsArg::Continue) => ReceiveErrors::Continue,
+                    None => ctx.config.btrfs.receive_errors,
+                };
+                let part = part.as_deref();
+                restore::hydrate_restore(ctx, &label, part, errors, from_cloud).await
+            }
+            RestoreCommand::Apply { label, part } => {
+                restore::apply_restore(ctx, &label, part.as_deref())
+            }
+            RestoreCommand::SwitchBack { part } => restore::switch_back(ctx, part.as_deref()),
+            RestoreCommand::Confirm { part } => restore::confirm_apply(ctx, part.as_deref()),
+            RestoreCommand::Test {
+                label,
+                part,
+                scratch,
+                sample,
+            } => {
+                let (part, scratch) = (part.as_deref(), scratch.as_deref());
+                restore::test_restore(ctx, &label, part, scratch, sample).await
+            }
+        },
+        CliCommand::Sync { action } => match action {
+            SyncCommand::Push {
+                manifest_only,
+                artifacts_only,
+            } => {
+                let scope = if manifest_only {
+                    sync::PushScope::ManifestOnly
+                } else if artifacts_only {
+                    sync::PushScope::ArtifactsOnly
+                } else {
+                    sync::PushScope::All
+                };
+                sync::sync_push(ctx, scope).await
+            }
+            SyncCommand::Pull {
+                label,
+                dest,
+                from,
+                to,
+                part,
+                mirror,
+                machine,
+            } => {
+                let scoped = machine.map(|name| ctx.with_machine(&name)).transpose()?;
+                let ctx = scoped.as_ref().unwrap_or(ctx);
+                let range = LabelRange::new(ctx.config.naming.label_scheme, from, to)?;
+                // A range replaces the label, so a lone positional is the destination.
+                let (label, dest) = match (range.is_set(), label, dest) {
+                    (true, Some(_), Some(_)) => {
+                        return Err(anyhow!("a label cannot be combined with --from/--to"));
+                    }
+                    (true, dest, None) => (None, dest),
+                    (_, label, dest) => (label, dest),
+                };
+                let (part, mirror) = (part.as_deref(), mirror.as_deref());
+                sync::sync_pull(ctx, label.as_deref(), &range, dest.as_deref(), part, mirror).await
+            }
+            SyncCommand::Gc { delete } => sync::sync_gc(ctx, delete).await,
+            SyncCommand::Reconcile { repair } => sync::sync_reconcile(ctx, repair).await,
+        },
+        CliCommand::Ws { action } => match action {
+            WsCommand::RunMonth { label, adopt, .. } => {
+                ws::ws_run_month(ctx, label.as_deref(), adopt.as_deref()).await
+            }
+            WsCommand::Maintain => ws::ws_maintain(ctx).await,
+            WsCommand::FlushQueue { ls_host, ls_user } => {
+                ws::ws_flush_queue(ctx, ls_host, ls_user).await
+            }
+            WsCommand::Request {
+                label,
+                parent,
+                auto_parent,
+                ls_host,
+                ls_user,
+                force,
+            } => {
+                let parent = parent.as_deref();
+                ws::ws_request(ctx, &label, parent, auto_parent, ls_host, ls_user, force).await
+            }
+        },
+        CliCommand::Ls { action } => match action {
+            LsCommand::Send {
+                label,
+                parent,
+                auto_parent,
+            } => ls::ls_send(ctx, &label, parent.as_deref(), auto_parent),
+            LsCommand::Remote { detail, from, to } => {
+                let range = LabelRange::new(ctx.config.naming.label_scheme, from, to)?;
+                ls::ls_remote(ctx, detail, &range).await
+            }
+        },
+        CliCommand::Verify {
+            label,
+            from,
+            to,
+            part,
+            cloud,
+            deep,
+            ..
+        } => {
+            let range = LabelRange::new(ctx.config.naming.label_scheme, from, to)?;
+            verify::verify(ctx, label.as_deref(), &range, part.as_deref(), cloud, deep).await
         }
     }
-    candidates.sort();
-    Ok(candidates.pop())
 }
 
-fn update_worktree_from_snapshot(cfg: &Config, snapshot_path: &str, label: &str) -> Result<()> {
-    let worktree = Path::new(&cfg.paths.dataset);
-    if worktree.exists() {
-        if btrfs::subvolume_exists(worktree.to_str().unwrap_or_default())? {
-            btrfs::subvolume_delete(worktree.to_str().unwrap_or_default())?;
-        } else {
-            let backup_name = format!(
-                "{}_backup_{}",
-                cfg.paths.dataset,
-                OffsetDateTime::now_utc().unix_timestamp()
-            );
-            fs::rename(worktree, &backup_name)
-                .with_context(|| format!("failed to move existing worktree to {backup_name}"))?;
-        }
-    }
-    btrfs::snapshot_writable(snapshot_path, worktree.to_str().unwrap_or_default())?;
-    println!("Working tree updated to dev@{label}");
-    Ok(())
+fn parse_now(value: &str) -> Result<FixedClock, String> {
+    FixedClock::parse(value).map_err(|err| err.to_string())
 }
 
-fn run_send_pipeline(
-    snapshot: &str,
-    parent: Option<&str>,
-    output_path: &str,
-    public_key: &str,
-) -> Result<()> {
-    let mut send_cmd = Command::new("btrfs");
-    if let Some(parent_path) = parent {
-        send_cmd.args(["send", "-p", parent_path, snapshot]);
-    } else {
-        send_cmd.args(["send", snapshot]);
-    }
-    let mut send_child = send_cmd
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("failed to start btrfs send")?;
-
-    let send_stdout = send_child
-        .stdout
-        .take()
-        .ok_or_else(|| anyhow!("failed to capture btrfs send stdout"))?;
-
-    let mut zstd_child = Command::new("zstd")
-        .args(["-3"])
-        .stdin(Stdio::from(send_stdout))
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("failed to start zstd")?;
-
-    let zstd_stdout = zstd_child
-        .stdout
-        .take()
-        .ok_or_else(|| anyhow!("failed to capture zstd stdout"))?;
-
-    let mut age_child = Command::new("age")
-        .args(["-R", public_key, "-o", output_path])
-        .stdin(Stdio::from(zstd_stdout))
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("failed to start age")?;
-
-    let age_status = age_child.wait().context("failed to wait on age")?;
-    let zstd_status = zstd_child.wait().context("failed to wait on zstd")?;
-    let send_status = send_child.wait().context("failed to wait on btrfs send")?;
-
-    if !send_status.success() {
-        return Err(anyhow!("btrfs send failed"));
-    }
-    if !zstd_status.success() {
-        return Err(anyhow!("zstd failed"));
-    }
-    if !age_status.success() {
-        return Err(anyhow!("age failed"));
+// Every dotted path [access] rules may name, groups as well as leaves.
+fn command_paths(command: &clap::Command, prefix: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    for sub in command.get_subcommands() {
+        let path = format!("{prefix}{}", sub.get_name());
+        paths.extend(command_paths(sub, &format!("{path}.")));
+        paths.push(path);
     }
-
-    Ok(())
+    paths
 }
 
-fn run_receive_pipeline(input_path: &str, snapshot_dir: &str, private_key: &str) -> Result<()> {
-    let mut age_child = Command::new("age")
-        .args(["-d", "-i", private_key, input_path])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("failed to start age decrypt")?;
-
-    let age_stdout = age_child
-        .stdout
-        .take()
-        .ok_or_else(|| anyhow!("failed to capture age stdout"))?;
-
-    let mut zstd_child = Command::new("zstd")
-        .args(["-d"])
-        .stdin(Stdio::from(age_stdout))
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("failed to start zstd")?;
-
-    let zstd_stdout = zstd_child
-        .stdout
-        .take()
-        .ok_or_else(|| anyhow!("failed to capture zstd stdout"))?;
-
-    let mut recv_child = Command::new("btrfs")
-        .args(["receive", snapshot_dir])
-        .stdin(Stdio::from(zstd_stdout))
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("failed to start btrfs receive")?;
-
-    let recv_status = recv_child.wait().context("failed to wait on btrfs receive")?;
-    let zstd_status = zstd_child.wait().context("failed to wait on zstd")?;
-    let age_status = age_child.wait().context("failed to wait on age")?;
-
-    if !age_status.success() {
-        return Err(anyhow!("age decrypt failed"));
-    }
-    if !zstd_status.success() {
-        return Err(anyhow!("zstd decode failed"));
+fn subcommand_path(matches: &ArgMatches) -> String {
+    let mut parts = Vec::new();
+    let mut current = matches;
+    while let Some((name, sub)) = current.subcommand() {
+        parts.push(name);
+        current = sub;
     }
-    if !recv_status.success() {
-        return Err(anyhow!("btrfs receive failed"));
-    }
-
-    Ok(())
+    parts.join(".")
 }
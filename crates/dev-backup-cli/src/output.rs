@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{self, IsTerminal};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const DEFAULT_WIDTH: usize = 80;
+const COLUMN_GAP: usize = 2;
+// A column is never narrowed below this to make a table fit.
+const MIN_COLUMN: usize = 8;
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+// Colour is for people at a terminal: --no-color, NO_COLOR set to anything
+// (https://no-color.org), TERM=dumb or a stream that is not a terminal, as
+// under cron or a log collector, each turn it off. Returns whether colour may
+// be used at all.
+pub fn init(no_color: bool) -> bool {
+    let disabled = no_color
+        || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+        || std::env::var_os("TERM").is_some_and(|term| term == "dumb");
+    COLOR.store(!disabled, Ordering::Relaxed);
+    !disabled
+}
+
+// Whether stderr, where warnings and errors go, may be coloured.
+pub fn stderr_color() -> bool {
+    COLOR.load(Ordering::Relaxed) && io::stderr().is_terminal()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Style {
+    Warning,
+    Error,
+}
+
+pub fn paint(text: &str, style: Style) -> String {
+    let code = match style {
+        Style::Warning => "1;33",
+        Style::Error => "1;31",
+    };
+    format!("\x1b[{code}m{text}\x1b[0m")
+}
+
+// Columns of the terminal on stdout, or None when stdout is not one. COLUMNS
+// wins over asking the terminal.
+pub fn terminal_width() -> Option<usize> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    let columns = std::env::var("COLUMNS").ok().and_then(|value| value.parse().ok());
+    Some(columns.filter(|&width| width > 0).or_else(stty_width).unwrap_or(DEFAULT_WIDTH))
+}
+
+// `stty size` prints "rows columns" for the terminal on its stdin.
+fn stty_width() -> Option<usize> {
+    let tty = File::open("/dev/tty").ok()?;
+    let output = Command::new("stty")
+        .arg("size")
+        .stdin(Stdio::from(tty))
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let size = String::from_utf8_lossy(&output.stdout);
+    size.split_whitespace().nth(1)?.parse().ok().filter(|&width| width > 0)
+}
+
+// Rows printed tab-separated, as scripts and `cut -f` expect, or when stdout
+// is a terminal, lined up in columns that fit its width: cells of the widest
+// column are cut short, ending in "…", to make up the difference.
+#[derive(Debug, Default)]
+pub struct Table {
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn row<I, S>(&mut self, cells: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.rows.push(cells.into_iter().map(Into::into).collect());
+    }
+
+    pub fn print(&self) {
+        for line in self.render(terminal_width()) {
+            println!("{line}");
+        }
+    }
+
+    fn render(&self, width: Option<usize>) -> Vec<String> {
+        let Some(width) = width else {
+            return self.rows.iter().map(|row| row.join("\t")).collect();
+        };
+        let mut widths: Vec<usize> = Vec::new();
+        for row in &self.rows {
+            for (index, cell) in row.iter().enumerate() {
+                let len = cell.chars().count();
+                match widths.get_mut(index) {
+                    Some(width) => *width = (*width).max(len),
+                    None => widths.push(len),
+                }
+            }
+        }
+        let total = widths.iter().sum::<usize>() + COLUMN_GAP * widths.len().saturating_sub(1);
+        if let Some(widest) = (0..widths.len()).max_by_key(|&index| widths[index]) {
+            let floor = MIN_COLUMN.min(widths[widest]);
+            widths[widest] = widths[widest].saturating_sub(total.saturating_sub(width)).max(floor);
+        }
+        self.rows
+            .iter()
+            .map(|row| {
+                let cells: Vec<String> = row
+                    .iter()
+                    .enumerate()
+                    .map(|(index, cell)| {
+                        let cell = fit(cell, widths[index]);
+                        if index + 1 == row.len() {
+                            cell
+                        } else {
+                            format!("{cell:<width$}", width = widths[index])
+                        }
+                    })
+                    .collect();
+                cells.join(&" ".repeat(COLUMN_GAP))
+            })
+            .collect()
+    }
+}
+
+fn fit(cell: &str, width: usize) -> String {
+    if cell.chars().count() <= width {
+        return cell.to_string();
+    }
+    let mut cut: String = cell.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
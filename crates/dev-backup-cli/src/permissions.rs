@@ -0,0 +1,151 @@
+use crate::context::AppContext;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_core::config::PathPolicy;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::process::Command;
+
+pub const DIR_CLASSES: &[(&str, &[&str])] = &[
+    ("artifacts", &["artifacts", "artifacts/anchors", "artifacts/incr"]),
+    ("manifests", &["manifests"]),
+    ("keys", &["keys"]),
+    ("restore", &["restore", "restore/snapshots"]),
+    ("tmp", &["tmp"]),
+    ("logs", &["logs"]),
+    ("locks", &["locks"]),
+];
+
+pub fn apply_dirs(ctx: &AppContext) -> Result<()> {
+    for (class, dirs) in DIR_CLASSES {
+        let mode = ctx.config.permissions.dir_mode(class)?;
+        let policy = ctx.config.permissions.policy(class);
+        for dir in dirs.iter() {
+            let path = ctx.ls_path(dir);
+            set_mode(&path, mode)?;
+            apply_ownership(&path, policy)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn apply_file(ctx: &AppContext, class: &str, path: &Path) -> Result<()> {
+    let mode = file_mode(ctx.config.permissions.dir_mode(class)?);
+    set_mode(path, mode)?;
+    apply_ownership(path, ctx.config.permissions.policy(class))
+}
+
+pub fn audit(ctx: &AppContext) -> Result<Vec<String>> {
+    let mut findings = Vec::new();
+    for (class, dirs) in DIR_CLASSES {
+        let dir_mode = ctx.config.permissions.dir_mode(class)?;
+        let policy = ctx.config.permissions.policy(class);
+        let uid = resolve_owner(policy)?;
+        let gid = resolve_group(policy)?;
+        for dir in dirs.iter() {
+            let path = ctx.ls_path(dir);
+            if !path.exists() {
+                continue;
+            }
+            check_path(&path, dir_mode, uid, gid, &mut findings)?;
+            for entry in fs::read_dir(&path)
+                .with_context(|| format!("failed to read directory: {}", path.display()))?
+            {
+                let entry = entry?;
+                if !entry.file_type()?.is_file() {
+                    continue;
+                }
+                let entry_path = entry.path();
+                let mut expected = file_mode(dir_mode);
+                if entry_path.extension().is_some_and(|ext| ext == "pub") {
+                    expected |= 0o444;
+                }
+                check_path(&entry_path, expected, uid, gid, &mut findings)?;
+            }
+        }
+    }
+    Ok(findings)
+}
+
+fn check_path(
+    path: &Path,
+    expected_mode: u32,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    findings: &mut Vec<String>,
+) -> Result<()> {
+    let meta = fs::metadata(path).with_context(|| format!("failed to stat {}", path.display()))?;
+    let mode = meta.permissions().mode() & 0o7777;
+    if mode & !expected_mode != 0 {
+        findings.push(format!(
+            "{} has mode {:04o}, expected at most {:04o}",
+            path.display(),
+            mode,
+            expected_mode
+        ));
+    }
+    if let Some(uid) = uid {
+        if meta.uid() != uid {
+            findings.push(format!("{} owned by uid {}, expected {}", path.display(), meta.uid(), uid));
+        }
+    }
+    if let Some(gid) = gid {
+        if meta.gid() != gid {
+            findings.push(format!("{} has gid {}, expected {}", path.display(), meta.gid(), gid));
+        }
+    }
+    Ok(())
+}
+
+fn file_mode(dir_mode: u32) -> u32 {
+    dir_mode & 0o666
+}
+
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("failed to set permissions on {}", path.display()))
+}
+
+fn apply_ownership(path: &Path, policy: Option<&PathPolicy>) -> Result<()> {
+    let uid = resolve_owner(policy)?;
+    let gid = resolve_group(policy)?;
+    if uid.is_none() && gid.is_none() {
+        return Ok(());
+    }
+    std::os::unix::fs::chown(path, uid, gid)
+        .with_context(|| format!("failed to change ownership of {}", path.display()))
+}
+
+fn resolve_owner(policy: Option<&PathPolicy>) -> Result<Option<u32>> {
+    match policy.and_then(|policy| policy.owner.as_deref()) {
+        Some(owner) => resolve_id(owner, &["id", "-u"]).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn resolve_group(policy: Option<&PathPolicy>) -> Result<Option<u32>> {
+    match policy.and_then(|policy| policy.group.as_deref()) {
+        Some(group) => resolve_id(group, &["getent", "group"]).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn resolve_id(name: &str, lookup: &[&str]) -> Result<u32> {
+    if let Ok(id) = name.parse::<u32>() {
+        return Ok(id);
+    }
+    let output = Command::new(lookup[0])
+        .args(&lookup[1..])
+        .arg(name)
+        .output()
+        .with_context(|| format!("failed to run {} for {name}", lookup[0]))?;
+    if !output.status.success() {
+        return Err(anyhow!("unknown user or group: {name}"));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.trim();
+    // `id -u` prints the uid; `getent group` prints name:x:gid:members.
+    let id = line.split(':').nth(2).unwrap_or(line);
+    id.parse::<u32>()
+        .map_err(|_| anyhow!("failed to resolve id for {name}: {line}"))
+}
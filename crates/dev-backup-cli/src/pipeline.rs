@@ -0,0 +1,565 @@
+use crate::interrupt;
+use crate::logging::SharedLog;
+use crate::progress::ProgressReader;
+use crate::watchdog::{Metered, Stage, Watchdog};
+use anyhow::{anyhow, Context, Result};
+use dev_backup_core::config::{AgeBackend, Compression, ReceiveErrors};
+use dev_backup_storage::crypto::{
+    decrypt_reader, encrypt_writer, finish_writer, AgeWriter,
+};
+use dev_backup_storage::header::{read_header, ArtifactHeader, ArtifactWriter};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread;
+
+// Artifacts are written under this suffix and renamed once complete.
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+pub fn run_send_pipeline(
+    snapshot: &str,
+    parent: Option<&str>,
+    output_path: &str,
+    header: ArtifactHeader,
+    public_key: &str,
+    backend: AgeBackend,
+    compression: Compression,
+) -> Result<()> {
+    write_complete(output_path, |partial| {
+        with_send_stream(snapshot, parent, |stream| {
+            let sink = EncryptSink::open(partial, header, public_key, backend, false)?;
+            compress_and_encrypt(stream, sink, compression)
+        })
+    })
+}
+
+// `write` fills `<output_path>.partial`, which is synced and renamed to
+// `output_path` only when it succeeds and deleted when it fails, so a build
+// that dies partway never leaves a truncated artifact under its real name.
+fn write_complete(output_path: &str, write: impl FnOnce(&str) -> Result<()>) -> Result<()> {
+    let partial = format!("{output_path}{PARTIAL_SUFFIX}");
+    let _pending = interrupt::partial_file(Path::new(&partial));
+    if let Err(err) = write(&partial) {
+        let _ = fs::remove_file(&partial);
+        return Err(err);
+    }
+    fs::rename(&partial, output_path)
+        .with_context(|| format!("failed to move {partial} to {output_path}"))?;
+    let dir = Path::new(output_path).parent().filter(|dir| !dir.as_os_str().is_empty());
+    File::open(dir.unwrap_or(Path::new(".")))
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("failed to sync the directory of {output_path}"))
+}
+
+// Like `run_send_pipeline`, but the encrypted artifact goes to `output`
+// instead of a file. The stream never passes through the age binary, so only
+// the native backend is supported, and as `output` cannot be rewound the
+// artifact is a headerless v1 one.
+pub fn run_send_stream<W: Write>(
+    snapshot: &str,
+    parent: Option<&str>,
+    output: W,
+    public_key: &str,
+    backend: AgeBackend,
+    compression: Compression,
+) -> Result<W> {
+    require_native_age(backend)?;
+    with_send_stream(snapshot, parent, |stream| {
+        compress_and_encrypt_into(stream, output, public_key, compression)
+    })
+}
+
+pub fn run_encrypt_stream<W: Write>(
+    input: impl Read,
+    output: W,
+    public_key: &str,
+    backend: AgeBackend,
+    compression: Compression,
+) -> Result<W> {
+    require_native_age(backend)?;
+    compress_and_encrypt_into(input, output, public_key, compression)
+}
+
+fn require_native_age(backend: AgeBackend) -> Result<()> {
+    if backend != AgeBackend::Native {
+        return Err(anyhow!("streaming artifacts requires crypto.age_backend = \"native\""));
+    }
+    Ok(())
+}
+
+fn with_send_stream<T>(
+    snapshot: &str,
+    parent: Option<&str>,
+    encode: impl FnOnce(ProgressReader<Metered<ChildStdout>>) -> Result<T>,
+) -> Result<T> {
+    let mut send_cmd = Command::new("btrfs");
+    if let Some(parent_path) = parent {
+        send_cmd.args(["send", "-p", parent_path, snapshot]);
+    } else {
+        send_cmd.args(["send", snapshot]);
+    }
+    let mut send_child = send_cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to start btrfs send")?;
+
+    let send_stdout = send_child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("failed to capture btrfs send stdout"))?;
+    let send_stderr = StderrTail::capture(&mut send_child, "btrfs send")?;
+    let watchdog = Watchdog::start();
+    let send = watchdog.add(Stage::Send, "btrfs send", send_child);
+
+    let encode_result = encode(ProgressReader(watchdog.reader("btrfs send", send_stdout)));
+    let send_status = watchdog.wait(send)?;
+    let tail = send_stderr.join()?;
+
+    watchdog.check()?;
+    if !send_status.success() {
+        return Err(failed("btrfs send", &tail));
+    }
+    encode_result
+}
+
+// Keeps this many of the last lines a child wrote to stderr for its error.
+const STDERR_TAIL_LINES: usize = 5;
+
+// Echoes a child's stderr as it comes, the way an inherited one would show,
+// and keeps its last lines so a failure can say why rather than leave the
+// reason somewhere in the scrollback.
+struct StderrTail {
+    name: &'static str,
+    reader: thread::JoinHandle<Vec<String>>,
+}
+
+impl StderrTail {
+    fn capture(child: &mut Child, name: &'static str) -> Result<Self> {
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| anyhow!("failed to capture {name} stderr"))?;
+        let reader = thread::spawn(move || {
+            let mut tail = Vec::new();
+            for line in BufReader::new(stderr).lines().map_while(|line| line.ok()) {
+                eprintln!("{line}");
+                push_tail(&mut tail, line);
+            }
+            tail
+        });
+        Ok(Self { name, reader })
+    }
+
+    fn join(self) -> Result<Vec<String>> {
+        self.reader
+            .join()
+            .map_err(|_| anyhow!("{} stderr thread panicked", self.name))
+    }
+}
+
+fn push_tail(tail: &mut Vec<String>, line: String) {
+    if line.trim().is_empty() {
+        return;
+    }
+    if tail.len() == STDERR_TAIL_LINES {
+        tail.remove(0);
+    }
+    tail.push(line);
+}
+
+// "<name> failed", followed by what it last said on stderr, if anything.
+fn failed(name: &str, tail: &[String]) -> anyhow::Error {
+    if tail.is_empty() {
+        return anyhow!("{name} failed");
+    }
+    let lines: Vec<&str> = tail.iter().map(|line| line.trim()).collect();
+    anyhow!("{name} failed: {}", lines.join("; "))
+}
+
+pub fn run_encrypt_pipeline(
+    input: impl Read,
+    output_path: &str,
+    header: ArtifactHeader,
+    public_key: &str,
+    backend: AgeBackend,
+    compression: Compression,
+    armor: bool,
+) -> Result<()> {
+    write_complete(output_path, |partial| {
+        let sink = EncryptSink::open(partial, header, public_key, backend, armor)?;
+        compress_and_encrypt(input, sink, compression)
+    })
+}
+
+// What a receive went through: how many errors btrfs receive reported, which
+// is only ever non-zero with `ReceiveErrors::Continue`, and the UUID the send
+// stream gives the subvolume it creates, when the stream starts as expected.
+#[derive(Debug, Clone, Default)]
+pub struct Received {
+    pub errors: usize,
+    pub stream_uuid: Option<String>,
+}
+
+// Receive's stderr is echoed and appended to `log`.
+pub fn run_receive_pipeline(
+    input_path: &str,
+    snapshot_dir: &str,
+    private_key: &str,
+    backend: AgeBackend,
+    errors: ReceiveErrors,
+    log: &SharedLog,
+) -> Result<Received> {
+    let input = open_input(input_path)?;
+    run_receive_stream(input, input_path, snapshot_dir, private_key, backend, errors, log)
+}
+
+// `run_receive_pipeline` for an artifact that is read as it arrives;
+// `source` only names it in the log.
+pub fn run_receive_stream(
+    input: impl BufRead + Send + 'static,
+    source: &str,
+    snapshot_dir: &str,
+    private_key: &str,
+    backend: AgeBackend,
+    errors: ReceiveErrors,
+    log: &SharedLog,
+) -> Result<Received> {
+    let log_path = log.path().to_path_buf();
+    let mut log = log.clone();
+    writeln!(log, "== btrfs receive {snapshot_dir} < {source}")?;
+
+    let mut recv_cmd = Command::new("btrfs");
+    recv_cmd.arg("receive");
+    if errors == ReceiveErrors::Continue {
+        recv_cmd.args(["--max-errors", "0"]);
+    }
+    let mut recv_child = recv_cmd
+        .arg(snapshot_dir)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to start btrfs receive")?;
+
+    let recv_stdin = recv_child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("failed to capture btrfs receive stdin"))?;
+    let recv_stderr = recv_child
+        .stderr
+        .take()
+        .ok_or_else(|| anyhow!("failed to capture btrfs receive stderr"))?;
+    let logger = thread::spawn(move || {
+        let mut reported = 0;
+        let mut tail = Vec::new();
+        for line in BufReader::new(recv_stderr).lines().map_while(|line| line.ok()) {
+            eprintln!("{line}");
+            let _ = writeln!(log, "{line}");
+            if line.starts_with("ERROR") {
+                reported += 1;
+            }
+            push_tail(&mut tail, line);
+        }
+        (reported, tail)
+    });
+
+    let watchdog = Watchdog::start();
+    let recv = watchdog.add(Stage::Receive, "btrfs receive", recv_child);
+    let recv_stdin = watchdog.writer("btrfs receive", recv_stdin);
+    let mut stdin = StreamHead { inner: recv_stdin, head: Vec::new() };
+    let input = watchdog.reader(&format!("reading {source}"), input);
+    let decode_result =
+        decrypt_and_decompress(ProgressReader(input), &mut stdin, private_key, backend);
+    let StreamHead { inner: recv_stdin, head } = stdin;
+    drop(recv_stdin);
+    let recv_status = watchdog.wait(recv)?;
+    let (reported, tail) = logger
+        .join()
+        .map_err(|_| anyhow!("btrfs receive log thread panicked"))?;
+
+    watchdog.check()?;
+    decode_result?;
+    if !recv_status.success() {
+        let err = failed("btrfs receive", &tail);
+        return Err(anyhow!("{err}; see {}", log_path.display()));
+    }
+
+    Ok(Received { errors: reported, stream_uuid: send_stream_uuid(&head) })
+}
+
+// Enough of the decoded stream for its first command, which names the
+// subvolume being created.
+const STREAM_HEAD_BYTES: usize = 8192;
+
+// Passes a send stream on to btrfs receive, keeping its first bytes.
+struct StreamHead<W> {
+    inner: W,
+    head: Vec<u8>,
+}
+
+impl<W: Write> Write for StreamHead<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        let wanted = STREAM_HEAD_BYTES.saturating_sub(self.head.len()).min(written);
+        self.head.extend_from_slice(&buf[..wanted]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// The UUID attribute of a send stream's first command (subvol or snapshot):
+// after `btrfs-stream\0` and a u32 version come commands of a u32 length, u16
+// type and u32 crc, whose attributes are a u16 type, u16 length and the data,
+// all little-endian. btrfs receive sets the new subvolume's received_uuid to it.
+fn send_stream_uuid(head: &[u8]) -> Option<String> {
+    const MAGIC: &[u8] = b"btrfs-stream\0";
+    const ATTR_UUID: u16 = 1;
+    let command = head.strip_prefix(MAGIC)?.get(4..)?;
+    let length = u32::from_le_bytes(command.get(..4)?.try_into().ok()?) as usize;
+    let mut attrs = command.get(10..10 + length)?;
+    while let [t0, t1, l0, l1, rest @ ..] = attrs {
+        let (kind, size) = (u16::from_le_bytes([*t0, *t1]), u16::from_le_bytes([*l0, *l1]));
+        let data = rest.get(..size as usize)?;
+        if kind == ATTR_UUID && data.len() == 16 {
+            let hex: String = data.iter().map(|byte| format!("{byte:02x}")).collect();
+            return Some(format!(
+                "{}-{}-{}-{}-{}",
+                &hex[..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..]
+            ));
+        }
+        attrs = &rest[size as usize..];
+    }
+    None
+}
+
+pub fn run_decrypt_pipeline(
+    input_path: &str,
+    output: impl Write,
+    private_key: &str,
+    backend: AgeBackend,
+) -> Result<()> {
+    decrypt_and_decompress(open_input(input_path)?, output, private_key, backend)
+}
+
+fn open_input(input_path: &str) -> Result<BufReader<File>> {
+    let file =
+        File::open(input_path).with_context(|| format!("failed to open artifact: {input_path}"))?;
+    Ok(BufReader::new(file))
+}
+
+fn compress_and_encrypt(
+    mut input: impl Read,
+    sink: EncryptSink,
+    compression: Compression,
+) -> Result<()> {
+    let mut encoder =
+        zstd::Encoder::new(sink, compression.level).context("failed to start zstd encoder")?;
+    if compression.threads > 0 {
+        encoder
+            .multithread(compression.threads)
+            .context("failed to enable zstd worker threads")?;
+    }
+    io::copy(&mut input, &mut encoder).context("failed to compress stream")?;
+    let sink = encoder.finish().context("failed to finish zstd stream")?;
+    sink.finish()
+}
+
+fn compress_and_encrypt_into<W: Write>(
+    mut input: impl Read,
+    output: W,
+    public_key: &str,
+    compression: Compression,
+) -> Result<W> {
+    let writer = encrypt_writer(public_key, output, false)?;
+    let mut encoder =
+        zstd::Encoder::new(writer, compression.level).context("failed to start zstd encoder")?;
+    if compression.threads > 0 {
+        encoder
+            .multithread(compression.threads)
+            .context("failed to enable zstd worker threads")?;
+    }
+    io::copy(&mut input, &mut encoder).context("failed to compress stream")?;
+    let writer = encoder.finish().context("failed to finish zstd stream")?;
+    finish_writer(writer)
+}
+
+fn decrypt_and_decompress(
+    input: impl BufRead + Send + 'static,
+    mut output: impl Write,
+    private_key: &str,
+    backend: AgeBackend,
+) -> Result<()> {
+    let mut source = DecryptSource::open(input, private_key, backend)?;
+    let decoded = zstd::Decoder::new(&mut source)
+        .context("failed to start zstd decoder")
+        .and_then(|mut decoder| {
+            io::copy(&mut decoder, &mut output).context("zstd decode failed")?;
+            output.flush().context("failed to flush decoded stream")
+        });
+    let finished = source.finish();
+    decoded?;
+    finished
+}
+
+enum EncryptSink {
+    Native(Box<AgeWriter<ArtifactWriter>>),
+    // The age binary writes to stdout, which a thread copies into the
+    // artifact after its header.
+    External(Child, ChildStdin, thread::JoinHandle<Result<()>>, StderrTail),
+}
+
+impl EncryptSink {
+    fn open(
+        output_path: &str,
+        header: ArtifactHeader,
+        public_key: &str,
+        backend: AgeBackend,
+        armor: bool,
+    ) -> Result<Self> {
+        let mut output = ArtifactWriter::create(output_path, header)?;
+        match backend {
+            AgeBackend::Native => {
+                let writer = encrypt_writer(public_key, output, armor)?;
+                Ok(Self::Native(Box::new(writer)))
+            }
+            AgeBackend::External => {
+                let recipient_flag = if public_key.starts_with("age1") { "-r" } else { "-R" };
+                let mut command = Command::new("age");
+                if armor {
+                    command.arg("-a");
+                }
+                let mut child = command
+                    .args([recipient_flag, public_key])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .context("failed to start age")?;
+                let stdin = child
+                    .stdin
+                    .take()
+                    .ok_or_else(|| anyhow!("failed to capture age stdin"))?;
+                let mut stdout = child
+                    .stdout
+                    .take()
+                    .ok_or_else(|| anyhow!("failed to capture age stdout"))?;
+                let stderr = StderrTail::capture(&mut child, "age")?;
+                let copier = thread::spawn(move || {
+                    io::copy(&mut stdout, &mut output).context("failed to write age output")?;
+                    output.finish()
+                });
+                Ok(Self::External(child, stdin, copier, stderr))
+            }
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Native(writer) => finish_writer(*writer)?.finish(),
+            Self::External(mut child, stdin, copier, stderr) => {
+                drop(stdin);
+                let status = child.wait().context("failed to wait on age")?;
+                let copied = copier
+                    .join()
+                    .map_err(|_| anyhow!("age output thread panicked"))?;
+                let tail = stderr.join()?;
+                if !status.success() {
+                    return Err(failed("age", &tail));
+                }
+                copied
+            }
+        }
+    }
+}
+
+impl Write for EncryptSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Native(writer) => writer.write(buf),
+            Self::External(_, stdin, ..) => stdin.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Native(writer) => writer.flush(),
+            Self::External(_, stdin, ..) => stdin.flush(),
+        }
+    }
+}
+
+// v2 artifacts have their header skipped here, so age only ever sees the
+// payload.
+enum DecryptSource {
+    Native(Box<dyn Read>),
+    External(Child, ChildStdout, thread::JoinHandle<io::Result<u64>>, StderrTail),
+}
+
+impl DecryptSource {
+    fn open(
+        mut input: impl BufRead + Send + 'static,
+        private_key: &str,
+        backend: AgeBackend,
+    ) -> Result<Self> {
+        read_header(&mut input)?;
+        match backend {
+            AgeBackend::Native => {
+                let reader = decrypt_reader(private_key, input)?;
+                Ok(Self::Native(Box::new(reader)))
+            }
+            AgeBackend::External => {
+                let mut child = Command::new("age")
+                    .args(["-d", "-i", private_key])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .context("failed to start age decrypt")?;
+                let mut stdin = child
+                    .stdin
+                    .take()
+                    .ok_or_else(|| anyhow!("failed to capture age stdin"))?;
+                let stdout = child
+                    .stdout
+                    .take()
+                    .ok_or_else(|| anyhow!("failed to capture age stdout"))?;
+                let stderr = StderrTail::capture(&mut child, "age decrypt")?;
+                let feeder = thread::spawn(move || io::copy(&mut input, &mut stdin));
+                Ok(Self::External(child, stdout, feeder, stderr))
+            }
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        if let Self::External(mut child, stdout, feeder, stderr) = self {
+            drop(stdout);
+            let status = child.wait().context("failed to wait on age")?;
+            let fed = feeder
+                .join()
+                .map_err(|_| anyhow!("age input thread panicked"))?;
+            let tail = stderr.join()?;
+            if !status.success() {
+                return Err(failed("age decrypt", &tail));
+            }
+            fed.context("failed to feed artifact to age")?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for DecryptSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Native(reader) => reader.read(buf),
+            Self::External(_, stdout, ..) => stdout.read(buf),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::format::{format_bytes, format_duration};
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+// Progress events for wrappers and GUIs (`--progress-fd N`): one JSON object
+// per line on that descriptor, apart from the console and `--json` output.
+// A stage ("snapshot", "build", "sync" or "hydrate") emits "start", then
+// "progress" as `bytes` counts towards `total` (null when it is not known
+// ahead, as for a send stream), then "done", or "failed" if it ends early.
+// Finished stages are also kept for the summary printed after the command.
+const EMIT_INTERVAL: Duration = Duration::from_millis(250);
+
+static SINK: Mutex<Sink> = Mutex::new(Sink { file: None, stage: None, finished: Vec::new() });
+
+struct Sink {
+    file: Option<File>,
+    stage: Option<StageState>,
+    finished: Vec<StageSummary>,
+}
+
+// Time and bytes spent in one stage, or in all stages of that name.
+#[derive(Debug, Clone)]
+pub struct StageSummary {
+    pub name: &'static str,
+    pub elapsed: Duration,
+    pub bytes: u64,
+}
+
+struct StageState {
+    name: &'static str,
+    item: String,
+    bytes: u64,
+    total: Option<u64>,
+    started: Instant,
+    emitted: Instant,
+}
+
+pub fn init(fd: Option<i32>) -> Result<()> {
+    let Some(fd) = fd else { return Ok(()) };
+    // Reopened through /dev/fd instead of adopting the descriptor, which would
+    // take unsafe code.
+    let file = OpenOptions::new()
+        .append(true)
+        .open(format!("/dev/fd/{fd}"))
+        .with_context(|| format!("--progress-fd {fd} is not open for writing"))?;
+    with_sink(|sink| sink.file = Some(file));
+    Ok(())
+}
+
+fn with_sink(update: impl FnOnce(&mut Sink)) {
+    update(&mut SINK.lock().unwrap_or_else(PoisonError::into_inner));
+}
+
+// The stages finished so far, merged by name in the order they first ran.
+pub fn summary() -> Vec<StageSummary> {
+    let mut merged: Vec<StageSummary> = Vec::new();
+    with_sink(|sink| {
+        for stage in &sink.finished {
+            match merged.iter_mut().find(|seen| seen.name == stage.name) {
+                Some(seen) => {
+                    seen.elapsed += stage.elapsed;
+                    seen.bytes += stage.bytes;
+                }
+                None => merged.push(stage.clone()),
+            }
+        }
+    });
+    merged
+}
+
+// `Summary: snapshot 2s, build 41m12s/38.0 GiB, sync 1h12m/19.0 GiB`, or None
+// when the command ran no stages.
+pub fn summary_line() -> Option<String> {
+    let stages = summary();
+    if stages.is_empty() {
+        return None;
+    }
+    let stages: Vec<String> = stages
+        .iter()
+        .map(|stage| {
+            let elapsed = time::Duration::try_from(stage.elapsed).unwrap_or(time::Duration::MAX);
+            match stage.bytes {
+                0 => format!("{} {}", stage.name, format_duration(elapsed)),
+                bytes => format!(
+                    "{} {}/{}",
+                    stage.name,
+                    format_duration(elapsed),
+                    format_bytes(bytes)
+                ),
+            }
+        })
+        .collect();
+    Some(format!("Summary: {}", stages.join(", ")))
+}
+
+impl Sink {
+    // A wrapper that stopped reading must not fail the backup, so write
+    // errors are ignored.
+    fn emit(&mut self, event: &str) {
+        let (Some(file), Some(stage)) = (self.file.as_mut(), self.stage.as_mut()) else {
+            return;
+        };
+        stage.emitted = Instant::now();
+        let elapsed = stage.started.elapsed().as_secs_f64();
+        let eta = stage.total.filter(|_| stage.bytes > 0).map(|total| {
+            let left = total.saturating_sub(stage.bytes) as f64;
+            (elapsed * left / stage.bytes as f64).round() as u64
+        });
+        let line = json!({
+            "event": event,
+            "stage": stage.name,
+            "item": stage.item,
+            "bytes": stage.bytes,
+            "total": stage.total,
+            "eta_secs": eta,
+            "elapsed_secs": elapsed.round() as u64,
+        });
+        let _ = writeln!(file, "{line}");
+    }
+}
+
+// One stage of a command. Only one runs at a time; bytes counted with
+// `advance` go to it.
+pub struct Stage {
+    finished: bool,
+}
+
+impl Stage {
+    pub fn begin(name: &'static str, total: Option<u64>) -> Self {
+        with_sink(|sink| {
+            let now = Instant::now();
+            let (item, bytes) = (String::new(), 0);
+            sink.stage = Some(StageState { name, item, bytes, total, started: now, emitted: now });
+            sink.emit("start");
+        });
+        Self { finished: false }
+    }
+
+    pub fn finish(mut self) {
+        self.end("done");
+    }
+
+    fn end(&mut self, event: &str) {
+        self.finished = true;
+        with_sink(|sink| {
+            sink.emit(event);
+            if let Some(stage) = sink.stage.take() {
+                let (name, elapsed) = (stage.name, stage.started.elapsed());
+                sink.finished.push(StageSummary { name, elapsed, bytes: stage.bytes });
+            }
+        });
+    }
+}
+
+impl Drop for Stage {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.end("failed");
+        }
+    }
+}
+
+// What the current stage is working on, e.g. a snapshot or object key.
+pub fn item(item: impl Into<String>) {
+    with_sink(|sink| {
+        if let Some(stage) = sink.stage.as_mut() {
+            stage.item = item.into();
+            sink.emit("progress");
+        }
+    });
+}
+
+pub fn advance(bytes: u64) {
+    with_sink(|sink| {
+        if let Some(stage) = sink.stage.as_mut() {
+            stage.bytes += bytes;
+            if stage.emitted.elapsed() >= EMIT_INTERVAL {
+                sink.emit("progress");
+            }
+        }
+    });
+}
+
+// Counts what is read through it towards the current stage.
+pub struct ProgressReader<R>(pub R);
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.0.read(buf)?;
+        advance(read as u64);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for ProgressReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.0.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        advance(amount as u64);
+        self.0.consume(amount);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::context::AppContext;
+use anyhow::{Context, Result};
+use dev_backup_core::config::validate_host;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Debug, Clone)]
+pub struct RemoteTarget {
+    pub user: String,
+    pub host: String,
+    pub port: Option<u16>,
+    pub ssh_options: Vec<String>,
+    pub control_persist_seconds: Option<u64>,
+}
+
+impl RemoteTarget {
+    pub fn resolve(ctx: &AppContext, ls_host: Option<String>, ls_user: Option<String>) -> Result<Self> {
+        let remote = ctx.config.remote.as_ref();
+        if let Some(remote) = remote {
+            remote.validate()?;
+        }
+        let default_user = std::env::var("USER").unwrap_or_else(|_| "chuck".to_string());
+        let host = ls_host
+            .or_else(|| remote.and_then(|remote| remote.ls_host.clone()))
+            .unwrap_or_else(|| "localhost".to_string());
+        validate_host(&host)?;
+        let user = ls_user
+            .or_else(|| remote.and_then(|remote| remote.ls_user.clone()))
+            .unwrap_or(default_user);
+        Ok(Self {
+            user,
+            host: strip_brackets(&host).to_string(),
+            port: remote.and_then(|remote| remote.ls_port),
+            ssh_options: remote
+                .map(|remote| remote.ssh_options.clone())
+                .unwrap_or_default(),
+            control_persist_seconds: match remote {
+                Some(remote) if !remote.ssh_multiplex => None,
+                Some(remote) => Some(remote.control_persist_seconds),
+                None => Some(60),
+            },
+        })
+    }
+
+    pub fn is_local(&self) -> bool {
+        matches!(self.host.as_str(), "localhost" | "127.0.0.1" | "::1")
+    }
+
+    pub fn ssh_destination(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match self.port {
+            Some(port) => format!("ssh://{}@{}:{}", self.user, host, port),
+            None => format!("ssh://{}@{}", self.user, host),
+        }
+    }
+
+    pub fn ssh_command(&self) -> Command {
+        let mut cmd = Command::new("ssh");
+        if let Some(persist) = self.control_persist_seconds {
+            for option in multiplex_options(persist) {
+                cmd.arg("-o").arg(option);
+            }
+        }
+        for option in &self.ssh_options {
+            cmd.arg("-o").arg(option);
+        }
+        cmd.arg(self.ssh_destination());
+        cmd
+    }
+
+    // ssh joins its trailing arguments with spaces and hands the result to the
+    // remote login shell, so each word is quoted here rather than trusted.
+    pub fn remote_command(&self, args: &[&str]) -> Command {
+        let mut cmd = self.ssh_command();
+        let words: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+        cmd.arg("--").arg(words.join(" "));
+        cmd
+    }
+}
+
+// Reuse one authenticated connection across the ssh (and sftp) invocations of
+// a run; the master lingers for `persist` seconds after the last one.
+pub fn multiplex_options(persist: u64) -> Vec<String> {
+    match control_dir() {
+        Ok(dir) => vec![
+            "ControlMaster=auto".to_string(),
+            format!("ControlPath={}/%C", dir.display()),
+            format!("ControlPersist={persist}"),
+        ],
+        Err(err) => {
+            tracing::warn!("ssh multiplexing disabled: {err:#}");
+            Vec::new()
+        }
+    }
+}
+
+pub fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./@:=,+".contains(c));
+    if safe {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+fn control_dir() -> Result<PathBuf> {
+    let base = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let user = std::env::var("USER").unwrap_or_else(|_| "user".to_string());
+    let dir = base.join(format!("dev-backup-ssh-{user}"));
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("failed to create ssh control directory: {}", dir.display()))?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))
+        .with_context(|| format!("failed to set permissions on {}", dir.display()))?;
+    Ok(dir)
+}
+
+fn strip_brackets(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::shell_quote;
+
+    #[test]
+    fn plain_words_are_left_alone() {
+        assert_eq!(shell_quote("2024-01"), "2024-01");
+        assert_eq!(shell_quote("/etc/dev-backup/config.toml"), "/etc/dev-backup/config.toml");
+    }
+
+    #[test]
+    fn hostile_words_are_single_quoted() {
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("2024-01; rm -rf /"), "'2024-01; rm -rf /'");
+        assert_eq!(shell_quote("$(id)"), "'$(id)'");
+        assert_eq!(shell_quote("`id`"), "'`id`'");
+        assert_eq!(shell_quote("a'b"), "'a'\\''b'");
+        assert_eq!(shell_quote("x\ny"), "'x\ny'");
+    }
+}
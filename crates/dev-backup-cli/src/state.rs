@@ -0,0 +1,144 @@
+use crate::context::AppContext;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_storage::crypto::{
+    decrypt_reader, encrypt_writer, finish_writer, generate_identity, identity_public_key,
+};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+const PRIVATE_MODE: u32 = 0o600;
+
+// Small files of WS-local state, such as the `ws run-month` queue. Each is
+// written 0600 through a temporary file and a rename; with [state] encrypt it
+// is kept as `<name>.age` for the identity at state.key_path instead. Reads
+// take whichever form is there, so turning encryption on or off converts a
+// file the next time it is written.
+pub fn read(ctx: &AppContext, path: &Path) -> Result<Option<String>> {
+    let encrypted = age_path(path);
+    if encrypted.exists() {
+        restrict(ctx, &encrypted)?;
+        let key = key_path(ctx).ok_or_else(|| {
+            anyhow!("{} is encrypted; set state.key_path to read it", encrypted.display())
+        })?;
+        let file = File::open(&encrypted)
+            .with_context(|| format!("failed to open {}", encrypted.display()))?;
+        let mut contents = String::new();
+        decrypt_reader(&key, file)?
+            .read_to_string(&mut contents)
+            .with_context(|| format!("failed to decrypt {}", encrypted.display()))?;
+        return Ok(Some(contents));
+    }
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            restrict(ctx, path)?;
+            Ok(Some(contents))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+pub fn write(ctx: &AppContext, path: &Path, contents: &str) -> Result<()> {
+    let (target, stale) = if ctx.config.state.encrypt {
+        let key = ensure_key(ctx)?;
+        let recipient = identity_public_key(&key)?;
+        let mut writer = encrypt_writer(&recipient, Vec::new(), false)?;
+        writer.write_all(contents.as_bytes()).context("failed to encrypt state")?;
+        write_private(&age_path(path), &finish_writer(writer)?)?;
+        (age_path(path), path.to_path_buf())
+    } else {
+        write_private(path, contents.as_bytes())?;
+        (path.to_path_buf(), age_path(path))
+    };
+    remove_file(&stale)
+        .with_context(|| format!("wrote {} but could not remove the old copy", target.display()))
+}
+
+pub fn remove(path: &Path) -> Result<()> {
+    remove_file(path)?;
+    remove_file(&age_path(path))
+}
+
+fn age_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".age");
+    path.with_file_name(name)
+}
+
+fn key_path(ctx: &AppContext) -> Option<String> {
+    ctx.config.state.key_path.clone()
+}
+
+// The identity state is encrypted to, generated the first time it is needed.
+fn ensure_key(ctx: &AppContext) -> Result<String> {
+    let key = key_path(ctx).ok_or_else(|| anyhow!("state.encrypt requires state.key_path"))?;
+    let path = Path::new(&key);
+    if path.exists() {
+        restrict(ctx, path)?;
+        return Ok(key);
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(PRIVATE_MODE)
+        .open(path)
+        .with_context(|| format!("failed to create state key: {key}"))?;
+    file.write_all(generate_identity().as_bytes())
+        .with_context(|| format!("failed to write state key: {key}"))?;
+    ctx.logger.info(format!("Generated state key {key}"));
+    Ok(key)
+}
+
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(PRIVATE_MODE)
+        .open(&tmp_path)
+        .with_context(|| format!("failed to create {}", tmp_path.display()))?;
+    // An old temporary file keeps the mode it was created with.
+    file.set_permissions(fs::Permissions::from_mode(PRIVATE_MODE))
+        .with_context(|| format!("failed to set mode on {}", tmp_path.display()))?;
+    file.write_all(contents)
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("failed to replace {}", path.display()))
+}
+
+// State others can read is put back to 0600, with a warning.
+fn restrict(ctx: &AppContext, path: &Path) -> Result<()> {
+    let mode = fs::metadata(path)
+        .with_context(|| format!("failed to stat {}", path.display()))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        fs::set_permissions(path, fs::Permissions::from_mode(PRIVATE_MODE))
+            .with_context(|| format!("failed to set mode on {}", path.display()))?;
+        ctx.logger.warn(format!(
+            "{} was mode {:o}; set it to 600",
+            path.display(),
+            mode & 0o777
+        ));
+    }
+    Ok(())
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
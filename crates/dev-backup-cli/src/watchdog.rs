@@ -0,0 +1,292 @@
+use crate::format::format_duration;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_core::config::Timeouts;
+use dev_backup_core::deadline::parse_duration;
+use std::io::{self, BufRead, Read, Write};
+use std::process::{Child, ExitStatus};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+// Every running watchdog, for `kill_all`.
+static LIVE: Mutex<Vec<Weak<Shared>>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Limits {
+    stall: Option<time::Duration>,
+    send: Option<time::Duration>,
+    receive: Option<time::Duration>,
+}
+
+// Reads [timeouts] once for every pipeline the command runs.
+pub fn init(timeouts: &Timeouts) -> Result<()> {
+    let parse = |name: &str, value: &Option<String>| {
+        value
+            .as_deref()
+            .map(parse_duration)
+            .transpose()
+            .with_context(|| format!("invalid timeouts.{name}"))
+    };
+    let limits = Limits {
+        stall: parse("stall", &timeouts.stall)?,
+        send: parse("send", &timeouts.send)?,
+        receive: parse("receive", &timeouts.receive)?,
+    };
+    let _ = LIMITS.set(limits);
+    Ok(())
+}
+
+// Kills and reaps the subprocesses of every pipeline still running, for a
+// command that is being interrupted.
+pub fn kill_all() {
+    let live = LIVE.lock().unwrap_or_else(PoisonError::into_inner).clone();
+    for shared in live.iter().filter_map(Weak::upgrade) {
+        let mut state = shared.lock();
+        for watched in &mut state.children {
+            if watched.status.is_none() {
+                let _ = watched.child.kill();
+                watched.status = watched.child.wait().ok();
+            }
+        }
+    }
+}
+
+// Which [timeouts] limit a subprocess runs under.
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+    Send,
+    Receive,
+}
+
+// Watches the subprocesses of one pipeline and the bytes moving between
+// them. When a stage runs past its limit, or nothing moves for
+// timeouts.stall, every subprocess is killed, which ends the pipeline's reads
+// and writes, and `check` names the stage that was at fault. Subprocesses
+// handed to it are waited for through it.
+pub struct Watchdog {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    limits: Limits,
+    state: Mutex<State>,
+}
+
+struct State {
+    children: Vec<Watched>,
+    moved: Instant,
+    // The stage a metered read or write is blocked on, if any.
+    waiting_on: Option<String>,
+    tripped: Option<String>,
+    done: bool,
+}
+
+struct Watched {
+    name: String,
+    child: Child,
+    started: Instant,
+    limit: Option<time::Duration>,
+    status: Option<ExitStatus>,
+}
+
+impl Watchdog {
+    pub fn start() -> Self {
+        let limits = LIMITS.get().copied().unwrap_or_default();
+        let state = State {
+            children: Vec::new(),
+            moved: Instant::now(),
+            waiting_on: None,
+            tripped: None,
+            done: false,
+        };
+        let shared = Arc::new(Shared { limits, state: Mutex::new(state) });
+        let mut live = LIVE.lock().unwrap_or_else(PoisonError::into_inner);
+        live.retain(|watchdog| watchdog.strong_count() > 0);
+        live.push(Arc::downgrade(&shared));
+        drop(live);
+        if limits.stall.is_some() || limits.send.is_some() || limits.receive.is_some() {
+            let monitor = Arc::clone(&shared);
+            thread::spawn(move || monitor.run());
+        }
+        Self { shared }
+    }
+
+    // Takes over `child`, whose pipes have already been taken; the returned
+    // id is for `wait`.
+    pub fn add(&self, stage: Stage, name: &str, child: Child) -> usize {
+        let limit = match stage {
+            Stage::Send => self.shared.limits.send,
+            Stage::Receive => self.shared.limits.receive,
+        };
+        let mut state = self.shared.lock();
+        state.children.push(Watched {
+            name: name.to_string(),
+            child,
+            started: Instant::now(),
+            limit,
+            status: None,
+        });
+        state.children.len() - 1
+    }
+
+    pub fn wait(&self, id: usize) -> Result<ExitStatus> {
+        loop {
+            {
+                let mut state = self.shared.lock();
+                let watched = &mut state.children[id];
+                if let Some(status) = watched.poll()? {
+                    return Ok(status);
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    // What stopped the pipeline, if the watchdog did. Checked before the
+    // errors its killing caused, which are only symptoms.
+    pub fn check(&self) -> Result<()> {
+        match self.shared.lock().tripped.clone() {
+            Some(reason) => Err(anyhow!(reason)),
+            None => Ok(()),
+        }
+    }
+
+    // `inner` as read from the output of stage `name`.
+    pub fn reader<R>(&self, name: &str, inner: R) -> Metered<R> {
+        Metered { name: name.to_string(), inner, shared: Arc::clone(&self.shared) }
+    }
+
+    // `inner` as written to the input of stage `name`.
+    pub fn writer<W>(&self, name: &str, inner: W) -> Metered<W> {
+        self.reader(name, inner)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.shared.lock().done = true;
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn run(&self) {
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let mut state = self.lock();
+            if state.done {
+                return;
+            }
+            let Some(reason) = state.overdue(&self.limits) else {
+                continue;
+            };
+            for watched in &mut state.children {
+                if watched.status.is_none() {
+                    let _ = watched.child.kill();
+                }
+            }
+            state.tripped = Some(reason);
+            return;
+        }
+    }
+
+    fn moving(&self, waiting_on: Option<&str>, moved: bool) {
+        let mut state = self.lock();
+        state.waiting_on = waiting_on.map(str::to_string);
+        if moved {
+            state.moved = Instant::now();
+        }
+    }
+}
+
+impl State {
+    fn overdue(&mut self, limits: &Limits) -> Option<String> {
+        let mut running = None;
+        for watched in &mut self.children {
+            if watched.poll().ok()?.is_some() {
+                continue;
+            }
+            running = Some(watched.name.clone());
+            if let Some(limit) = watched.limit {
+                if watched.started.elapsed() > limit.unsigned_abs() {
+                    let name = &watched.name;
+                    return Some(format!("{name} timed out after {}", format_duration(limit)));
+                }
+            }
+        }
+        // Stages are added upstream first, so when nothing is blocked on a
+        // read or write, the last one still running is holding things up.
+        let running = running?;
+        let stall = limits.stall?;
+        if self.moved.elapsed() <= stall.unsigned_abs() {
+            return None;
+        }
+        let stage = self.waiting_on.as_deref().unwrap_or(&running);
+        Some(format!(
+            "{stage} stalled: no bytes moved for {}; stopped the pipeline",
+            format_duration(stall)
+        ))
+    }
+}
+
+impl Watched {
+    fn poll(&mut self) -> Result<Option<ExitStatus>> {
+        if self.status.is_none() {
+            self.status = self
+                .child
+                .try_wait()
+                .with_context(|| format!("failed to wait on {}", self.name))?;
+        }
+        Ok(self.status)
+    }
+}
+
+// Records each read or write for the stall check, and while one is blocked,
+// which stage it is waiting on.
+pub struct Metered<T> {
+    name: String,
+    inner: T,
+    shared: Arc<Shared>,
+}
+
+impl<R: Read> Read for Metered<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.shared.moving(Some(&self.name), false);
+        let read = self.inner.read(buf);
+        self.shared.moving(None, matches!(read, Ok(n) if n > 0));
+        read
+    }
+}
+
+impl<R: BufRead> BufRead for Metered<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.shared.moving(Some(&self.name), false);
+        let filled = self.inner.fill_buf();
+        self.shared.moving(None, false);
+        filled
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.shared.moving(None, amount > 0);
+        self.inner.consume(amount);
+    }
+}
+
+impl<W: Write> Write for Metered<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.shared.moving(Some(&self.name), false);
+        let written = self.inner.write(buf);
+        self.shared.moving(None, matches!(written, Ok(n) if n > 0));
+        written
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
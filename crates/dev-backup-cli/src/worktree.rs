@@ -0,0 +1,152 @@
+use crate::commands::snapshot::local_snapshot_labels;
+use crate::context::AppContext;
+use anyhow::{anyhow, Context, Result};
+use dev_backup_btrfs as btrfs;
+use dev_backup_core::config::DirtyCheck;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+use time::format_description;
+use time::OffsetDateTime;
+
+// How many of the changes found are named in the error.
+const SHOWN_CHANGES: usize = 5;
+
+// Refuses to go on when [worktree] dirty_check finds work in the dataset that
+// no snapshot holds, unless `force` is set, in which case it only warns. A
+// check that cannot run (no git, or git refusing a repository another user
+// owns) counts as dirty, so --force still gets past it.
+pub fn ensure_clean(ctx: &AppContext, force: bool) -> Result<()> {
+    let dataset = Path::new(&ctx.config.paths.dataset);
+    if !dataset.exists() {
+        return Ok(());
+    }
+    let changes = match ctx.config.worktree.dirty_check {
+        DirtyCheck::Off => return Ok(()),
+        DirtyCheck::Git => git_changes(dataset),
+        DirtyCheck::Mtime => mtime_changes(ctx, dataset),
+        DirtyCheck::Command => command_changes(&ctx.config.worktree.dirty_command, dataset),
+    };
+    let changes = changes.unwrap_or_else(|err| vec![format!("could not check it: {err:#}")]);
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let mut shown = changes.iter().take(SHOWN_CHANGES).cloned().collect::<Vec<_>>().join("; ");
+    if changes.len() > SHOWN_CHANGES {
+        shown.push_str(&format!("; and {} more", changes.len() - SHOWN_CHANGES));
+    }
+    if force {
+        ctx.logger.warn(format!("replacing a working tree with unsaved work (--force): {shown}"));
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{} has work no snapshot holds: {shown}; snapshot it first or pass --force",
+        dataset.display()
+    ))
+}
+
+// `git status --porcelain` in every repository under the dataset; one that
+// reports anything is dirty.
+fn git_changes(dataset: &Path) -> Result<Vec<String>> {
+    let mut repos = Vec::new();
+    find_repos(dataset, &mut repos)?;
+    let mut changes = Vec::new();
+    for repo in repos {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&repo)
+            .args(["status", "--porcelain"])
+            .stderr(Stdio::piped())
+            .output()
+            .context("failed to run git status")?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().next().unwrap_or_default().trim();
+            changes.push(format!("{} (git status failed: {reason})", repo.display()));
+            continue;
+        }
+        let count = String::from_utf8_lossy(&output.stdout).lines().count();
+        if count > 0 {
+            changes.push(format!("{} ({count} uncommitted change(s))", repo.display()));
+        }
+    }
+    Ok(changes)
+}
+
+// Directories holding a .git entry; their insides are left to git.
+fn find_repos(dir: &Path, repos: &mut Vec<PathBuf>) -> Result<()> {
+    if dir.join(".git").exists() {
+        repos.push(dir.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            find_repos(&entry.path(), repos)?;
+        }
+    }
+    Ok(())
+}
+
+// Files modified after the newest local snapshot was taken. With no snapshot
+// to compare against, nothing in the tree is saved.
+fn mtime_changes(ctx: &AppContext, dataset: &Path) -> Result<Vec<String>> {
+    let Some(label) = local_snapshot_labels(ctx)?.pop() else {
+        return Ok(vec!["no local snapshot to compare it with".to_string()]);
+    };
+    let taken = snapshot_taken(&ctx.snapshot_path(&label))?;
+    let mut changes = Vec::new();
+    newer_files(dataset, taken, &mut changes)?;
+    Ok(changes)
+}
+
+// The creation time btrfs records for the subvolume. The times of its root
+// directory are no use: a snapshot, received or not, keeps its source's.
+fn snapshot_taken(snapshot: &str) -> Result<SystemTime> {
+    let created = btrfs::subvolume_details(snapshot)?
+        .creation_time
+        .ok_or_else(|| anyhow!("btrfs shows no creation time for {snapshot}"))?;
+    let format = format_description::parse(
+        "[year]-[month]-[day] [hour]:[minute]:[second] [offset_hour sign:mandatory][offset_minute]",
+    )?;
+    let created = OffsetDateTime::parse(&created, &format)
+        .with_context(|| format!("unexpected creation time for {snapshot}: {created}"))?;
+    Ok(created.into())
+}
+
+fn newer_files(dir: &Path, since: SystemTime, changes: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            newer_files(&entry.path(), since, changes)?;
+        } else if entry.metadata()?.modified()? > since {
+            changes.push(format!("{} changed", entry.path().display()));
+        }
+    }
+    Ok(())
+}
+
+// Runs in the dataset; anything it prints, or a non-zero exit, means dirty.
+fn command_changes(command: &[String], dataset: &Path) -> Result<Vec<String>> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("worktree.dirty_command is empty"))?;
+    let output = Command::new(program)
+        .args(args)
+        .current_dir(dataset)
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("failed to run worktree.dirty_command {program}"))?;
+    let mut changes: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if !output.status.success() && changes.is_empty() {
+        changes.push(format!("worktree.dirty_command exited with {}", output.status));
+    }
+    Ok(changes)
+}
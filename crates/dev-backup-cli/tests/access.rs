@@ -0,0 +1,49 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn access_denies_subcommand_but_allows_siblings() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path())
+        .section("[access]\nallow = [\"restore\", \"config\"]\ndeny = [\"restore.apply\"]\n")
+        .write();
+
+    let output = run(&config_path, &["restore", "apply", "2024-01"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("`restore apply` is not permitted"), "{stderr}");
+
+    let output = run(&config_path, &["ws", "run-month", "2024-01"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("`ws run-month` is not permitted"), "{stderr}");
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn a_rule_that_names_no_command_is_rejected() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).section("[access]\ndeny = [\"restore apply\"]\n").write();
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("access.deny: unknown command \"restore apply\""), "{stderr}");
+}
+
+#[test]
+fn backup_now_refuses_before_the_snapshot_when_sync_is_denied() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().section("[access]\ndeny = [\"sync\"]\n").write();
+
+    let output = run(&config_path, &["backup-now", "--label", "2024-01"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("`sync push` is not permitted"), "{stderr}");
+    assert_eq!(fs::read_dir(tmp.path().join("snapshots")).unwrap().count(), 0);
+}
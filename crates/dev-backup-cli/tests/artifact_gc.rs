@@ -0,0 +1,57 @@
+mod common;
+
+use common::{run_ok, setup};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn gc_flags_unreferenced_and_corrupt_files_and_quarantines_them() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).crypto().write();
+    run_ok(&config_path, &["init", "ls"]);
+    let stream = root.join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    for (label, parent) in [("2024-01", None), ("2024-02", Some("2024-01"))] {
+        let mut args = vec!["artifact", "ingest", "--label", label];
+        if let Some(parent) = parent {
+            args.extend(["--parent", parent]);
+        }
+        args.push(stream.to_str().unwrap());
+        run_ok(&config_path, &args);
+    }
+
+    let ls = root.join("ls");
+    let anchor = ls.join("artifacts/anchors/dev@2024-01.full.send.zst.age");
+    let incremental = ls.join("artifacts/incr/dev@2024-02.incr.from_2024-01.send.zst.age");
+    let stray = ls.join("artifacts/anchors/dev@2023-01.full.send.zst.age");
+    fs::write(&stray, b"left behind").unwrap();
+    fs::write(&incremental, b"bit rot").unwrap();
+
+    let stdout = run_ok(&config_path, &["artifact", "gc"]);
+    assert!(
+        stdout.starts_with(
+            "artifacts/anchors/dev@2023-01.full.send.zst.age\tunreferenced\n\
+             artifacts/incr/dev@2024-02.incr.from_2024-01.send.zst.age\tchecksum mismatch\n"
+        ),
+        "{stdout}"
+    );
+    assert!(stdout.contains("rerun with --quarantine"), "{stdout}");
+    assert!(stray.exists() && incremental.exists());
+
+    let stdout = run_ok(&config_path, &["--dry-run", "artifact", "gc", "--quarantine"]);
+    assert!(stdout.contains("Dry run: would move"), "{stdout}");
+    assert!(stray.exists());
+
+    let stdout = run_ok(&config_path, &["artifact", "gc", "--quarantine"]);
+    assert!(stdout.contains("Moved 2 artifact file(s)"), "{stdout}");
+    assert!(!stray.exists() && !incremental.exists());
+    assert!(anchor.exists());
+    let quarantine = ls.join("tmp/quarantine/artifacts");
+    assert_eq!(
+        fs::read(quarantine.join("anchors/dev@2023-01.full.send.zst.age")).unwrap(),
+        b"left behind"
+    );
+    assert!(quarantine.join("incr/dev@2024-02.incr.from_2024-01.send.zst.age").exists());
+    assert_eq!(run_ok(&config_path, &["artifact", "gc"]), "");
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use common::{command, run_ok, setup};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tempfile::tempdir;
+
+fn register(config_path: &Path, artifact: &Path, flag: &str) {
+    let artifact = artifact.to_str().unwrap();
+    let args = ["--now", "2024-01-31T12:00:00Z", "artifact", "register", artifact, flag];
+    run_ok(config_path, &args);
+}
+
+fn register_all(config_path: &Path, artifacts: &[PathBuf]) -> Output {
+    command(config_path)
+        .args(["artifact", "register"])
+        .args(artifacts)
+        .output()
+        .unwrap()
+}
+
+fn manifest_rows(root: &Path) -> Vec<String> {
+    fs::read_to_string(root.join("ls/manifests/snapshots_v2.tsv"))
+        .unwrap()
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .skip(1)
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn register_copy_keeps_original() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let artifact = tmp.path().join("dev@2024-01.full.send.zst.age");
+    fs::write(&artifact, b"anchor").unwrap();
+
+    register(&config_path, &artifact, "--copy");
+
+    let stored = tmp.path().join("ls/artifacts/anchors/dev@2024-01.full.send.zst.age");
+    assert!(artifact.exists());
+    assert_eq!(fs::read(&stored).unwrap(), b"anchor");
+    let rows = manifest_rows(tmp.path());
+    assert_eq!(rows.len(), 1);
+    assert!(rows[0].starts_with("2024-01-31T12:00:00Z\t2024-01\tanchor\t"));
+    assert!(rows[0].contains(stored.to_str().unwrap()));
+}
+
+#[test]
+fn register_in_place_records_existing_path() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let artifact = tmp.path().join("dev@2024-01.full.send.zst.age");
+    fs::write(&artifact, b"anchor").unwrap();
+
+    register(&config_path, &artifact, "--in-place");
+
+    assert!(artifact.exists());
+    assert!(!tmp.path().join("ls/artifacts/anchors/dev@2024-01.full.send.zst.age").exists());
+    let rows = manifest_rows(tmp.path());
+    assert_eq!(rows.len(), 1);
+    let canonical = fs::canonicalize(&artifact).unwrap();
+    assert!(rows[0].contains(canonical.to_str().unwrap()));
+}
+
+#[test]
+fn register_several_appends_them_together() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let anchor = tmp.path().join("dev@2024-01.full.send.zst.age");
+    let incremental = tmp.path().join("dev@2024-02.incr.from_2024-01.send.zst.age");
+    fs::write(&anchor, b"anchor").unwrap();
+    fs::write(&incremental, b"incremental").unwrap();
+
+    let output = register_all(&config_path, &[anchor, incremental]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Registered 2 artifacts"), "{stdout}");
+    let rows = manifest_rows(tmp.path());
+    assert_eq!(rows.len(), 2);
+    assert!(rows[0].contains("\t2024-01\tanchor\t"), "{rows:?}");
+    assert!(rows[1].contains("\t2024-02\tincremental\t2024-01\t"), "{rows:?}");
+}
+
+#[test]
+fn register_batch_with_a_bad_name_registers_nothing() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let anchor = tmp.path().join("dev@2024-01.full.send.zst.age");
+    let stray = tmp.path().join("notes.txt");
+    fs::write(&anchor, b"anchor").unwrap();
+    fs::write(&stray, b"stray").unwrap();
+
+    let output = register_all(&config_path, &[anchor.clone(), stray]);
+    assert!(!output.status.success());
+    assert!(anchor.exists());
+    assert!(!tmp.path().join("ls/manifests/snapshots_v2.tsv").exists());
+}
+
+#[test]
+fn register_puts_the_files_back_when_the_manifest_append_fails() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let anchor = tmp.path().join("dev@2024-01.full.send.zst.age");
+    let incremental = tmp.path().join("dev@2024-02.incr.from_2024-01.send.zst.age");
+    fs::write(&anchor, b"anchor").unwrap();
+    fs::write(&incremental, b"incremental").unwrap();
+    // The rewrite goes through this temporary file, which cannot be created.
+    fs::create_dir_all(tmp.path().join("ls/manifests/snapshots_v2.tsv.tmp")).unwrap();
+
+    let output = register_all(&config_path, &[anchor.clone(), incremental.clone()]);
+    assert!(!output.status.success());
+    assert_eq!(fs::read(&anchor).unwrap(), b"anchor");
+    assert_eq!(fs::read(&incremental).unwrap(), b"incremental");
+    assert!(!tmp.path().join("ls/artifacts/anchors/dev@2024-01.full.send.zst.age").exists());
+    assert!(!tmp
+        .path()
+        .join("ls/artifacts/incrementals/dev@2024-02.incr.from_2024-01.send.zst.age")
+        .exists());
+}
+
+#[test]
+fn register_refuses_what_an_interrupted_build_left() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let partial = tmp.path().join("dev@2024-01.full.send.zst.age.partial");
+    fs::write(&partial, b"anchor").unwrap();
+    let output = register_all(&config_path, std::slice::from_ref(&partial));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("partial artifact"));
+
+    // A v2 header whose payload checksum was never filled in.
+    let body = format!(
+        "dev-backup-artifact/v2\nlabel: 2024-01\nparent: -\ncompression: zstd-3\n\
+         created: 2024-01-31T12:00:00Z\npayload-sha256: {}\n",
+        "0".repeat(64)
+    );
+    let body_path = tmp.path().join("header.txt");
+    fs::write(&body_path, &body).unwrap();
+    let digest = Command::new("sha256sum").arg(&body_path).output().unwrap();
+    let digest = String::from_utf8_lossy(&digest.stdout);
+    let digest = digest.split_whitespace().next().unwrap();
+    let artifact = tmp.path().join("dev@2024-01.full.send.zst.age");
+    fs::write(&artifact, format!("{body}header-sha256: {digest}\ntruncated")).unwrap();
+    let output = register_all(&config_path, std::slice::from_ref(&artifact));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("partial artifact left by an interrupted build"), "{stderr}");
+    assert!(artifact.exists());
+    assert!(!tmp.path().join("ls/manifests/snapshots_v2.tsv").exists());
+}
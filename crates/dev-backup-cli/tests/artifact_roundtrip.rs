@@ -0,0 +1,92 @@
+mod common;
+
+use common::{run_ok, setup};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn ingest_and_export_round_trip_in_process() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path())
+        .crypto()
+        .section("[compression]\nlevel = 9\nthreads = 2\n")
+        .write();
+    let stream = tmp.path().join("stream.bin");
+    let payload: Vec<u8> = (0..200_000u32).map(|value| (value % 251) as u8).collect();
+    fs::write(&stream, &payload).unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    let public_key = fs::read_to_string(tmp.path().join("ls/keys/ls_dev_backup.pub")).unwrap();
+    assert!(public_key.starts_with("age1"), "{public_key}");
+
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    let artifact = tmp.path().join("ls/artifacts/anchors/dev@2024-01.full.send.zst.age");
+    let encrypted = fs::read(&artifact).unwrap();
+    assert!(encrypted.starts_with(b"dev-backup-artifact/v2\n"));
+
+    let exported = tmp.path().join("exported.bin");
+    run_ok(&config_path, &["artifact", "export", "2024-01", exported.to_str().unwrap()]);
+    assert_eq!(fs::read(&exported).unwrap(), payload);
+}
+
+#[test]
+fn inspect_reads_the_header_and_headerless_artifacts_still_export() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path())
+        .crypto()
+        .section("[compression]\nlevel = 9\nthreads = 2\n")
+        .write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    let args = ["artifact", "ingest", "--label", "2024-02", "--parent", "2024-01"];
+    run_ok(&config_path, &[&args[..], &[stream.to_str().unwrap()]].concat());
+    let artifact = tmp.path().join("ls/artifacts/incr/dev@2024-02.incr.from_2024-01.send.zst.age");
+    let path = artifact.to_str().unwrap();
+
+    let report = run_ok(&config_path, &["artifact", "inspect", "--verify", path]);
+    assert!(report.starts_with("format: v2\nlabel: 2024-02\nparent: 2024-01\n"), "{report}");
+    assert!(report.contains("compression: zstd-9\n"), "{report}");
+    assert!(report.ends_with("payload: ok\n"), "{report}");
+
+    // Dropping the header leaves exactly what v1 wrote.
+    let encrypted = fs::read(&artifact).unwrap();
+    let marker = b"header-sha256: ";
+    let start = encrypted.windows(marker.len()).position(|w| w == marker).unwrap();
+    let end = start + encrypted[start..].iter().position(|b| *b == b'\n').unwrap() + 1;
+    fs::write(&artifact, &encrypted[end..]).unwrap();
+
+    let report = run_ok(&config_path, &["artifact", "inspect", path]);
+    assert!(report.starts_with("format: v1 (no header)\nlabel: 2024-02\n"), "{report}");
+    let exported = tmp.path().join("exported.bin");
+    run_ok(&config_path, &["artifact", "export", "2024-02", exported.to_str().unwrap()]);
+    assert_eq!(fs::read(&exported).unwrap(), b"send stream");
+}
+
+#[test]
+fn armored_ingest_is_ascii_and_exports() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path())
+        .crypto()
+        .section("[compression]\nlevel = 9\nthreads = 2\n")
+        .write();
+    let stream = tmp.path().join("config-dump.toml");
+    let payload = b"[paths]\ndataset = \"/srv/dev\"\n".to_vec();
+    fs::write(&stream, &payload).unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(
+        &config_path,
+        &["artifact", "ingest", "--label", "2024-02", "--armor", stream.to_str().unwrap()],
+    );
+    let artifact = tmp.path().join("ls/artifacts/anchors/dev@2024-02.full.send.zst.age");
+    let encrypted = fs::read_to_string(&artifact).unwrap();
+    assert!(encrypted.starts_with("dev-backup-artifact/v2\n"), "{encrypted}");
+    assert!(encrypted.contains("\n-----BEGIN AGE ENCRYPTED FILE-----\n"), "{encrypted}");
+    assert!(encrypted.trim_end().ends_with("-----END AGE ENCRYPTED FILE-----"));
+
+    let exported = tmp.path().join("exported.toml");
+    run_ok(&config_path, &["artifact", "export", "2024-02", exported.to_str().unwrap()]);
+    assert_eq!(fs::read(&exported).unwrap(), payload);
+}
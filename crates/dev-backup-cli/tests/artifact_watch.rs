@@ -0,0 +1,47 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn artifact_watch_once_registers_valid_artifacts() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let inbox = tmp.path().join("inbox");
+    fs::create_dir_all(&inbox).unwrap();
+
+    let good = inbox.join("dev@2024-01.full.send.zst.age");
+    fs::write(&good, b"anchor").unwrap();
+    // sha256("anchor")
+    fs::write(
+        inbox.join("dev@2024-01.full.send.zst.age.sha256"),
+        "79bfb0e2ba76b9d447606ddbcc494834f05a4c11deb052e74b49ea307a3c5bcd  dev@2024-01.full.send.zst.age\n",
+    )
+    .unwrap();
+    let bad_hash = inbox.join("dev@2024-02.incr.from_2024-01.send.zst.age");
+    fs::write(&bad_hash, b"incr").unwrap();
+    fs::write(
+        inbox.join("dev@2024-02.incr.from_2024-01.send.zst.age.sha256"),
+        "0000  dev@2024-02.incr.from_2024-01.send.zst.age\n",
+    )
+    .unwrap();
+    let bad_name = inbox.join("dev@garbage.send.zst.age");
+    fs::write(&bad_name, b"junk").unwrap();
+
+    let output = run(&config_path, &["artifact", "watch", inbox.to_str().unwrap(), "--once"]);
+    assert!(output.status.success());
+
+    let registered = tmp
+        .path()
+        .join("ls/artifacts/anchors/dev@2024-01.full.send.zst.age");
+    assert!(registered.exists());
+    assert!(!good.exists());
+    assert!(bad_hash.exists());
+    assert!(bad_name.exists());
+
+    let manifest = fs::read_to_string(tmp.path().join("ls/manifests/snapshots_v2.tsv")).unwrap();
+    let rows: Vec<&str> = manifest.lines().skip(2).collect();
+    assert_eq!(rows.len(), 1);
+    assert!(rows[0].contains("\t2024-01\tanchor\t"));
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use common::{command, setup, stdout_ok};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use tempfile::tempdir;
+
+// `btrfs send` writes its own arguments as the stream.
+const FAKE_BTRFS: &str = "#!/bin/sh\necho \"$@\"\n";
+
+// Runs from out/, which nothing should be written to.
+fn run(root: &Path, config_path: &Path, args: &[&str]) -> Output {
+    fs::create_dir_all(root.join("out")).unwrap();
+    command(config_path).args(args).current_dir(root.join("out")).output().unwrap()
+}
+
+fn run_ok(root: &Path, config_path: &Path, args: &[&str]) -> String {
+    stdout_ok(run(root, config_path, args))
+}
+
+fn row(label: &str, parent: &str) -> String {
+    let kind = if parent.is_empty() { "anchor" } else { "incremental" };
+    format!("{label}-01T00:00:00Z\t{label}\t{kind}\t{parent}\t1\taa\t\t")
+}
+
+#[test]
+fn build_picks_the_newest_manifest_label_with_a_local_snapshot() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let rows = [row("2024-01", ""), row("2024-02", "2024-01")];
+    let config_path = setup(root).crypto().btrfs(FAKE_BTRFS).manifest(&rows).write();
+    run_ok(root, &config_path, &["init", "ls"]);
+    // 2024-02 was pruned locally and 2024-04 never reached the manifest, so
+    // neither can be the parent of 2024-03.
+    for label in ["2024-01", "2024-03", "2024-04"] {
+        fs::create_dir_all(root.join(format!("snapshots/dev@{label}"))).unwrap();
+    }
+
+    let stdout = run_ok(root, &config_path, &["artifact", "build", "2024-03", "latest"]);
+    assert!(stdout.contains("Parent: dev@2024-01"), "{stdout}");
+    assert!(root.join("ls/tmp/dev@2024-03.incr.from_2024-01.send.zst.age").exists());
+
+    let args = ["artifact", "build", "2024-03", "2024-01", "--auto-parent"];
+    let output = run(root, &config_path, &args);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn build_without_an_earlier_label_is_an_anchor() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).crypto().btrfs(FAKE_BTRFS).write();
+    run_ok(root, &config_path, &["init", "ls"]);
+    fs::create_dir_all(root.join("snapshots/dev@2024-01")).unwrap();
+
+    let out = root.join("out/artifacts");
+    let args = ["artifact", "build", "2024-01", "--auto-parent", "--output-dir", out.to_str().unwrap()];
+    let stdout = run_ok(root, &config_path, &args);
+    assert!(stdout.contains("building an anchor"), "{stdout}");
+    assert!(out.join("dev@2024-01.full.send.zst.age").exists());
+}
+
+#[test]
+fn ls_send_picks_the_newest_restored_label_before_the_target() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let rows = [row("2024-01", ""), row("2024-02", "2024-01"), row("2024-03", "2024-02")];
+    let config_path = setup(root).crypto().btrfs(FAKE_BTRFS).manifest(&rows).write();
+    let restore_dir = root.join("ls/restore/snapshots");
+    for label in ["2024-01", "2024-02", "2024-03"] {
+        fs::create_dir_all(restore_dir.join(format!("dev@{label}"))).unwrap();
+    }
+
+    let stdout = run_ok(root, &config_path, &["ls", "send", "2024-03", "--auto-parent"]);
+    let expected = format!(
+        "send -p {dir}/dev@2024-02 {dir}/dev@2024-03",
+        dir = restore_dir.display()
+    );
+    assert_eq!(stdout.trim(), expected);
+
+    let stdout = run_ok(root, &config_path, &["ls", "send", "2024-02", "latest"]);
+    assert!(stdout.starts_with(&format!("send -p {}/dev@2024-01 ", restore_dir.display())));
+}
+
+#[test]
+fn build_with_register_files_the_artifact_and_its_row() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).crypto().btrfs(FAKE_BTRFS).write();
+    run_ok(root, &config_path, &["init", "ls"]);
+    fs::create_dir_all(root.join("snapshots/dev@2024-01")).unwrap();
+
+    let args = ["artifact", "build", "2024-01", "--auto-parent", "--register"];
+    let stdout = run_ok(root, &config_path, &args);
+    assert!(stdout.contains("Registered artifact"), "{stdout}");
+    let artifact = root.join("ls/artifacts/anchors/dev@2024-01.full.send.zst.age");
+    assert!(artifact.exists());
+    assert_eq!(fs::read_dir(root.join("ls/tmp")).unwrap().count(), 0);
+    let manifest = fs::read_to_string(root.join("ls/manifests/snapshots_v2.tsv")).unwrap();
+    let row = manifest.lines().last().unwrap();
+    let size = fs::metadata(&artifact).unwrap().len().to_string();
+    assert!(row.contains(&format!("\tanchor\t\t{size}\t")), "{manifest}");
+    assert!(row.contains(&artifact.display().to_string()), "{manifest}");
+}
+
+#[test]
+fn build_writes_to_the_configured_staging_directory() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let staging = root.join("staging");
+    let config_path = setup(root)
+        .paths(&format!("artifact_staging = \"{}\"\n", staging.display()))
+        .crypto()
+        .btrfs(FAKE_BTRFS)
+        .write();
+    run_ok(root, &config_path, &["init", "ls"]);
+    fs::create_dir_all(root.join("snapshots/dev@2024-01")).unwrap();
+
+    run_ok(root, &config_path, &["artifact", "build", "2024-01"]);
+    assert!(staging.join("dev@2024-01.full.send.zst.age").exists());
+    assert_eq!(fs::read_dir(root.join("out")).unwrap().count(), 0);
+}
+
+#[test]
+fn a_failed_send_says_why() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).crypto().btrfs(FAKE_BTRFS).write();
+    run_ok(root, &config_path, &["init", "ls"]);
+    fs::create_dir_all(root.join("snapshots/dev@2024-01")).unwrap();
+    let script = "#!/bin/sh\necho 'At subvol dev@2024-01' >&2\n\
+                  echo 'ERROR: send ioctl failed with -5: Input/output error' >&2\nexit 1\n";
+    fs::write(root.join("bin/btrfs"), script).unwrap();
+
+    let output = run(root, &config_path, &["artifact", "build", "2024-01"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let expected = "btrfs send failed: At subvol dev@2024-01; ERROR: send ioctl failed with -5";
+    assert!(stderr.contains(expected), "{stderr}");
+    assert!(!root.join("ls/tmp/dev@2024-01.full.send.zst.age").exists());
+}
+
+#[test]
+fn an_interrupted_build_stops_send_and_removes_the_partial_artifact() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).crypto().btrfs(FAKE_BTRFS).write();
+    run_ok(root, &config_path, &["init", "ls"]);
+    fs::create_dir_all(root.join("snapshots/dev@2024-01")).unwrap();
+    let pid_file = root.join("send.pid");
+    let script = format!(
+        "#!/bin/sh\n[ \"$1\" = send ] || exit 0\necho $$ > {}\nprintf stream\nexec sleep 60\n",
+        pid_file.display()
+    );
+    fs::write(root.join("bin/btrfs"), script).unwrap();
+
+    let mut child = command(&config_path)
+        .args(["artifact", "build", "2024-01"])
+        .current_dir(root.join("out"))
+        .spawn()
+        .unwrap();
+    let partial = root.join("ls/tmp/dev@2024-01.full.send.zst.age.partial");
+    for _ in 0..200 {
+        if partial.exists() && pid_file.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    assert!(partial.exists());
+    let interrupted = Command::new("kill").arg("-INT").arg(child.id().to_string()).status().unwrap();
+    assert!(interrupted.success());
+
+    let status = child.wait().unwrap();
+    assert_eq!(status.code(), Some(130));
+    assert!(!partial.exists());
+    let send_pid = fs::read_to_string(&pid_file).unwrap();
+    assert!(!Path::new(&format!("/proc/{}", send_pid.trim())).exists());
+}
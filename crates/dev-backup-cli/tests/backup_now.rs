@@ -0,0 +1,132 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+// Snapshots are plain directories.
+const FAKE_BTRFS: &str = "#!/bin/sh\n\
+    case \"$1 $2\" in\n\
+    \"subvolume snapshot\") mkdir -p \"$5\" ;;\n\
+    \"subvolume delete\") rm -rf \"$3\" ;;\n\
+    *) exit 1 ;;\n\
+    esac\n";
+
+// Writes each notification and then the run's status to notified.txt.
+fn notify(root: &Path) -> String {
+    let notified = root.join("notified.txt").display().to_string();
+    let script = format!("cat > {notified}; echo $DEV_BACKUP_STATUS >> {notified}");
+    format!("[notify]\ncommand = [\"sh\", \"-c\", \"{script}\"]\n")
+}
+
+#[test]
+fn backed_up_month_only_pushes_and_notifies() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().section(&notify(tmp.path())).write();
+    let artifact = tmp.path().join("dev@2024-05.full.send.zst.age");
+    fs::write(&artifact, b"anchor").unwrap();
+    let now = "2024-05-10T00:00:00Z";
+    let register = ["--now", now, "artifact", "register", artifact.to_str().unwrap()];
+    let output = run(&config_path, &register);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = run(&config_path, &["--now", "2024-05-20T00:00:00Z", "backup-now"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("dev@2024-05: already in the manifest"), "{stdout}");
+    assert!(stdout.contains("Push: done"), "{stdout}");
+    let bucket = tmp.path().join("bucket");
+    assert!(bucket.join("artifacts/anchors/dev@2024-05.full.send.zst.age").exists());
+
+    let notified = fs::read_to_string(tmp.path().join("notified.txt")).unwrap();
+    assert!(notified.contains("Status: ok"), "{notified}");
+    assert!(notified.ends_with("ok\n"), "{notified}");
+}
+
+#[test]
+fn failed_run_still_notifies() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().section(&notify(tmp.path())).write();
+
+    // The dataset is a plain directory, so the snapshot step fails.
+    let output = run(&config_path, &["--now", "2024-06-02T00:00:00Z", "backup-now"]);
+    assert!(!output.status.success());
+    let notified = fs::read_to_string(tmp.path().join("notified.txt")).unwrap();
+    assert!(notified.contains("Status: failed"), "{notified}");
+    assert!(notified.ends_with("failed\n"), "{notified}");
+}
+
+#[test]
+fn each_notify_channel_renders_its_own_template() {
+    let tmp = tempdir().unwrap();
+    let slack = tmp.path().join("slack.txt").display().to_string();
+    let mail = tmp.path().join("mail.txt").display().to_string();
+    let notify = format!(
+        "[[notify]]\nname = \"slack\"\ncommand = [\"sh\", \"-c\", \"cat > {slack}\"]\n\
+         template = \"{{outcome}}: dev@{{label}} of {{dataset}} ({{bytes}}) {{{{{{error}}}}}}\"\n\n\
+         [[notify]]\ncommand = [\"sh\", \"-c\", \"cat > {mail}\"]\n"
+    );
+    let config_path = setup(tmp.path()).cloud().section(&notify).write();
+
+    let output = run(&config_path, &["--now", "2024-06-02T00:00:00Z", "backup-now"]);
+    assert!(!output.status.success());
+    let dataset = tmp.path().join("dataset");
+    let slack = fs::read_to_string(&slack).unwrap();
+    let expected = format!("failed: dev@2024-06 of {} (0 B) {{", dataset.display());
+    assert!(slack.starts_with(&expected), "{slack}");
+    assert!(slack.ends_with("}\n"), "{slack}");
+    // No template: the plain summary.
+    let mail = fs::read_to_string(&mail).unwrap();
+    assert!(mail.contains("Status: failed"), "{mail}");
+}
+
+#[test]
+fn unknown_template_fields_are_rejected_up_front() {
+    let tmp = tempdir().unwrap();
+    let notify = "[notify]\ncommand = [\"true\"]\ntemplate = \"{host} is done\"\n";
+    let config_path = setup(tmp.path()).cloud().section(notify).write();
+
+    let output = run(&config_path, &["--now", "2024-06-02T00:00:00Z", "config", "validate"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unknown field {host}"), "{stderr}");
+}
+
+#[test]
+fn backup_run_rolls_back_the_snapshot_when_the_build_fails() {
+    let tmp = tempdir().unwrap();
+    // There is no [crypto], so the build fails.
+    let config_path = setup(tmp.path()).cloud().btrfs(FAKE_BTRFS).write();
+
+    let output = run(&config_path, &["--now", "2024-06-02T00:00:00Z", "backup", "run"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[1/5] snapshot: dev@2024-06 taken"), "{stdout}");
+    assert!(stdout.contains("[2/5] policy: anchor"), "{stdout}");
+    assert!(stdout.contains("[3/5] build: failed"), "{stdout}");
+    assert!(stdout.contains("Rolled back: removed "), "{stdout}");
+    assert!(!tmp.path().join("snapshots/dev@2024-06").exists());
+}
+
+#[test]
+fn backup_run_builds_an_anchor_after_a_restore_to_another_label() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().btrfs(FAKE_BTRFS).write();
+    let artifact = tmp.path().join("dev@2024-05.full.send.zst.age");
+    fs::write(&artifact, b"anchor").unwrap();
+    let now = "2024-05-10T00:00:00Z";
+    let register = ["--now", now, "artifact", "register", artifact.to_str().unwrap()];
+    let output = run(&config_path, &register);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    fs::create_dir_all(tmp.path().join("snapshots/dev@2024-05")).unwrap();
+    // What `restore apply 2024-04` leaves behind.
+    fs::write(tmp.path().join("snapshots/.rebaseline"), "2024-04\n").unwrap();
+
+    let output = run(&config_path, &["--now", "2024-06-02T00:00:00Z", "backup", "run"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[2/5] policy: anchor"), "{stdout}");
+    assert!(stdout.contains("restored to dev@2024-04, so this month is an anchor"), "{stdout}");
+    // The build failed (no [crypto]), so the next run still re-baselines.
+    assert!(tmp.path().join("snapshots/.rebaseline").exists());
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use tempfile::tempdir;
+
+// `btrfs send` repeats its arguments far past the 1 MiB sample.
+const FAKE_BTRFS: &str = "#!/bin/sh\nyes \"$@\" | head -c 4194304\n";
+
+#[test]
+fn bench_measures_each_stage_and_recommends_settings() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).cloud().btrfs(FAKE_BTRFS).write();
+    fs::create_dir_all(root.join("snapshots/dev@2024-01")).unwrap();
+
+    let output = run(&config_path, &["bench", "--sample-mib", "1"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("btrfs send: "), "{stdout}");
+    assert!(stdout.contains("1.0 MiB of dev@2024-01"), "{stdout}");
+    assert!(stdout.contains("zstd level 15, 0 worker(s): "), "{stdout}");
+    assert!(stdout.contains("age: "), "{stdout}");
+    assert!(stdout.contains("upload: "), "{stdout}");
+    assert!(stdout.contains("Recommended: [compression] level = "), "{stdout}");
+    let leftovers = fs::read_dir(root.join("bucket/bench")).map_or(0, |dir| dir.count());
+    assert_eq!(leftovers, 0);
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+const CHURN: &str = "[churn]\nprobe_seconds = 0\nmax_changed_bytes = 1048576\naction = \"delay\"\n";
+
+// Stands in for btrfs-progs: `find-new` reports a 1 GiB extent for each line
+// left in `<root>/churn` (consuming one per probe) and nothing after, and a
+// snapshot records how many probes ran before it.
+const FAKE_BTRFS: &str = r#"#!/bin/sh
+root=$(dirname "$(dirname "$0")")
+case "$1 $2" in
+  "subvolume show") echo "Generation: 7" ;;
+  "subvolume find-new")
+    echo probe >> "$root/probes"
+    if [ -s "$root/churn" ]; then
+      sed -i 1d "$root/churn"
+      echo "inode 257 file offset 0 len 1073741824 disk start 0 offset 0 gen 8 flags NONE data"
+    fi ;;
+  "subvolume snapshot") mkdir "$5" && wc -l < "$root/probes" > "$5/probes" ;;
+  *) echo "fake btrfs: unsupported: $*" >&2; exit 1 ;;
+esac
+"#;
+
+fn probes_before_snapshot(root: &Path) -> String {
+    fs::read_to_string(root.join("snapshots/dev@2024-01/probes")).unwrap().trim().to_string()
+}
+
+#[test]
+fn a_churning_dataset_delays_the_snapshot_until_it_settles() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let churn = format!("{CHURN}max_wait_seconds = 600\n");
+    let config_path = setup(root).btrfs(FAKE_BTRFS).section(&churn).write();
+    fs::write(root.join("churn"), "busy\nbusy\n").unwrap();
+
+    let output = run(&config_path, &["snapshot", "2024-01"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let log = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(log.matches("delaying snapshot").count(), 2, "{log}");
+    assert!(!log.contains("snapshotting anyway"), "{log}");
+    assert_eq!(probes_before_snapshot(root), "3");
+}
+
+#[test]
+fn without_max_wait_seconds_delay_only_warns() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).btrfs(FAKE_BTRFS).section(CHURN).write();
+    fs::write(root.join("churn"), "busy\nbusy\n").unwrap();
+
+    let output = run(&config_path, &["snapshot", "2024-01"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let log = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!log.contains("delaying snapshot"), "{log}");
+    assert!(log.contains("waited 0s, snapshotting anyway"), "{log}");
+    assert_eq!(probes_before_snapshot(root), "1");
+}
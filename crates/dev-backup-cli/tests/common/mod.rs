@@ -0,0 +1,232 @@
+// Shared by the integration tests: each writes a config under a temporary
+// root with `setup` and runs the binary against it. Not every test uses
+// every helper.
+#![allow(dead_code)]
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+pub const MANIFEST_HEADER: &str =
+    "ts\tlabel\ttype\tparent\tbytes\tsha256\tlocal_path\tobject_key\n";
+
+// A config at `<root>/config.toml` with dataset, snapshots and ls_root under
+// `root`; everything else is opt-in.
+pub struct Setup {
+    root: PathBuf,
+    file: String,
+    preamble: String,
+    paths: String,
+    snapshots: Option<PathBuf>,
+    datasets: Option<String>,
+    cloud: Option<PathBuf>,
+    crypto: Option<String>,
+    sections: Vec<String>,
+    btrfs: Option<String>,
+    manifest: Option<Vec<String>>,
+}
+
+pub fn setup(root: &Path) -> Setup {
+    Setup {
+        root: root.to_path_buf(),
+        file: "config.toml".to_string(),
+        preamble: String::new(),
+        paths: String::new(),
+        snapshots: None,
+        datasets: None,
+        cloud: None,
+        crypto: None,
+        sections: Vec::new(),
+        btrfs: None,
+        manifest: None,
+    }
+}
+
+impl Setup {
+    // Writes the config as `<root>/<file>`, for several hosts sharing a root.
+    pub fn named(mut self, file: &str) -> Self {
+        self.file = file.to_string();
+        self
+    }
+
+    // Lines before [paths], such as `include`.
+    pub fn preamble(mut self, toml: &str) -> Self {
+        self.preamble = toml.to_string();
+        self
+    }
+
+    // More [paths] lines.
+    pub fn paths(mut self, toml: &str) -> Self {
+        self.paths = toml.to_string();
+        self
+    }
+
+    // paths.snapshots somewhere other than `<root>/snapshots`; the test
+    // creates it, if it should exist.
+    pub fn snapshots(mut self, path: &Path) -> Self {
+        self.snapshots = Some(path.to_path_buf());
+        self
+    }
+
+    // [[dataset]] tables in place of paths.dataset and paths.snapshots; the
+    // test creates their directories.
+    pub fn datasets(mut self, toml: &str) -> Self {
+        self.datasets = Some(toml.to_string());
+        self
+    }
+
+    // A local backend at `<root>/bucket`.
+    pub fn cloud(self) -> Self {
+        let bucket = self.root.join("bucket");
+        self.cloud_at(&bucket)
+    }
+
+    // A local backend at `bucket`, for hosts sharing one.
+    pub fn cloud_at(mut self, bucket: &Path) -> Self {
+        self.cloud = Some(bucket.to_path_buf());
+        self
+    }
+
+    // The LS keypair under ls/keys, which `init ls` creates.
+    pub fn crypto(self) -> Self {
+        self.crypto_with("")
+    }
+
+    // As crypto, with more [crypto] lines.
+    pub fn crypto_with(mut self, toml: &str) -> Self {
+        self.crypto = Some(toml.to_string());
+        self
+    }
+
+    // Appended as is, after everything else.
+    pub fn section(mut self, toml: &str) -> Self {
+        self.sections.push(toml.to_string());
+        self
+    }
+
+    // A script installed as `<root>/bin/btrfs`, which `run` puts first on PATH.
+    pub fn btrfs(mut self, script: &str) -> Self {
+        self.btrfs = Some(script.to_string());
+        self
+    }
+
+    // Rows for ls/manifests/snapshots_v2.tsv, below the header.
+    pub fn manifest<S: AsRef<str>>(mut self, rows: &[S]) -> Self {
+        self.manifest = Some(rows.iter().map(|row| row.as_ref().to_string()).collect());
+        self
+    }
+
+    pub fn write(self) -> PathBuf {
+        let root = &self.root;
+        let ls_root = root.join("ls");
+        fs::create_dir_all(&ls_root).unwrap();
+        let mut contents = format!("{}[paths]\n", self.preamble);
+        if self.datasets.is_none() {
+            fs::create_dir_all(root.join("dataset")).unwrap();
+            let snapshots = match &self.snapshots {
+                Some(path) => path.clone(),
+                None => {
+                    fs::create_dir_all(root.join("snapshots")).unwrap();
+                    root.join("snapshots")
+                }
+            };
+            contents.push_str(&format!(
+                "dataset = \"{}\"\nsnapshots = \"{}\"\n",
+                root.join("dataset").display(),
+                snapshots.display()
+            ));
+        }
+        contents.push_str(&format!("ls_root = \"{}\"\n{}", ls_root.display(), self.paths));
+        if let Some(datasets) = &self.datasets {
+            contents.push_str(&format!("\n{datasets}"));
+        }
+        if let Some(bucket) = &self.cloud {
+            contents.push_str(&format!(
+                "\n[cloud]\nbackend = \"local\"\nlocal_root = \"{}\"\n",
+                bucket.display()
+            ));
+        }
+        if let Some(extra) = &self.crypto {
+            contents.push_str(&format!(
+                "\n[crypto]\nage_public_key = \"{}\"\nage_private_key_path = \"{}\"\n{extra}",
+                ls_root.join("keys/ls_dev_backup.pub").display(),
+                ls_root.join("keys/ls_dev_backup.key").display()
+            ));
+        }
+        for section in &self.sections {
+            contents.push('\n');
+            contents.push_str(section);
+        }
+        if let Some(script) = &self.btrfs {
+            fs::create_dir_all(root.join("bin")).unwrap();
+            write_script(&root.join("bin/btrfs"), script);
+        }
+        if let Some(rows) = &self.manifest {
+            write_manifest(root, rows);
+        }
+
+        let config_path = root.join(&self.file);
+        fs::write(&config_path, contents).unwrap();
+        config_path
+    }
+}
+
+// [[dataset]] tables for `home` and `projects` (prefix `proj`), for
+// Setup::datasets, with their directories created.
+pub fn two_datasets(root: &Path) -> String {
+    for dir in ["home", "home-snapshots", "projects", "projects-snapshots"] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    format!(
+        "[[dataset]]\nname = \"home\"\npath = \"{root}/home\"\n\
+         snapshots = \"{root}/home-snapshots\"\n\n\
+         [[dataset]]\nname = \"projects\"\nprefix = \"proj\"\npath = \"{root}/projects\"\n\
+         snapshots = \"{root}/projects-snapshots\"\n",
+        root = root.display(),
+    )
+}
+
+pub fn write_script(path: &Path, contents: &str) {
+    fs::write(path, contents).unwrap();
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+// Replaces ls/manifests/snapshots_v2.tsv with the header and `rows`.
+pub fn write_manifest<S: AsRef<str>>(root: &Path, rows: &[S]) {
+    let manifests = root.join("ls/manifests");
+    fs::create_dir_all(&manifests).unwrap();
+    let mut body = MANIFEST_HEADER.to_string();
+    for row in rows {
+        body.push_str(row.as_ref());
+        body.push('\n');
+    }
+    fs::write(manifests.join("snapshots_v2.tsv"), body).unwrap();
+}
+
+// The binary with --config, and `<root>/bin` first on PATH when a test put
+// stubs there.
+pub fn command(config_path: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_dev-backup"));
+    command.arg("--config").arg(config_path);
+    let bin = config_path.parent().unwrap().join("bin");
+    if bin.is_dir() {
+        let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+        command.env("PATH", path);
+    }
+    command
+}
+
+pub fn run(config_path: &Path, args: &[&str]) -> Output {
+    command(config_path).args(args).output().unwrap()
+}
+
+// Runs a command that must succeed and returns its stdout.
+pub fn run_ok(config_path: &Path, args: &[&str]) -> String {
+    stdout_ok(run(config_path, args))
+}
+
+pub fn stdout_ok(output: Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
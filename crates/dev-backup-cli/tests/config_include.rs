@@ -0,0 +1,70 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use tempfile::tempdir;
+
+// The main config carries an ssh option `config validate` rejects, so a
+// passing run shows an included file replaced it.
+const REMOTE: &str = "[remote]\nls_host = \"backup.lan\"\nssh_options = [\"-oProxyCommand\"]\n";
+
+#[test]
+fn included_files_override_the_main_config() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path())
+        .preamble("include = [\"secrets.toml\"]\n\n")
+        .section(REMOTE)
+        .write();
+    fs::write(tmp.path().join("secrets.toml"), "[remote]\nssh_options = [\"ConnectTimeout=10\"]\n")
+        .unwrap();
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn later_includes_override_earlier_ones() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path())
+        .preamble("include = [\"secrets.toml\", \"local.toml\"]\n\n")
+        .section(REMOTE)
+        .write();
+    fs::write(tmp.path().join("secrets.toml"), "[remote]\nssh_options = [\"ConnectTimeout=10\"]\n")
+        .unwrap();
+    fs::write(tmp.path().join("local.toml"), "[remote]\nssh_options = [\"-oProxyCommand\"]\n")
+        .unwrap();
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("ssh_options"), "{stderr}");
+}
+
+#[test]
+fn missing_include_is_an_error() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path())
+        .preamble("include = [\"secrets.toml\"]\n\n")
+        .section(REMOTE)
+        .write();
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("secrets.toml"), "{stderr}");
+}
+
+#[test]
+fn nested_include_is_rejected() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path())
+        .preamble("include = [\"secrets.toml\"]\n\n")
+        .section(REMOTE)
+        .write();
+    fs::write(tmp.path().join("secrets.toml"), "include = [\"more.toml\"]\n").unwrap();
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("include is only read from the main config"), "{stderr}");
+}
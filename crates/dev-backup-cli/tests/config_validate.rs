@@ -0,0 +1,94 @@
+mod common;
+
+use common::{run, setup};
+use tempfile::tempdir;
+
+#[test]
+fn config_validate_accepts_ipv6_host_and_port() {
+    let tmp = tempdir().unwrap();
+    let remote =
+        "[remote]\nls_host = \"fd00::10\"\nls_port = 2222\nssh_options = [\"ConnectTimeout=10\"]\n";
+    let config_path = setup(tmp.path()).section(remote).write();
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn config_validate_rejects_malformed_ssh_option() {
+    let tmp = tempdir().unwrap();
+    let remote = "[remote]\nls_host = \"backup.lan\"\nssh_options = [\"-oProxyCommand\"]\n";
+    let config_path = setup(tmp.path()).section(remote).write();
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("ssh_options"));
+}
+
+#[test]
+fn config_validate_accepts_sftp_url() {
+    let tmp = tempdir().unwrap();
+    for url in ["sftp://backup@nas.lan:2222/volume1/dev", "sftp://[fd00::20]/srv/dev"] {
+        let cloud = format!(
+            "[cloud]\nbackend = \"sftp\"\nurl = \"{url}\"\nssh_options = [\"ConnectTimeout=10\"]\n"
+        );
+        let config_path = setup(tmp.path()).section(&cloud).write();
+        let output = run(&config_path, &["config", "validate"]);
+        assert!(output.status.success(), "{url}: {}", String::from_utf8_lossy(&output.stderr));
+    }
+}
+
+#[test]
+fn config_validate_rejects_malformed_sftp_url() {
+    let tmp = tempdir().unwrap();
+    for url in ["s3://bucket/dev", "sftp://nas.lan", "sftp://nas;id/srv", "sftp://nas.lan:0/srv"] {
+        let cloud = format!(
+            "[cloud]\nbackend = \"sftp\"\nurl = \"{url}\"\nssh_options = [\"ConnectTimeout=10\"]\n"
+        );
+        let config_path = setup(tmp.path()).section(&cloud).write();
+        let output = run(&config_path, &["config", "validate"]);
+        assert!(!output.status.success(), "{url}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("cloud.url"), "{url}: {stderr}");
+    }
+}
+
+#[test]
+#[cfg_attr(not(feature = "cloud"), ignore = "the r2 backend needs the cloud feature")]
+fn config_validate_checks_multipart_part_size() {
+    let tmp = tempdir().unwrap();
+    for (settings, ok) in [
+        ("multipart_part_mib = 128\nmultipart_threshold_mib = 1024\n", true),
+        ("multipart_part_mib = 4\n", false),
+        ("multipart_part_mib = 128\nmultipart_threshold_mib = 64\n", false),
+    ] {
+        let cloud = format!(
+            "[cloud]\nendpoint = \"https://r2.example\"\nbucket = \"dev\"\n\
+             access_key = \"id\"\nsecret_key = \"secret\"\n{settings}"
+        );
+        let config_path = setup(tmp.path()).section(&cloud).write();
+        let output = run(&config_path, &["config", "validate"]);
+        assert_eq!(output.status.success(), ok, "{settings}");
+        if !ok {
+            assert!(String::from_utf8_lossy(&output.stderr).contains("multipart"));
+        }
+    }
+}
+
+#[test]
+fn r2_backend_needs_the_cloud_feature() {
+    let tmp = tempdir().unwrap();
+    let cloud = "[cloud]\nendpoint = \"https://r2.example\"\nbucket = \"dev\"\n\
+                 access_key = \"a\"\nsecret_key = \"s\"\n";
+    let config_path = setup(tmp.path()).section(cloud).write();
+
+    let output = run(&config_path, &["config", "validate"]);
+    if cfg!(feature = "cloud") {
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    } else {
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("built without cloud support"), "{stderr}");
+    }
+}
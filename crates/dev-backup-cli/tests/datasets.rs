@@ -0,0 +1,75 @@
+mod common;
+
+use common::{run, run_ok, setup, two_datasets};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn datasets_keep_separate_chains() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).datasets(&two_datasets(root)).cloud().crypto().write();
+    let stream = root.join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    let stream = stream.to_str().unwrap();
+
+    run_ok(&config_path, &["config", "validate"]);
+    run_ok(&config_path, &["init", "ls"]);
+    for dataset in ["home", "projects"] {
+        let args = ["--dataset", dataset, "artifact", "ingest", "--label", "2024-01", stream];
+        run_ok(&config_path, &args);
+    }
+    let ls = root.join("ls");
+    assert!(ls.join("artifacts/anchors/home@2024-01.full.send.zst.age").exists());
+    assert!(ls.join("artifacts/anchors/proj@2024-01.full.send.zst.age").exists());
+    let home = fs::read_to_string(ls.join("manifests/datasets/home.tsv")).unwrap();
+    assert!(home.contains("home@2024-01") && !home.contains("proj@"), "{home}");
+
+    // Without --dataset, status and sync push go through every dataset.
+    let status = run_ok(&config_path, &["status"]);
+    assert!(status.contains("Dataset home:") && status.contains("Dataset projects:"), "{status}");
+    assert!(status.contains("Latest: home@2024-01"), "{status}");
+    assert!(status.contains("Latest: proj@2024-01"), "{status}");
+    run_ok(&config_path, &["sync", "push"]);
+    let bucket = root.join("bucket");
+    assert!(bucket.join("manifests/datasets/home.tsv").exists());
+    assert!(bucket.join("manifests/datasets/projects.tsv").exists());
+    assert!(bucket.join("artifacts/anchors/proj@2024-01.full.send.zst.age").exists());
+
+    // Restores act on one dataset, so they have to be told which.
+    let output = run(&config_path, &["restore", "plan", "latest"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("restore plan needs --dataset (one of: home, projects)"), "{stderr}");
+    let plan = run_ok(&config_path, &["--dataset", "projects", "restore", "plan", "latest"]);
+    assert!(plan.trim().ends_with("proj@2024-01.full.send.zst.age"), "{plan}");
+
+    let output = run(&config_path, &["--dataset", "docker", "status"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no [[dataset]] named \"docker\""));
+}
+
+#[test]
+fn dataset_config_mistakes_are_rejected() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+
+    let config_path = setup(root)
+        .datasets(&two_datasets(root))
+        .section("[split]\nparts = [\"cache\"]\n")
+        .write();
+    let output = run(&config_path, &["config", "validate"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cannot be combined with [[dataset]]"), "{stderr}");
+
+    let config_path =
+        setup(root).paths("dataset = \"/home/dev\"\n").datasets(&two_datasets(root)).write();
+    let output = run(&config_path, &["config", "validate"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("[[dataset]] replaces paths.dataset"), "{stderr}");
+
+    let shared_prefix = two_datasets(root).replace("prefix = \"proj\"", "prefix = \"home\"");
+    let config_path = setup(root).datasets(&shared_prefix).write();
+    let output = run(&config_path, &["config", "validate"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("datasets share the prefix \"home\""), "{stderr}");
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn watch_stops_at_max_runtime() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let inbox = tmp.path().join("inbox");
+    fs::create_dir_all(&inbox).unwrap();
+
+    let args = ["--max-runtime", "0s", "artifact", "watch", inbox.to_str().unwrap()];
+    let output = run(&config_path, &args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Deadline reached"), "{stdout}");
+}
+
+#[test]
+fn invalid_deadline_is_rejected() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+
+    let output = run(&config_path, &["--deadline", "25:00", "config", "validate"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid deadline"), "{stderr}");
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::{run, setup, write_manifest};
+use std::fs;
+use tempfile::tempdir;
+
+// Thresholds far above any real free space put the disk in its critical state.
+const HUGE_MIB: u64 = 1 << 40;
+
+#[test]
+fn maintain_prunes_oldest_snapshots_but_keeps_parents() {
+    let tmp = tempdir().unwrap();
+    let disk = format!("[disk]\ncritical_free_mib = {HUGE_MIB}\n");
+    let config_path = setup(tmp.path()).section(&disk).write();
+    for label in ["2024-01", "2024-02", "2024-03", "2024-04"] {
+        fs::create_dir_all(tmp.path().join(format!("snapshots/dev@{label}"))).unwrap();
+    }
+    // 2024-04 is not registered yet, so the next incremental may still need 2024-02.
+    let rows = [
+        "2024-01-01T00:00:00Z\t2024-01\tanchor\t\t1\taa\t\t",
+        "2024-02-01T00:00:00Z\t2024-02\tincremental\t2024-01\t1\tbb\t\t",
+    ];
+    write_manifest(tmp.path(), &rows);
+
+    let output = run(&config_path, &["--dry-run", "ws", "maintain"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("below disk.critical_free_mib"), "{stderr}");
+    assert!(stdout.contains("would delete snapshot"), "{stdout}");
+    assert!(stdout.contains("dev@2024-01"), "{stdout}");
+    assert!(stdout.contains("dev@2024-03"), "{stdout}");
+    assert!(!stdout.contains("dev@2024-02"), "{stdout}");
+    assert!(!stdout.contains("dev@2024-04"), "{stdout}");
+    assert!(tmp.path().join("snapshots/dev@2024-01").exists());
+}
+
+#[test]
+fn maintain_does_nothing_above_the_threshold() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).section("[disk]\ncritical_free_mib = 0\n").write();
+    fs::create_dir_all(tmp.path().join("snapshots/dev@2024-01")).unwrap();
+
+    let output = run(&config_path, &["ws", "maintain"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("nothing to prune"));
+    assert!(tmp.path().join("snapshots/dev@2024-01").exists());
+}
+
+#[test]
+fn snapshot_is_refused_below_the_floor() {
+    let tmp = tempdir().unwrap();
+    let disk = format!("[disk]\ncritical_free_mib = {HUGE_MIB}\nfloor_free_mib = {HUGE_MIB}\n");
+    let config_path = setup(tmp.path()).section(&disk).write();
+
+    let output = run(&config_path, &["snapshot", "2024-05"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("below disk.floor_free_mib"), "{stderr}");
+    assert!(!tmp.path().join("snapshots/dev@2024-05").exists());
+}
+
+#[test]
+fn floor_above_critical_is_rejected() {
+    let tmp = tempdir().unwrap();
+    let disk = "[disk]\ncritical_free_mib = 100\nfloor_free_mib = 200\n";
+    let config_path = setup(tmp.path()).section(disk).write();
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("must not exceed disk.critical_free_mib"), "{stderr}");
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::{run, run_ok, setup, write_script};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Output;
+use tempfile::tempdir;
+
+// Runs with a fake `btrfs` that reports `version`, so the checks do not
+// depend on what the host has installed.
+fn doctor(config_path: &Path, version: &str, args: &[&str]) -> Output {
+    let bin = config_path.parent().unwrap().join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    write_script(&bin.join("btrfs"), &format!("#!/bin/sh\necho \"btrfs-progs {version}\"\n"));
+    run(config_path, &[&["doctor"], args].concat())
+}
+
+#[test]
+fn doctor_reports_problems_with_fixes() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).cloud().crypto().write();
+    fs::create_dir_all(root.join("bucket")).unwrap();
+    run_ok(&config_path, &["init", "ls"]);
+    let key = root.join("ls/keys/ls_dev_backup.key");
+    fs::set_permissions(&key, fs::Permissions::from_mode(0o644)).unwrap();
+    fs::set_permissions(root.join("ls/manifests"), fs::Permissions::from_mode(0o777)).unwrap();
+
+    let output = doctor(&config_path, "v4.4", &[]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("ok    config:"), "{stdout}");
+    assert!(stdout.contains("WARN  btrfs-progs: v4.4 is older than v5.10"), "{stdout}");
+    // The temp dir is not on btrfs.
+    assert!(stdout.contains("FAIL  dataset:"), "{stdout}");
+    assert!(stdout.contains(&format!("fix: chmod 600 {}", key.display())), "{stdout}");
+    assert!(stdout.contains("FAIL  permissions:"), "{stdout}");
+    assert!(stdout.contains("fix: run `dev-backup init ls`"), "{stdout}");
+    assert!(stdout.contains("ok    cloud: reachable"), "{stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("check(s) failed"), "{stderr}");
+
+    // Reapplying the policy clears the permission findings.
+    run_ok(&config_path, &["init", "ls"]);
+    fs::set_permissions(&key, fs::Permissions::from_mode(0o600)).unwrap();
+    let output = doctor(&config_path, "v6.6.3", &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("ok    btrfs-progs: v6.6.3"), "{stdout}");
+    assert!(stdout.contains("ok    keys:"), "{stdout}");
+    assert!(stdout.contains("ok    permissions:"), "{stdout}");
+}
+
+#[test]
+fn doctor_json_emits_one_object_per_check() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).cloud().crypto().write();
+
+    let output = doctor(&config_path, "v6.6.3", &["--json"]);
+    assert!(!output.status.success());
+    let checks: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let cloud = checks.iter().find(|check| check["check"] == "cloud").unwrap();
+    assert_eq!(cloud["status"], "fail");
+    assert_eq!(cloud["fix"], "mount or create local_root");
+    let keys = checks.iter().find(|check| check["check"] == "keys").unwrap();
+    assert_eq!(keys["status"], "warn");
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn dry_run_register_and_push_leave_everything_in_place() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    run_ok(&config_path, &["init", "ls"]);
+    let artifact = tmp.path().join("dev@2024-01.full.send.zst.age");
+    fs::write(&artifact, b"anchor").unwrap();
+    let manifest_path = tmp.path().join("ls/manifests/snapshots_v2.tsv");
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+
+    let register = ["--dry-run", "artifact", "register", artifact.to_str().unwrap()];
+    let stdout = run_ok(&config_path, &register);
+    let dest = tmp.path().join("ls/artifacts/anchors/dev@2024-01.full.send.zst.age");
+    let expected = format!("Dry run: would move {} to {}", artifact.display(), dest.display());
+    assert!(stdout.contains(&expected), "{stdout}");
+    assert!(stdout.contains("Dry run: would add dev@2024-01 to the manifest"), "{stdout}");
+    assert!(artifact.exists() && !dest.exists());
+    assert_eq!(fs::read_to_string(&manifest_path).unwrap(), manifest);
+
+    run_ok(&config_path, &["artifact", "register", artifact.to_str().unwrap()]);
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+    let stdout = run_ok(&config_path, &["sync", "push", "--dry-run"]);
+    let key = "artifacts/anchors/dev@2024-01.full.send.zst.age";
+    assert!(stdout.contains(&format!("Dry run: would upload {} as {key}", dest.display())));
+    assert!(stdout.contains("as manifests/snapshots_v2.tsv"), "{stdout}");
+    assert!(!tmp.path().join("bucket").exists());
+    assert_eq!(fs::read_to_string(&manifest_path).unwrap(), manifest);
+}
+
+#[test]
+fn dry_run_apply_lists_the_replacement_steps() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    run_ok(&config_path, &["init", "ls"]);
+    let artifact = tmp.path().join("dev@2024-01.full.send.zst.age");
+    fs::write(&artifact, b"anchor").unwrap();
+    run_ok(&config_path, &["artifact", "register", artifact.to_str().unwrap()]);
+    let restored = tmp.path().join("ls/restore/snapshots/dev@2024-01");
+    fs::create_dir_all(&restored).unwrap();
+    let dataset = tmp.path().join("dataset");
+    fs::write(dataset.join("live.txt"), b"live").unwrap();
+
+    let stdout = run_ok(&config_path, &["--dry-run", "restore", "apply", "2024-01"]);
+    let dataset_path = dataset.display();
+    let moved = format!("Dry run: would move {dataset_path} to {dataset_path}.old");
+    assert!(stdout.contains(&moved), "{stdout}");
+    let snapshot = format!("would snapshot {} to {}.new", restored.display(), dataset.display());
+    assert!(stdout.contains(&snapshot), "{stdout}");
+    assert!(!stdout.contains("Working tree updated"), "{stdout}");
+    assert!(dataset.join("live.txt").exists());
+}
+
+#[test]
+fn dry_run_is_refused_where_it_is_not_supported() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let output = run(&config_path, &["--dry-run", "init", "ls"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--dry-run is only supported by: restore apply"), "{stderr}");
+    assert!(!tmp.path().join("ls/manifests").exists());
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+// `btrfs subvolume show` reports the generation kept in `<path>/.gen`; `btrfs
+// send` writes its own arguments as the stream.
+const FAKE_BTRFS: &str = "#!/bin/sh\n\
+    if [ \"$1 $2\" = \"subvolume show\" ]; then\n\
+    [ -f \"$3/.gen\" ] || exit 1\n\
+    echo \"Generation: $(cat \"$3/.gen\")\"\n\
+    exit 0\n\
+    fi\n\
+    echo \"$@\"\n";
+
+// Runs a command that must succeed and returns the warnings it printed.
+fn warnings(config_path: &Path, args: &[&str]) -> String {
+    let output = run(config_path, args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn set_generation(path: PathBuf, generation: u64) {
+    fs::create_dir_all(&path).unwrap();
+    fs::write(path.join(".gen"), generation.to_string()).unwrap();
+}
+
+#[test]
+fn builds_record_generations_and_warn_when_the_dataset_is_unchanged() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).crypto().btrfs(FAKE_BTRFS).write();
+    run_ok(&config_path, &["init", "ls"]);
+    set_generation(root.join("dataset"), 10);
+    set_generation(root.join("snapshots/dev@2024-01"), 11);
+    run_ok(&config_path, &["artifact", "build", "2024-01"]);
+
+    let table = fs::read_to_string(root.join("ls/manifests/generations.tsv")).unwrap();
+    assert!(table.contains("dev@2024-01\t10\t11\n"), "{table}");
+
+    set_generation(root.join("snapshots/dev@2024-02"), 12);
+    let stderr = warnings(&config_path, &["artifact", "build", "2024-02", "2024-01"]);
+    assert!(stderr.contains("has not changed since dev@2024-01 was built"), "{stderr}");
+
+    set_generation(root.join("dataset"), 13);
+    set_generation(root.join("snapshots/dev@2024-03"), 14);
+    let stderr = warnings(&config_path, &["artifact", "build", "2024-03", "2024-02"]);
+    assert!(!stderr.contains("has not changed"), "{stderr}");
+    let table = fs::read_to_string(root.join("ls/manifests/generations.tsv")).unwrap();
+    assert!(table.contains("dev@2024-03\t13\t14\n"), "{table}");
+}
+
+#[test]
+fn a_recreated_snapshot_is_refused_as_itself_and_as_a_parent() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).crypto().btrfs(FAKE_BTRFS).write();
+    run_ok(&config_path, &["init", "ls"]);
+    set_generation(root.join("dataset"), 10);
+    set_generation(root.join("snapshots/dev@2024-01"), 11);
+    run_ok(&config_path, &["artifact", "build", "2024-01"]);
+
+    set_generation(root.join("dataset"), 20);
+    set_generation(root.join("snapshots/dev@2024-01"), 21);
+    let output = run(&config_path, &["artifact", "build", "2024-01"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("snapshot dev@2024-01 was recreated"), "{stderr}");
+
+    set_generation(root.join("snapshots/dev@2024-02"), 22);
+    let output = run(&config_path, &["artifact", "build", "2024-02", "2024-01"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("parent snapshot dev@2024-01 was recreated"), "{stderr}");
+    assert!(!root.join("ls/tmp/dev@2024-02.incr.from_2024-01.send.zst.age").exists());
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn manifest_rows_with_hostile_fields_are_rejected() {
+    let hostile_rows = [
+        "2024-01-01T00:00:00Z\t2024-01; touch pwned\tanchor\t\t1\taa\t/a\t",
+        "2024-01-01T00:00:00Z\t2024-01\tanchor\t$(id)\t1\taa\t/a\t",
+        "2024-01-01T00:00:00Z\t2024-01\tanchor\t\t1\taa\t-rf\t",
+        "2024-01-01T00:00:00Z\t2024-01\tanchor\t\t1\taa\t/a\t../../etc/passwd",
+        "2024-01-01T00:00:00Z\t2024-01\tanchor\t\t1\taa\t/a\t/etc/passwd",
+        "2024-01-01T00:00:00Z\t2024-01\tanchor; id\t\t1\taa\t/a\t",
+    ];
+    for row in hostile_rows {
+        let tmp = tempdir().unwrap();
+        let config_path = setup(tmp.path()).manifest(&[row]).write();
+
+        let output = run(&config_path, &["restore", "plan", "latest"]);
+        assert!(!output.status.success(), "accepted {row:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("invalid manifest row 2"), "{row:?}: {stderr}");
+    }
+}
+
+#[test]
+fn hostile_labels_are_rejected_before_use() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+
+    for label in ["2024-01 --help", "2024-01;id", "../2024-01", "$(id)"] {
+        let output = run(&config_path, &["snapshot", label]);
+        assert!(!output.status.success(), "accepted {label:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("label must be YYYY-MM"), "{label:?}: {stderr}");
+    }
+}
+
+#[test]
+fn artifact_names_with_hostile_labels_are_not_registered() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let artifact = tmp.path().join("dev@2024-01$(id).full.send.zst.age");
+    fs::write(&artifact, b"anchor").unwrap();
+
+    let output = run(&config_path, &["artifact", "register", "--copy", artifact.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("label must be YYYY-MM"));
+    assert!(!tmp.path().join("ls/artifacts/anchors").exists());
+    let manifest = tmp.path().join("ls/manifests/snapshots_v2.tsv");
+    let rows = fs::read_to_string(manifest).unwrap_or_default();
+    assert!(!rows.contains("$(id)"), "{rows}");
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::{command, setup};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::tempdir;
+
+// `btrfs receive` counts its runs in $COUNT and creates dev@2024-01.
+const FAKE_BTRFS: &str = r#"#!/bin/sh
+case "$1" in
+  receive)
+    cat > /dev/null
+    echo $(($(cat "$COUNT" 2>/dev/null || echo 0) + 1)) > "$COUNT"
+    mkdir "$2/dev@2024-01" ;;
+  *) exit 1 ;;
+esac
+"#;
+
+fn counting(root: &Path, config_path: &Path, args: &[&str]) -> Command {
+    let mut command = command(config_path);
+    command.args(args).env("COUNT", root.join("receives"));
+    command
+}
+
+#[test]
+fn hydrate_waits_for_and_then_skips_a_label_another_run_is_receiving() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root)
+        .crypto()
+        .section("[btrfs]\nverify_receive = \"off\"\n")
+        .btrfs(FAKE_BTRFS)
+        .write();
+    let stream = root.join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    let ingest = ["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()];
+    for args in [&["init", "ls"][..], &ingest[..]] {
+        let output = counting(root, &config_path, args).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    fs::create_dir_all(root.join("ls/locks/hydrate")).unwrap();
+    let mut held = File::create(root.join("ls/locks/hydrate/dev@2024-01.lock")).unwrap();
+    held.lock().unwrap();
+    writeln!(held, "4242").unwrap();
+    let child = counting(root, &config_path, &["restore", "hydrate", "2024-01"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+    assert!(!root.join("receives").exists(), "hydrate did not wait for the other run");
+
+    // The other run finishes its receive and lets go.
+    fs::create_dir_all(root.join("ls/restore/snapshots/dev@2024-01")).unwrap();
+    held.unlock().unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Waiting for dev@2024-01, which pid 4242 is receiving"), "{stdout}");
+    assert!(stdout.contains("Snapshot hydrated by another run"), "{stdout}");
+    assert!(!root.join("receives").exists());
+
+    // Nothing holds it now, so a fresh hydration receives it.
+    fs::remove_dir(root.join("ls/restore/snapshots/dev@2024-01")).unwrap();
+    let args = ["restore", "hydrate", "2024-01"];
+    let output = counting(root, &config_path, &args).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read_to_string(root.join("receives")).unwrap().trim(), "1");
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use common::command;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use tempfile::tempdir;
+
+// Presets fill in {home} and {user} from the environment.
+fn run_as_sam(config_path: &Path, args: &[&str]) -> Output {
+    command(config_path)
+        .args(args)
+        .env("HOME", "/home/sam")
+        .env("USER", "sam")
+        .env_remove("SUDO_USER")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn every_preset_writes_a_config_that_validates() {
+    let tmp = tempdir().unwrap();
+    for preset in ["laptop", "homelab", "team-ls"] {
+        let config_path = tmp.path().join(preset).join("config.toml");
+        let output = run_as_sam(&config_path, &["init", "--preset", preset]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains(&format!("Wrote the {preset} preset")), "{stdout}");
+
+        let contents = fs::read_to_string(&config_path).unwrap();
+        assert!(contents.contains(&format!("--config {}", config_path.display())));
+        assert!(!contents.contains("{home}") && !contents.contains("{user}"), "{contents}");
+        let output = run_as_sam(&config_path, &["config", "validate"]);
+        assert!(output.status.success(), "{preset}: {}", String::from_utf8_lossy(&output.stderr));
+    }
+    let laptop = fs::read_to_string(tmp.path().join("laptop/config.toml")).unwrap();
+    assert!(laptop.contains("dataset = \"/home/sam\""), "{laptop}");
+    assert!(laptop.contains("ls_user = \"sam\""), "{laptop}");
+}
+
+#[test]
+fn a_preset_never_replaces_an_existing_config() {
+    let tmp = tempdir().unwrap();
+    let config_path = tmp.path().join("config.toml");
+    fs::write(&config_path, "# mine\n").unwrap();
+
+    let output = run_as_sam(&config_path, &["init", "--preset", "laptop"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("already exists"), "{stderr}");
+    assert_eq!(fs::read_to_string(&config_path).unwrap(), "# mine\n");
+
+    let output = run_as_sam(&config_path, &["init", "ls", "--preset", "laptop"]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::{run, setup};
+use serde_json::Value;
+use tempfile::tempdir;
+
+const ROWS: [&str; 2] = [
+    "2024-01-01T00:00:00Z\t2024-01\tanchor\t\t60000000\taa\t\t",
+    "2024-02-01T00:00:00Z\t2024-02\tincremental\t2024-01\t30000000\tbb\t\t",
+];
+
+const RECOVERY: &str = "[recovery]\ndownload_mbps = 8\ndecrypt_mib_per_sec = 1\n\
+                        receive_mib_per_sec = 1\nobjective = \"1h\"\n";
+
+fn lines(bytes: &[u8]) -> Vec<Value> {
+    String::from_utf8_lossy(bytes)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|_| panic!("not JSON: {line}")))
+        .collect()
+}
+
+#[test]
+fn status_emits_one_object() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).section(RECOVERY).manifest(&ROWS).write();
+
+    let output = run(&config_path, &["--json", "status"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let lines = lines(&output.stdout);
+    assert_eq!(lines.len(), 1, "{lines:?}");
+    let status = &lines[0];
+    assert_eq!(status["latest"], "2024-02");
+    assert_eq!(status["artifacts"]["registered"], 2);
+    assert_eq!(status["chain_artifacts"], 2);
+    assert_eq!(status["chain_bytes"], 90000000);
+    assert_eq!(status["rto_seconds"], 261);
+    assert_eq!(status["objective_met"], true);
+}
+
+#[test]
+fn restore_plan_emits_one_record_per_line() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).section(RECOVERY).manifest(&ROWS).write();
+
+    let output = run(&config_path, &["--json", "restore", "plan", "2024-02"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let labels: Vec<Value> =
+        lines(&output.stdout).iter().map(|record| record["label"].clone()).collect();
+    assert_eq!(labels, ["2024-01", "2024-02"]);
+}
+
+#[test]
+fn errors_are_json_on_stderr() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).section(RECOVERY).manifest(&ROWS).write();
+
+    let output = run(&config_path, &["--json", "restore", "plan", "2030-01"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let errors = lines(&output.stderr);
+    assert_eq!(errors.last().unwrap()["level"], "error", "{errors:?}");
+}
+
+#[test]
+fn report_monthly_is_one_object() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).section(RECOVERY).manifest(&ROWS).write();
+
+    let output = run(&config_path, &["--json", "report", "monthly", "2024-02"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let lines = lines(&output.stdout);
+    assert_eq!(lines.len(), 1, "{lines:?}");
+    assert_eq!(lines[0]["label"], "2024-02");
+    let report = lines[0]["report"].as_str().unwrap();
+    assert!(report.starts_with("dev-backup monthly report: 2024-02\n"), "{report}");
+}
+
+#[test]
+fn no_color_leaves_plain_prefixes() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).section(RECOVERY).manifest(&ROWS).write();
+
+    let output = run(&config_path, &["--no-color", "restore", "plan", "2030-01"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("error: "), "{stderr}");
+    assert!(!stderr.contains('\x1b'), "{stderr}");
+}
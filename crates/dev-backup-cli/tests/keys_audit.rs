@@ -0,0 +1,44 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use tempfile::tempdir;
+
+const S3: &str = "[cloud]\nendpoint = \"https://example.invalid\"\nbucket = \"bucket\"\n\
+                  access_key_id = \"id\"\nsecret_access_key = \"secret\"\n";
+
+#[test]
+fn audit_flags_artifacts_only_a_missing_key_can_open() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).section(S3).crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+
+    let report = run_ok(&config_path, &["keys", "audit"]);
+    let row = report.lines().find(|line| line.starts_with("dev@2024-01")).unwrap();
+    assert_eq!(row, "dev@2024-01\t1xX25519\tprimary\tok");
+
+    // Rotate the LS key without keeping the old one around.
+    let other = tempdir().unwrap();
+    let other_config = setup(other.path()).section(S3).crypto().write();
+    run_ok(&other_config, &["init", "ls"]);
+    let key_path = tmp.path().join("ls/keys/ls_dev_backup.key");
+    let escrow = tmp.path().join("escrow.key");
+    fs::copy(&key_path, &escrow).unwrap();
+    fs::copy(other.path().join("ls/keys/ls_dev_backup.key"), &key_path).unwrap();
+
+    let output = run(&config_path, &["keys", "audit"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("dev@2024-01\t1xX25519\t-\tMISSING KEY"), "{stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("dev@2024-01"), "{stderr}");
+
+    let escrow_line = format!("escrow_identities = [\"{}\"]\n", escrow.display());
+    let config_path = setup(tmp.path()).section(S3).crypto_with(&escrow_line).write();
+    let report = run_ok(&config_path, &["keys", "audit"]);
+    let expected = format!("dev@2024-01\t1xX25519\tescrow:{}\tok", escrow.display());
+    assert!(report.contains(&expected), "{report}");
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+const FAKE_BTRFS: &str = r#"#!/bin/sh
+case "$1 $2" in
+  "subvolume snapshot")
+    shift 2
+    if [ "$1" = "-r" ]; then shift; fi
+    cp -a "$1" "$2" ;;
+  *) echo "fake btrfs: unsupported: $*" >&2; exit 1 ;;
+esac
+"#;
+
+fn naming(scheme: &str) -> String {
+    format!("[naming]\nlabel_scheme = \"{scheme}\"\n")
+}
+
+fn register(root: &Path, config_path: &Path, name: &str) -> String {
+    let artifact = root.join(name);
+    fs::write(&artifact, name).unwrap();
+    run_ok(config_path, &["artifact", "register", "--copy", artifact.to_str().unwrap()])
+}
+
+#[test]
+fn weekly_labels_are_snapshotted_registered_and_restored() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).btrfs(FAKE_BTRFS).section(&naming("week")).write();
+
+    let output = run(&config_path, &["snapshot", "2024-05"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("label must be YYYY-Www"), "{stderr}");
+
+    run_ok(&config_path, &["snapshot", "2024-W05"]);
+    assert!(root.join("snapshots/dev@2024-W05").is_dir());
+
+    register(root, &config_path, "dev@2024-W05.full.send.zst.age");
+    register(root, &config_path, "dev@2024-W06.incr.from_2024-W05.send.zst.age");
+    let plan = run_ok(&config_path, &["restore", "plan", "latest"]);
+    let plan: Vec<&str> = plan.lines().collect();
+    assert_eq!(plan.len(), 2, "{plan:?}");
+    assert!(plan[1].ends_with("dev@2024-W06.incr.from_2024-W05.send.zst.age"), "{plan:?}");
+}
+
+#[test]
+fn freeform_labels_extend_a_monthly_history() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).btrfs(FAKE_BTRFS).section(&naming("month")).write();
+    register(root, &config_path, "dev@2024-04.full.send.zst.age");
+
+    // Switching schemes keeps the monthly rows usable as parents.
+    let config = fs::read_to_string(&config_path).unwrap().replace("\"month\"", "\"freeform\"");
+    fs::write(&config_path, config).unwrap();
+    let output = run(&config_path, &["snapshot", "2024-05"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("label must be YYYY-MM-DD or YYYY-MM-DD-tag"), "{stderr}");
+
+    register(root, &config_path, "dev@2024-05-03-pre-upgrade.incr.from_2024-04.send.zst.age");
+    let plan = run_ok(&config_path, &["restore", "plan", "2024-05-03-pre-upgrade"]);
+    assert_eq!(plan.lines().count(), 2, "{plan}");
+    let status = run_ok(&config_path, &["status"]);
+    assert!(status.contains("dev@2024-05-03-pre-upgrade"), "{status}");
+}
+
+#[test]
+fn auto_labels_follow_the_scheme() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let now = ["--now", "2024-01-31T12:00:00Z"];
+    let expected = [("month", "dev@2024-01"), ("week", "dev@2024-W05"), ("day", "dev@2024-01-31")];
+    for (scheme, name) in expected {
+        let config_path = setup(root).btrfs(FAKE_BTRFS).section(&naming(scheme)).write();
+        run_ok(&config_path, &[&now[..], &["snapshot", "--auto"]].concat());
+        assert!(root.join("snapshots").join(name).is_dir(), "{scheme}");
+    }
+
+    let config_path = setup(root).btrfs(FAKE_BTRFS).section(&naming("month")).write();
+    assert!(!run(&config_path, &["snapshot"]).status.success());
+    assert!(!run(&config_path, &["snapshot", "2024-02", "--auto"]).status.success());
+}
+
+#[test]
+fn ranges_stay_within_one_scheme() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).btrfs(FAKE_BTRFS).section(&naming("week")).write();
+    for name in ["dev@2024-05", "dev@2024-02-03", "dev@2024-W05", "dev@2024-W06"] {
+        fs::create_dir_all(root.join("snapshots").join(name)).unwrap();
+    }
+    let labels = |from: &str, to: &str| {
+        let stdout = run_ok(&config_path, &["snapshot", "list", "--from", from, "--to", to]);
+        stdout.lines().map(|line| line.split('\t').next().unwrap().to_string()).collect::<Vec<_>>()
+    };
+
+    assert_eq!(labels("2024-W01", "2024-W05"), ["2024-W05"]);
+    // A day label sorts between these as a string but is not a month.
+    assert_eq!(labels("2024-01", "2024-12"), ["2024-05"]);
+
+    let output = run(&config_path, &["snapshot", "list", "--from", "2024-01", "--to", "2024-W06"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("use different label schemes (YYYY-MM and YYYY-Www)"), "{stderr}");
+}
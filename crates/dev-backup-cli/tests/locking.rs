@@ -0,0 +1,118 @@
+mod common;
+
+use common::{command, run, setup};
+use std::fs::{self, File};
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+use tempfile::tempdir;
+
+#[test]
+fn push_waits_for_a_run_holding_the_manifest_lock() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).cloud().crypto().write();
+    assert!(run(&config_path, &["init", "ls"]).status.success());
+    let stream = root.join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    let ingest = ["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()];
+    assert!(run(&config_path, &ingest).status.success());
+
+    let held = File::create(root.join("ls/locks/dev.manifest.lock")).unwrap();
+    held.lock().unwrap();
+    let mut child = command(&config_path)
+        .args(["sync", "push"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+    assert!(child.try_wait().unwrap().is_none(), "push did not wait for the lock");
+    assert!(!root.join("bucket/artifacts").exists());
+
+    held.unlock().unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("held by another run"), "{stdout}");
+    assert!(root.join("bucket/artifacts/anchors/dev@2024-01.full.send.zst.age").exists());
+}
+
+#[test]
+fn commands_that_do_not_write_ignore_held_locks() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).cloud().crypto().write();
+    assert!(run(&config_path, &["init", "ls"]).status.success());
+
+    let held = File::create(root.join("ls/locks/dev.manifest.lock")).unwrap();
+    held.lock().unwrap();
+    let output = run(&config_path, &["status"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn register_waits_for_a_push_holding_the_manifest_lock() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).cloud().crypto().write();
+    assert!(run(&config_path, &["init", "ls"]).status.success());
+    let stream = root.join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    let ingest = ["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()];
+    assert!(run(&config_path, &ingest).status.success());
+    let artifact = root.join("dev@2024-02.incr.from_2024-01.send.zst.age");
+    fs::copy(root.join("ls/artifacts/anchors/dev@2024-01.full.send.zst.age"), &artifact).unwrap();
+
+    let held = File::create(root.join("ls/locks/dev.manifest.lock")).unwrap();
+    held.lock().unwrap();
+    let mut child = command(&config_path)
+        .args(["artifact", "register", "--copy", artifact.to_str().unwrap()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+    assert!(child.try_wait().unwrap().is_none(), "register did not wait for the lock");
+
+    // Nothing is written while another run holds the lock.
+    let manifest = root.join("ls/manifests/snapshots_v2.tsv");
+    let before = fs::read_to_string(&manifest).unwrap();
+    assert!(!before.contains("2024-02"), "{before}");
+    held.unlock().unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(fs::read_to_string(&manifest).unwrap().contains("\t2024-02\t"));
+}
+
+#[test]
+fn gc_waits_for_another_run_holding_the_manifest_lock() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).cloud().crypto().write();
+    assert!(run(&config_path, &["init", "ls"]).status.success());
+    let stream = root.join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    let ingest = ["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()];
+    assert!(run(&config_path, &ingest).status.success());
+    let stray = root.join("ls/artifacts/anchors/dev@2023-01.full.send.zst.age");
+    fs::write(&stray, b"stray").unwrap();
+
+    for gc in [["artifact", "gc", "--quarantine"], ["sync", "gc", "--delete"]] {
+        let held = File::create(root.join("ls/locks/dev.manifest.lock")).unwrap();
+        held.lock().unwrap();
+        let mut child = command(&config_path)
+            .args(gc)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_millis(500));
+        assert!(child.try_wait().unwrap().is_none(), "{gc:?} did not wait for the lock");
+        held.unlock().unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(String::from_utf8_lossy(&output.stdout).contains("held by another run"));
+    }
+    assert!(!stray.exists());
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::{command, setup};
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use tempfile::tempdir;
+
+const ROW: &str = "2024-01-01T00:00:00Z\t2024-01\tanchor\t\t60000000\taa\t\t";
+
+fn run(config_path: &Path, args: &[&str]) -> Output {
+    command(config_path).args(args).env_remove("DEV_BACKUP_LOG").output().unwrap()
+}
+
+fn log_contents(logs_dir: &Path) -> String {
+    let mut contents = String::new();
+    for entry in fs::read_dir(logs_dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("dev-backup.") && name.ends_with(".log"), "{name}");
+        contents.push_str(&fs::read_to_string(&path).unwrap());
+    }
+    contents
+}
+
+#[test]
+fn runs_are_written_to_the_log_file() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).manifest(&[ROW]).write();
+    fs::create_dir_all(tmp.path().join("ls/logs")).unwrap();
+
+    let output = run(&config_path, &["status"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Latest: dev@2024-01"));
+
+    let failed = run(&config_path, &["restore", "plan", "2030-01"]);
+    assert!(!failed.status.success());
+    let stderr = String::from_utf8_lossy(&failed.stderr);
+    assert!(stderr.starts_with("error: "), "{stderr}");
+
+    let log = log_contents(&tmp.path().join("ls/logs"));
+    assert!(log.contains("INFO command{name=status}"), "{log}");
+    assert!(log.contains("Latest: dev@2024-01"), "{log}");
+    assert!(log.contains("ERROR command{name=restore plan}"), "{log}");
+}
+
+#[test]
+fn quiet_hides_progress_but_still_logs_it() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).manifest(&[ROW]).write();
+    fs::create_dir_all(tmp.path().join("ls/logs")).unwrap();
+
+    let output = run(&config_path, &["--quiet", "status"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.is_empty(), "{}", String::from_utf8_lossy(&output.stdout));
+    let log = log_contents(&tmp.path().join("ls/logs"));
+    assert!(log.contains("Latest: dev@2024-01"), "{log}");
+}
+
+#[test]
+fn verbose_shows_debug_events() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).manifest(&[ROW]).write();
+    fs::create_dir_all(tmp.path().join("ls/logs")).unwrap();
+
+    let output = run(&config_path, &["--verbose", "status"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("debug: loading config"), "{stderr}");
+}
+
+#[test]
+fn encrypted_logs_only_open_with_the_ls_identity() {
+    let tmp = tempdir().unwrap();
+    let logs = "[logs]\nencrypt = true\n";
+    let config_path = setup(tmp.path()).crypto().section(logs).manifest(&[ROW]).write();
+    fs::create_dir_all(tmp.path().join("ls/logs")).unwrap();
+    let keys = tmp.path().join("ls/keys");
+    let output = run(&config_path, &["init", "ls"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = run(&config_path, &["status"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!run(&config_path, &["restore", "plan", "2030-01"]).status.success());
+
+    let mut logs = Vec::new();
+    for entry in fs::read_dir(tmp.path().join("ls/logs")).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("dev-backup.") && name.ends_with(".log.age"), "{name}");
+        let bytes = fs::read(&path).unwrap();
+        assert!(bytes.starts_with(b"age-encryption.org/v1"), "{name}");
+        assert!(!String::from_utf8_lossy(&bytes).contains("2024-01"), "{name}");
+        logs.push(path.to_string_lossy().to_string());
+    }
+    logs.sort();
+
+    let args: Vec<&str> =
+        ["logs", "decrypt"].into_iter().chain(logs.iter().map(String::as_str)).collect();
+    let output = run(&config_path, &args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let log = String::from_utf8_lossy(&output.stdout);
+    assert!(log.contains("Latest: dev@2024-01"), "{log}");
+    assert!(log.contains("ERROR command{name=restore plan}"), "{log}");
+
+    // Without the identity the files stay closed.
+    fs::remove_file(keys.join("ls_dev_backup.key")).unwrap();
+    let output = run(&config_path, &args);
+    assert!(!output.status.success());
+}
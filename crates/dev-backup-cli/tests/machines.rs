@@ -0,0 +1,116 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+// Every config shares one LS and bucket; `machine` is its naming.machine_id.
+fn machine_config(root: &Path, machine: Option<&str>) -> PathBuf {
+    let config = setup(root).named(&format!("{}.toml", machine.unwrap_or("ls"))).cloud().crypto();
+    match machine {
+        Some(id) => config.section(&format!("[naming]\nmachine_id = \"{id}\"\n")).write(),
+        None => config.write(),
+    }
+}
+
+#[test]
+fn two_workstations_share_one_ls_and_bucket() {
+    let tmp = tempdir().unwrap();
+    let ls = machine_config(tmp.path(), None);
+    run_ok(&ls, &["init", "ls"]);
+    for machine in ["ws1", "ws2"] {
+        let stream = tmp.path().join(format!("{machine}.bin"));
+        fs::write(&stream, machine).unwrap();
+        let config = machine_config(tmp.path(), Some(machine));
+        run_ok(&config, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    }
+
+    let key = "artifacts/machines/ws2/anchors/ws2~dev@2024-01.full.send.zst.age";
+    assert!(tmp.path().join("ls").join(key).exists());
+    let manifest = fs::read_to_string(tmp.path().join("ls/manifests/snapshots_v2.tsv")).unwrap();
+    assert!(manifest.contains("\tregistered\tws1\n"), "{manifest}");
+    assert!(manifest.contains("\tregistered\tws2\n"), "{manifest}");
+
+    // Each workstation only sees its own rows; the LS has to be told which.
+    let ws1 = machine_config(tmp.path(), Some("ws1"));
+    let plan = run_ok(&ws1, &["restore", "plan", "2024-01"]);
+    assert!(plan.contains("/machines/ws1/"), "{plan}");
+    let output = run(&ls, &["restore", "plan", "2024-01"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("more than one machine (ws1, ws2)"), "{stderr}");
+    let plan = run_ok(&ls, &["restore", "plan", "2024-01", "--machine", "ws2"]);
+    assert!(plan.trim_end().ends_with(key), "{plan}");
+
+    run_ok(&ls, &["sync", "push"]);
+    let pulled = tmp.path().join("pulled");
+    let dest = pulled.to_str().unwrap();
+    let output = run(&ls, &["sync", "pull", "2024-01", dest]);
+    assert!(!output.status.success());
+    run_ok(&ls, &["sync", "pull", "2024-01", dest, "--machine", "ws1"]);
+    assert!(pulled.join("artifacts/machines/ws1/anchors").exists());
+    assert!(!pulled.join("artifacts/machines/ws2").exists());
+}
+
+#[test]
+fn gc_on_one_workstation_keeps_what_other_machines_reference() {
+    let tmp = tempdir().unwrap();
+    let ls = machine_config(tmp.path(), None);
+    run_ok(&ls, &["init", "ls"]);
+    // A row from before machine ids, with an empty machine column.
+    let stream = tmp.path().join("ls.bin");
+    fs::write(&stream, "ls").unwrap();
+    run_ok(&ls, &["artifact", "ingest", "--label", "2024-02", stream.to_str().unwrap()]);
+    for machine in ["ws1", "ws2"] {
+        let stream = tmp.path().join(format!("{machine}.bin"));
+        fs::write(&stream, machine).unwrap();
+        let config = machine_config(tmp.path(), Some(machine));
+        run_ok(&config, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    }
+    run_ok(&ls, &["sync", "push"]);
+
+    let ws1 = machine_config(tmp.path(), Some("ws1"));
+    let listed = run_ok(&ws1, &["sync", "gc", "--delete"]);
+    assert!(listed.contains("Deleted 0 unreferenced object(s)"), "{listed}");
+    let listed = run_ok(&ws1, &["artifact", "gc", "--quarantine"]);
+    assert!(!listed.contains('\t'), "{listed}");
+    for key in [
+        "artifacts/machines/ws2/anchors/ws2~dev@2024-01.full.send.zst.age",
+        "artifacts/anchors/dev@2024-02.full.send.zst.age",
+    ] {
+        assert!(tmp.path().join("bucket").join(key).exists(), "{key}");
+        assert!(tmp.path().join("ls").join(key).exists(), "{key}");
+    }
+}
+
+#[test]
+fn rewriting_one_machines_rows_keeps_the_file_order() {
+    let tmp = tempdir().unwrap();
+    let ls = machine_config(tmp.path(), None);
+    run_ok(&ls, &["init", "ls"]);
+    let ws1 = machine_config(tmp.path(), Some("ws1"));
+    let ws2 = machine_config(tmp.path(), Some("ws2"));
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    let source = stream.to_str().unwrap();
+    // ws2's clock is behind, so its row is out of timestamp order.
+    for (config, now, label) in [
+        (&ws1, "2024-02-01T00:00:00Z", "2024-02"),
+        (&ws2, "2024-01-01T00:00:00Z", "2024-01"),
+        (&ws1, "2024-03-01T00:00:00Z", "2024-03"),
+    ] {
+        run_ok(config, &["--now", now, "artifact", "ingest", "--label", label, source]);
+    }
+
+    run_ok(&ws1, &["sync", "push"]);
+    let manifest = fs::read_to_string(tmp.path().join("ls/manifests/snapshots_v2.tsv")).unwrap();
+    let labels: Vec<&str> = manifest
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .skip(1)
+        .map(|line| line.split('\t').nth(1).unwrap())
+        .collect();
+    assert_eq!(labels, ["2024-02", "2024-01", "2024-03"], "{manifest}");
+    assert_eq!(manifest.matches("\tpushed\t").count(), 2, "{manifest}");
+}
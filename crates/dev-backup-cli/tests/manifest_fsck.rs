@@ -0,0 +1,78 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use tempfile::tempdir;
+
+const GOOD_ROW: &str = "2024-01-01T00:00:00Z\t2024-01\tanchor\t\t1\taa\t/a\t";
+const SHORT_ROW: &str = "2024-02-01T00:00:00Z\t2024-02\tincremental\t2024-01\t1";
+const BAD_BYTES_ROW: &str = "2024-03-01T00:00:00Z\t2024-03\tincremental\t2024-01\tlots\tcc\t/c\t";
+const ROWS: [&str; 3] = [GOOD_ROW, SHORT_ROW, BAD_BYTES_ROW];
+
+#[test]
+fn malformed_rows_are_skipped_with_a_warning() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).manifest(&ROWS).write();
+
+    let output = run(&config_path, &["restore", "plan", "latest"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "/a");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("skipping invalid manifest row 3: expected 8 fields, found 5"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("invalid manifest row 4: bytes: invalid digit"), "{stderr}");
+}
+
+#[test]
+fn verify_and_rewrites_refuse_malformed_rows() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).manifest(&ROWS).write();
+
+    let output = run(&config_path, &["verify", "--all"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid manifest row 3"), "{stderr}");
+    assert!(stderr.contains("(and 1 more)"), "{stderr}");
+
+    let manifest = tmp.path().join("ls/manifests/snapshots_v2.tsv");
+    let before = fs::read_to_string(&manifest).unwrap();
+    // The good row is dated after --now, so fix-timestamps has a change to write.
+    let args = ["manifest", "fix-timestamps", "--now", "2023-12-01T00:00:00Z"];
+    let output = run(&config_path, &args);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("refusing to rewrite a manifest with malformed rows"), "{stderr}");
+    assert_eq!(fs::read_to_string(&manifest).unwrap(), before);
+}
+
+#[test]
+fn fsck_fix_quarantines_malformed_rows() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).manifest(&ROWS).write();
+    let manifests = tmp.path().join("ls/manifests");
+
+    let output = run(&config_path, &["manifest", "fsck"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("invalid manifest row 3: expected 8 fields"), "{stdout}");
+    assert!(stdout.contains("invalid manifest row 4: bytes:"), "{stdout}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("2 malformed manifest row(s)"));
+
+    let output = run(&config_path, &["manifest", "fsck", "--fix"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let quarantine = fs::read_to_string(manifests.join("snapshots_v2.tsv.quarantine")).unwrap();
+    assert!(quarantine.contains("# line 3: expected 8 fields, found 5\n"), "{quarantine}");
+    assert!(quarantine.contains(&format!("{SHORT_ROW}\n")), "{quarantine}");
+    assert!(quarantine.contains(&format!("{BAD_BYTES_ROW}\n")), "{quarantine}");
+    let manifest = fs::read_to_string(manifests.join("snapshots_v2.tsv")).unwrap();
+    assert_eq!(manifest.lines().count(), 3, "{manifest}");
+    assert!(manifest.contains("\t2024-01\t"));
+
+    let output = run(&config_path, &["manifest", "fsck"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No malformed manifest rows"));
+    let output = run(&config_path, &["verify", "--all"]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("invalid manifest row"));
+}
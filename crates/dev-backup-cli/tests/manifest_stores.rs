@@ -0,0 +1,180 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use tempfile::tempdir;
+
+const SQLITE_REPLICA: &str = "[manifest]\nreplica = \"sqlite\"\n";
+const SQLITE_PRIMARY: &str = "[manifest]\nprimary = \"sqlite\"\nreplica = \"tsv\"\n";
+const SQLITE_ONLY: &str = "[manifest]\nprimary = \"sqlite\"\n";
+
+#[test]
+fn writes_are_mirrored_and_either_store_can_be_primary() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).crypto().section(SQLITE_REPLICA).write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    assert!(tmp.path().join("ls/manifests/snapshots_v2.sqlite").exists());
+    let stdout = run_ok(&config_path, &["manifest", "check"]);
+    assert!(stdout.contains("Manifest stores agree: 1 record(s) in tsv and sqlite"), "{stdout}");
+
+    let config_path = setup(tmp.path()).crypto().section(SQLITE_PRIMARY).write();
+    let plan = run_ok(&config_path, &["restore", "plan", "2024-01"]);
+    assert!(plan.contains("dev@2024-01.full.send.zst.age"), "{plan}");
+}
+
+#[test]
+fn check_reports_drift_and_repair_rewrites_replica() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).crypto().write();
+    let manifest_dir = tmp.path().join("ls/manifests");
+    fs::create_dir_all(&manifest_dir).unwrap();
+    fs::write(
+        manifest_dir.join("snapshots_v2.tsv"),
+        "ts\tlabel\ttype\tparent\tbytes\tsha256\tlocal_path\tobject_key\n\
+         2024-01-01T00:00:00Z\t2024-01\tanchor\t\t1\taa\t\t\n",
+    )
+    .unwrap();
+
+    let output = run(&config_path, &["manifest", "check"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no manifest replica"));
+
+    let config_path = setup(tmp.path()).crypto().section(SQLITE_REPLICA).write();
+    let output = run(&config_path, &["manifest", "check"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("row 1: dev@2024-01 missing from replica"), "{stdout}");
+
+    let stdout = run_ok(&config_path, &["manifest", "check", "--repair"]);
+    assert!(stdout.contains("Rewrote the sqlite replica from tsv (1 record(s))"), "{stdout}");
+    run_ok(&config_path, &["manifest", "check"]);
+}
+
+#[test]
+fn tsv_store_cannot_be_dropped() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).crypto().section(SQLITE_ONLY).write();
+    let output = run(&config_path, &["status"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("tsv store"));
+}
+
+#[test]
+fn appending_to_a_manifest_without_mirror_keys_upgrades_it() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).crypto().write();
+    let manifest_dir = tmp.path().join("ls/manifests");
+    fs::create_dir_all(&manifest_dir).unwrap();
+    fs::write(
+        manifest_dir.join("snapshots_v2.tsv"),
+        "ts\tlabel\ttype\tparent\tbytes\tsha256\tlocal_path\tobject_key\n\
+         2024-01-01T00:00:00Z\t2024-01\tanchor\t\t1\taa\t\t\n",
+    )
+    .unwrap();
+    let artifact = tmp.path().join("dev@2024-02.incr.from_2024-01.send.zst.age");
+    fs::write(&artifact, b"incr").unwrap();
+
+    run_ok(&config_path, &["artifact", "register", artifact.to_str().unwrap()]);
+    let manifest = fs::read_to_string(manifest_dir.join("snapshots_v2.tsv")).unwrap();
+    let mut lines = manifest.lines().skip(1);
+    let header = lines.next().unwrap();
+    assert!(header.ends_with("\tobject_key\tmirror_keys\tstatus\tmachine"), "{manifest}");
+    assert!(lines.all(|line| line.ends_with("\tregistered\t")), "{manifest}");
+    let plan = run_ok(&config_path, &["restore", "plan", "2024-02"]);
+    assert_eq!(plan.lines().count(), 2, "{plan}");
+}
+
+#[test]
+fn batch_register_lands_in_both_stores() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).crypto().section(SQLITE_PRIMARY).write();
+    let anchor = tmp.path().join("dev@2024-01.full.send.zst.age");
+    let incremental = tmp.path().join("dev@2024-02.incr.from_2024-01.send.zst.age");
+    fs::write(&anchor, b"anchor").unwrap();
+    fs::write(&incremental, b"incremental").unwrap();
+
+    run_ok(
+        &config_path,
+        &["artifact", "register", anchor.to_str().unwrap(), incremental.to_str().unwrap()],
+    );
+    let stdout = run_ok(&config_path, &["manifest", "check"]);
+    assert!(stdout.contains("Manifest stores agree: 2 record(s)"), "{stdout}");
+}
+
+#[test]
+fn migrate_imports_the_tsv_manifest_into_sqlite() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    run_ok(&config_path, &["init", "ls"]);
+    for label in ["2024-01", "2024-02"] {
+        run_ok(&config_path, &["artifact", "ingest", "--label", label, stream.to_str().unwrap()]);
+    }
+
+    let stdout = run_ok(&config_path, &["manifest", "migrate", "--sqlite"]);
+    assert!(stdout.contains("Imported 2 record(s)"), "{stdout}");
+    assert!(stdout.contains("(schema version 5)"), "{stdout}");
+    assert!(stdout.contains("primary = \"sqlite\""), "{stdout}");
+    let stdout = run_ok(&config_path, &["manifest", "migrate", "--sqlite"]);
+    assert!(stdout.contains("already holds the 2 record(s)"), "{stdout}");
+
+    let config_path = setup(tmp.path()).crypto().section(SQLITE_PRIMARY).write();
+    let stdout = run_ok(&config_path, &["manifest", "check"]);
+    assert!(stdout.contains("Manifest stores agree: 2 record(s)"), "{stdout}");
+    // Looked up through the label index; the push that follows has no [cloud].
+    let output = run(&config_path, &["backup-now", "--label", "2024-02"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("dev@2024-02: already in the manifest"), "{stdout}");
+
+    // A database that disagrees with the TSV is not overwritten.
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-03", stream.to_str().unwrap()]);
+    fs::write(tmp.path().join("ls/manifests/snapshots_v2.tsv"), "").unwrap();
+    let output = run(&config_path, &["manifest", "migrate"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("already holds 3 different record(s)"), "{stderr}");
+}
+
+#[test]
+fn migrate_upgrades_older_tsv_layouts() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).crypto().write();
+    let manifest_dir = tmp.path().join("ls/manifests");
+    fs::create_dir_all(&manifest_dir).unwrap();
+    fs::write(
+        manifest_dir.join("snapshots_v1.tsv"),
+        "ts\tlabel\ttype\tparent\tbytes\tsha256\tlocal_path\tobject_key\n\
+         2024-01-01T00:00:00Z\t2024-01\tanchor\t\t1\taa\t\t\n",
+    )
+    .unwrap();
+
+    let stdout = run_ok(&config_path, &["--dry-run", "manifest", "migrate"]);
+    assert!(stdout.contains("would upgrade"), "{stdout}");
+    assert!(!manifest_dir.join("snapshots_v2.tsv").exists());
+
+    let stdout = run_ok(&config_path, &["manifest", "migrate"]);
+    assert!(stdout.contains("(schema 1 (unversioned))"), "{stdout}");
+    assert!(!manifest_dir.join("snapshots_v2.sqlite").exists());
+    let manifest = fs::read_to_string(manifest_dir.join("snapshots_v2.tsv")).unwrap();
+    assert!(manifest.starts_with("# dev-backup manifest schema 4\nts\t"), "{manifest}");
+    assert!(manifest.contains("\t2024-01\t"), "{manifest}");
+    let stdout = run_ok(&config_path, &["manifest", "migrate"]);
+    assert!(stdout.contains("already at schema 4"), "{stdout}");
+    let plan = run_ok(&config_path, &["restore", "plan", "2024-01"]);
+    assert_eq!(plan.lines().count(), 1, "{plan}");
+
+    // A manifest from a newer dev-backup is refused rather than misread.
+    fs::write(
+        manifest_dir.join("snapshots_v2.tsv"),
+        manifest.replace("schema 4", "schema 9"),
+    )
+    .unwrap();
+    let output = run(&config_path, &["restore", "plan", "2024-01"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("uses manifest schema 9"));
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn fix_timestamps_pulls_future_row_before_its_successor() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path())
+        .manifest(&[
+            "2024-01-01T00:00:00Z\t2024-01\tanchor\t\t1\taa\t/a\t",
+            "2099-01-01T00:00:00Z\t2024-02\tincremental\t2024-01\t1\tbb\t/b\t",
+            "2024-03-01T00:00:00Z\t2024-03\tincremental\t2024-02\t1\tcc\t/c\t",
+        ])
+        .write();
+    let manifest_path = tmp.path().join("ls/manifests/snapshots_v2.tsv");
+
+    let output = run(&config_path, &["manifest", "fix-timestamps"]);
+    assert!(output.status.success());
+
+    let manifest = fs::read_to_string(&manifest_path).unwrap();
+    let timestamps: Vec<&str> = manifest
+        .lines()
+        .skip(2)
+        .map(|line| line.split('\t').next().unwrap())
+        .collect();
+    assert_eq!(
+        timestamps,
+        vec!["2024-01-01T00:00:00Z", "2024-02-29T23:59:59Z", "2024-03-01T00:00:00Z"]
+    );
+    assert!(manifest_path.with_extension("tsv.bak").exists());
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::{command, setup};
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use tempfile::tempdir;
+
+fn run(config_path: &Path, args: &[&str]) -> Output {
+    command(config_path).args(["--now", "2024-01-31T12:00:00Z"]).args(args).output().unwrap()
+}
+
+#[test]
+fn register_and_plan_follow_the_template() {
+    let tmp = tempdir().unwrap();
+    let naming =
+        "[naming]\nprefix = \"home\"\nsnapshot_name_template = \"backup-{prefix}-{label}\"\n";
+    let config_path = setup(tmp.path()).section(naming).write();
+    let artifact = tmp.path().join("backup-home-2024-01.full.send.zst.age");
+    fs::write(&artifact, b"anchor").unwrap();
+    let path = artifact.to_str().unwrap();
+
+    let output = run(&config_path, &["artifact", "register", path, "--copy"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stored = tmp.path().join("ls/artifacts/anchors/backup-home-2024-01.full.send.zst.age");
+    assert!(stored.exists());
+
+    let output = run(&config_path, &["restore", "plan", "2024-01"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let plan = String::from_utf8_lossy(&output.stdout);
+    assert!(plan.contains("backup-home-2024-01.full.send.zst.age"), "{plan}");
+
+    let old = tmp.path().join("dev@2024-02.incr.from_2024-01.send.zst.age");
+    fs::write(&old, b"incr").unwrap();
+    let output = run(&config_path, &["artifact", "register", old.to_str().unwrap(), "--copy"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid artifact name"));
+}
+
+#[test]
+fn validate_rejects_template_without_label() {
+    let tmp = tempdir().unwrap();
+    let naming = "[naming]\nsnapshot_name_template = \"{prefix}-snap\"\n";
+    let config_path = setup(tmp.path()).section(naming).write();
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("{label}"), "{stderr}");
+}
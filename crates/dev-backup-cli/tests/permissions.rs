@@ -0,0 +1,69 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tempfile::tempdir;
+
+const MODES: &str = "[permissions.artifacts]\nmode = \"0750\"\n\n\
+                     [permissions.manifests]\nmode = \"0710\"\n";
+
+// doctor checks for btrfs on PATH; a stub keeps it off the host's install.
+const BTRFS: &str = "#!/bin/sh\necho \"btrfs-progs v6.6\"\n";
+
+fn mode(path: &Path) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode() & 0o7777
+}
+
+#[test]
+fn init_and_register_apply_the_configured_modes() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).crypto().section(MODES).btrfs(BTRFS).write();
+    run_ok(&config_path, &["init", "ls"]);
+
+    let ls = root.join("ls");
+    assert_eq!(mode(&ls.join("artifacts")), 0o750);
+    assert_eq!(mode(&ls.join("artifacts/anchors")), 0o750);
+    assert_eq!(mode(&ls.join("artifacts/incr")), 0o750);
+    assert_eq!(mode(&ls.join("manifests")), 0o710);
+    assert_eq!(mode(&ls.join("manifests/snapshots_v2.tsv")), 0o600);
+    // Classes without a [permissions] entry fall back to 0700.
+    assert_eq!(mode(&ls.join("keys")), 0o700);
+    assert_eq!(mode(&ls.join("tmp")), 0o700);
+
+    let artifact = root.join("dev@2024-01.full.send.zst.age");
+    fs::write(&artifact, b"anchor").unwrap();
+    fs::set_permissions(&artifact, fs::Permissions::from_mode(0o666)).unwrap();
+    run_ok(&config_path, &["artifact", "register", artifact.to_str().unwrap(), "--copy"]);
+
+    let stored = ls.join("artifacts/anchors/dev@2024-01.full.send.zst.age");
+    assert_eq!(mode(&stored), 0o640);
+    assert_eq!(mode(&ls.join("manifests/snapshots_v2.tsv")), 0o600);
+}
+
+#[test]
+fn doctor_reports_a_loosened_mode() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).crypto().section(MODES).btrfs(BTRFS).write();
+    run_ok(&config_path, &["init", "ls"]);
+    let artifact = root.join("dev@2024-01.full.send.zst.age");
+    fs::write(&artifact, b"anchor").unwrap();
+    run_ok(&config_path, &["artifact", "register", artifact.to_str().unwrap(), "--copy"]);
+
+    let stdout = String::from_utf8_lossy(&run(&config_path, &["doctor"]).stdout).into_owned();
+    assert!(stdout.contains("ok    permissions:"), "{stdout}");
+
+    let stored = root.join("ls/artifacts/anchors/dev@2024-01.full.send.zst.age");
+    fs::set_permissions(&stored, fs::Permissions::from_mode(0o644)).unwrap();
+    let output = run(&config_path, &["doctor"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("FAIL  permissions:"), "{stdout}");
+    assert!(
+        stdout.contains(&format!("{} has mode 0644, expected at most 0640", stored.display())),
+        "{stdout}"
+    );
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use tempfile::tempdir;
+
+// Runs the binary with fd 3 redirected to `events`.
+fn run_with_fd3(config_path: &Path, events: &Path, args: &[&str]) -> Output {
+    Command::new("sh")
+        .arg("-c")
+        .arg("exec \"$0\" \"$@\" 3>>\"$EVENTS\"")
+        .arg(env!("CARGO_BIN_EXE_dev-backup"))
+        .arg("--config")
+        .arg(config_path)
+        .args(args)
+        .env("EVENTS", events)
+        .output()
+        .unwrap()
+}
+
+fn field<'a>(line: &'a str, name: &str) -> &'a str {
+    let start = line.find(&format!("\"{name}\":")).unwrap() + name.len() + 3;
+    let rest = &line[start..];
+    &rest[..rest.find([',', '}']).unwrap()]
+}
+
+#[test]
+fn sync_push_reports_progress_on_the_given_fd() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    run_ok(&config_path, &["init", "ls"]);
+    for (label, body) in [("2024-01", &b"first stream"[..]), ("2024-02", &[7u8; 4096][..])] {
+        let stream = tmp.path().join(format!("{label}.bin"));
+        fs::write(&stream, body).unwrap();
+        run_ok(&config_path, &["artifact", "ingest", "--label", label, stream.to_str().unwrap()]);
+    }
+    let total: u64 = ["dev@2024-01.full", "dev@2024-02.full"]
+        .iter()
+        .map(|name| {
+            let path = tmp.path().join(format!("ls/artifacts/anchors/{name}.send.zst.age"));
+            fs::metadata(path).unwrap().len()
+        })
+        .sum();
+
+    let events = tmp.path().join("events.jsonl");
+    let output = run_with_fd3(&config_path, &events, &["sync", "push", "--progress-fd", "3"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("\"event\""), "{stdout}");
+    let summary = stdout.lines().last().unwrap();
+    assert!(summary.starts_with("Summary: sync "), "{stdout}");
+    assert!(summary.ends_with(" KiB"), "{stdout}");
+
+    let events = fs::read_to_string(&events).unwrap();
+    let lines: Vec<&str> = events.lines().collect();
+    let first = lines.first().unwrap();
+    assert_eq!(field(first, "event"), "\"start\"", "{events}");
+    assert_eq!(field(first, "stage"), "\"sync\"", "{events}");
+    assert_eq!(field(first, "total"), total.to_string(), "{events}");
+    assert!(events.contains("dev@2024-02.full.send.zst.age"), "{events}");
+    let last = lines.last().unwrap();
+    assert_eq!(field(last, "event"), "\"done\"", "{events}");
+    assert_eq!(field(last, "bytes"), total.to_string(), "{events}");
+}
+
+#[test]
+fn progress_fd_must_be_open() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let output = run(&config_path, &["status", "--progress-fd", "99"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--progress-fd 99 is not open for writing"), "{stderr}");
+}
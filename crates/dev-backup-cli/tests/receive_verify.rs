@@ -0,0 +1,157 @@
+mod common;
+
+use common::{command, setup};
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use tempfile::tempdir;
+
+// `btrfs receive` counts its runs in $COUNT and creates dev@2024-01, which
+// only gets a received UUID ($RECEIVED_UUID) from run $GOOD_FROM on. While
+// $COUNT.fail exists it fails once the directory is there.
+const FAKE_BTRFS: &str = r#"#!/bin/sh
+case "$1 $2" in
+  "receive "*)
+    cat > /dev/null
+    n=$(($(cat "$COUNT" 2>/dev/null || echo 0) + 1))
+    echo "$n" > "$COUNT"
+    mkdir "$2/dev@2024-01"
+    if [ -f "$COUNT.fail" ]; then
+      echo "ERROR: short read from stream" >&2
+      exit 1
+    fi
+    if [ "$n" -ge "$GOOD_FROM" ]; then
+      echo "Received UUID: $RECEIVED_UUID" > "$2/dev@2024-01/.show"
+    else
+      echo "Received UUID: -" > "$2/dev@2024-01/.show"
+    fi ;;
+  "subvolume show") [ -f "$3/.show" ] && cat "$3/.show" ;;
+  "subvolume delete") rm -r "$3" ;;
+  *) exit 1 ;;
+esac
+"#;
+const STREAM_UUID: &str = "00112233-4455-6677-8899-aabbccddeeff";
+
+fn verifying(verify_receive: &str) -> String {
+    format!("[btrfs]\nverify_receive = \"{verify_receive}\"\n")
+}
+
+fn run(root: &Path, config_path: &Path, args: &[&str], good_from: u32, uuid: &str) -> Output {
+    command(config_path)
+        .args(args)
+        .env("COUNT", root.join("receives"))
+        .env("GOOD_FROM", good_from.to_string())
+        .env("RECEIVED_UUID", uuid)
+        .output()
+        .unwrap()
+}
+
+// A send stream whose first command is a subvol with STREAM_UUID.
+fn send_stream() -> Vec<u8> {
+    let mut attrs = Vec::new();
+    let uuid: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+    for (kind, data) in [(15u16, &b"dev@2024-01"[..]), (1, &uuid[..]), (2, &[0; 8][..])] {
+        attrs.extend_from_slice(&kind.to_le_bytes());
+        attrs.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        attrs.extend_from_slice(data);
+    }
+    let mut stream = b"btrfs-stream\0".to_vec();
+    stream.extend_from_slice(&1u32.to_le_bytes());
+    stream.extend_from_slice(&(attrs.len() as u32).to_le_bytes());
+    stream.extend_from_slice(&1u16.to_le_bytes());
+    stream.extend_from_slice(&0u32.to_le_bytes());
+    stream.extend_from_slice(&attrs);
+    stream
+}
+
+fn ingest(root: &Path, config_path: &Path) {
+    let stream = root.join("stream.bin");
+    fs::write(&stream, send_stream()).unwrap();
+    let ingest = ["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()];
+    for args in [&["init", "ls"][..], &ingest[..]] {
+        let output = run(root, config_path, args, 1, STREAM_UUID);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+}
+
+// Hydrates 2024-01 and returns the output with how many receives ran.
+fn hydrate(verify_receive: &str, good_from: u32, uuid: &str) -> (Output, String, bool) {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let verify = verifying(verify_receive);
+    let config_path = setup(root).crypto().section(&verify).btrfs(FAKE_BTRFS).write();
+    ingest(root, &config_path);
+
+    let output = run(root, &config_path, &["restore", "hydrate", "2024-01"], good_from, uuid);
+    let receives = fs::read_to_string(root.join("receives")).unwrap().trim().to_string();
+    let received = root.join("ls/restore/snapshots/dev@2024-01").exists();
+    (output, receives, received)
+}
+
+#[test]
+fn a_complete_receive_passes_the_first_time() {
+    let (output, receives, received) = hydrate("retry", 1, STREAM_UUID);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(receives, "1");
+    assert!(received);
+}
+
+#[test]
+fn an_incomplete_receive_is_retried_once() {
+    let (output, receives, received) = hydrate("retry", 2, STREAM_UUID);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("receiving it again"), "{stderr}");
+    assert_eq!(receives, "2");
+    assert!(received);
+
+    let (output, receives, received) = hydrate("retry", 3, STREAM_UUID);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("incomplete after btrfs receive: its received_uuid is not set"));
+    assert_eq!(receives, "2");
+    assert!(!received);
+}
+
+#[test]
+fn a_received_uuid_from_another_stream_fails_without_retry_under_fail() {
+    let (output, receives, received) = hydrate("fail", 1, "ffffffff-4455-6677-8899-aabbccddeeff");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("does not match the stream's {STREAM_UUID}")), "{stderr}");
+    assert_eq!(receives, "1");
+    assert!(!received);
+}
+
+#[test]
+fn verify_receive_off_skips_the_check() {
+    let (output, receives, received) = hydrate("off", 3, STREAM_UUID);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(receives, "1");
+    assert!(received);
+}
+
+#[test]
+fn a_failed_receive_is_deleted_so_hydrate_can_run_again() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).crypto().section(&verifying("retry")).btrfs(FAKE_BTRFS).write();
+    ingest(root, &config_path);
+    let target = root.join("ls/restore/snapshots/dev@2024-01");
+    let hydrate = ["restore", "hydrate", "2024-01"];
+
+    fs::write(root.join("receives.fail"), "").unwrap();
+    let output = run(root, &config_path, &hydrate, 1, STREAM_UUID);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("short read from stream"), "{stderr}");
+    assert!(!target.exists());
+
+    fs::remove_file(root.join("receives.fail")).unwrap();
+    let output = run(root, &config_path, &hydrate, 1, STREAM_UUID);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("hydrated by another run"), "{stderr}");
+    assert_eq!(fs::read_to_string(root.join("receives")).unwrap().trim(), "2");
+    assert!(target.join(".show").exists());
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{run_ok, setup};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+// An anchor kept on the LS and an incremental that only has an object key.
+fn rows(root: &Path) -> Vec<String> {
+    let anchor = root.join("anchor.age");
+    fs::write(&anchor, vec![0u8; 100]).unwrap();
+    vec![
+        format!("2024-01-01T00:00:00Z\t2024-01\tanchor\t\t100\taa\t{}\t", anchor.display()),
+        "2024-02-01T00:00:00Z\t2024-02\tincremental\t2024-01\t10\tbb\t\tartifacts/incr/b".into(),
+    ]
+}
+
+fn report(config_path: &Path, diagram: &str) -> String {
+    let args = ["--now", "2024-02-02T00:00:00Z", "report", "monthly", "--diagram", diagram];
+    run_ok(config_path, &args)
+}
+
+#[test]
+fn monthly_report_summarizes_chain_and_policy() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).manifest(&rows(tmp.path())).write();
+
+    let stdout = report(&config_path, "mermaid");
+    assert!(stdout.contains("dev-backup monthly report: 2024-02"));
+    assert!(stdout.contains("dev@2024-02  incremental from 2024-01  10 B"));
+    assert!(stdout.contains("next run: incremental"));
+    assert!(stdout.contains("upcoming anchor: by 2025-01"));
+    assert!(stdout.contains("dev@2024-01  ok"));
+    assert!(stdout.contains("L2024_01 --> L2024_02"));
+}
+
+#[test]
+fn monthly_report_names_snapshots_through_the_naming_template() {
+    let tmp = tempdir().unwrap();
+    let naming = "[naming]\nprefix = \"work\"\nsnapshot_name_template = \"{label}.{prefix}\"\n";
+    let config_path = setup(tmp.path()).section(naming).manifest(&rows(tmp.path())).write();
+
+    let stdout = report(&config_path, "ascii");
+    assert!(!stdout.contains("dev@"), "{stdout}");
+    assert!(stdout.contains("2024-02.work  incremental from 2024-01"), "{stdout}");
+    assert!(stdout.contains("current anchor: 2024-01.work"), "{stdout}");
+    assert!(stdout.contains("2024-01.work  ok"), "{stdout}");
+    assert!(stdout.contains("└─ 2024-02.work [incr 10 B]"), "{stdout}");
+
+    let stdout = report(&config_path, "mermaid");
+    assert!(stdout.contains("L2024_02[\"2024-02.work (incr, 10 B)\"]"), "{stdout}");
+}
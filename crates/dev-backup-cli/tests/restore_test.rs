@@ -0,0 +1,34 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn restore_test_cleans_up_its_scratch_directory_when_receive_fails() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+
+    // Not a btrfs send stream (and possibly no btrfs at all), so the receive
+    // fails; the test directory must not be left behind either way.
+    let scratch = tmp.path().join("scratch");
+    let output = run(
+        &config_path,
+        &["restore", "test", "--scratch", scratch.to_str().unwrap(), "--sample", "5"],
+    );
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Test-restoring dev@2024-01 into"), "{stdout}");
+    assert_eq!(fs::read_dir(&scratch).unwrap().count(), 0);
+
+    let output = run(&config_path, &["restore", "test", "2023-12"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("label not found in manifest: 2023-12"), "{stderr}");
+    assert!(!tmp.path().join("ls/tmp/restore-test").exists());
+}
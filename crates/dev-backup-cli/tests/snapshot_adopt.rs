@@ -0,0 +1,74 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+// `btrfs subvolume show` prints `<path>/.show`, and fails without it.
+const FAKE_BTRFS: &str = "#!/bin/sh\n[ -f \"$3/.show\" ] && cat \"$3/.show\"\n";
+const DATASET_UUID: &str = "0f6c3e1a-1111-4a4a-9b9b-000000000001";
+
+// The dataset is subvolume DATASET_UUID, with foreign snapshots under `.snapshots`.
+fn config_with_dataset_uuid(root: &Path) -> PathBuf {
+    let config_path = setup(root).btrfs(FAKE_BTRFS).write();
+    fs::create_dir_all(root.join(".snapshots")).unwrap();
+    let show = format!("dataset\n\tUUID: \t\t{DATASET_UUID}\n\tParent UUID: \t\t-\n");
+    fs::write(root.join("dataset/.show"), show).unwrap();
+    config_path
+}
+
+// A snapper-style snapshot at `.snapshots/<number>/snapshot`.
+fn foreign_snapshot(root: &Path, number: u32, parent_uuid: &str, flags: &str) -> String {
+    let path = root.join(format!(".snapshots/{number}/snapshot"));
+    fs::create_dir_all(&path).unwrap();
+    let show = format!(
+        "{number}/snapshot\n\tUUID: \t\t9e0d-{number}\n\tParent UUID: \t\t{parent_uuid}\n\
+         \tFlags: \t\t{flags}\n"
+    );
+    fs::write(path.join(".show"), show).unwrap();
+    path.display().to_string()
+}
+
+#[test]
+fn a_read_only_snapshot_of_the_dataset_is_moved_into_place() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = config_with_dataset_uuid(root);
+    let source = foreign_snapshot(root, 42, DATASET_UUID, "readonly");
+
+    let output = run(&config_path, &["snapshot", "2024-03", "--adopt", &source]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Adopted snapshot"), "{stdout}");
+    assert!(stdout.contains("Snapshot already exists"), "{stdout}");
+    assert!(root.join("snapshots/dev@2024-03/.show").exists());
+    assert!(!Path::new(&source).exists());
+}
+
+#[test]
+fn writable_foreign_or_clashing_snapshots_are_refused() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = config_with_dataset_uuid(root);
+    let cases = [
+        (foreign_snapshot(root, 1, DATASET_UUID, "-"), "is not a read-only snapshot"),
+        (foreign_snapshot(root, 2, "7a7a-other", "readonly"), "is not a snapshot of"),
+        (root.join(".snapshots/3").display().to_string(), "must be a btrfs subvolume"),
+    ];
+    for (source, message) in &cases {
+        let output = run(&config_path, &["snapshot", "2024-03", "--adopt", source]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{stderr}");
+        assert!(!root.join("snapshots/dev@2024-03").exists());
+    }
+
+    fs::create_dir_all(root.join("snapshots/dev@2024-03")).unwrap();
+    let source = foreign_snapshot(root, 4, DATASET_UUID, "readonly");
+    let output = run(&config_path, &["snapshot", "2024-03", "--adopt", &source]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("already exists"), "{stderr}");
+    assert!(Path::new(&source).exists());
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::{command, setup};
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use tempfile::tempdir;
+
+// `btrfs subvolume delete` removes the directory.
+const FAKE_BTRFS: &str = "#!/bin/sh\n[ \"$2\" = delete ] && rm -r \"$3\"\n";
+
+fn run(config_path: &Path, args: &[&str]) -> Output {
+    command(config_path).args(["--now", "2024-03-15T00:00:00Z"]).args(args).output().unwrap()
+}
+
+const ROWS: [&str; 2] = [
+    "2024-01-31T00:00:00Z\t2024-01\tanchor\t\t1000\taa\t\t",
+    "2024-02-29T00:00:00Z\t2024-02\tincremental\t2024-01\t10\tbb\t\t",
+];
+
+#[test]
+fn the_next_parent_is_only_deleted_with_force() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).btrfs(FAKE_BTRFS).manifest(&ROWS).write();
+    for label in ["2024-01", "2024-02"] {
+        fs::create_dir_all(root.join(format!("snapshots/dev@{label}"))).unwrap();
+    }
+
+    let output = run(&config_path, &["snapshot", "delete", "2024-02"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let refusal = "dev@2024-02 is the parent of the next planned incremental";
+    assert!(stderr.contains(refusal), "{stderr}");
+    assert!(root.join("snapshots/dev@2024-02").exists());
+
+    let output = run(&config_path, &["snapshot", "delete", "2024-01"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!root.join("snapshots/dev@2024-01").exists());
+
+    let output = run(&config_path, &["snapshot", "delete", "2024-02", "--force"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("warning: deleting dev@2024-02"), "{stderr}");
+    assert!(!root.join("snapshots/dev@2024-02").exists());
+}
+
+#[test]
+fn nothing_is_protected_when_an_anchor_is_due() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    // The incrementals outweigh the anchor, so the next run takes an anchor.
+    let rows = [ROWS[0], "2024-02-29T00:00:00Z\t2024-02\tincremental\t2024-01\t5000\tbb\t\t"];
+    let config_path = setup(root).btrfs(FAKE_BTRFS).manifest(&rows).write();
+    fs::create_dir_all(root.join("snapshots/dev@2024-02")).unwrap();
+
+    let output = run(&config_path, &["--dry-run", "snapshot", "delete", "2024-02"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Dry run: would delete snapshot"), "{stdout}");
+    assert!(root.join("snapshots/dev@2024-02").exists());
+
+    let output = run(&config_path, &["snapshot", "delete", "2024-02"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!root.join("snapshots/dev@2024-02").exists());
+
+    let output = run(&config_path, &["snapshot", "delete", "2024-02"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("snapshot not found"), "{stderr}");
+}
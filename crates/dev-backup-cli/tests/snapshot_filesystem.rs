@@ -0,0 +1,54 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+// `btrfs filesystem show` reports the uuid in `<path>/.fsid`; every other
+// command fails, as snapshotting across filesystems does.
+const FAKE_BTRFS: &str = "#!/bin/sh\n[ \"$1\" = filesystem ] && [ -f \"$3/.fsid\" ] || exit 1\n\
+                          printf 'Label: none  uuid: %s\\n' \"$(cat \"$3/.fsid\")\"\n";
+
+// The dataset is on filesystem 5d1b0c2e; `snapshots` is wherever the test puts it.
+fn config_with_snapshots(root: &Path, snapshots: &Path) -> PathBuf {
+    let config_path = setup(root).snapshots(snapshots).btrfs(FAKE_BTRFS).write();
+    fs::write(root.join("dataset/.fsid"), "5d1b0c2e-aaaa-4c4c-8d8d-000000000001").unwrap();
+    config_path
+}
+
+#[test]
+fn a_snapshot_root_on_another_filesystem_is_rejected_with_guidance() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let disk = root.join("disk");
+    fs::create_dir_all(&disk).unwrap();
+    fs::write(disk.join(".fsid"), "91e7f3a0-bbbb-4d4d-8e8e-000000000002").unwrap();
+    // Not created yet, so judged by the filesystem it would be created on.
+    let config_path = config_with_snapshots(root, &disk.join("snapshots"));
+
+    for args in [&["config", "validate"][..], &["snapshot", "2024-01"]] {
+        let output = run(&config_path, args);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("btrfs cannot snapshot across filesystems"), "{stderr}");
+        assert!(stderr.contains("91e7f3a0-bbbb"), "{stderr}");
+    }
+}
+
+#[test]
+fn a_snapshot_root_on_the_dataset_filesystem_passes() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let snapshots = root.join("snapshots");
+    fs::create_dir_all(&snapshots).unwrap();
+    fs::write(snapshots.join(".fsid"), "5d1b0c2e-aaaa-4c4c-8d8d-000000000001").unwrap();
+    let config_path = config_with_snapshots(root, &snapshots);
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = run(&config_path, &["snapshot", "2024-01"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("across filesystems"), "{stderr}");
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::{run_ok, setup};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+// `btrfs subvolume show` prints `<path>/.show`, and fails without it.
+const FAKE_BTRFS: &str = "#!/bin/sh\n[ -f \"$3/.show\" ] && cat \"$3/.show\"\n";
+
+fn snapshot(root: &Path, name: &str, show: Option<&str>) {
+    let path = root.join("snapshots").join(name);
+    fs::create_dir_all(&path).unwrap();
+    if let Some(show) = show {
+        fs::write(path.join(".show"), show).unwrap();
+    }
+}
+
+#[test]
+fn snapshot_list_shows_btrfs_details_and_manifest_rows() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let rows = ["2024-01-01T00:00:00Z\t2024-01\tanchor\t\t1\taa\t\t"];
+    let config_path = setup(root).btrfs(FAKE_BTRFS).manifest(&rows).write();
+    let show = "dev@2024-01\n\tName: \t\t\tdev@2024-01\n\
+                \tCreation time: \t\t2024-01-31 12:00:00 +0000\n\
+                \tGeneration: \t\t11\n\tFlags: \t\t\treadonly\n";
+    snapshot(root, "dev@2024-01", Some(show));
+    let show = "\tCreation time: \t\t2024-02-29 12:00:00 +0000\n\tFlags: \t\t\t-\n";
+    snapshot(root, "dev@2024-02", Some(show));
+    snapshot(root, "dev@2024-03", None);
+    snapshot(root, "other@2024-01", None);
+    snapshot(root, "dev@2024-01.home", None);
+
+    let stdout = run_ok(&config_path, &["snapshot", "list"]);
+    assert_eq!(
+        stdout,
+        "2024-01\t2024-01-31 12:00:00 +0000\tro\tanchor\n\
+         2024-02\t2024-02-29 12:00:00 +0000\trw\t-\n\
+         2024-03\t-\t?\t-\n"
+    );
+
+    let stdout = run_ok(&config_path, &["--json", "snapshot", "list"]);
+    let first = stdout.lines().next().unwrap();
+    assert!(first.contains("\"snapshot\":\"dev@2024-01\""), "{stdout}");
+    assert!(first.contains("\"readonly\":true"), "{stdout}");
+    assert!(first.contains("\"artifact\":\"anchor\""), "{stdout}");
+    assert!(stdout.lines().nth(2).unwrap().contains("\"readonly\":null"), "{stdout}");
+}
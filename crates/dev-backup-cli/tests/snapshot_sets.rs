@@ -0,0 +1,80 @@
+mod common;
+
+use common::{run, run_ok, setup, two_datasets};
+use std::fs;
+use tempfile::tempdir;
+
+// Snapshots are plain copies; a source holding a .fail file cannot be
+// snapshotted.
+const FAKE_BTRFS: &str = r#"#!/bin/sh
+case "$1 $2" in
+  "subvolume snapshot")
+    shift 2
+    if [ "$1" = "-r" ]; then shift; fi
+    test ! -e "$1/.fail" && cp -a "$1" "$2" ;;
+  "subvolume delete") rm -rf "$3" ;;
+  *) echo "fake btrfs: unsupported: $*" >&2; exit 1 ;;
+esac
+"#;
+
+#[test]
+fn snapshot_set_covers_every_dataset_and_restores_together() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let datasets = two_datasets(root);
+    let config_path = setup(root).datasets(&datasets).cloud().crypto().btrfs(FAKE_BTRFS).write();
+    fs::write(root.join("home/notes"), b"home").unwrap();
+
+    let stdout = run_ok(&config_path, &["snapshot-set", "2024-01"]);
+    assert!(stdout.contains("Recorded snapshot set 2024-01: home, projects"), "{stdout}");
+    assert_eq!(fs::read(root.join("home-snapshots/home@2024-01/notes")).unwrap(), b"home");
+    assert!(root.join("projects-snapshots/proj@2024-01").is_dir());
+    let sets = fs::read_to_string(root.join("ls/manifests/sets.tsv")).unwrap();
+    assert!(sets.starts_with("ts\tlabel\tdatasets\n"), "{sets}");
+    assert!(sets.contains("\t2024-01\thome,projects\n"), "{sets}");
+
+    // A restore of the set's label needs no --dataset and covers both.
+    let stream = root.join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    run_ok(&config_path, &["init", "ls"]);
+    for dataset in ["home", "projects"] {
+        let args = ["--dataset", dataset, "artifact", "ingest", "--label", "2024-01"];
+        run_ok(&config_path, &[&args[..], &[stream.to_str().unwrap()]].concat());
+    }
+    let plan = run_ok(&config_path, &["restore", "plan", "2024-01"]);
+    assert!(plan.contains("home@2024-01.full.send.zst.age"), "{plan}");
+    assert!(plan.contains("proj@2024-01.full.send.zst.age"), "{plan}");
+
+    let output = run(&config_path, &["restore", "plan", "latest"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("or the label of a snapshot set"), "{stderr}");
+
+    run_ok(&config_path, &["sync", "push"]);
+    assert!(root.join("bucket/manifests/sets.tsv").exists());
+}
+
+#[test]
+fn failed_member_snapshot_leaves_no_partial_set() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let datasets = two_datasets(root);
+    let config_path = setup(root).datasets(&datasets).cloud().crypto().btrfs(FAKE_BTRFS).write();
+    fs::write(root.join("projects/.fail"), b"").unwrap();
+
+    let output = run(&config_path, &["snapshot-set", "2024-02"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("snapshot set 2024-02 was not taken"), "{stderr}");
+    assert!(!root.join("home-snapshots/home@2024-02").exists());
+    assert!(!root.join("projects-snapshots/proj@2024-02").exists());
+    assert!(!root.join("ls/manifests/sets.tsv").exists());
+
+    // A member snapshot left over from elsewhere is not adopted into a set.
+    fs::remove_file(root.join("projects/.fail")).unwrap();
+    fs::create_dir_all(root.join("home-snapshots/home@2024-02")).unwrap();
+    let output = run(&config_path, &["snapshot-set", "2024-02"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+    assert!(!root.join("projects-snapshots/proj@2024-02").exists());
+}
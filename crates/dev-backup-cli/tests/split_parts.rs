@@ -0,0 +1,59 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+const SPLIT: &str = "[split]\nparts = [\"vms\"]\n";
+
+fn stage(root: &Path, name: &str) -> String {
+    let path = root.join(name);
+    fs::write(&path, name.as_bytes()).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn part_artifacts_get_their_own_chain() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().section(SPLIT).write();
+    let ls = tmp.path().join("ls");
+
+    let main = stage(tmp.path(), "dev@2024-01.full.send.zst.age");
+    let part = stage(tmp.path(), "vms@2024-01.full.send.zst.age");
+    for path in [&main, &part] {
+        let output = run(&config_path, &["artifact", "register", path]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    assert!(ls.join("artifacts/parts/vms/anchors/vms@2024-01.full.send.zst.age").exists());
+    let part_manifest = fs::read_to_string(ls.join("manifests/parts/vms.tsv")).unwrap();
+    assert!(part_manifest.contains("vms@2024-01.full.send.zst.age"), "{part_manifest}");
+    let main_manifest = fs::read_to_string(ls.join("manifests/snapshots_v2.tsv")).unwrap();
+    assert!(!main_manifest.contains("vms@"), "{main_manifest}");
+
+    let plan = run(&config_path, &["restore", "plan", "2024-01", "--part", "vms"]);
+    assert!(plan.status.success(), "{}", String::from_utf8_lossy(&plan.stderr));
+    let stdout = String::from_utf8_lossy(&plan.stdout);
+    assert_eq!(stdout.lines().count(), 1, "{stdout}");
+    assert!(stdout.contains("parts/vms/anchors"), "{stdout}");
+
+    let push = run(&config_path, &["sync", "push"]);
+    assert!(push.status.success(), "{}", String::from_utf8_lossy(&push.stderr));
+    let bucket = tmp.path().join("bucket");
+    assert!(bucket.join("manifests/parts/vms.tsv").exists());
+    assert!(bucket.join("artifacts/parts/vms/anchors/vms@2024-01.full.send.zst.age").exists());
+}
+
+#[test]
+fn unknown_part_is_rejected() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().section(SPLIT).write();
+    let path = stage(tmp.path(), "photos@2024-01.full.send.zst.age");
+
+    let output = run(&config_path, &["artifact", "register", &path]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("split.parts"), "{stderr}");
+    assert!(Path::new(&path).exists());
+}
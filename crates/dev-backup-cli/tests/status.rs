@@ -0,0 +1,76 @@
+mod common;
+
+use common::{run, setup};
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use tempfile::tempdir;
+
+const ROWS: [&str; 2] = [
+    "2024-01-01T00:00:00Z\t2024-01\tanchor\t\t60000000\taa\t\t",
+    "2024-02-01T00:00:00Z\t2024-02\tincremental\t2024-01\t30000000\tbb\t\t",
+];
+
+fn recovery(objective: &str) -> String {
+    format!(
+        "[recovery]\ndownload_mbps = 8\ndecrypt_mib_per_sec = 1\n\
+         receive_mib_per_sec = 1\nobjective = \"{objective}\"\n"
+    )
+}
+
+fn status(config_path: &Path) -> Output {
+    run(config_path, &["status"])
+}
+
+#[test]
+fn status_estimates_restore_time_for_latest_chain() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).section(&recovery("1h")).manifest(&ROWS).write();
+
+    let output = status(&config_path);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Latest: dev@2024-02"), "{stdout}");
+    assert!(stdout.contains("Artifacts: 2 registered"), "{stdout}");
+    assert!(stdout.contains("Restore chain: 2 artifact(s), 85.8 MiB"), "{stdout}");
+    let expected =
+        "Estimated RTO: 4m22s (download 1m30s at 8 Mbit/s, decrypt 1m26s, receive 1m26s)";
+    assert!(stdout.contains(expected), "{stdout}");
+    assert!(stdout.contains("Recovery objective: 1h00m (met)"), "{stdout}");
+}
+
+#[test]
+fn status_summarizes_chain_bytes_and_push_state() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).section(&recovery("1h")).manifest(&ROWS).write();
+    let ls_root = tmp.path().join("ls");
+
+    let output = status(&config_path);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Chain depth: 1 incremental(s) since anchor 2024-01"), "{stdout}");
+    assert!(
+        stdout.contains("Artifact bytes: 85.8 MiB (anchors 57.2 MiB, incrementals 28.6 MiB)"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("Not pushed: 2024-01, 2024-02"), "{stdout}");
+    assert!(stdout.contains("Last push: never"), "{stdout}");
+
+    fs::write(ls_root.join("manifests/last_push"), "2024-02-01T06:00:00Z\n").unwrap();
+    let output = run(&config_path, &["--now", "2024-02-04T07:00:00Z", "status"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Last push: 2024-02-01T06:00:00Z (3d ago)"), "{stdout}");
+}
+
+#[test]
+fn status_warns_when_objective_is_exceeded() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).section(&recovery("2m")).manifest(&ROWS).write();
+
+    let output = status(&config_path);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("estimated RTO 4m22s exceeds the recovery objective of 2m00s"),
+        "{stderr}"
+    );
+}
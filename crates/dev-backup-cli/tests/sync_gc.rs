@@ -0,0 +1,73 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn gc_lists_unreferenced_artifacts_and_deletes_them_on_request() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).cloud().crypto().write();
+    run_ok(&config_path, &["init", "ls"]);
+    let stream = root.join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    run_ok(&config_path, &["sync", "push"]);
+
+    let bucket = root.join("bucket");
+    let kept = bucket.join("artifacts/anchors/dev@2024-01.full.send.zst.age");
+    let stale = bucket.join("artifacts/anchors/dev@2023-01.full.send.zst.age");
+    fs::write(&stale, b"stale anchor").unwrap();
+    fs::write(bucket.join("manifests/notes.txt"), b"not an artifact").unwrap();
+
+    let stdout = run_ok(&config_path, &["sync", "gc"]);
+    assert!(stdout.starts_with("artifacts/anchors/dev@2023-01.full.send.zst.age\t"), "{stdout}");
+    assert_eq!(stdout.lines().count(), 2, "{stdout}");
+    assert!(stdout.contains("rerun with --delete"), "{stdout}");
+    assert!(stale.exists());
+
+    let stdout = run_ok(&config_path, &["--dry-run", "sync", "gc", "--delete"]);
+    assert!(stdout.contains("Dry run: would delete artifacts/anchors/dev@2023-01"), "{stdout}");
+    assert!(stale.exists());
+
+    let stdout = run_ok(&config_path, &["sync", "gc", "--delete"]);
+    assert!(stdout.contains("Deleted 1 unreferenced object(s)"), "{stdout}");
+    assert!(!stale.exists());
+    assert!(kept.exists());
+    assert!(bucket.join("manifests/notes.txt").exists());
+    assert_eq!(run_ok(&config_path, &["sync", "gc"]), "");
+}
+
+#[test]
+fn gc_refuses_to_run_without_a_manifest() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let output = run(&config_path, &["sync", "gc", "--delete"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("refusing to collect garbage"), "{stderr}");
+}
+
+#[test]
+fn gc_keeps_objects_another_host_pushed_to_the_same_bucket() {
+    let tmp = tempdir().unwrap();
+    let bucket = tmp.path().join("a/bucket");
+    let first = setup(&tmp.path().join("a")).cloud().crypto().write();
+    let second = setup(&tmp.path().join("b")).cloud_at(&bucket).crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    let source = stream.to_str().unwrap();
+
+    run_ok(&first, &["init", "ls"]);
+    run_ok(&second, &["init", "ls"]);
+    run_ok(&first, &["artifact", "ingest", "--label", "2024-01", source]);
+    run_ok(&first, &["sync", "push"]);
+    run_ok(&second, &["artifact", "ingest", "--label", "2024-02", source]);
+    run_ok(&second, &["sync", "push"]);
+
+    let stdout = run_ok(&first, &["sync", "gc", "--delete"]);
+    assert!(stdout.contains("Deleted 0 unreferenced object(s)"), "{stdout}");
+    assert!(bucket.join("artifacts/anchors/dev@2024-01.full.send.zst.age").exists());
+    assert!(bucket.join("artifacts/anchors/dev@2024-02.full.send.zst.age").exists());
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::thread;
+use tempfile::tempdir;
+
+// Serves `dir` read-only, like a CDN in front of the bucket, to requests that
+// carry `?token=secret`.
+fn serve(dir: PathBuf) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            let mut parts = request.split_whitespace();
+            let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            let path = target.strip_suffix("?token=secret").map(|key| dir.join(&key[1..]));
+            let body = path.and_then(|path| fs::read(path).ok());
+            let head = match &body {
+                Some(body) => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", body.len()),
+                None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n".to_string(),
+            };
+            let _ = stream.write_all(format!("{head}Connection: close\r\n\r\n").as_bytes());
+            if let (Some(body), "GET") = (body, method) {
+                let _ = stream.write_all(&body);
+            }
+        }
+    });
+    port
+}
+
+#[test]
+fn pull_from_a_read_only_http_mirror() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let bucket = root.join("bucket");
+    let ls = setup(root).named("ls.toml").cloud_at(&bucket).crypto().write();
+    let stream = root.join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    run_ok(&ls, &["init", "ls"]);
+    run_ok(&ls, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    run_ok(&ls, &["sync", "push"]);
+
+    let port = serve(bucket.clone());
+    let url = format!("http://127.0.0.1:{port}/?token=secret");
+    let http = format!("[cloud]\nbackend = \"http\"\nurl = \"{url}\"\n");
+    let dr = setup(root).named("dr.toml").crypto().section(&http).write();
+    let pulled = root.join("pulled");
+    run_ok(&dr, &["sync", "pull", "latest", pulled.to_str().unwrap()]);
+    let key = "artifacts/anchors/dev@2024-01.full.send.zst.age";
+    assert_eq!(fs::read(pulled.join(key)).unwrap(), fs::read(bucket.join(key)).unwrap());
+
+    let output = run(&dr, &["sync", "push"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("read-only"), "{stderr}");
+}
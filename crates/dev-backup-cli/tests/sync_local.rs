@@ -0,0 +1,308 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+#[test]
+fn push_and_pull_through_local_backend() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    run_ok(&config_path, &["sync", "push"]);
+
+    let bucket = tmp.path().join("bucket");
+    let key = "artifacts/anchors/dev@2024-01.full.send.zst.age";
+    assert_eq!(
+        fs::read(bucket.join(key)).unwrap(),
+        fs::read(tmp.path().join("ls").join(key)).unwrap()
+    );
+    assert!(bucket.join("manifests/snapshots_v2.tsv").exists());
+    let status = run_ok(&config_path, &["status"]);
+    assert!(status.contains("Not pushed: none"), "{status}");
+    assert!(status.contains("Last push: "), "{status}");
+    assert!(!status.contains("Last push: never"), "{status}");
+
+    let listing = run_ok(&config_path, &["ls", "remote"]);
+    assert!(listing.contains(key), "{listing}");
+    let detail = run_ok(&config_path, &["ls", "remote", "--detail"]);
+    let row = detail.lines().find(|line| line.starts_with(key)).unwrap();
+    assert!(row.ends_with("\tok"), "{detail}");
+
+    let pulled = tmp.path().join("pulled");
+    run_ok(&config_path, &["sync", "pull", "latest", pulled.to_str().unwrap()]);
+    assert_eq!(fs::read(pulled.join(key)).unwrap(), fs::read(bucket.join(key)).unwrap());
+}
+
+#[test]
+fn local_backend_requires_absolute_root() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud_at(Path::new("relative/bucket")).crypto().write();
+
+    let output = run(&config_path, &["config", "validate"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cloud.local_root"));
+}
+
+#[test]
+fn pull_and_list_a_label_range() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    let source = stream.to_str().unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    for (label, parent) in [
+        ("2024-01", None),
+        ("2024-02", Some("2024-01")),
+        ("2024-03", None),
+        ("2024-04", Some("2024-03")),
+    ] {
+        let mut args = vec!["artifact", "ingest", "--label", label];
+        if let Some(parent) = parent {
+            args.extend(["--parent", parent]);
+        }
+        args.push(source);
+        run_ok(&config_path, &args);
+    }
+    run_ok(&config_path, &["sync", "push"]);
+
+    let pulled = tmp.path().join("pulled");
+    let dest = pulled.to_str().unwrap();
+    run_ok(&config_path, &["sync", "pull", "--from", "2024-02", "--to", "2024-03", dest]);
+    // 2024-01 comes along as the anchor 2024-02 depends on.
+    assert!(pulled.join("artifacts/anchors/dev@2024-01.full.send.zst.age").exists());
+    assert!(pulled.join("artifacts/incr/dev@2024-02.incr.from_2024-01.send.zst.age").exists());
+    assert!(pulled.join("artifacts/anchors/dev@2024-03.full.send.zst.age").exists());
+    assert!(!pulled.join("artifacts/incr/dev@2024-04.incr.from_2024-03.send.zst.age").exists());
+
+    let listing = run_ok(&config_path, &["ls", "remote", "--from", "2024-02", "--to", "2024-03"]);
+    let keys: Vec<&str> = listing.lines().map(|line| line.split('\t').next().unwrap()).collect();
+    assert_eq!(
+        keys,
+        [
+            "artifacts/anchors/dev@2024-03.full.send.zst.age",
+            "artifacts/incr/dev@2024-02.incr.from_2024-01.send.zst.age"
+        ]
+    );
+}
+
+#[test]
+fn hydrate_fetches_artifacts_missing_locally_from_the_cloud() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    run_ok(&config_path, &["sync", "push"]);
+
+    let key = "artifacts/anchors/dev@2024-01.full.send.zst.age";
+    fs::remove_file(tmp.path().join("ls").join(key)).unwrap();
+    fs::write(tmp.path().join("bucket").join(key), b"tampered").unwrap();
+
+    let output = run(&config_path, &["restore", "hydrate", "2024-01"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("checksum mismatch for artifacts/anchors/dev@2024-01"), "{stderr}");
+    assert!(!tmp.path().join("ls/tmp/hydrate/dev@2024-01.full.send.zst.age").exists());
+}
+
+#[test]
+fn hydrate_from_cloud_streams_without_scratch_space() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    run_ok(&config_path, &["sync", "push"]);
+
+    let key = "artifacts/anchors/dev@2024-01.full.send.zst.age";
+    fs::remove_file(tmp.path().join("ls").join(key)).unwrap();
+    fs::write(tmp.path().join("bucket").join(key), b"tampered").unwrap();
+
+    let output = run(&config_path, &["restore", "hydrate", "--from-cloud", "2024-01"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Streaming dev@2024-01 from cloud"), "{stdout}");
+    assert!(!stdout.contains("Downloading"), "{stdout}");
+    assert!(!tmp.path().join("ls/tmp/hydrate").exists());
+}
+
+#[test]
+fn streamed_ingest_uploads_without_a_local_artifact() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    let args = ["artifact", "ingest", "--stream", "--label", "2024-01", stream.to_str().unwrap()];
+    run_ok(&config_path, &args);
+
+    let key = "artifacts/anchors/dev@2024-01.full.send.zst.age";
+    let uploaded = tmp.path().join("bucket").join(key);
+    assert!(uploaded.exists());
+    assert!(!tmp.path().join("ls").join(key).exists());
+
+    let manifest = fs::read_to_string(tmp.path().join("ls/manifests/snapshots_v2.tsv")).unwrap();
+    let row: Vec<&str> = manifest.lines().nth(2).unwrap().split('\t').collect();
+    assert_eq!(row[4], fs::metadata(&uploaded).unwrap().len().to_string());
+    let digest = Command::new("sha256sum").arg(&uploaded).output().unwrap();
+    assert!(String::from_utf8_lossy(&digest.stdout).starts_with(row[5]));
+    assert_eq!((row[6], row[7]), ("", key));
+
+    run_ok(&config_path, &["sync", "push"]);
+    let dest = tmp.path().join("pulled");
+    run_ok(&config_path, &["sync", "pull", "2024-01", dest.to_str().unwrap()]);
+    assert_eq!(fs::read(dest.join(key)).unwrap(), fs::read(&uploaded).unwrap());
+}
+
+#[test]
+fn push_can_hold_back_either_half() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+
+    let bucket = tmp.path().join("bucket");
+    let key = "artifacts/anchors/dev@2024-01.full.send.zst.age";
+    let output = run(&config_path, &["sync", "push", "--manifest-only"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 artifact(s) that are not pushed yet"), "{stderr}");
+    assert!(bucket.join("manifests/snapshots_v2.tsv").exists());
+    assert!(!bucket.join(key).exists());
+
+    fs::remove_dir_all(&bucket).unwrap();
+    let stdout = run_ok(&config_path, &["sync", "push", "--artifacts-only"]);
+    assert!(stdout.contains("manifests were held back"), "{stdout}");
+    assert!(bucket.join(key).exists());
+    assert!(!bucket.join("manifests/snapshots_v2.tsv").exists());
+}
+
+#[test]
+fn concurrent_hosts_merge_rather_than_clobber_the_manifest() {
+    let tmp = tempdir().unwrap();
+    let bucket = tmp.path().join("a/bucket");
+    let first = setup(&tmp.path().join("a")).cloud().crypto().write();
+    let second = setup(&tmp.path().join("b")).cloud_at(&bucket).crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    let source = stream.to_str().unwrap();
+
+    run_ok(&first, &["init", "ls"]);
+    run_ok(&second, &["init", "ls"]);
+    run_ok(&first, &["artifact", "ingest", "--label", "2024-01", source]);
+    run_ok(&first, &["sync", "push"]);
+    run_ok(&second, &["artifact", "ingest", "--label", "2024-02", source]);
+    let stdout = run_ok(&second, &["sync", "push"]);
+    assert!(stdout.contains("Merged 1 row(s) from manifests/snapshots_v2.tsv"), "{stdout}");
+
+    let published = fs::read_to_string(bucket.join("manifests/snapshots_v2.tsv")).unwrap();
+    assert!(published.contains("\t2024-01\t") && published.contains("\t2024-02\t"), "{published}");
+    // Known to the second host now, but its artifact is only in the bucket.
+    let plan = run_ok(&second, &["restore", "plan", "2024-01"]);
+    assert_eq!(plan.trim(), "", "{plan}");
+
+    // The same label built twice from different data cannot be merged.
+    fs::write(&stream, b"other send stream").unwrap();
+    run_ok(&first, &["artifact", "ingest", "--label", "2024-03", source]);
+    run_ok(&first, &["sync", "push"]);
+    fs::write(&stream, b"yet another send stream").unwrap();
+    run_ok(&second, &["artifact", "ingest", "--label", "2024-03", source]);
+    let output = run(&second, &["sync", "push"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("dev@2024-03 has a different artifact"), "{stderr}");
+    let after = fs::read_to_string(bucket.join("manifests/snapshots_v2.tsv")).unwrap();
+    assert_eq!(after.matches("\t2024-03\t").count(), 1, "{after}");
+}
+
+#[test]
+fn a_merge_conflict_names_the_snapshot_through_the_naming_template() {
+    let tmp = tempdir().unwrap();
+    let bucket = tmp.path().join("a/bucket");
+    let mut hosts = Vec::new();
+    for host in ["a", "b"] {
+        let naming = "[naming]\nsnapshot_name_template = \"snap-{prefix}-{label}\"\n";
+        let config_path =
+            setup(&tmp.path().join(host)).cloud_at(&bucket).crypto().section(naming).write();
+        run_ok(&config_path, &["init", "ls"]);
+        hosts.push(config_path);
+    }
+    let stream = tmp.path().join("stream.bin");
+    let source = stream.to_str().unwrap();
+
+    fs::write(&stream, b"send stream").unwrap();
+    run_ok(&hosts[0], &["artifact", "ingest", "--label", "2024-03", source]);
+    run_ok(&hosts[0], &["sync", "push"]);
+    fs::write(&stream, b"other send stream").unwrap();
+    run_ok(&hosts[1], &["artifact", "ingest", "--label", "2024-03", source]);
+    let output = run(&hosts[1], &["sync", "push"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("snap-dev-2024-03 has a different artifact"), "{stderr}");
+}
+
+#[test]
+fn pull_rejects_a_manifest_that_does_not_match_its_checksum() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    run_ok(&config_path, &["sync", "push"]);
+
+    let manifest = tmp.path().join("bucket/manifests/snapshots_v2.tsv");
+    let checksum = fs::read_to_string(manifest.with_extension("tsv.sha256")).unwrap();
+    let sha256 = Command::new("sha256sum").arg(&manifest).output().unwrap();
+    let sha256 = String::from_utf8_lossy(&sha256.stdout);
+    assert_eq!(
+        checksum,
+        format!("{}  snapshots_v2.tsv\n", sha256.split_whitespace().next().unwrap())
+    );
+
+    let contents = fs::read_to_string(&manifest).unwrap();
+    fs::write(&manifest, &contents[..contents.len() - 20]).unwrap();
+    let pulled = tmp.path().join("pulled");
+    let output = run(&config_path, &["sync", "pull", "2024-01", pulled.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("does not match manifests/snapshots_v2.tsv.sha256"), "{stderr}");
+}
+
+#[test]
+fn pull_resolves_an_alias_pushed_by_another_host() {
+    let tmp = tempdir().unwrap();
+    let bucket = tmp.path().join("a/bucket");
+    let first = setup(&tmp.path().join("a")).cloud().crypto().write();
+    let second = setup(&tmp.path().join("b")).cloud_at(&bucket).crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    let source = stream.to_str().unwrap();
+
+    run_ok(&first, &["init", "ls"]);
+    run_ok(&first, &["artifact", "ingest", "--label", "2024-01", source]);
+    run_ok(&first, &["artifact", "ingest", "--label", "2024-02", source]);
+    run_ok(&first, &["alias", "set", "stable", "2024-01"]);
+    run_ok(&first, &["sync", "push"]);
+
+    let pulled = tmp.path().join("pulled");
+    run_ok(&second, &["sync", "pull", "stable", pulled.to_str().unwrap()]);
+    assert!(pulled.join("artifacts/anchors/dev@2024-01.full.send.zst.age").exists());
+    assert!(!pulled.join("artifacts/anchors/dev@2024-02.full.send.zst.age").exists());
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn usb_mirror(usb_root: &Path) -> String {
+    format!(
+        "[[mirrors]]\nname = \"usb\"\nbackend = \"local\"\nlocal_root = \"{}\"\n",
+        usb_root.display()
+    )
+}
+
+const KEY: &str = "artifacts/anchors/dev@2024-01.full.send.zst.age";
+
+#[test]
+fn push_mirrors_every_target_and_pull_reads_a_mirror() {
+    let tmp = tempdir().unwrap();
+    let usb = tmp.path().join("usb");
+    let config_path = setup(tmp.path()).cloud().crypto().section(&usb_mirror(&usb)).write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    run_ok(&config_path, &["sync", "push"]);
+
+    let bucket = tmp.path().join("bucket");
+    for root in [&bucket, &usb] {
+        assert!(root.join(KEY).exists(), "{}", root.display());
+        let manifest = fs::read_to_string(root.join("manifests/snapshots_v2.tsv")).unwrap();
+        assert!(manifest.contains(&format!("\t{KEY}\tusb={KEY}\tpushed\t\n")), "{manifest}");
+    }
+
+    // The R2 side is gone; the mirror alone is enough to pull.
+    fs::remove_dir_all(&bucket).unwrap();
+    let pulled = tmp.path().join("pulled");
+    run_ok(&config_path, &["sync", "pull", "latest", pulled.to_str().unwrap(), "--mirror", "usb"]);
+    assert_eq!(fs::read(pulled.join(KEY)).unwrap(), fs::read(usb.join(KEY)).unwrap());
+}
+
+#[test]
+fn failed_mirror_is_reported_and_retried() {
+    let tmp = tempdir().unwrap();
+    let usb = tmp.path().join("usb");
+    fs::write(&usb, b"not a directory").unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().section(&usb_mirror(&usb)).write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    let output = run(&config_path, &["sync", "push"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("sync push failed for mirror(s): usb"), "{stderr}");
+    assert!(tmp.path().join("bucket").join(KEY).exists());
+    let manifest = fs::read_to_string(tmp.path().join("ls/manifests/snapshots_v2.tsv")).unwrap();
+    assert!(!manifest.contains("usb="), "{manifest}");
+
+    fs::remove_file(&usb).unwrap();
+    run_ok(&config_path, &["sync", "push"]);
+    assert!(usb.join(KEY).exists());
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn ingest(root: &Path, config_path: &Path, label: &str) {
+    let stream = root.join("stream.bin");
+    fs::write(&stream, format!("send stream {label}")).unwrap();
+    run_ok(config_path, &["artifact", "ingest", "--label", label, stream.to_str().unwrap()]);
+}
+
+#[test]
+fn reconcile_records_uploads_the_manifest_does_not_know_about() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).cloud().crypto().write();
+    run_ok(&config_path, &["init", "ls"]);
+    ingest(root, &config_path, "2024-01");
+    run_ok(&config_path, &["sync", "push"]);
+    let stdout = run_ok(&config_path, &["sync", "reconcile"]);
+    assert!(stdout.contains("Manifest, [cloud] and the LS agree"), "{stdout}");
+
+    // Uploaded by hand, so the manifest row still has no object_key.
+    ingest(root, &config_path, "2024-02");
+    let key = "artifacts/anchors/dev@2024-02.full.send.zst.age";
+    fs::copy(root.join("ls").join(key), root.join("bucket").join(key)).unwrap();
+
+    let output = run(&config_path, &["sync", "reconcile"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("2024-02\tunrecorded upload\t{key}")), "{stdout}");
+
+    let stdout = run_ok(&config_path, &["--dry-run", "sync", "reconcile", "--repair"]);
+    assert!(stdout.contains(&format!("Dry run: would record {key} for 2024-02")), "{stdout}");
+    let manifest = fs::read_to_string(root.join("ls/manifests/snapshots_v2.tsv")).unwrap();
+    assert_eq!(manifest.matches(key).count(), 1, "{manifest}");
+
+    let stdout = run_ok(&config_path, &["sync", "reconcile", "--repair"]);
+    assert!(stdout.contains("Recorded object_key for 1 artifact(s)"), "{stdout}");
+    let manifest = fs::read_to_string(root.join("ls/manifests/snapshots_v2.tsv")).unwrap();
+    assert_eq!(manifest.matches(key).count(), 2, "{manifest}");
+    let stdout = run_ok(&config_path, &["sync", "reconcile"]);
+    assert!(stdout.contains("Manifest, [cloud] and the LS agree"), "{stdout}");
+}
+
+#[test]
+fn reconcile_reports_missing_and_mismatched_files() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = setup(root).cloud().crypto().write();
+    run_ok(&config_path, &["init", "ls"]);
+    ingest(root, &config_path, "2024-01");
+    ingest(root, &config_path, "2024-02");
+    run_ok(&config_path, &["sync", "push"]);
+
+    let first = "artifacts/anchors/dev@2024-01.full.send.zst.age";
+    let second = "artifacts/anchors/dev@2024-02.full.send.zst.age";
+    fs::remove_file(root.join("bucket").join(first)).unwrap();
+    fs::remove_file(root.join("ls").join(second)).unwrap();
+    fs::write(root.join("bucket").join(second), b"truncated").unwrap();
+
+    let output = run(&config_path, &["sync", "reconcile", "--repair"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("2024-01\tmissing upload\t{first}")), "{stdout}");
+    assert!(stdout.contains("2024-02\tmissing local file\t"), "{stdout}");
+    assert!(stdout.contains(&format!("2024-02\tsize mismatch\t{second} is 9 bytes")), "{stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("3 mismatch(es)"), "{stderr}");
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{run, run_ok, setup};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn verify_reports_local_and_cloud_mismatches() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    for (label, parent) in [("2024-01", None), ("2024-02", Some("2024-01"))] {
+        let mut args = vec!["artifact", "ingest", "--label", label];
+        if let Some(parent) = parent {
+            args.extend(["--parent", parent]);
+        }
+        args.push(stream.to_str().unwrap());
+        run_ok(&config_path, &args);
+    }
+    run_ok(&config_path, &["sync", "push"]);
+
+    let report = run_ok(&config_path, &["verify", "--all", "--cloud"]);
+    assert!(report.contains("dev@2024-01\tlocal ok\tcloud ok\tpass"), "{report}");
+    assert!(report.contains("Verified 2 artifact(s)"), "{report}");
+    let manifest = fs::read_to_string(tmp.path().join("ls/manifests/snapshots_v2.tsv")).unwrap();
+    assert_eq!(manifest.matches("\tverified\t\n").count(), 2, "{manifest}");
+
+    let anchor = "artifacts/anchors/dev@2024-01.full.send.zst.age";
+    let incr = "artifacts/incr/dev@2024-02.incr.from_2024-01.send.zst.age";
+    fs::write(tmp.path().join("ls").join(anchor), b"tampered").unwrap();
+    fs::remove_file(tmp.path().join("bucket").join(incr)).unwrap();
+
+    // The label only covers its own artifact, which is still intact locally.
+    let report = run_ok(&config_path, &["verify", "2024-02"]);
+    assert!(report.contains("dev@2024-02\tlocal ok\t-\tpass"), "{report}");
+
+    let output = run(&config_path, &["verify", "--all", "--cloud"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("dev@2024-01\tFAIL: local sha256 mismatch\tcloud ok\tFAIL"));
+    assert!(stdout.contains("dev@2024-02\tlocal ok\tFAIL: cloud object missing\tFAIL"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2 of 2 artifact(s) failed verification"), "{stderr}");
+}
+
+#[test]
+fn verify_needs_a_label_or_all() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    assert!(!run(&config_path, &["verify"]).status.success());
+    assert!(!run(&config_path, &["verify", "--all", "2024-01"]).status.success());
+}
+
+#[test]
+fn deep_verify_decodes_artifacts_whose_checksum_matches() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+
+    run_ok(&config_path, &["init", "ls"]);
+    run_ok(&config_path, &["artifact", "ingest", "--label", "2024-01", stream.to_str().unwrap()]);
+    // Registering computes the checksum over whatever is there, so a damaged
+    // artifact still passes the sha256 check.
+    let damaged = tmp.path().join("dev@2024-02.incr.from_2024-01.send.zst.age");
+    fs::write(&damaged, b"not an age file").unwrap();
+    run_ok(&config_path, &["artifact", "register", damaged.to_str().unwrap()]);
+    run_ok(&config_path, &["verify", "--all"]);
+
+    let output = run(&config_path, &["verify", "--all", "--deep"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("dev@2024-01\tlocal ok (decoded)\t-\tpass"), "{stdout}");
+    assert!(stdout.contains("dev@2024-02\tFAIL: local does not decode"), "{stdout}");
+}
+
+#[test]
+fn verify_checks_each_label_in_a_range() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).cloud().crypto().write();
+    let stream = tmp.path().join("stream.bin");
+    fs::write(&stream, b"send stream").unwrap();
+    run_ok(&config_path, &["init", "ls"]);
+    for label in ["2024-01", "2024-02", "2024-03"] {
+        run_ok(&config_path, &["artifact", "ingest", "--label", label, stream.to_str().unwrap()]);
+    }
+
+    let report = run_ok(&config_path, &["verify", "--from", "2024-02", "--to", "2024-03"]);
+    assert!(!report.contains("dev@2024-01"), "{report}");
+    assert!(report.contains("dev@2024-02\tlocal ok\t-\tpass"), "{report}");
+    assert!(report.contains("dev@2024-03\tlocal ok\t-\tpass"), "{report}");
+    assert!(report.contains("Verified 2 artifact(s)"), "{report}");
+
+    let output = run(&config_path, &["verify", "--from", "2025-01"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no labels in the manifest from 2025-01"), "{stderr}");
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use common::{command, run, run_ok, setup, write_script};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tempfile::tempdir;
+
+const HEADER: &str = "# dev-backup manifest schema 4\n\
+                      ts\tlabel\ttype\tparent\tbytes\tsha256\tlocal_path\tobject_key\tmirror_keys\tstatus\tmachine\n";
+
+fn flush(config_path: &Path, args: &[&str]) -> Output {
+    command(config_path).args(["ws", "flush-queue"]).args(args).output().unwrap()
+}
+
+fn sha256(path: &Path) -> String {
+    let output = Command::new("sha256sum").arg(path).output().unwrap();
+    String::from_utf8_lossy(&output.stdout).split_whitespace().next().unwrap().to_string()
+}
+
+// Queues `(ts, filename, contents)` the way `ws run-month` does.
+fn queue(root: &Path, artifacts: &[(&str, &str, &[u8])]) -> PathBuf {
+    let dir = root.join("snapshots/.queue");
+    fs::create_dir_all(&dir).unwrap();
+    let mut rows = HEADER.to_string();
+    for (ts, filename, contents) in artifacts {
+        let path = dir.join(filename);
+        fs::write(&path, contents).unwrap();
+        let label = &filename[4..11];
+        let (kind, parent) = match filename.split_once(".incr.from_") {
+            Some((_, rest)) => ("incremental", &rest[..7]),
+            None => ("anchor", ""),
+        };
+        rows.push_str(&format!(
+            "{ts}\t{label}\t{kind}\t{parent}\t{}\t{}\t{}\t\t\tbuilt\t\n",
+            contents.len(),
+            sha256(&path),
+            path.display()
+        ));
+    }
+    fs::write(dir.join("queue.tsv"), rows).unwrap();
+    dir
+}
+
+#[test]
+fn flush_registers_queued_labels_oldest_first() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let dir = queue(
+        tmp.path(),
+        &[
+            ("2024-01-31T12:00:00Z", "dev@2024-01.full.send.zst.age", b"anchor"),
+            ("2024-02-29T12:00:00Z", "dev@2024-02.incr.from_2024-01.send.zst.age", b"incr"),
+        ],
+    );
+
+    let output = flush(&config_path, &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Push: skipped, no [cloud] configured"), "{stdout}");
+
+    let manifest = fs::read_to_string(tmp.path().join("ls/manifests/snapshots_v2.tsv")).unwrap();
+    let labels: Vec<&str> = manifest
+        .lines()
+        .skip(2)
+        .map(|line| line.split('\t').nth(1).unwrap())
+        .collect();
+    assert_eq!(labels, ["2024-01", "2024-02"], "{manifest}");
+    let incremental = "ls/artifacts/incr/dev@2024-02.incr.from_2024-01.send.zst.age";
+    assert!(tmp.path().join(incremental).exists());
+    assert_eq!(fs::read_to_string(dir.join("queue.tsv")).unwrap(), HEADER);
+
+    let output = flush(&config_path, &[]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Nothing queued"));
+}
+
+#[test]
+fn unreachable_ls_leaves_the_queue_alone() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let dir = queue(
+        tmp.path(),
+        &[("2024-01-31T12:00:00Z", "dev@2024-01.full.send.zst.age", b"anchor")],
+    );
+    fs::create_dir_all(tmp.path().join("bin")).unwrap();
+    let script = "#!/bin/sh\necho 'ssh: connect to host ls: Network is unreachable' >&2\nexit 255\n";
+    write_script(&tmp.path().join("bin/ssh"), script);
+    let before = fs::read_to_string(dir.join("queue.tsv")).unwrap();
+
+    let output = flush(&config_path, &["--ls-host", "ls"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("copying"));
+    assert_eq!(fs::read_to_string(dir.join("queue.tsv")).unwrap(), before);
+    assert!(dir.join("dev@2024-01.full.send.zst.age").exists());
+}
+
+#[test]
+fn a_queued_artifact_that_changed_is_not_shipped() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let dir = queue(
+        tmp.path(),
+        &[("2024-01-31T12:00:00Z", "dev@2024-01.full.send.zst.age", b"anchor")],
+    );
+    fs::write(dir.join("dev@2024-01.full.send.zst.age"), b"truncated").unwrap();
+
+    let output = flush(&config_path, &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("changed since it was built"));
+    assert!(!tmp.path().join("ls/manifests/snapshots_v2.tsv").exists());
+}
+
+#[test]
+fn encrypted_state_replaces_the_plain_queue() {
+    let tmp = tempdir().unwrap();
+    let key = tmp.path().join("state/state.key");
+    let state = format!("[state]\nencrypt = true\nkey_path = \"{}\"\n", key.display());
+    let config_path = setup(tmp.path()).section(&state).write();
+    let dir = queue(
+        tmp.path(),
+        &[
+            ("2024-01-31T12:00:00Z", "dev@2024-01.full.send.zst.age", b"anchor"),
+            ("2024-02-29T12:00:00Z", "dev@2024-02.incr.from_2024-01.send.zst.age", b"incr"),
+        ],
+    );
+    // 2024-02 fails its checksum, so it stays queued once 2024-01 is shipped.
+    fs::write(dir.join("dev@2024-02.incr.from_2024-01.send.zst.age"), b"changed").unwrap();
+
+    let output = flush(&config_path, &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("queue.tsv was mode 644; set it to 600"), "{stderr}");
+    assert!(!dir.join("queue.tsv").exists());
+    let encrypted = fs::read(dir.join("queue.tsv.age")).unwrap();
+    assert!(!String::from_utf8_lossy(&encrypted).contains("2024-02"));
+    for path in [key.clone(), dir.join("queue.tsv.age")] {
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    // The next flush reads the queue back and fails on the same artifact.
+    let output = flush(&config_path, &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("dev@2024-02.incr.from_2024-01.send.zst.age changed"), "{stderr}");
+}
+
+#[test]
+fn an_unfinished_build_stops_the_flush() {
+    let tmp = tempdir().unwrap();
+    let config_path = setup(tmp.path()).write();
+    let dir = queue(
+        tmp.path(),
+        &[("2024-01-31T12:00:00Z", "dev@2024-01.full.send.zst.age", b"anchor")],
+    );
+    // What `ws run-month 2024-02` leaves queued when it is killed mid-build.
+    let pending = "2024-02-29T12:00:00Z\t2024-02\tincremental\t2024-01\t0\t\t\t\t\tpending\t\n";
+    let mut rows = fs::read_to_string(dir.join("queue.tsv")).unwrap();
+    rows.push_str(pending);
+    fs::write(dir.join("queue.tsv"), &rows).unwrap();
+
+    let output = flush(&config_path, &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("the build of dev@2024-02 did not finish"), "{stderr}");
+    let manifest = fs::read_to_string(tmp.path().join("ls/manifests/snapshots_v2.tsv")).unwrap();
+    assert!(manifest.contains("\t2024-01\t"), "{manifest}");
+    assert_eq!(fs::read_to_string(dir.join("queue.tsv")).unwrap(), format!("{HEADER}{pending}"));
+}
+
+#[test]
+fn a_failed_build_leaves_nothing_queued() {
+    let tmp = tempdir().unwrap();
+    // Snapshots are plain directories, and nothing can be sent from them.
+    let btrfs = "#!/bin/sh\n[ \"$2\" = snapshot ] && mkdir -p \"$5\"\n";
+    let config_path = setup(tmp.path()).crypto().btrfs(btrfs).write();
+    run_ok(&config_path, &["init", "ls"]);
+
+    let output = run(&config_path, &["ws", "run-month", "2024-01"]);
+    assert!(!output.status.success());
+    assert!(tmp.path().join("snapshots/dev@2024-01").is_dir());
+    let queued = fs::read_to_string(tmp.path().join("snapshots/.queue/queue.tsv")).unwrap();
+    assert_eq!(queued, HEADER);
+}
@@ -0,0 +1,254 @@
+mod common;
+
+use common::{command, run_ok, setup, write_script};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tempfile::tempdir;
+
+// Stands in for btrfs-progs: a subvolume is a directory holding a .subvol
+// marker, and a send stream is the subvolume name on one line followed by the
+// contents of its single file. `subvolume show` prints the subvolume's .show
+// file, if it has one.
+const FAKE_BTRFS: &str = r#"#!/bin/sh
+case "$1 $2" in
+  "receive "*)
+    read -r name || exit 1
+    mkdir "$2/$name" && cat > "$2/$name/data" && touch "$2/$name/.subvol" ;;
+  "subvolume show") test -e "$3/.subvol" || exit 1; cat "$3/.show" 2>/dev/null; exit 0 ;;
+  "subvolume snapshot")
+    shift 2
+    if [ "$1" = "-r" ]; then shift; fi
+    cp -a "$1" "$2" ;;
+  "subvolume delete") rm -rf "$3" ;;
+  *) echo "fake btrfs: unsupported: $*" >&2; exit 1 ;;
+esac
+"#;
+
+// The dataset holds "old tree" until a request replaces it.
+fn config_with_old_tree(root: &Path) -> PathBuf {
+    let config_path = setup(root).btrfs(FAKE_BTRFS).write();
+    fs::write(root.join("dataset/data"), b"old tree").unwrap();
+    config_path
+}
+
+// `ls_send` is the body of the stub run in place of `dev-backup ls send`; its
+// arguments are recorded in `ls-send.args`.
+fn request(root: &Path, config_path: &Path, ls_send: &str, args: &[&str]) -> Output {
+    let stub = root.join("bin/ls-send");
+    write_script(
+        &stub,
+        &format!("#!/bin/sh\necho \"$@\" > {}\n{ls_send}\n", root.join("ls-send.args").display()),
+    );
+    command(config_path)
+        .args(["ws", "request"])
+        .args(args)
+        .env("DEV_BACKUP_LS_SEND", &stub)
+        .output()
+        .unwrap()
+}
+
+fn staged(root: &Path) -> Vec<String> {
+    fs::read_dir(root.join("snapshots/.staging"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect()
+}
+
+#[test]
+fn request_receives_the_stream_and_updates_the_worktree() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = config_with_old_tree(root);
+    fs::create_dir_all(root.join("snapshots/dev@2024-01")).unwrap();
+    fs::write(root.join("snapshots/dev@2024-01/.subvol"), b"").unwrap();
+
+    let output = request(
+        root,
+        &config_path,
+        "echo dev@2024-02; printf 'new tree'",
+        &["2024-02", "--auto-parent"],
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let args = fs::read_to_string(root.join("ls-send.args")).unwrap();
+    assert!(args.ends_with("ls send 2024-02 2024-01\n"), "{args}");
+    assert_eq!(fs::read(root.join("snapshots/dev@2024-02/data")).unwrap(), b"new tree");
+    assert!(!root.join("snapshots/.staging/dev@2024-02").exists());
+    assert_eq!(fs::read(root.join("dataset/data")).unwrap(), b"new tree");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Working tree updated to dev@2024-02"), "{stdout}");
+    let rebaseline = fs::read_to_string(root.join("snapshots/.rebaseline")).unwrap();
+    assert_eq!(rebaseline, "2024-02\n");
+
+    // The snapshot now exists, so asking again is refused, even once the
+    // previous tree is confirmed away.
+    fs::remove_dir_all(root.join("dataset.old")).unwrap();
+    let again = request(root, &config_path, "exit 0", &["2024-02"]);
+    assert!(!again.status.success());
+    assert!(String::from_utf8_lossy(&again.stderr).contains("snapshot already exists"));
+}
+
+#[test]
+fn failed_send_leaves_nothing_behind() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = config_with_old_tree(root);
+
+    let output = request(
+        root,
+        &config_path,
+        "echo dev@2024-02; printf 'partial'; exit 3",
+        &["2024-02"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("ls send failed"), "{stderr}");
+    assert_eq!(staged(root), Vec::<String>::new());
+    assert!(!root.join("snapshots/dev@2024-02").exists());
+    assert_eq!(fs::read(root.join("dataset/data")).unwrap(), b"old tree");
+}
+
+#[test]
+fn stream_for_another_snapshot_is_rejected() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = config_with_old_tree(root);
+
+    let output = request(root, &config_path, "echo dev@2023-12; printf 'x'", &["2024-02"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("received snapshot missing"), "{stderr}");
+    assert_eq!(staged(root), Vec::<String>::new());
+    assert!(!root.join("snapshots/dev@2024-02").exists());
+    assert_eq!(fs::read(root.join("dataset/data")).unwrap(), b"old tree");
+}
+
+#[test]
+fn the_previous_tree_is_kept_until_confirmed() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = config_with_old_tree(root);
+    let output = request(root, &config_path, "echo dev@2024-02; printf 'new tree'", &["2024-02"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read(root.join("dataset/data")).unwrap(), b"new tree");
+    assert_eq!(fs::read(root.join("dataset.old/data")).unwrap(), b"old tree");
+    assert!(!root.join("dataset.new").exists());
+
+    run_ok(&config_path, &["restore", "switch-back"]);
+    assert_eq!(fs::read(root.join("dataset/data")).unwrap(), b"old tree");
+    assert_eq!(fs::read(root.join("dataset.old/data")).unwrap(), b"new tree");
+    run_ok(&config_path, &["restore", "confirm"]);
+    assert!(!root.join("dataset.old").exists());
+    assert_eq!(fs::read(root.join("dataset/data")).unwrap(), b"old tree");
+}
+
+#[test]
+fn a_tree_still_kept_from_the_last_request_stops_the_next_before_receiving() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = config_with_old_tree(root);
+    let output = request(root, &config_path, "echo dev@2024-02; printf 'new tree'", &["2024-02"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    fs::remove_file(root.join("ls-send.args")).unwrap();
+
+    let output = request(root, &config_path, "echo dev@2024-03; printf 'newer'", &["2024-03"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("dataset.old is still kept from the last apply"), "{stderr}");
+    assert!(!root.join("ls-send.args").exists());
+    assert!(!root.join("snapshots/dev@2024-03").exists());
+}
+
+#[test]
+fn a_dirty_repository_in_the_tree_needs_force() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = config_with_old_tree(root);
+    let repo = root.join("dataset/project");
+    fs::create_dir_all(&repo).unwrap();
+    let init = Command::new("git").arg("init").arg("-q").arg(&repo).status().unwrap();
+    assert!(init.success());
+    fs::write(repo.join("notes.txt"), b"a week of work").unwrap();
+    let ls_send = "echo dev@2024-02; printf 'new tree'";
+
+    let output = request(root, &config_path, ls_send, &["2024-02"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("project (1 uncommitted change(s))"), "{stderr}");
+    assert!(stderr.contains("pass --force"), "{stderr}");
+    assert!(!root.join("ls-send.args").exists());
+    assert!(repo.join("notes.txt").exists());
+
+    let output = request(root, &config_path, ls_send, &["2024-02", "--force"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read(root.join("dataset/data")).unwrap(), b"new tree");
+    assert!(root.join("dataset.old/project/notes.txt").exists());
+}
+
+#[test]
+fn a_git_status_that_fails_counts_as_dirty() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = config_with_old_tree(root);
+    fs::create_dir_all(root.join("dataset/project/.git")).unwrap();
+    let git = "#!/bin/sh\necho \"fatal: detected dubious ownership in repository\" >&2\nexit 128\n";
+    write_script(&root.join("bin/git"), git);
+    let ls_send = "echo dev@2024-02; printf 'new tree'";
+
+    let output = request(root, &config_path, ls_send, &["2024-02"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("project (git status failed: fatal: detected dubious"), "{stderr}");
+    assert!(stderr.contains("pass --force"), "{stderr}");
+
+    let output = request(root, &config_path, ls_send, &["2024-02", "--force"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read(root.join("dataset/data")).unwrap(), b"new tree");
+}
+
+#[test]
+fn the_mtime_check_uses_the_time_btrfs_took_the_snapshot() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = config_with_old_tree(root);
+    let mut config = fs::read_to_string(&config_path).unwrap();
+    config.push_str("\n[worktree]\ndirty_check = \"mtime\"\n");
+    fs::write(&config_path, config).unwrap();
+    // Made after dataset/data was written, but taken long before that.
+    let snapshot = root.join("snapshots/dev@2024-01");
+    fs::create_dir_all(&snapshot).unwrap();
+    fs::write(snapshot.join(".subvol"), b"").unwrap();
+    fs::write(snapshot.join(".show"), "\tCreation time: \t\t2000-01-01 00:00:00 +0000\n").unwrap();
+
+    let output = request(root, &config_path, "echo dev@2024-02; printf 'new tree'", &["2024-02"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("dataset/data changed"), "{stderr}");
+}
+
+#[test]
+fn a_receive_that_stops_reading_is_killed() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    let config_path = config_with_old_tree(root);
+    let mut config = fs::read_to_string(&config_path).unwrap();
+    config.push_str("\n[timeouts]\nstall = \"1s\"\n");
+    fs::write(&config_path, config).unwrap();
+    // Receive takes the subvolume's name and then hangs, like one whose
+    // destination filled up.
+    let hanging = FAKE_BTRFS.replace(
+        "mkdir \"$2/$name\" && cat > \"$2/$name/data\"",
+        "mkdir \"$2/$name\" && exec sleep 60",
+    );
+    write_script(&root.join("bin/btrfs"), &hanging);
+
+    let started = std::time::Instant::now();
+    let output = request(root, &config_path, "echo dev@2024-02; printf 'new tree'", &["2024-02"]);
+    assert!(started.elapsed().as_secs() < 30);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("btrfs receive stalled: no bytes moved for 1s"), "{stderr}");
+    assert!(!root.join("snapshots/.staging/dev@2024-02").exists());
+    assert_eq!(fs::read(root.join("dataset/data")).unwrap(), b"old tree");
+}
@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct AliasStore {
+    path: PathBuf,
+}
+
+impl AliasStore {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn read(&self) -> Result<BTreeMap<String, String>> {
+        let mut aliases = BTreeMap::new();
+        if !self.path.exists() {
+            return Ok(aliases);
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_path(&self.path)
+            .with_context(|| format!("failed to read aliases: {}", self.path.display()))?;
+        for result in reader.records() {
+            let row = result.context("failed to parse alias row")?;
+            let name = row.get(0).unwrap_or_default();
+            let label = row.get(1).unwrap_or_default();
+            if name.is_empty() || label.is_empty() {
+                return Err(anyhow!("malformed alias row in {}", self.path.display()));
+            }
+            aliases.insert(name.to_string(), label.to_string());
+        }
+        Ok(aliases)
+    }
+
+    pub fn set(&self, name: &str, label: &str) -> Result<Option<String>> {
+        let mut aliases = self.read()?;
+        let previous = aliases.insert(name.to_string(), label.to_string());
+        self.write(&aliases)?;
+        Ok(previous)
+    }
+
+    pub fn remove(&self, name: &str) -> Result<Option<String>> {
+        let mut aliases = self.read()?;
+        let previous = aliases.remove(name);
+        if previous.is_some() {
+            self.write(&aliases)?;
+        }
+        Ok(previous)
+    }
+
+    fn write(&self, aliases: &BTreeMap<String, String>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create alias directory: {}", parent.display()))?;
+        }
+        let tmp_path = self.path.with_extension("tsv.tmp");
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(b'\t')
+            .from_path(&tmp_path)
+            .with_context(|| format!("failed to create aliases: {}", tmp_path.display()))?;
+        writer
+            .write_record(["alias", "label"])
+            .context("failed to write alias header")?;
+        for (name, label) in aliases {
+            writer
+                .write_record([name, label])
+                .context("failed to write alias row")?;
+        }
+        writer.flush().context("failed to flush aliases")?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("failed to replace aliases: {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+pub fn is_valid_alias_name(name: &str) -> bool {
+    name != "latest"
+        && name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
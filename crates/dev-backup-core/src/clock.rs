@@ -0,0 +1,38 @@
+use anyhow::{Context, Result};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> OffsetDateTime;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock {
+    now: OffsetDateTime,
+}
+
+impl FixedClock {
+    pub fn new(now: OffsetDateTime) -> Self {
+        Self { now }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        let now = OffsetDateTime::parse(value, &Rfc3339)
+            .with_context(|| format!("invalid RFC 3339 timestamp: {value}"))?;
+        Ok(Self::new(now))
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> OffsetDateTime {
+        self.now
+    }
+}
@@ -1,49 +1,1029 @@
-use anyhow::{Context, Result};
-use serde::Deserialize;
+use crate::naming::{
+    is_valid_stream, LabelScheme, NameTemplate, DEFAULT_PREFIX, DEFAULT_TEMPLATE,
+};
+use crate::notify::MessageTemplate;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer};
 use std::fs;
+use std::net::Ipv6Addr;
 use std::path::Path;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub paths: Paths,
+    #[serde(default, rename = "dataset")]
+    pub datasets: Vec<Dataset>,
     pub cloud: Option<Cloud>,
+    #[serde(default)]
+    pub mirrors: Vec<Mirror>,
     pub crypto: Option<Crypto>,
     pub remote: Option<Remote>,
+    #[serde(default)]
+    pub permissions: Permissions,
+    pub churn: Option<Churn>,
+    pub disk: Option<Disk>,
+    #[serde(default)]
+    pub access: Access,
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default)]
+    pub btrfs: BtrfsConfig,
+    #[serde(default)]
+    pub recovery: Recovery,
+    #[serde(default)]
+    pub manifest: ManifestConfig,
+    #[serde(default)]
+    pub split: Split,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub notify: Vec<Notify>,
+    #[serde(default)]
+    pub naming: Naming,
+    #[serde(default)]
+    pub logs: Logs,
+    #[serde(default)]
+    pub worktree: Worktree,
+    #[serde(default)]
+    pub state: State,
+    #[serde(default)]
+    pub timeouts: Timeouts,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Paths {
+    #[serde(default)]
     pub dataset: String,
+    #[serde(default)]
     pub snapshots: String,
     pub ls_root: String,
+    // Where `artifact build`, `backup-now` and `artifact ingest` write
+    // artifacts before they are registered; <ls_root>/tmp when unset.
+    pub artifact_staging: Option<String>,
+}
+
+// One of several subvolumes backed up from the same host, in place of
+// paths.dataset and paths.snapshots. Each has its own snapshot root, stream
+// prefix (its name unless set) and manifest; `--dataset` picks one.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Dataset {
+    pub name: String,
+    pub path: String,
+    pub snapshots: String,
+    pub prefix: Option<String>,
+}
+
+impl Dataset {
+    pub fn prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or(&self.name)
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Cloud {
+    #[serde(default)]
+    pub backend: CloudBackend,
+    #[serde(default)]
     pub endpoint: String,
+    #[serde(default)]
     pub bucket: String,
+    #[serde(default)]
     pub access_key: String,
+    #[serde(default)]
     pub secret_key: String,
+    pub https_proxy: Option<String>,
+    pub ca_bundle_path: Option<String>,
+    pub local_root: Option<String>,
+    pub url: Option<String>,
+    #[serde(default)]
+    pub ssh_options: Vec<String>,
+    // r2 only: files larger than the threshold go up as a resumable multipart
+    // upload in parts of multipart_part_mib.
+    #[serde(default = "default_multipart_threshold_mib")]
+    pub multipart_threshold_mib: u64,
+    #[serde(default = "default_multipart_part_mib")]
+    pub multipart_part_mib: u64,
+}
+
+fn default_multipart_threshold_mib() -> u64 {
+    256
+}
+
+fn default_multipart_part_mib() -> u64 {
+    64
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CloudBackend {
+    #[default]
+    R2,
+    Local,
+    Sftp,
+    Http,
+}
+
+impl Cloud {
+    pub fn validate(&self) -> Result<()> {
+        match self.backend {
+            CloudBackend::R2 => {
+                for (name, value) in [
+                    ("cloud.endpoint", &self.endpoint),
+                    ("cloud.bucket", &self.bucket),
+                    ("cloud.access_key", &self.access_key),
+                    ("cloud.secret_key", &self.secret_key),
+                ] {
+                    if value.is_empty() {
+                        return Err(anyhow!("{name} must be set for the r2 backend"));
+                    }
+                }
+                // S3 multipart limits: parts of 5 MiB to 5 GiB.
+                if !(5..=5120).contains(&self.multipart_part_mib) {
+                    return Err(anyhow!("cloud.multipart_part_mib must be between 5 and 5120"));
+                }
+                if self.multipart_threshold_mib < self.multipart_part_mib {
+                    return Err(anyhow!(
+                        "cloud.multipart_threshold_mib must be at least cloud.multipart_part_mib"
+                    ));
+                }
+            }
+            CloudBackend::Local => match self.local_root.as_deref() {
+                Some(root) if Path::new(root).is_absolute() => {}
+                _ => return Err(anyhow!("cloud.local_root must be an absolute path")),
+            },
+            CloudBackend::Sftp => {
+                let url = self
+                    .url
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("cloud.url must be set for the sftp backend"))?;
+                SftpUrl::parse(url)?;
+                validate_ssh_options("cloud.ssh_options", &self.ssh_options)?;
+            }
+            CloudBackend::Http => match self.url.as_deref() {
+                Some(url) if url.starts_with("https://") || url.starts_with("http://") => {}
+                _ => return Err(anyhow!("cloud.url must be an http:// or https:// URL")),
+            },
+        }
+        Ok(())
+    }
+}
+
+// Extra storage targets that `sync push` copies every artifact and manifest
+// to after [cloud]. The manifest tracks each mirror's object key separately.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Mirror {
+    pub name: String,
+    #[serde(flatten)]
+    pub target: Cloud,
+}
+
+fn validate_mirrors(cloud: Option<&Cloud>, mirrors: &[Mirror]) -> Result<()> {
+    if !mirrors.is_empty() && cloud.is_none() {
+        return Err(anyhow!("[[mirrors]] requires a [cloud] target"));
+    }
+    for (index, mirror) in mirrors.iter().enumerate() {
+        let name = &mirror.name;
+        if !is_valid_target_name(name) || name == "cloud" {
+            return Err(anyhow!("mirrors.name is invalid: {name:?}"));
+        }
+        if mirrors[..index].iter().any(|other| &other.name == name) {
+            return Err(anyhow!("mirror {name:?} is listed twice"));
+        }
+        mirror
+            .target
+            .validate()
+            .with_context(|| format!("invalid mirror {name:?}"))?;
+    }
+    Ok(())
+}
+
+pub fn is_valid_target_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// sftp://[user@]host[:port]/absolute/path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SftpUrl {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    pub path: String,
+}
+
+impl SftpUrl {
+    pub fn parse(url: &str) -> Result<Self> {
+        let invalid = || anyhow!("cloud.url must look like sftp://user@host/path: {url:?}");
+        let rest = url.strip_prefix("sftp://").ok_or_else(invalid)?;
+        let (authority, path) = rest.split_at(rest.find('/').ok_or_else(invalid)?);
+        if path.len() < 2 || path.chars().any(char::is_control) {
+            return Err(invalid());
+        }
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (Some(user), host_port),
+            None => (None, authority),
+        };
+        if let Some(user) = user {
+            if user.is_empty() || user.contains(|c: char| c.is_whitespace() || c == ':') {
+                return Err(invalid());
+            }
+        }
+        let (host, port) = match host_port.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, after) = bracketed.split_once(']').ok_or_else(invalid)?;
+                (host, after.strip_prefix(':'))
+            }
+            None => match host_port.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            },
+        };
+        validate_host(host).map_err(|_| invalid())?;
+        let port = port
+            .map(|port| port.parse::<u16>().ok().filter(|&port| port > 0).ok_or_else(invalid))
+            .transpose()?;
+        Ok(Self {
+            user: user.map(str::to_string),
+            host: host.to_string(),
+            port,
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Crypto {
     pub age_public_key: Option<String>,
     pub age_private_key_path: Option<String>,
+    #[serde(default)]
+    pub age_backend: AgeBackend,
+    // Identity files kept away from LS (offline copies, a second admin's
+    // key); only `keys audit` reads them.
+    #[serde(default)]
+    pub escrow_identities: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AgeBackend {
+    #[default]
+    Native,
+    External,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct BtrfsConfig {
+    #[serde(default)]
+    pub backend: BtrfsBackend,
+    #[serde(default)]
+    pub receive_errors: ReceiveErrors,
+    #[serde(default)]
+    pub verify_receive: VerifyReceive,
+}
+
+// `native` issues subvolume ioctls directly instead of running `btrfs`;
+// send, receive and churn probing still need btrfs-progs either way.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BtrfsBackend {
+    #[default]
+    Cli,
+    Native,
+}
+
+// `continue` runs `btrfs receive --max-errors 0`, so a stream with failing
+// commands is applied as far as it goes instead of stopping at the first error.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiveErrors {
+    #[default]
+    Abort,
+    Continue,
+}
+
+// What hydration does once btrfs receive exits successfully: a receive fed a
+// stream that was cut short upstream can exit 0 and leave a subvolume without
+// its received_uuid. `retry` deletes such a subvolume and receives it once
+// more before failing, `fail` fails straight away and `off` skips the check.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyReceive {
+    #[default]
+    Retry,
+    Fail,
+    Off,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Remote {
     pub ls_host: Option<String>,
     pub ls_user: Option<String>,
+    pub ls_port: Option<u16>,
+    #[serde(default)]
+    pub ssh_options: Vec<String>,
+    #[serde(default = "default_ssh_multiplex")]
+    pub ssh_multiplex: bool,
+    #[serde(default = "default_control_persist_seconds")]
+    pub control_persist_seconds: u64,
+}
+
+fn default_ssh_multiplex() -> bool {
+    true
+}
+
+fn default_control_persist_seconds() -> u64 {
+    60
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct Compression {
+    #[serde(default = "default_compression_level")]
+    pub level: i32,
+    // 0 compresses on the calling thread; N > 0 adds N zstd worker threads.
+    #[serde(default)]
+    pub threads: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            level: default_compression_level(),
+            threads: 0,
+        }
+    }
+}
+
+fn default_compression_level() -> i32 {
+    3
+}
+
+// Top-level directories of the dataset that are nested subvolumes of their
+// own. A snapshot of the dataset skips nested subvolumes, so each part gets
+// its own snapshots, artifacts and manifest, i.e. an independent chain.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Split {
+    #[serde(default)]
+    pub parts: Vec<String>,
+}
+
+impl Split {
+    // `prefix` is the dataset's own stream name, which a part cannot reuse.
+    pub fn validate(&self, prefix: &str) -> Result<()> {
+        for (index, part) in self.parts.iter().enumerate() {
+            if !is_valid_stream(part) || part == prefix {
+                return Err(anyhow!("split.parts entry is invalid: {part:?}"));
+            }
+            if self.parts[..index].contains(part) {
+                return Err(anyhow!("split.parts lists {part:?} twice"));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Snapshot and artifact names; see `NameTemplate` and `LabelScheme`. Changing
+// the prefix or template after backups exist leaves the old snapshots and
+// artifacts unrecognised.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Naming {
+    #[serde(default = "default_naming_prefix")]
+    pub prefix: String,
+    #[serde(default = "default_snapshot_name_template")]
+    pub snapshot_name_template: String,
+    #[serde(default)]
+    pub label_scheme: LabelScheme,
+    // Set on each workstation when several share one LS or bucket; it goes
+    // into artifact names, object keys and manifest rows.
+    #[serde(default)]
+    pub machine_id: Option<String>,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Self {
+            prefix: default_naming_prefix(),
+            snapshot_name_template: default_snapshot_name_template(),
+            label_scheme: LabelScheme::default(),
+            machine_id: None,
+        }
+    }
+}
+
+impl Naming {
+    pub fn template(&self) -> Result<NameTemplate> {
+        NameTemplate::new(&self.prefix, &self.snapshot_name_template)?
+            .with_machine(self.machine_id.as_deref())
+    }
+}
+
+fn default_naming_prefix() -> String {
+    DEFAULT_PREFIX.to_string()
+}
+
+fn default_snapshot_name_template() -> String {
+    DEFAULT_TEMPLATE.to_string()
+}
+
+// Run when `backup-now` finishes, without a shell. The message is written to
+// its stdin and DEV_BACKUP_STATUS is set to "ok" or "failed". `[notify]` is
+// one channel and `[[notify]]` several, each with its own `template` for the
+// message; without one the channel gets the plain run summary.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Notify {
+    pub name: Option<String>,
+    pub command: Vec<String>,
+    pub template: Option<String>,
+}
+
+impl Notify {
+    pub fn validate(&self) -> Result<()> {
+        match self.command.first() {
+            Some(program) if !program.is_empty() => {}
+            _ => return Err(anyhow!("notify.command must name a program")),
+        }
+        self.message_template()?;
+        Ok(())
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or_else(|| self.command.first().map(String::as_str))
+            .unwrap_or_default()
+    }
+
+    pub fn message_template(&self) -> Result<MessageTemplate> {
+        MessageTemplate::parse(self.template.as_deref().unwrap_or("{summary}"))
+            .with_context(|| format!("invalid notify.template for {}", self.display_name()))
+    }
+}
+
+fn one_or_many<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+// Reads come from `primary`; every write goes to the primary and then to the
+// replica. The TSV store has to be one of the two because it is what sync
+// uploads and what other hosts download.
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct ManifestConfig {
+    #[serde(default)]
+    pub primary: ManifestBackend,
+    pub replica: Option<ManifestBackend>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestBackend {
+    #[default]
+    Tsv,
+    Sqlite,
+}
+
+impl ManifestBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ManifestBackend::Tsv => "tsv",
+            ManifestBackend::Sqlite => "sqlite",
+        }
+    }
+}
+
+impl ManifestConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.replica == Some(self.primary) {
+            return Err(anyhow!("manifest.replica must differ from manifest.primary"));
+        }
+        if self.primary != ManifestBackend::Tsv && self.replica != Some(ManifestBackend::Tsv) {
+            return Err(anyhow!("manifest: the tsv store must be the primary or the replica"));
+        }
+        Ok(())
+    }
+}
+
+// Assumed throughput for the restore-time estimate in `status`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Recovery {
+    #[serde(default = "default_download_mbps")]
+    pub download_mbps: f64,
+    #[serde(default = "default_decrypt_mib_per_sec")]
+    pub decrypt_mib_per_sec: f64,
+    #[serde(default = "default_receive_mib_per_sec")]
+    pub receive_mib_per_sec: f64,
+    pub objective: Option<String>,
+    // Scratch space `restore hydrate` may fill with downloaded artifacts when
+    // fetching the next one from the cloud ahead of time.
+    #[serde(default = "default_prefetch_budget_mib")]
+    pub prefetch_budget_mib: u64,
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Self {
+            download_mbps: default_download_mbps(),
+            decrypt_mib_per_sec: default_decrypt_mib_per_sec(),
+            receive_mib_per_sec: default_receive_mib_per_sec(),
+            objective: None,
+            prefetch_budget_mib: default_prefetch_budget_mib(),
+        }
+    }
+}
+
+fn default_prefetch_budget_mib() -> u64 {
+    8192
+}
+
+fn default_download_mbps() -> f64 {
+    100.0
+}
+
+fn default_decrypt_mib_per_sec() -> f64 {
+    200.0
+}
+
+fn default_receive_mib_per_sec() -> f64 {
+    150.0
+}
+
+impl Recovery {
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("recovery.download_mbps", self.download_mbps),
+            ("recovery.decrypt_mib_per_sec", self.decrypt_mib_per_sec),
+            ("recovery.receive_mib_per_sec", self.receive_mib_per_sec),
+        ] {
+            if !value.is_finite() || value <= 0.0 {
+                return Err(anyhow!("{name} must be a positive number"));
+            }
+        }
+        if let Some(objective) = self.objective.as_deref() {
+            crate::deadline::parse_duration(objective).context("invalid recovery.objective")?;
+        }
+        Ok(())
+    }
+}
+
+// With `encrypt`, run logs and restore receive logs are written as age files
+// for crypto.age_public_key; `dev-backup logs decrypt` reads them back.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Logs {
+    #[serde(default)]
+    pub encrypt: bool,
+}
+
+// How `ws request` tells the working tree holds work no snapshot has, before
+// it replaces the tree: "git" runs `git status --porcelain` in each repository
+// under the dataset, "mtime" looks for files changed since the newest local
+// snapshot was taken, "command" runs `dirty_command` in the dataset (output or
+// a non-zero exit means dirty) and "off" skips the check.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Worktree {
+    #[serde(default)]
+    pub dirty_check: DirtyCheck,
+    #[serde(default)]
+    pub dirty_command: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DirtyCheck {
+    #[default]
+    Git,
+    Mtime,
+    Command,
+    Off,
+}
+
+impl Worktree {
+    pub fn validate(&self) -> Result<()> {
+        if self.dirty_check == DirtyCheck::Command && self.dirty_command.is_empty() {
+            return Err(anyhow!("worktree.dirty_check = \"command\" requires worktree.dirty_command"));
+        }
+        Ok(())
+    }
+}
+
+// WS-local state beside the snapshots (the `ws run-month` queue and the
+// restore marker) is always written 0600. With `encrypt` it is kept as age
+// files for the identity at `key_path`, which is created on first use.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct State {
+    #[serde(default)]
+    pub encrypt: bool,
+    pub key_path: Option<String>,
+}
+
+impl State {
+    pub fn validate(&self) -> Result<()> {
+        match self.key_path.as_deref() {
+            None if self.encrypt => Err(anyhow!("state.encrypt requires state.key_path")),
+            Some(path) if !Path::new(path).is_absolute() => {
+                Err(anyhow!("state.key_path must be an absolute path: {path:?}"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Limits on the subprocesses of a send or receive pipeline, as durations like
+// "90m" or "6h": how long `btrfs send` (or `ls send`) and `btrfs receive` may
+// run, and how long no bytes may move between the stages. Whichever trips
+// first stops the whole pipeline.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Timeouts {
+    pub stall: Option<String>,
+    pub send: Option<String>,
+    pub receive: Option<String>,
+}
+
+impl Timeouts {
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [("stall", &self.stall), ("send", &self.send), ("receive", &self.receive)]
+        {
+            if let Some(value) = value {
+                crate::deadline::parse_duration(value)
+                    .with_context(|| format!("invalid timeouts.{name}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Access {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl Access {
+    // `command` is the dotted subcommand path, e.g. "restore.apply". A rule
+    // for "restore" covers every restore subcommand.
+    pub fn permits(&self, command: &str) -> bool {
+        let matches =
+            |rule: &String| command == rule || command.starts_with(&format!("{rule}."));
+        if self.deny.iter().any(matches) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(matches)
+    }
+
+    // `commands` lists every command path, groups included. A rule naming
+    // none of them would silently match nothing.
+    pub fn validate(&self, commands: &[String]) -> Result<()> {
+        for (list, rules) in [("allow", &self.allow), ("deny", &self.deny)] {
+            if let Some(rule) = rules.iter().find(|rule| !commands.contains(rule)) {
+                return Err(anyhow!(
+                    "access.{list}: unknown command {rule:?}; name a command like \"restore\" \
+                     or \"restore.apply\""
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check(&self, command: &str) -> Result<()> {
+        if !self.permits(command) {
+            let command = command.replace('.', " ");
+            return Err(anyhow!("command `{command}` is not permitted on this host by [access]"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Churn {
+    #[serde(default = "default_probe_seconds")]
+    pub probe_seconds: u64,
+    pub max_changed_bytes: u64,
+    #[serde(default)]
+    pub action: ChurnAction,
+    // How long "delay" keeps probing. The default 0 snapshots after the first
+    // busy probe, so "delay" then behaves like "warn".
+    #[serde(default)]
+    pub max_wait_seconds: u64,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChurnAction {
+    #[default]
+    Warn,
+    Delay,
+}
+
+fn default_probe_seconds() -> u64 {
+    30
+}
+
+// Free space on the snapshots filesystem. Below `critical_free_mib`,
+// `ws maintain` prunes the oldest snapshots that no incremental still needs;
+// below `floor_free_mib` no new snapshot is taken.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Disk {
+    pub critical_free_mib: Option<u64>,
+    pub floor_free_mib: Option<u64>,
+}
+
+impl Disk {
+    pub fn validate(&self) -> Result<()> {
+        if let (Some(critical), Some(floor)) = (self.critical_free_mib, self.floor_free_mib) {
+            if floor > critical {
+                return Err(anyhow!("disk.floor_free_mib must not exceed disk.critical_free_mib"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Permissions {
+    pub artifacts: Option<PathPolicy>,
+    pub manifests: Option<PathPolicy>,
+    pub keys: Option<PathPolicy>,
+    pub restore: Option<PathPolicy>,
+    pub logs: Option<PathPolicy>,
+    pub tmp: Option<PathPolicy>,
+    pub locks: Option<PathPolicy>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PathPolicy {
+    pub mode: Option<String>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+pub const DEFAULT_DIR_MODE: u32 = 0o700;
+
+impl Permissions {
+    pub fn policy(&self, class: &str) -> Option<&PathPolicy> {
+        match class {
+            "artifacts" => self.artifacts.as_ref(),
+            "manifests" => self.manifests.as_ref(),
+            "keys" => self.keys.as_ref(),
+            "restore" => self.restore.as_ref(),
+            "logs" => self.logs.as_ref(),
+            "tmp" => self.tmp.as_ref(),
+            "locks" => self.locks.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn dir_mode(&self, class: &str) -> Result<u32> {
+        match self.policy(class).and_then(|policy| policy.mode.as_deref()) {
+            Some(mode) => parse_mode(mode)
+                .with_context(|| format!("invalid permissions.{class}.mode: {mode:?}")),
+            None => Ok(DEFAULT_DIR_MODE),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for class in ["artifacts", "manifests", "keys", "restore", "logs", "tmp", "locks"] {
+            self.dir_mode(class)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_mode(mode: &str) -> Result<u32> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    let value = u32::from_str_radix(digits, 8).map_err(|_| anyhow!("mode must be octal"))?;
+    if value > 0o7777 {
+        return Err(anyhow!("mode out of range"));
+    }
+    Ok(value)
+}
+
+impl Remote {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(host) = self.ls_host.as_deref() {
+            validate_host(host)?;
+        }
+        if let Some(user) = self.ls_user.as_deref() {
+            if user.is_empty() || user.contains(|c: char| c.is_whitespace() || c == '@') {
+                return Err(anyhow!("remote.ls_user is invalid: {user:?}"));
+            }
+        }
+        if self.ls_port == Some(0) {
+            return Err(anyhow!("remote.ls_port must be between 1 and 65535"));
+        }
+        validate_ssh_options("remote.ssh_options", &self.ssh_options)
+    }
+}
+
+fn validate_ssh_options(name: &str, options: &[String]) -> Result<()> {
+    for option in options {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| anyhow!("{name} entry must be Key=Value: {option:?}"))?;
+        if key.is_empty()
+            || value.is_empty()
+            || key.starts_with('-')
+            || !key.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return Err(anyhow!("{name} entry is invalid: {option:?}"));
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_host(host: &str) -> Result<()> {
+    let bare = host
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+        .unwrap_or(host);
+    if bare.contains(':') {
+        bare.parse::<Ipv6Addr>()
+            .map_err(|_| anyhow!("remote.ls_host is not a valid IPv6 address: {host}"))?;
+        return Ok(());
+    }
+    if bare.is_empty()
+        || bare.starts_with('-')
+        || !bare
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+    {
+        return Err(anyhow!("remote.ls_host is invalid: {host:?}"));
+    }
+    Ok(())
+}
+
+// Later files override earlier ones, and all of them override the main
+// config, so secrets can live in a root-only file next to a shared one.
+fn include_files(path: &Path, table: &mut toml::Table, items: Vec<toml::Value>) -> Result<()> {
+    for item in items {
+        let toml::Value::String(include) = item else {
+            return Err(anyhow!("include must be a list of paths"));
+        };
+        // Relative entries are taken from the main config's directory.
+        let include = path.parent().unwrap_or(Path::new("")).join(include);
+        let contents = fs::read_to_string(&include)
+            .with_context(|| format!("failed to read config: {}", include.display()))?;
+        let included: toml::Table = toml::from_str(&contents)
+            .with_context(|| format!("failed to parse config: {}", include.display()))?;
+        if included.contains_key("include") {
+            return Err(anyhow!("{}: include is only read from the main config", include.display()));
+        }
+        merge_tables(table, included);
+    }
+    Ok(())
+}
+
+// Tables merge key by key; any other value from a later file, lists
+// included, replaces the earlier one.
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(value)) => {
+                merge_tables(existing, value)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
 }
 
 impl Config {
     pub fn load(path: impl AsRef<Path>) -> Result<Self> {
-        let contents = fs::read_to_string(&path)
-            .with_context(|| format!("failed to read config: {}", path.as_ref().display()))?;
-        let cfg = toml::from_str(&contents)
-            .with_context(|| format!("failed to parse config: {}", path.as_ref().display()))?;
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config: {}", path.display()))?;
+        let mut table: toml::Table = toml::from_str(&contents)
+            .with_context(|| format!("failed to parse config: {}", path.display()))?;
+        let parsed = match table.remove("include") {
+            // Parsed from the text so errors keep their line and column.
+            None => toml::from_str(&contents).map_err(anyhow::Error::from),
+            Some(toml::Value::Array(items)) => {
+                include_files(path, &mut table, items)?;
+                toml::Value::Table(table).try_into().map_err(anyhow::Error::from)
+            }
+            Some(_) => return Err(anyhow!("include must be a list of paths")),
+        };
+        let cfg: Config =
+            parsed.with_context(|| format!("failed to parse config: {}", path.display()))?;
+        // Checked here because selecting a dataset fills these paths in.
+        let paths = &cfg.paths;
+        let has_paths = !paths.dataset.is_empty() || !paths.snapshots.is_empty();
+        if !cfg.datasets.is_empty() && has_paths {
+            return Err(anyhow!(
+                "[[dataset]] replaces paths.dataset and paths.snapshots; remove them from [paths]"
+            ));
+        }
         Ok(cfg)
     }
+
+    pub fn validate(&self) -> Result<()> {
+        let mut paths = vec![("paths.ls_root", &self.paths.ls_root)];
+        if self.datasets.is_empty() {
+            let Paths { dataset, snapshots, ls_root, .. } = &self.paths;
+            if dataset.is_empty() || snapshots.is_empty() || ls_root.is_empty() {
+                return Err(anyhow!("paths.dataset, paths.snapshots and paths.ls_root must be set"));
+            }
+            paths.push(("paths.dataset", &self.paths.dataset));
+            paths.push(("paths.snapshots", &self.paths.snapshots));
+        }
+        if let Some(staging) = &self.paths.artifact_staging {
+            paths.push(("paths.artifact_staging", staging));
+        }
+        for dataset in &self.datasets {
+            paths.push(("dataset.path", &dataset.path));
+            paths.push(("dataset.snapshots", &dataset.snapshots));
+        }
+        for (name, value) in paths {
+            if !Path::new(value).is_absolute() || value.chars().any(char::is_control) {
+                return Err(anyhow!("{name} must be an absolute path: {value:?}"));
+            }
+        }
+        self.validate_datasets()?;
+        if let Some(cloud) = &self.cloud {
+            cloud.validate()?;
+        }
+        validate_mirrors(self.cloud.as_ref(), &self.mirrors)?;
+        if let Some(remote) = &self.remote {
+            remote.validate()?;
+        }
+        if let Some(disk) = &self.disk {
+            disk.validate()?;
+        }
+        self.permissions.validate()?;
+        self.recovery.validate()?;
+        self.manifest.validate()?;
+        self.naming.template()?;
+        self.split.validate(&self.naming.prefix)?;
+        self.worktree.validate()?;
+        self.state.validate()?;
+        self.timeouts.validate()?;
+        for notify in &self.notify {
+            notify.validate()?;
+        }
+        if self.logs.encrypt && self.log_recipient().is_none() {
+            return Err(anyhow!("logs.encrypt requires crypto.age_public_key"));
+        }
+        if !(1..=22).contains(&self.compression.level) {
+            return Err(anyhow!("compression.level must be between 1 and 22"));
+        }
+        Ok(())
+    }
+
+    // The recipient log files are encrypted to, or None to write them in the
+    // clear.
+    pub fn log_recipient(&self) -> Option<&str> {
+        let crypto = self.crypto.as_ref().filter(|_| self.logs.encrypt)?;
+        crypto.age_public_key.as_deref()
+    }
+
+    pub fn validate_datasets(&self) -> Result<()> {
+        if !self.datasets.is_empty() && !self.split.parts.is_empty() {
+            return Err(anyhow!("[split] parts cannot be combined with [[dataset]]"));
+        }
+        for (index, dataset) in self.datasets.iter().enumerate() {
+            let earlier = &self.datasets[..index];
+            if !is_valid_target_name(&dataset.name) {
+                return Err(anyhow!("dataset name is invalid: {:?}", dataset.name));
+            }
+            if earlier.iter().any(|other| other.name == dataset.name) {
+                return Err(anyhow!("[[dataset]] {:?} is listed twice", dataset.name));
+            }
+            NameTemplate::new(dataset.prefix(), &self.naming.snapshot_name_template)
+                .with_context(|| format!("dataset {:?} has an invalid prefix", dataset.name))?;
+            if earlier.iter().any(|other| other.prefix() == dataset.prefix()) {
+                return Err(anyhow!("datasets share the prefix {:?}", dataset.prefix()));
+            }
+        }
+        Ok(())
+    }
+
+    // Points paths.dataset, paths.snapshots and naming.prefix at the named
+    // [[dataset]].
+    pub fn select_dataset(&mut self, name: &str) -> Result<()> {
+        let dataset = self
+            .datasets
+            .iter()
+            .find(|dataset| dataset.name == name)
+            .cloned()
+            .ok_or_else(|| {
+                let names: Vec<&str> = self.datasets.iter().map(|d| d.name.as_str()).collect();
+                anyhow!("no [[dataset]] named {name:?} (configured: {})", names.join(", "))
+            })?;
+        self.naming.prefix = dataset.prefix().to_string();
+        self.paths.dataset = dataset.path;
+        self.paths.snapshots = dataset.snapshots;
+        Ok(())
+    }
 }
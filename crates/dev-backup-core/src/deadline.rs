@@ -0,0 +1,105 @@
+use anyhow::{anyhow, Context, Result};
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime, Time, UtcOffset};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+    at: OffsetDateTime,
+}
+
+impl Deadline {
+    pub fn at(at: OffsetDateTime) -> Self {
+        Self { at }
+    }
+
+    // "HH:MM" is the next occurrence of that wall-clock time at the local
+    // offset, as a cron window is written; a full RFC 3339 timestamp is taken
+    // as-is. `local` is None when the offset could not be determined.
+    pub fn parse_time(value: &str, now: OffsetDateTime, local: Option<UtcOffset>) -> Result<Self> {
+        if let Ok(at) = OffsetDateTime::parse(value, &Rfc3339) {
+            return Ok(Self::at(at));
+        }
+        let (hour, minute) = value
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid deadline {value:?}: expected HH:MM or RFC 3339"))?;
+        let hour: u8 = hour.parse().with_context(|| format!("invalid deadline hour: {value}"))?;
+        let minute: u8 =
+            minute.parse().with_context(|| format!("invalid deadline minute: {value}"))?;
+        let time = Time::from_hms(hour, minute, 0)
+            .map_err(|_| anyhow!("invalid deadline {value:?}: out of range"))?;
+        let local = local.ok_or_else(|| {
+            anyhow!("cannot tell the local UTC offset for deadline {value:?}; use RFC 3339")
+        })?;
+        let mut at = now.to_offset(local).replace_time(time);
+        if at <= now {
+            at += Duration::days(1);
+        }
+        Ok(Self::at(at))
+    }
+
+    pub fn after(value: &str, now: OffsetDateTime) -> Result<Self> {
+        Ok(Self::at(now + parse_duration(value)?))
+    }
+
+    pub fn time(&self) -> OffsetDateTime {
+        self.at
+    }
+
+    pub fn reached(&self, now: OffsetDateTime) -> bool {
+        now >= self.at
+    }
+}
+
+// Accepts a sequence of <number><unit> pairs with units h, m and s, e.g. "3h",
+// "90m" or "1h30m".
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let mut total = Duration::ZERO;
+    let mut digits = String::new();
+    for ch in value.chars() {
+        if ch.is_ascii_digit() {
+            digits.push(ch);
+            continue;
+        }
+        let amount: i64 = digits
+            .parse()
+            .map_err(|_| anyhow!("invalid duration {value:?}: expected e.g. 3h or 90m"))?;
+        total += match ch {
+            'h' => Duration::hours(amount),
+            'm' => Duration::minutes(amount),
+            's' => Duration::seconds(amount),
+            _ => return Err(anyhow!("invalid duration {value:?}: unknown unit {ch:?}")),
+        };
+        digits.clear();
+    }
+    if !digits.is_empty() || value.is_empty() {
+        return Err(anyhow!("invalid duration {value:?}: expected e.g. 3h or 90m"));
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(value: &str) -> OffsetDateTime {
+        OffsetDateTime::parse(value, &Rfc3339).unwrap()
+    }
+
+    #[test]
+    fn a_wall_clock_deadline_is_read_at_the_local_offset() {
+        let now = at("2024-01-01T23:30:00Z");
+        let plus_two = UtcOffset::from_hms(2, 0, 0).ok();
+        // 01:30 locally: 02:00 is half an hour away, 01:00 is tomorrow.
+        let deadline = Deadline::parse_time("02:00", now, plus_two).unwrap();
+        assert_eq!(deadline.time(), at("2024-01-02T00:00:00Z"));
+        let deadline = Deadline::parse_time("01:00", now, plus_two).unwrap();
+        assert_eq!(deadline.time(), at("2024-01-02T23:00:00Z"));
+    }
+
+    #[test]
+    fn a_wall_clock_deadline_needs_the_local_offset() {
+        let now = at("2024-01-01T23:30:00Z");
+        assert!(Deadline::parse_time("02:00", now, None).is_err());
+        assert!(Deadline::parse_time("2024-01-02T00:00:00Z", now, None).is_ok());
+    }
+}
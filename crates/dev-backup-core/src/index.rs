@@ -0,0 +1,248 @@
+use crate::manifest::ManifestRecord;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+// A label may appear on several rows (re-registration, a rebuilt artifact).
+// The effective row for a label is the one with the latest ts; ties go to the
+// row that comes later in the manifest. All other rows are superseded and
+// only visible through `records()` and `history()`.
+#[derive(Debug, Clone, Default)]
+pub struct ManifestIndex {
+    records: Vec<ManifestRecord>,
+    by_label: HashMap<String, usize>,
+    by_type: HashMap<String, Vec<usize>>,
+    children: HashMap<String, Vec<String>>,
+}
+
+impl ManifestIndex {
+    pub fn new(records: Vec<ManifestRecord>) -> Self {
+        let mut by_label: HashMap<String, usize> = HashMap::new();
+        for (index, record) in records.iter().enumerate() {
+            match by_label.get(&record.label) {
+                Some(&current) if records[current].ts > record.ts => {}
+                _ => {
+                    by_label.insert(record.label.clone(), index);
+                }
+            }
+        }
+
+        let mut effective: Vec<usize> = by_label.values().copied().collect();
+        effective.sort_unstable();
+        let mut by_type: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut children: HashMap<String, Vec<String>> = HashMap::new();
+        for index in effective {
+            let record = &records[index];
+            by_type
+                .entry(record.record_type.clone())
+                .or_default()
+                .push(index);
+            if !record.parent.is_empty() {
+                children
+                    .entry(record.parent.clone())
+                    .or_default()
+                    .push(record.label.clone());
+            }
+        }
+
+        Self {
+            records,
+            by_label,
+            by_type,
+            children,
+        }
+    }
+
+    pub fn records(&self) -> &[ManifestRecord] {
+        &self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn get(&self, label: &str) -> Option<&ManifestRecord> {
+        self.by_label.get(label).map(|&index| &self.records[index])
+    }
+
+    pub fn require(&self, label: &str) -> Result<&ManifestRecord> {
+        self.get(label)
+            .ok_or_else(|| anyhow!("label not found in manifest: {label}"))
+    }
+
+    pub fn history(&self, label: &str) -> Vec<&ManifestRecord> {
+        self.records.iter().filter(|record| record.label == label).collect()
+    }
+
+    // The machines with a row for `label`, in manifest order. More than one
+    // means the index mixes workstations and the label is ambiguous.
+    pub fn machines(&self, label: &str) -> Vec<&str> {
+        let mut machines: Vec<&str> = Vec::new();
+        for record in self.history(label) {
+            if !machines.contains(&record.machine.as_str()) {
+                machines.push(&record.machine);
+            }
+        }
+        machines
+    }
+
+    pub fn of_type<'a>(&'a self, record_type: &str) -> impl Iterator<Item = &'a ManifestRecord> {
+        self.by_type
+            .get(record_type)
+            .into_iter()
+            .flatten()
+            .map(|&index| &self.records[index])
+    }
+
+    pub fn parent(&self, label: &str) -> Option<&ManifestRecord> {
+        let record = self.get(label)?;
+        if record.parent.is_empty() {
+            return None;
+        }
+        self.get(&record.parent)
+    }
+
+    pub fn children(&self, label: &str) -> &[String] {
+        self.children.get(label).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    // Anchor-first chain ending at `label`.
+    pub fn chain(&self, label: &str) -> Result<Vec<&ManifestRecord>> {
+        self.chain_until(label, |_| false)
+    }
+
+    // Like `chain`, but stops walking back once `have(parent)` says the parent
+    // is already available, e.g. because it has been hydrated.
+    pub fn chain_until(
+        &self,
+        label: &str,
+        have: impl Fn(&str) -> bool,
+    ) -> Result<Vec<&ManifestRecord>> {
+        let mut chain = Vec::new();
+        let mut current = label;
+        loop {
+            let record = self.require(current)?;
+            if chain.len() >= self.by_label.len() {
+                return Err(anyhow!("cycle in manifest chain at {current}"));
+            }
+            chain.push(record);
+            if record.record_type == "anchor" {
+                break;
+            }
+            if record.parent.is_empty() {
+                return Err(anyhow!("incremental record missing parent for {current}"));
+            }
+            if have(&record.parent) {
+                break;
+            }
+            current = &record.parent;
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::RecordStatus;
+    use time::macros::datetime;
+    use time::OffsetDateTime;
+
+    fn record(ts: OffsetDateTime, label: &str, parent: &str, sha256: &str) -> ManifestRecord {
+        ManifestRecord {
+            ts,
+            label: label.to_string(),
+            record_type: if parent.is_empty() { "anchor" } else { "incremental" }.to_string(),
+            parent: parent.to_string(),
+            bytes: 1,
+            sha256: sha256.to_string(),
+            local_path: String::new(),
+            object_key: String::new(),
+            mirror_keys: String::new(),
+            status: RecordStatus::Registered,
+            machine: String::new(),
+        }
+    }
+
+    fn sample() -> ManifestIndex {
+        ManifestIndex::new(vec![
+            record(datetime!(2024-01-01 0:00 UTC), "2024-01", "", "a"),
+            record(datetime!(2024-02-01 0:00 UTC), "2024-02", "2024-01", "b1"),
+            record(datetime!(2024-03-01 0:00 UTC), "2024-03", "2024-02", "c"),
+            record(datetime!(2024-02-02 0:00 UTC), "2024-02", "2024-01", "b2"),
+        ])
+    }
+
+    #[test]
+    fn latest_duplicate_row_wins() {
+        let index = sample();
+        assert_eq!(index.require("2024-02").unwrap().sha256, "b2");
+        assert_eq!(index.history("2024-02").len(), 2);
+        assert_eq!(index.records().len(), 4);
+    }
+
+    #[test]
+    fn later_row_wins_on_equal_timestamps() {
+        let ts = datetime!(2024-01-01 0:00 UTC);
+        let index = ManifestIndex::new(vec![
+            record(ts, "2024-01", "", "first"),
+            record(ts, "2024-01", "", "second"),
+        ]);
+        assert_eq!(index.require("2024-01").unwrap().sha256, "second");
+    }
+
+    #[test]
+    fn earlier_timestamp_does_not_supersede() {
+        let index = ManifestIndex::new(vec![
+            record(datetime!(2024-01-02 0:00 UTC), "2024-01", "", "newer"),
+            record(datetime!(2024-01-01 0:00 UTC), "2024-01", "", "older"),
+        ]);
+        assert_eq!(index.require("2024-01").unwrap().sha256, "newer");
+    }
+
+    #[test]
+    fn type_and_adjacency_use_effective_rows() {
+        let index = sample();
+        let incrementals: Vec<&str> =
+            index.of_type("incremental").map(|r| r.label.as_str()).collect();
+        assert_eq!(incrementals, vec!["2024-03", "2024-02"]);
+        assert_eq!(index.children("2024-01"), ["2024-02".to_string()]);
+        assert_eq!(index.children("2024-02"), ["2024-03".to_string()]);
+        assert!(index.children("2024-03").is_empty());
+        assert_eq!(index.parent("2024-03").unwrap().sha256, "b2");
+        assert!(index.parent("2024-01").is_none());
+    }
+
+    #[test]
+    fn chain_walks_back_to_anchor() {
+        let index = sample();
+        let chain: Vec<&str> = index
+            .chain("2024-03")
+            .unwrap()
+            .iter()
+            .map(|r| r.sha256.as_str())
+            .collect();
+        assert_eq!(chain, vec!["a", "b2", "c"]);
+
+        let partial = index.chain_until("2024-03", |label| label == "2024-02").unwrap();
+        assert_eq!(partial.len(), 1);
+    }
+
+    #[test]
+    fn chain_reports_missing_parent_and_cycles() {
+        let index = ManifestIndex::new(vec![record(
+            datetime!(2024-02-01 0:00 UTC),
+            "2024-02",
+            "2024-01",
+            "b",
+        )]);
+        assert!(index.chain("2024-02").is_err());
+
+        let index = ManifestIndex::new(vec![
+            record(datetime!(2024-01-01 0:00 UTC), "2024-01", "2024-02", "a"),
+            record(datetime!(2024-02-01 0:00 UTC), "2024-02", "2024-01", "b"),
+        ]);
+        let err = index.chain("2024-02").unwrap_err();
+        assert!(err.to_string().contains("cycle"));
+    }
+}
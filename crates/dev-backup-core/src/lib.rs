@@ -1,3 +1,13 @@
+pub mod alias;
+pub mod clock;
 pub mod config;
+pub mod deadline;
+pub mod index;
 pub mod manifest;
+pub mod naming;
+pub mod notify;
 pub mod policy;
+pub mod recovery;
+pub mod sets;
+pub mod skew;
+pub mod sqlite;
@@ -1,11 +1,19 @@
-use anyhow::{Context, Result};
+use crate::config::{is_valid_target_name, ManifestBackend};
+use crate::index::ManifestIndex;
+use crate::naming::{is_valid_machine_id, LabelScheme, NameTemplate};
+use crate::sqlite::SqliteManifest;
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs::{self, OpenOptions};
-use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use time::OffsetDateTime;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ManifestRecord {
-    pub ts: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub ts: OffsetDateTime,
     pub label: String,
     #[serde(rename = "type")]
     pub record_type: String,
@@ -14,20 +22,518 @@ pub struct ManifestRecord {
     pub sha256: String,
     pub local_path: String,
     pub object_key: String,
+    // `name=key` per [[mirrors]] target the artifact has been pushed to,
+    // separated by `;`. Rows written before mirrors existed leave it empty.
+    #[serde(default)]
+    pub mirror_keys: String,
+    // Rows written before the column existed get `inferred_status` on read.
+    #[serde(default)]
+    pub status: RecordStatus,
+    // naming.machine_id of the workstation the artifact came from, so several
+    // workstations can share one LS and bucket. Empty for a single machine.
+    #[serde(default)]
+    pub machine: String,
 }
 
+// Where an artifact is in its lifecycle. Statuses only move forward, except
+// that uploading a verified artifact again takes it back to `pushed`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordStatus {
+    Pending,
+    Built,
+    #[default]
+    Registered,
+    Pushed,
+    Verified,
+}
+
+impl RecordStatus {
+    pub const ALL: [RecordStatus; 5] = [
+        RecordStatus::Pending,
+        RecordStatus::Built,
+        RecordStatus::Registered,
+        RecordStatus::Pushed,
+        RecordStatus::Verified,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordStatus::Pending => "pending",
+            RecordStatus::Built => "built",
+            RecordStatus::Registered => "registered",
+            RecordStatus::Pushed => "pushed",
+            RecordStatus::Verified => "verified",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|status| status.as_str() == value)
+    }
+
+    pub fn can_become(self, next: RecordStatus) -> bool {
+        next >= self || (self == RecordStatus::Verified && next == RecordStatus::Pushed)
+    }
+}
+
+impl ManifestRecord {
+    // Every field ends up in a path or an argv somewhere (btrfs, age, ssh), so
+    // rows are held to the exact shapes dev-backup itself writes.
+    pub fn validate(&self) -> Result<()> {
+        if !is_valid_label(&self.label) {
+            return Err(anyhow!("invalid label {:?}", self.label));
+        }
+        if self.record_type != "anchor" && self.record_type != "incremental" {
+            return Err(anyhow!("invalid type {:?}", self.record_type));
+        }
+        if !self.parent.is_empty() && !is_valid_label(&self.parent) {
+            return Err(anyhow!("invalid parent {:?}", self.parent));
+        }
+        if !self.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!("invalid sha256 {:?}", self.sha256));
+        }
+        if !self.local_path.is_empty() && !Path::new(&self.local_path).is_absolute() {
+            return Err(anyhow!("local_path must be absolute: {:?}", self.local_path));
+        }
+        if !is_relative_key(&self.object_key) {
+            return Err(anyhow!("object_key must be a relative path: {:?}", self.object_key));
+        }
+        if self.status >= RecordStatus::Pushed && self.object_key.is_empty() {
+            return Err(anyhow!("{} record without an object_key", self.status.as_str()));
+        }
+        for entry in self.mirror_keys.split(';').filter(|entry| !entry.is_empty()) {
+            let valid = entry.split_once('=').is_some_and(|(name, key)| {
+                is_valid_target_name(name) && !key.is_empty() && is_relative_key(key)
+            });
+            if !valid {
+                return Err(anyhow!("invalid mirror_keys entry {entry:?}"));
+            }
+        }
+        if !self.machine.is_empty() && !is_valid_machine_id(&self.machine) {
+            return Err(anyhow!("invalid machine {:?}", self.machine));
+        }
+        Ok(())
+    }
+
+    pub fn advance(&mut self, next: RecordStatus) -> Result<()> {
+        if !self.status.can_become(next) {
+            return Err(anyhow!(
+                "{}: cannot go from {} to {}",
+                self.label,
+                self.status.as_str(),
+                next.as_str()
+            ));
+        }
+        self.status = next;
+        Ok(())
+    }
+
+    // What the fields say about a row that predates the status column.
+    pub fn inferred_status(&self) -> RecordStatus {
+        if self.object_key.is_empty() {
+            RecordStatus::Registered
+        } else {
+            RecordStatus::Pushed
+        }
+    }
+
+    pub fn mirror_key(&self, target: &str) -> Option<&str> {
+        self.mirror_keys
+            .split(';')
+            .filter_map(|entry| entry.split_once('='))
+            .find(|(name, _)| *name == target)
+            .map(|(_, key)| key)
+    }
+
+    pub fn set_mirror_key(&mut self, target: &str, key: &str) {
+        let mut entries: Vec<String> = self
+            .mirror_keys
+            .split(';')
+            .filter(|entry| {
+                !entry.is_empty() && entry.split_once('=').map(|(name, _)| name) != Some(target)
+            })
+            .map(str::to_string)
+            .collect();
+        entries.push(format!("{target}={key}"));
+        self.mirror_keys = entries.join(";");
+    }
+}
+
+fn is_relative_key(key: &str) -> bool {
+    Path::new(key)
+        .components()
+        .all(|part| matches!(part, Component::Normal(_)))
+}
+
+// True for a label under any `LabelScheme`; new labels are checked against
+// the configured one instead.
+pub fn is_valid_label(label: &str) -> bool {
+    LabelScheme::ALL.iter().any(|scheme| scheme.accepts(label))
+}
+
+// Version of the TSV layout, written in a comment line above the header.
+// 1 ends at object_key, 2 adds mirror_keys, 3 adds status and 4 adds
+// machine. Files from before the line existed are recognised by their columns.
+pub const TSV_SCHEMA_VERSION: u32 = 4;
+const TSV_SCHEMA_PREFIX: &str = "# dev-backup manifest schema ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TsvSchema {
+    pub version: u32,
+    // False when the version was inferred from the columns.
+    pub declared: bool,
+}
+
+impl TsvSchema {
+    pub fn is_current(&self) -> bool {
+        self.declared && self.version == TSV_SCHEMA_VERSION
+    }
+}
+
+impl fmt::Display for TsvSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "schema {}", self.version)?;
+        if !self.declared {
+            write!(f, " (unversioned)")?;
+        }
+        Ok(())
+    }
+}
+
+const TSV_HEADER: [&str; 11] = [
+    "ts",
+    "label",
+    "type",
+    "parent",
+    "bytes",
+    "sha256",
+    "local_path",
+    "object_key",
+    "mirror_keys",
+    "status",
+    "machine",
+];
+
+// How reads treat TSV rows that do not parse or validate. Lenient reads skip
+// them with a warning so one bad row does not stop every command; strict reads
+// (verify) fail on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+    Strict,
+    Lenient,
+}
+
+// A TSV row that could not be read; `line` is its line in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedRow {
+    pub line: u64,
+    pub reason: String,
+    pub raw: String,
+}
+
+impl fmt::Display for MalformedRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid manifest row {}: {}", self.line, self.reason)
+    }
+}
+
+// The TSV manifest at `path`, optionally paired with an SQLite copy. Reads
+// come from the primary; writes go to the primary and then the replica, and a
+// failed replica write only warns so the replica cannot block a run.
+//
+// With a machine set, reads only return that machine's rows and rewrites keep
+// every other machine's rows as they were.
 pub struct ManifestStore {
     path: PathBuf,
+    sqlite: Option<SqliteManifest>,
+    primary: ManifestBackend,
+    machine: Option<String>,
 }
 
 impl ManifestStore {
     pub fn new(path: impl AsRef<Path>) -> Self {
         Self {
             path: path.as_ref().to_path_buf(),
+            sqlite: None,
+            primary: ManifestBackend::Tsv,
+            machine: None,
         }
     }
 
+    pub fn with_sqlite(mut self, path: impl AsRef<Path>, primary: ManifestBackend) -> Self {
+        self.sqlite = Some(SqliteManifest::new(path));
+        self.primary = primary;
+        self
+    }
+
+    pub fn with_machine(mut self, machine: Option<&str>) -> Self {
+        self.machine = machine.map(str::to_string);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn machine(&self) -> Option<&str> {
+        self.machine.as_deref()
+    }
+
+    pub fn primary(&self) -> ManifestBackend {
+        self.primary
+    }
+
+    pub fn replica(&self) -> Option<ManifestBackend> {
+        self.sqlite.as_ref().map(|_| match self.primary {
+            ManifestBackend::Tsv => ManifestBackend::Sqlite,
+            ManifestBackend::Sqlite => ManifestBackend::Tsv,
+        })
+    }
+
     pub fn ensure_initialized(&self) -> Result<()> {
+        self.ensure_tsv()?;
+        if let Some(sqlite) = &self.sqlite {
+            sqlite.ensure_initialized()?;
+        }
+        Ok(())
+    }
+
+    pub fn read_records(&self) -> Result<Vec<ManifestRecord>> {
+        self.read_records_with(ReadMode::Lenient)
+    }
+
+    pub fn read_records_with(&self, mode: ReadMode) -> Result<Vec<ManifestRecord>> {
+        Ok(self.read_all_records_with(mode)?.into_iter().filter(|r| self.selects(r)).collect())
+    }
+
+    // The rows of every machine, whatever this store is filtered to.
+    pub fn read_all_records(&self) -> Result<Vec<ManifestRecord>> {
+        self.read_all_records_with(ReadMode::Lenient)
+    }
+
+    fn read_all_records_with(&self, mode: ReadMode) -> Result<Vec<ManifestRecord>> {
+        match self.primary {
+            ManifestBackend::Tsv => self.read_tsv(mode),
+            ManifestBackend::Sqlite => self.read_from(ManifestBackend::Sqlite),
+        }
+    }
+
+    fn selects(&self, record: &ManifestRecord) -> bool {
+        self.machine.as_deref().is_none_or(|machine| record.machine == machine)
+    }
+
+    pub fn read_from(&self, backend: ManifestBackend) -> Result<Vec<ManifestRecord>> {
+        match (backend, &self.sqlite) {
+            (ManifestBackend::Tsv, _) => self.read_tsv(ReadMode::Lenient),
+            (ManifestBackend::Sqlite, Some(sqlite)) => sqlite.read_records(),
+            (ManifestBackend::Sqlite, None) => Err(anyhow!("sqlite manifest is not configured")),
+        }
+    }
+
+    // The rows of one label, from the SQLite label index when that store is
+    // the primary.
+    pub fn records_for_label(&self, label: &str) -> Result<Vec<ManifestRecord>> {
+        match (self.primary, &self.sqlite) {
+            (ManifestBackend::Sqlite, Some(sqlite)) => {
+                let records = sqlite.records_for_label(label)?;
+                Ok(records.into_iter().filter(|r| self.selects(r)).collect())
+            }
+            _ => Ok(self.read_records()?.into_iter().filter(|r| r.label == label).collect()),
+        }
+    }
+
+    pub fn read_index(&self) -> Result<ManifestIndex> {
+        Ok(ManifestIndex::new(self.read_records()?))
+    }
+
+    pub fn read_index_with(&self, mode: ReadMode) -> Result<ManifestIndex> {
+        Ok(ManifestIndex::new(self.read_records_with(mode)?))
+    }
+
+    pub fn read_sorted_records(&self) -> Result<Vec<ManifestRecord>> {
+        Ok(sort_records_by_ts(self.read_records()?))
+    }
+
+    pub fn append_record(&self, record: &ManifestRecord) -> Result<()> {
+        record.validate().context("refusing to append manifest record")?;
+        self.append_to(self.primary, record)?;
+        if let Some(replica) = self.replica() {
+            warn_replica(replica, self.append_to(replica, record));
+        }
+        Ok(())
+    }
+
+    // All or nothing: the TSV is rewritten through a temporary file and
+    // renamed into place, SQLite inserts the rows in one transaction.
+    pub fn append_records(&self, records: &[ManifestRecord]) -> Result<()> {
+        for record in records {
+            record.validate().context("refusing to append manifest record")?;
+        }
+        self.append_all_to(self.primary, records)?;
+        if let Some(replica) = self.replica() {
+            warn_replica(replica, self.append_all_to(replica, records));
+        }
+        Ok(())
+    }
+
+    // Refuses while the TSV primary has malformed rows: the records were read
+    // without them, so rewriting would drop them silently.
+    pub fn write_records(&self, records: &[ManifestRecord]) -> Result<()> {
+        for record in records {
+            record.validate().context("refusing to write manifest record")?;
+        }
+        if self.primary == ManifestBackend::Tsv {
+            self.read_tsv(ReadMode::Strict)
+                .context("refusing to rewrite a manifest with malformed rows")?;
+        }
+        let merged;
+        let records = match self.machine {
+            None => records,
+            // File order matters (later rows win, skew is spotted by it), so
+            // other machines' rows keep their place and this machine's fill
+            // the slots its old rows held, in order; any extra go at the end.
+            Some(_) => {
+                let mut mine = records.iter().cloned();
+                let mut rows = Vec::new();
+                for row in self.read_all_records()? {
+                    if !self.selects(&row) {
+                        rows.push(row);
+                    } else if let Some(record) = mine.next() {
+                        rows.push(record);
+                    }
+                }
+                rows.extend(mine);
+                merged = rows;
+                &merged
+            }
+        };
+        self.write_to(self.primary, records)?;
+        if let Some(replica) = self.replica() {
+            warn_replica(replica, self.write_to(replica, records));
+        }
+        Ok(())
+    }
+
+    // Overwrites the replica with the primary's rows.
+    pub fn repair_replica(&self) -> Result<usize> {
+        let replica = self
+            .replica()
+            .ok_or_else(|| anyhow!("no manifest replica is configured"))?;
+        let records = self.read_all_records()?;
+        self.write_to(replica, &records)?;
+        Ok(records.len())
+    }
+
+    pub fn malformed_rows(&self) -> Result<Vec<MalformedRow>> {
+        Ok(self.scan_tsv()?.1)
+    }
+
+    pub fn quarantine_path(&self) -> PathBuf {
+        self.path.with_extension("tsv.quarantine")
+    }
+
+    // Moves malformed rows out of the TSV into the quarantine file, each
+    // preceded by a comment with its line number and reason, and rewrites the
+    // TSV with the rows that remain.
+    pub fn quarantine_malformed(&self) -> Result<Vec<MalformedRow>> {
+        let (records, malformed) = self.scan_tsv()?;
+        if malformed.is_empty() {
+            return Ok(malformed);
+        }
+        let quarantine = self.quarantine_path();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&quarantine)
+            .with_context(|| format!("failed to open {}", quarantine.display()))?;
+        for row in &malformed {
+            writeln!(file, "# line {}: {}\n{}", row.line, row.reason, row.raw)
+                .with_context(|| format!("failed to write {}", quarantine.display()))?;
+        }
+        file.sync_all()
+            .with_context(|| format!("failed to sync {}", quarantine.display()))?;
+        self.write_tsv(&records)?;
+        Ok(malformed)
+    }
+
+    // The layout of the TSV, or None when it does not exist yet.
+    pub fn tsv_schema(&self) -> Result<Option<TsvSchema>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read manifest: {}", self.path.display()))
+            }
+        };
+        let mut first = String::new();
+        BufReader::new(file)
+            .read_line(&mut first)
+            .with_context(|| format!("failed to read manifest: {}", self.path.display()))?;
+        if let Some(version) = first.strip_prefix(TSV_SCHEMA_PREFIX) {
+            let version = version.trim().parse().map_err(|_| {
+                anyhow!("invalid schema line in {}: {}", self.path.display(), first.trim_end())
+            })?;
+            return Ok(Some(TsvSchema { version, declared: true }));
+        }
+        let columns: Vec<&str> = first.trim_end_matches(['\r', '\n']).split('\t').collect();
+        let version = if columns.contains(&"machine") {
+            4
+        } else if columns.contains(&"status") {
+            3
+        } else if columns.contains(&"mirror_keys") {
+            2
+        } else {
+            1
+        };
+        Ok(Some(TsvSchema { version, declared: false }))
+    }
+
+    // Rewrites `source` (this manifest, or an older file it replaces) at this
+    // manifest's path in the current layout. Returns the layout it was in, or
+    // None when there was nothing to upgrade.
+    pub fn upgrade_tsv(&self, source: &Path) -> Result<Option<TsvSchema>> {
+        let old = ManifestStore::new(source);
+        let Some(schema) = old.tsv_schema()? else {
+            return Ok(None);
+        };
+        if schema.is_current() && source == self.path {
+            return Ok(None);
+        }
+        let records = old.read_tsv(ReadMode::Strict)?;
+        self.write_tsv(&records)?;
+        Ok(Some(schema))
+    }
+
+    fn append_to(&self, backend: ManifestBackend, record: &ManifestRecord) -> Result<()> {
+        match (backend, &self.sqlite) {
+            (ManifestBackend::Tsv, _) => self.append_tsv(record),
+            (ManifestBackend::Sqlite, Some(sqlite)) => sqlite.append_record(record),
+            (ManifestBackend::Sqlite, None) => Err(anyhow!("sqlite manifest is not configured")),
+        }
+    }
+
+    fn append_all_to(&self, backend: ManifestBackend, records: &[ManifestRecord]) -> Result<()> {
+        match (backend, &self.sqlite) {
+            (ManifestBackend::Tsv, _) => {
+                let mut all = self.read_tsv(ReadMode::Strict)?;
+                all.extend_from_slice(records);
+                self.write_tsv(&all)
+            }
+            (ManifestBackend::Sqlite, Some(sqlite)) => sqlite.append_records(records),
+            (ManifestBackend::Sqlite, None) => Err(anyhow!("sqlite manifest is not configured")),
+        }
+    }
+
+    fn write_to(&self, backend: ManifestBackend, records: &[ManifestRecord]) -> Result<()> {
+        match (backend, &self.sqlite) {
+            (ManifestBackend::Tsv, _) => self.write_tsv(records),
+            (ManifestBackend::Sqlite, Some(sqlite)) => sqlite.write_records(records),
+            (ManifestBackend::Sqlite, None) => Err(anyhow!("sqlite manifest is not configured")),
+        }
+    }
+
+    fn ensure_tsv(&self) -> Result<()> {
         if self.path.exists() {
             return Ok(());
         }
@@ -35,79 +541,273 @@ impl ManifestStore {
             fs::create_dir_all(parent)
                 .with_context(|| format!("failed to create manifest directory: {}", parent.display()))?;
         }
-        let mut writer = csv::WriterBuilder::new()
-            .delimiter(b'\t')
-            .from_path(&self.path)
+        let mut file = fs::File::create(&self.path)
             .with_context(|| format!("failed to create manifest: {}", self.path.display()))?;
+        writeln!(file, "{TSV_SCHEMA_PREFIX}{TSV_SCHEMA_VERSION}")
+            .context("failed to write manifest schema line")?;
+        let mut writer = csv::WriterBuilder::new().delimiter(b'\t').from_writer(file);
         writer
-            .write_record([
-                "ts",
-                "label",
-                "type",
-                "parent",
-                "bytes",
-                "sha256",
-                "local_path",
-                "object_key",
-            ])
+            .write_record(TSV_HEADER)
             .context("failed to write manifest header")?;
         writer.flush().context("failed to flush manifest header")?;
         Ok(())
     }
 
-    pub fn read_records(&self) -> Result<Vec<ManifestRecord>> {
-        if !self.path.exists() {
-            return Ok(Vec::new());
+    fn read_tsv(&self, mode: ReadMode) -> Result<Vec<ManifestRecord>> {
+        let (records, malformed) = self.scan_tsv()?;
+        match (mode, malformed.first()) {
+            (_, None) => {}
+            (ReadMode::Strict, Some(first)) => {
+                let more = match malformed.len() - 1 {
+                    0 => String::new(),
+                    n => format!(" (and {n} more)"),
+                };
+                return Err(anyhow!(
+                    "{first}{more} in {}; run `dev-backup manifest fsck --fix` to quarantine it",
+                    self.path.display()
+                ));
+            }
+            (ReadMode::Lenient, Some(_)) => {
+                for row in &malformed {
+                    tracing::warn!(
+                        "skipping {row} in {}; run `dev-backup manifest fsck --fix`",
+                        self.path.display()
+                    );
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    // Every row that parses and validates, and every row that does not.
+    fn scan_tsv(&self) -> Result<(Vec<ManifestRecord>, Vec<MalformedRow>)> {
+        let Some(schema) = self.tsv_schema()? else {
+            return Ok((Vec::new(), Vec::new()));
+        };
+        if schema.version > TSV_SCHEMA_VERSION {
+            return Err(anyhow!(
+                "{} uses manifest schema {}, but this dev-backup only understands up to {}; \
+                 upgrade dev-backup",
+                self.path.display(),
+                schema.version,
+                TSV_SCHEMA_VERSION
+            ));
         }
         let mut reader = csv::ReaderBuilder::new()
             .delimiter(b'\t')
+            .flexible(true)
+            .comment(Some(b'#'))
             .from_path(&self.path)
             .with_context(|| format!("failed to read manifest: {}", self.path.display()))?;
+        let headers = reader
+            .byte_headers()
+            .context("failed to read manifest header")?
+            .clone();
+        let has_status = headers.iter().any(|name| name == b"status");
         let mut records = Vec::new();
-        for result in reader.deserialize() {
-            let record: ManifestRecord = result.context("failed to parse manifest row")?;
-            records.push(record);
+        let mut malformed = Vec::new();
+        for result in reader.byte_records() {
+            let row = result
+                .with_context(|| format!("failed to read manifest: {}", self.path.display()))?;
+            match parse_row(&row, &headers, has_status) {
+                Ok(record) => records.push(record),
+                Err(reason) => malformed.push(MalformedRow {
+                    line: row.position().map_or(0, |position| position.line()),
+                    reason,
+                    raw: row
+                        .iter()
+                        .map(String::from_utf8_lossy)
+                        .collect::<Vec<_>>()
+                        .join("\t"),
+                }),
+            }
         }
-        Ok(records)
+        Ok((records, malformed))
     }
 
-    pub fn append_record(&self, record: &ManifestRecord) -> Result<()> {
+    fn append_tsv(&self, record: &ManifestRecord) -> Result<()> {
+        // Manifests from before mirror_keys or status have fewer columns;
+        // appending a wider row would make the file unreadable, so rewrite it
+        // instead.
+        if self.path.exists() && !self.tsv_header_is_current()? {
+            let mut records = self.read_tsv(ReadMode::Strict)?;
+            records.push(record.clone());
+            return self.write_tsv(&records);
+        }
         let file = OpenOptions::new()
             .append(true)
             .create(true)
             .open(&self.path)
             .with_context(|| format!("failed to open manifest: {}", self.path.display()))?;
-        let mut writer = csv::WriterBuilder::new().delimiter(b'\t').from_writer(file);
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(b'\t')
+            .has_headers(false)
+            .from_writer(file);
         writer.serialize(record).context("failed to append manifest record")?;
         writer.flush().context("failed to flush manifest")?;
         Ok(())
     }
 
-    pub fn write_records(&self, records: &[ManifestRecord]) -> Result<()> {
+    fn tsv_header_is_current(&self) -> Result<bool> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .comment(Some(b'#'))
+            .from_path(&self.path)
+            .with_context(|| format!("failed to read manifest: {}", self.path.display()))?;
+        let headers = reader.headers().context("failed to read manifest header")?;
+        Ok(headers.iter().eq(TSV_HEADER))
+    }
+
+    fn write_tsv(&self, records: &[ManifestRecord]) -> Result<()> {
         if let Some(parent) = self.path.parent() {
             fs::create_dir_all(parent)
                 .with_context(|| format!("failed to create manifest directory: {}", parent.display()))?;
         }
-        let mut writer = csv::WriterBuilder::new()
-            .delimiter(b'\t')
-            .from_path(&self.path)
-            .with_context(|| format!("failed to create manifest: {}", self.path.display()))?;
-        writer
-            .write_record([
-                "ts",
-                "label",
-                "type",
-                "parent",
-                "bytes",
-                "sha256",
-                "local_path",
-                "object_key",
-            ])
-            .context("failed to write manifest header")?;
-        for record in records {
-            writer.serialize(record).context("failed to write manifest record")?;
+        // Written beside the manifest and renamed over it, so a crash leaves
+        // either the old rows or the new ones.
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = self.path.with_file_name(tmp_name);
+        let file = fs::File::create(&tmp_path)
+            .with_context(|| format!("failed to create manifest: {}", tmp_path.display()))?;
+        let file = write_tsv_rows(file, records)?;
+        file.sync_all().context("failed to sync manifest")?;
+        if let Ok(existing) = fs::metadata(&self.path) {
+            fs::set_permissions(&tmp_path, existing.permissions())
+                .with_context(|| format!("failed to set mode on {}", tmp_path.display()))?;
         }
-        writer.flush().context("failed to flush manifest")?;
-        Ok(())
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("failed to replace manifest: {}", self.path.display()))
+    }
+}
+
+// The schema line, header and `records`, as a TSV manifest holds them.
+fn write_tsv_rows<W: Write>(mut out: W, records: &[ManifestRecord]) -> Result<W> {
+    writeln!(out, "{TSV_SCHEMA_PREFIX}{TSV_SCHEMA_VERSION}")
+        .context("failed to write manifest schema line")?;
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .from_writer(out);
+    writer
+        .write_record(TSV_HEADER)
+        .context("failed to write manifest header")?;
+    for record in records {
+        writer.serialize(record).context("failed to write manifest record")?;
+    }
+    writer
+        .into_inner()
+        .map_err(|err| anyhow!("failed to flush manifest: {}", err.error()))
+}
+
+// A TSV manifest kept in memory rather than at a path, such as one that is
+// stored encrypted; `records_from_tsv` reads it back and refuses malformed
+// rows.
+pub fn records_to_tsv(records: &[ManifestRecord]) -> Result<String> {
+    let bytes = write_tsv_rows(Vec::new(), records)?;
+    String::from_utf8(bytes).context("manifest rows are not UTF-8")
+}
+
+pub fn records_from_tsv(contents: &str) -> Result<Vec<ManifestRecord>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(contents.as_bytes());
+    let headers = reader
+        .byte_headers()
+        .context("failed to read manifest header")?
+        .clone();
+    let has_status = headers.iter().any(|name| name == b"status");
+    let mut records = Vec::new();
+    for result in reader.byte_records() {
+        let row = result.context("failed to read manifest row")?;
+        let line = row.position().map_or(0, |position| position.line());
+        let record = parse_row(&row, &headers, has_status)
+            .map_err(|reason| anyhow!("line {line}: {reason}"))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn parse_row(
+    row: &csv::ByteRecord,
+    headers: &csv::ByteRecord,
+    has_status: bool,
+) -> std::result::Result<ManifestRecord, String> {
+    if row.len() != headers.len() {
+        return Err(format!("expected {} fields, found {}", headers.len(), row.len()));
+    }
+    let mut record: ManifestRecord = row.deserialize(Some(headers)).map_err(|err| {
+        match err.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => match err.field() {
+                Some(field) => {
+                    let name = String::from_utf8_lossy(&headers[field as usize]);
+                    format!("{name}: {}", err.kind())
+                }
+                None => err.kind().to_string(),
+            },
+            _ => err.to_string(),
+        }
+    })?;
+    if !has_status {
+        record.status = record.inferred_status();
+    }
+    record.validate().map_err(|err| format!("{err:#}"))?;
+    Ok(record)
+}
+
+fn warn_replica(replica: ManifestBackend, result: Result<()>) {
+    if let Err(err) = result {
+        tracing::warn!(
+            "manifest replica ({}) is now out of date: {err:#}; \
+             run `dev-backup manifest check --repair`",
+            replica.as_str()
+        );
     }
 }
+
+// The rows of `remote` (another copy of this manifest, e.g. the one in the
+// bucket) that `local` does not have, ready to append: their local_path names
+// a file on some other host, so it is cleared. A label whose artifact differs
+// between the two is a conflict neither side can settle. Labels are compared
+// per machine, since every machine has its own chain. `stream` is the dataset
+// prefix or split part the manifest belongs to, for naming a conflict.
+pub fn remote_only_records(
+    local: &[ManifestRecord],
+    remote: &[ManifestRecord],
+    naming: &NameTemplate,
+    stream: &str,
+) -> Result<Vec<ManifestRecord>> {
+    let mut missing = Vec::new();
+    for record in remote {
+        let mut same_label = local
+            .iter()
+            .filter(|row| row.label == record.label && row.machine == record.machine)
+            .peekable();
+        if same_label.peek().is_none() {
+            missing.push(ManifestRecord {
+                local_path: String::new(),
+                ..record.clone()
+            });
+        } else if !same_label.any(|row| row.sha256 == record.sha256) {
+            let name = naming.name(stream, &record.label);
+            let name = match record.machine.as_str() {
+                "" => name,
+                machine => format!("{machine}~{name}"),
+            };
+            return Err(anyhow!(
+                "{name} has a different artifact in the local manifest than in the remote one \
+                 (sha256 {})",
+                record.sha256
+            ));
+        }
+    }
+    Ok(missing)
+}
+
+// Stable, so rows sharing a timestamp keep their append order.
+pub fn sort_records_by_ts(mut records: Vec<ManifestRecord>) -> Vec<ManifestRecord> {
+    records.sort_by_key(|record| record.ts);
+    records
+}
@@ -0,0 +1,298 @@
+use crate::manifest::is_valid_label;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use time::{Date, Month, OffsetDateTime};
+
+pub const DEFAULT_PREFIX: &str = "dev";
+pub const DEFAULT_TEMPLATE: &str = "{prefix}@{label}";
+
+// How snapshot subvolumes are named: the template with `{prefix}` and
+// `{label}` filled in. The whole dataset uses `prefix`; split parts use their
+// part name. Artifact files are the snapshot name plus a type suffix, after
+// `{machine}~` when naming.machine_id is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    prefix: String,
+    before: String,
+    between: String,
+    after: String,
+    label_first: bool,
+    machine: Option<String>,
+}
+
+impl NameTemplate {
+    pub fn new(prefix: &str, template: &str) -> Result<Self> {
+        if !is_valid_stream(prefix) {
+            return Err(anyhow!("naming.prefix is invalid: {prefix:?}"));
+        }
+        let invalid = |why: &str| anyhow!("naming.snapshot_name_template {template:?} {why}");
+        let literal_ok = |text: &str| {
+            text.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '@' | '_' | '-' | '+' | '.'))
+        };
+        let (prefix_at, label_at) = match (template.find("{prefix}"), template.find("{label}")) {
+            (Some(prefix_at), Some(label_at)) => (prefix_at, label_at),
+            _ => return Err(invalid("must contain {prefix} and {label}")),
+        };
+        let label_first = label_at < prefix_at;
+        let (first, second) = if label_first {
+            (("{label}", label_at), ("{prefix}", prefix_at))
+        } else {
+            (("{prefix}", prefix_at), ("{label}", label_at))
+        };
+        let before = &template[..first.1];
+        let between = &template[first.1 + first.0.len()..second.1];
+        let after = &template[second.1 + second.0.len()..];
+        for text in [before, between, after] {
+            if !literal_ok(text) {
+                return Err(invalid("may only add letters, digits and @ _ - + . around the fields"));
+            }
+        }
+        if between.is_empty() {
+            return Err(invalid("needs a separator between {prefix} and {label}"));
+        }
+        if before.starts_with('.') {
+            return Err(invalid("must not produce hidden names"));
+        }
+        Ok(Self {
+            prefix: prefix.to_string(),
+            before: before.to_string(),
+            between: between.to_string(),
+            after: after.to_string(),
+            label_first,
+            machine: None,
+        })
+    }
+
+    pub fn with_machine(mut self, machine: Option<&str>) -> Result<Self> {
+        if let Some(machine) = machine {
+            if !is_valid_machine_id(machine) {
+                return Err(anyhow!("naming.machine_id is invalid: {machine:?}"));
+            }
+        }
+        self.machine = machine.map(str::to_string);
+        Ok(self)
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn machine(&self) -> Option<&str> {
+        self.machine.as_deref()
+    }
+
+    pub fn name(&self, stream: &str, label: &str) -> String {
+        let (first, second) = if self.label_first { (label, stream) } else { (stream, label) };
+        format!("{}{first}{}{second}{}", self.before, self.between, self.after)
+    }
+
+    // Splits a snapshot name back into (stream, label). Labels may contain the
+    // separator, so each place it occurs is tried and the longest label that
+    // is valid under any scheme wins.
+    pub fn parse(&self, name: &str) -> Option<(String, String)> {
+        let inner = name.strip_prefix(&self.before)?.strip_suffix(&self.after)?;
+        let split = |at: usize| {
+            let (first, second) = (&inner[..at], &inner[at + self.between.len()..]);
+            let (stream, label) = if self.label_first { (second, first) } else { (first, second) };
+            (is_valid_stream(stream) && is_valid_label(label))
+                .then(|| (stream.to_string(), label.to_string()))
+        };
+        let between = self.between.as_str();
+        if self.label_first {
+            inner.rmatch_indices(between).find_map(|(at, _)| split(at))
+        } else {
+            inner.match_indices(between).find_map(|(at, _)| split(at))
+        }
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self::new(DEFAULT_PREFIX, DEFAULT_TEMPLATE).expect("default naming is valid")
+    }
+}
+
+// How new labels are written (`naming.label_scheme`). Within one scheme string
+// order is time order. Labels already in the manifest or on disk are accepted
+// under any scheme, so switching schemes keeps the history usable.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelScheme {
+    #[default]
+    Month,
+    Day,
+    Week,
+    // A day with an optional `-tag` for ad-hoc backups, e.g. 2024-05-03-pre-upgrade.
+    Freeform,
+}
+
+const MAX_LABEL_LEN: usize = 64;
+
+impl LabelScheme {
+    pub const ALL: [LabelScheme; 4] = [Self::Month, Self::Day, Self::Week, Self::Freeform];
+
+    pub fn format(self) -> &'static str {
+        match self {
+            Self::Month => "YYYY-MM",
+            Self::Day => "YYYY-MM-DD",
+            Self::Week => "YYYY-Www",
+            Self::Freeform => "YYYY-MM-DD or YYYY-MM-DD-tag",
+        }
+    }
+
+    pub fn accepts(self, label: &str) -> bool {
+        match self {
+            Self::Month => parse_month(label).is_some(),
+            Self::Day => parse_day(label).is_some(),
+            Self::Week => {
+                let week = label
+                    .get(..4)
+                    .filter(|year| digits(year))
+                    .and_then(|_| label.get(4..)?.strip_prefix("-W"));
+                week.is_some_and(|week| {
+                    week.len() == 2 && digits(week) && (1..=53).contains(&week.parse().unwrap_or(0))
+                })
+            }
+            Self::Freeform => {
+                let (day, tag) = (label.get(..10), label.get(10..));
+                let tag_ok = |tag: &str| match tag.strip_prefix('-') {
+                    Some(tag) => {
+                        !tag.is_empty()
+                            && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                    }
+                    None => tag.is_empty(),
+                };
+                label.len() <= MAX_LABEL_LEN
+                    && day.is_some_and(|day| parse_day(day).is_some())
+                    && tag.is_some_and(tag_ok)
+            }
+        }
+    }
+
+    // The label a run at `now` gets when none is given.
+    pub fn label_at(self, now: OffsetDateTime) -> String {
+        match self {
+            Self::Month => format!("{:04}-{:02}", now.year(), u8::from(now.month())),
+            Self::Day | Self::Freeform => {
+                format!("{:04}-{:02}-{:02}", now.year(), u8::from(now.month()), now.day())
+            }
+            Self::Week => {
+                let (year, week, _) = now.to_iso_week_date();
+                format!("{year:04}-W{week:02}")
+            }
+        }
+    }
+}
+
+fn digits(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_digit())
+}
+
+fn parse_month(label: &str) -> Option<(i32, Month)> {
+    let (year, month) = label.split_once('-')?;
+    if year.len() != 4 || month.len() != 2 || !digits(year) || !digits(month) {
+        return None;
+    }
+    Some((year.parse().ok()?, Month::try_from(month.parse::<u8>().ok()?).ok()?))
+}
+
+fn parse_day(label: &str) -> Option<Date> {
+    let (month, day) = (label.get(..7)?, label.get(7..)?.strip_prefix('-')?);
+    let (year, month) = parse_month(month)?;
+    if day.len() != 2 || !digits(day) {
+        return None;
+    }
+    Date::from_calendar_date(year, month, day.parse().ok()?).ok()
+}
+
+pub fn is_valid_stream(stream: &str) -> bool {
+    !stream.is_empty()
+        && stream
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Machine ids end up in file names and object keys, so they follow the same
+// rules as streams.
+pub fn is_valid_machine_id(machine: &str) -> bool {
+    is_valid_stream(machine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_template_round_trips() {
+        let naming = NameTemplate::default();
+        assert_eq!(naming.name("dev", "2024-05"), "dev@2024-05");
+        assert_eq!(naming.parse("vms@2024-05"), Some(("vms".into(), "2024-05".into())));
+        assert_eq!(naming.parse("dev-2024-05"), None);
+    }
+
+    #[test]
+    fn custom_template_with_label_first() {
+        let naming = NameTemplate::new("home", "backup-{label}_{prefix}.snap").unwrap();
+        assert_eq!(naming.name("home", "2024-05"), "backup-2024-05_home.snap");
+        assert_eq!(
+            naming.parse("backup-2024-05_home.snap"),
+            Some(("home".into(), "2024-05".into()))
+        );
+        // Names other tools put in the same directory are not ours.
+        assert_eq!(naming.parse("2024-05-01_12-00-00"), None);
+    }
+
+    #[test]
+    fn separator_inside_the_label_is_fine() {
+        let naming = NameTemplate::new("dev", "{prefix}-{label}").unwrap();
+        assert_eq!(naming.parse("my-dev-2024-05"), Some(("my-dev".into(), "2024-05".into())));
+    }
+
+    #[test]
+    fn labels_of_every_scheme_parse_back() {
+        let naming = NameTemplate::new("dev", "{prefix}-{label}").unwrap();
+        for label in ["2024-05", "2024-05-31", "2024-W05", "2024-05-03-pre-upgrade"] {
+            let name = naming.name("my-dev", label);
+            assert_eq!(naming.parse(&name), Some(("my-dev".into(), label.into())), "{name}");
+        }
+        let naming = NameTemplate::new("dev", "{label}-{prefix}").unwrap();
+        let parsed = naming.parse("2024-05-03-nightly-home");
+        assert_eq!(parsed, Some(("home".into(), "2024-05-03-nightly".into())));
+    }
+
+    #[test]
+    fn schemes_check_their_own_shape() {
+        use LabelScheme::*;
+        let cases = [
+            (Month, "2024-05", true),
+            (Month, "2024-13", false),
+            (Month, "2024-05-01", false),
+            (Day, "2024-02-29", true),
+            (Day, "2023-02-29", false),
+            (Week, "2024-W01", true),
+            (Week, "2024-W54", false),
+            (Week, "2024-05", false),
+            (Freeform, "2024-05-03", true),
+            (Freeform, "2024-05-03-pre-upgrade", true),
+            (Freeform, "2024-05-03-", false),
+            (Freeform, "2024-05-03-a;id", false),
+            (Freeform, "2024-05-pre", false),
+        ];
+        for (scheme, label, ok) in cases {
+            assert_eq!(scheme.accepts(label), ok, "{scheme:?} {label}");
+        }
+        let now = OffsetDateTime::from_unix_timestamp(1_704_153_600).unwrap(); // 2024-01-02
+        assert_eq!(Month.label_at(now), "2024-01");
+        assert_eq!(Day.label_at(now), "2024-01-02");
+        assert_eq!(Week.label_at(now), "2024-W01");
+    }
+
+    #[test]
+    fn bad_templates_are_rejected() {
+        for template in ["{label}", "{prefix}{label}", "{prefix}/{label}", ".{prefix}@{label}"] {
+            assert!(NameTemplate::new("dev", template).is_err(), "{template}");
+        }
+        assert!(NameTemplate::new("a/b", DEFAULT_TEMPLATE).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Result};
+
+pub const VARIABLES: [&str; 7] =
+    ["label", "dataset", "bytes", "duration", "outcome", "error", "summary"];
+
+// A notification message with `{variable}` fields from VARIABLES; `{{` and
+// `}}` are literal braces. Bad fields are rejected when the config is loaded
+// rather than when a run is already over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(String),
+}
+
+impl MessageTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        return Err(anyhow!("notify template has an unclosed field {{{name}"));
+                    }
+                    if !VARIABLES.contains(&name.as_str()) {
+                        return Err(anyhow!(
+                            "notify template has an unknown field {{{name}}} (known: {})",
+                            VARIABLES.join(", ")
+                        ));
+                    }
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                    parts.push(Part::Field(name));
+                }
+                '}' => return Err(anyhow!("notify template has an unmatched '}}'")),
+                c => text.push(c),
+            }
+        }
+        parts.push(Part::Text(text));
+        Ok(Self { parts })
+    }
+
+    // Fields missing from `values` render empty.
+    pub fn render(&self, values: &[(&str, &str)]) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Field(name) => {
+                    if let Some((_, value)) = values.iter().find(|(key, _)| key == name) {
+                        out.push_str(value);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_fields_and_escaped_braces() {
+        let template = MessageTemplate::parse("{{dev}} {label}: {outcome}{error}").unwrap();
+        let values = [("label", "2024-02"), ("outcome", "ok")];
+        assert_eq!(template.render(&values), "{dev} 2024-02: ok");
+    }
+
+    #[test]
+    fn rejects_unknown_and_unclosed_fields() {
+        assert!(MessageTemplate::parse("{host}").is_err());
+        assert!(MessageTemplate::parse("{label").is_err());
+        assert!(MessageTemplate::parse("done }").is_err());
+    }
+}
@@ -1,6 +1,7 @@
+use crate::clock::{Clock, SystemClock};
 use crate::manifest::ManifestRecord;
 use anyhow::{Context, Result};
-use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use time::OffsetDateTime;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SnapshotDecision {
@@ -14,33 +15,45 @@ pub struct PolicyInput {
     pub max_months_between_anchor: i64,
 }
 
-impl Default for PolicyInput {
-    fn default() -> Self {
+impl PolicyInput {
+    pub fn at(clock: &dyn Clock) -> Self {
         Self {
-            now: OffsetDateTime::now_utc(),
+            now: clock.now(),
             max_months_between_anchor: 12,
         }
     }
 }
 
-pub fn decide_snapshot_type(records: &[ManifestRecord], input: PolicyInput) -> Result<SnapshotDecision> {
-    if records.is_empty() {
-        return Ok(SnapshotDecision::Anchor);
+impl Default for PolicyInput {
+    fn default() -> Self {
+        Self::at(&SystemClock)
     }
+}
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainStatus {
+    pub anchor_label: String,
+    pub anchor_ts: OffsetDateTime,
+    pub anchor_bytes: u64,
+    pub incremental_bytes: u64,
+    pub incremental_count: usize,
+    pub months_since_anchor: i64,
+}
+
+pub fn chain_status(records: &[ManifestRecord], now: OffsetDateTime) -> Result<ChainStatus> {
     let last_anchor = records
         .iter()
         .rev()
         .find(|r| r.record_type == "anchor")
         .context("no anchor found in manifest")?;
 
-    let anchor_ts = OffsetDateTime::parse(&last_anchor.ts, &Rfc3339)
-        .context("failed to parse anchor timestamp")?;
+    let anchor_ts = last_anchor.ts;
 
-    let diff_seconds = (input.now - anchor_ts).whole_seconds();
+    let diff_seconds = (now - anchor_ts).whole_seconds();
     let diff_months = diff_seconds / 2_592_000; // approx 30 days
 
     let mut sum_incr: u64 = 0;
+    let mut count_incr = 0;
     let mut seen_anchor = false;
     for record in records {
         if record == last_anchor {
@@ -49,16 +62,32 @@ pub fn decide_snapshot_type(records: &[ManifestRecord], input: PolicyInput) -> R
         }
         if seen_anchor {
             sum_incr = sum_incr.saturating_add(record.bytes);
+            count_incr += 1;
         }
     }
 
-    let anchor_bytes = last_anchor.bytes.max(1);
+    Ok(ChainStatus {
+        anchor_label: last_anchor.label.clone(),
+        anchor_ts,
+        anchor_bytes: last_anchor.bytes.max(1),
+        incremental_bytes: sum_incr,
+        incremental_count: count_incr,
+        months_since_anchor: diff_months,
+    })
+}
+
+pub fn decide_snapshot_type(records: &[ManifestRecord], input: PolicyInput) -> Result<SnapshotDecision> {
+    if records.is_empty() {
+        return Ok(SnapshotDecision::Anchor);
+    }
+
+    let status = chain_status(records, input.now)?;
 
-    if diff_months >= input.max_months_between_anchor {
+    if status.months_since_anchor >= input.max_months_between_anchor {
         return Ok(SnapshotDecision::Anchor);
     }
 
-    if sum_incr >= anchor_bytes {
+    if status.incremental_bytes >= status.anchor_bytes {
         return Ok(SnapshotDecision::Anchor);
     }
 
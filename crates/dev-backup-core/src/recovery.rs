@@ -0,0 +1,32 @@
+use crate::config::Recovery;
+use crate::manifest::ManifestRecord;
+use time::Duration;
+
+// Rough time to bring a chain back: every artifact is downloaded, then
+// decrypted/decompressed and received. Artifact sizes are the compressed
+// sizes from the manifest, so the decrypt and receive rates are per byte of
+// artifact rather than per byte of restored data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestoreEstimate {
+    pub bytes: u64,
+    pub download: Duration,
+    pub decrypt: Duration,
+    pub receive: Duration,
+}
+
+impl RestoreEstimate {
+    pub fn total(&self) -> Duration {
+        self.download + self.decrypt + self.receive
+    }
+}
+
+pub fn estimate_restore(chain: &[&ManifestRecord], recovery: &Recovery) -> RestoreEstimate {
+    let bytes = chain.iter().map(|record| record.bytes).sum::<u64>();
+    let seconds_at = |bytes_per_second: f64| Duration::seconds_f64(bytes as f64 / bytes_per_second);
+    RestoreEstimate {
+        bytes,
+        download: seconds_at(recovery.download_mbps * 1_000_000.0 / 8.0),
+        decrypt: seconds_at(recovery.decrypt_mib_per_sec * 1024.0 * 1024.0),
+        receive: seconds_at(recovery.receive_mib_per_sec * 1024.0 * 1024.0),
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+const HEADER: [&str; 3] = ["ts", "label", "datasets"];
+
+// Datasets snapshotted together under one label by `snapshot-set`, so they
+// can be restored to the same point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotSet {
+    pub ts: OffsetDateTime,
+    pub label: String,
+    pub datasets: Vec<String>,
+}
+
+// Append-only TSV of snapshot sets; the last row for a label wins.
+pub struct SnapshotSetStore {
+    path: PathBuf,
+}
+
+impl SnapshotSetStore {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn read(&self) -> Result<Vec<SnapshotSet>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_path(&self.path)
+            .with_context(|| format!("failed to read snapshot sets: {}", self.path.display()))?;
+        let mut sets = Vec::new();
+        for result in reader.records() {
+            let row = result.context("failed to parse snapshot set row")?;
+            let (ts, label, datasets) = match (row.get(0), row.get(1), row.get(2)) {
+                (Some(ts), Some(label), Some(datasets)) if !datasets.is_empty() => {
+                    (ts, label, datasets)
+                }
+                _ => return Err(anyhow!("malformed snapshot set row in {}", self.path.display())),
+            };
+            sets.push(SnapshotSet {
+                ts: OffsetDateTime::parse(ts, &Rfc3339)
+                    .with_context(|| format!("invalid snapshot set timestamp: {ts}"))?,
+                label: label.to_string(),
+                datasets: datasets.split(',').map(str::to_string).collect(),
+            });
+        }
+        Ok(sets)
+    }
+
+    pub fn find(&self, label: &str) -> Result<Option<SnapshotSet>> {
+        Ok(self.read()?.into_iter().rev().find(|set| set.label == label))
+    }
+
+    pub fn append(&self, set: &SnapshotSet) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory: {}", parent.display()))?;
+        }
+        let new_file = !self.path.exists();
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open snapshot sets: {}", self.path.display()))?;
+        let mut writer = csv::WriterBuilder::new().delimiter(b'\t').from_writer(file);
+        if new_file {
+            writer
+                .write_record(HEADER)
+                .context("failed to write snapshot set header")?;
+        }
+        writer
+            .write_record([&set.ts.format(&Rfc3339)?, &set.label, &set.datasets.join(",")])
+            .context("failed to write snapshot set row")?;
+        writer.flush().context("failed to flush snapshot sets")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use crate::manifest::ManifestRecord;
+use std::fmt;
+use time::{Duration, OffsetDateTime};
+
+pub const FUTURE_TOLERANCE: Duration = Duration::minutes(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkewKind {
+    BeforePrevious { previous: OffsetDateTime },
+    InFuture { now: OffsetDateTime },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClockSkew {
+    pub index: usize,
+    pub label: String,
+    pub ts: OffsetDateTime,
+    pub kind: SkewKind,
+}
+
+impl fmt::Display for ClockSkew {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            SkewKind::BeforePrevious { previous } => write!(
+                f,
+                "manifest row {} ({}) has ts {} earlier than the preceding row ({})",
+                self.index + 1,
+                self.label,
+                self.ts,
+                previous
+            ),
+            SkewKind::InFuture { now } => write!(
+                f,
+                "manifest row {} ({}) has ts {} in the future (now {})",
+                self.index + 1,
+                self.label,
+                self.ts,
+                now
+            ),
+        }
+    }
+}
+
+pub fn find_clock_skew(records: &[ManifestRecord], now: OffsetDateTime) -> Vec<ClockSkew> {
+    let mut issues = Vec::new();
+    let mut previous: Option<OffsetDateTime> = None;
+    for (index, record) in records.iter().enumerate() {
+        let ts = record.ts;
+        if ts > now + FUTURE_TOLERANCE {
+            issues.push(ClockSkew {
+                index,
+                label: record.label.clone(),
+                ts,
+                kind: SkewKind::InFuture { now },
+            });
+        }
+        if let Some(previous) = previous {
+            if ts < previous {
+                issues.push(ClockSkew {
+                    index,
+                    label: record.label.clone(),
+                    ts,
+                    kind: SkewKind::BeforePrevious { previous },
+                });
+            }
+        }
+        previous = Some(previous.map_or(ts, |p| p.max(ts)));
+    }
+    issues
+}
+
+// Rows are in append order, which is the order they really happened in.
+// Walking backwards, each row is pulled to just before its successor (or to
+// `now` for the last row), so a future-dated row lands between its
+// neighbours rather than being pushed ahead of everything after it.
+pub fn fix_timestamps(
+    records: &mut [ManifestRecord],
+    now: OffsetDateTime,
+) -> Vec<(usize, OffsetDateTime, OffsetDateTime)> {
+    let mut changes = Vec::new();
+    let mut ceiling = now;
+    for index in (0..records.len()).rev() {
+        let ts = records[index].ts;
+        if ts > ceiling {
+            let fixed = if index + 1 == records.len() {
+                ceiling
+            } else {
+                ceiling - Duration::seconds(1)
+            };
+            changes.push((index, ts, fixed));
+            records[index].ts = fixed;
+            ceiling = fixed;
+        } else {
+            ceiling = ts;
+        }
+    }
+    changes.reverse();
+    changes
+}
@@ -0,0 +1,270 @@
+use crate::manifest::{ManifestRecord, RecordStatus};
+use anyhow::{anyhow, Context, Result};
+use rusqlite::{params, Connection};
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+// The manifest as an SQLite table. `seq` keeps manifest order so the rows
+// compare one-to-one with the TSV store.
+pub struct SqliteManifest {
+    path: PathBuf,
+}
+
+impl SqliteManifest {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn open(&self) -> Result<Connection> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create manifest directory: {}", parent.display()))?;
+        }
+        let conn = Connection::open(&self.path)
+            .with_context(|| format!("failed to open manifest database: {}", self.path.display()))?;
+        migrate(&conn)
+            .with_context(|| format!("failed to migrate {}", self.path.display()))?;
+        Ok(conn)
+    }
+
+    pub fn ensure_initialized(&self) -> Result<()> {
+        self.open().map(|_| ())
+    }
+
+    pub fn schema_version(&self) -> Result<usize> {
+        let conn = self.open()?;
+        user_version(&conn)
+    }
+
+    pub fn read_records(&self) -> Result<Vec<ManifestRecord>> {
+        self.query("", [])
+    }
+
+    // Served from the label index instead of a scan of the whole history.
+    pub fn records_for_label(&self, label: &str) -> Result<Vec<ManifestRecord>> {
+        self.query("WHERE label = ?1", [label])
+    }
+
+    fn query<P: rusqlite::Params>(&self, filter: &str, params: P) -> Result<Vec<ManifestRecord>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let conn = self.open()?;
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT ts, label, type, parent, bytes, sha256, local_path, object_key,
+                        mirror_keys, status, machine
+                 FROM records {filter} ORDER BY seq"
+            ))
+            .context("failed to query manifest database")?;
+        let rows = statement
+            .query_map(params, |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(9)?,
+                    ManifestRecord {
+                        ts: OffsetDateTime::UNIX_EPOCH,
+                        label: row.get(1)?,
+                        record_type: row.get(2)?,
+                        parent: row.get(3)?,
+                        bytes: row.get::<_, i64>(4)?.max(0) as u64,
+                        sha256: row.get(5)?,
+                        local_path: row.get(6)?,
+                        object_key: row.get(7)?,
+                        mirror_keys: row.get(8)?,
+                        status: RecordStatus::default(),
+                        machine: row.get(10)?,
+                    },
+                ))
+            })
+            .context("failed to query manifest database")?;
+        let mut records = Vec::new();
+        for (index, row) in rows.enumerate() {
+            let (ts, status, mut record) = row.context("failed to read manifest database row")?;
+            record.ts = OffsetDateTime::parse(&ts, &Rfc3339)
+                .map_err(|err| anyhow!("invalid ts {ts:?}: {err}"))
+                .with_context(|| format!("invalid manifest database row {}", index + 1))?;
+            // Rows from before the status column hold ''.
+            record.status = match status.as_str() {
+                "" => record.inferred_status(),
+                value => RecordStatus::parse(value)
+                    .ok_or_else(|| anyhow!("invalid status {value:?}"))
+                    .with_context(|| format!("invalid manifest database row {}", index + 1))?,
+            };
+            record
+                .validate()
+                .with_context(|| format!("invalid manifest database row {}", index + 1))?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    pub fn append_record(&self, record: &ManifestRecord) -> Result<()> {
+        let conn = self.open()?;
+        insert(&conn, record)
+    }
+
+    pub fn append_records(&self, records: &[ManifestRecord]) -> Result<()> {
+        let mut conn = self.open()?;
+        let tx = conn.transaction().context("failed to start manifest transaction")?;
+        for record in records {
+            insert(&tx, record)?;
+        }
+        tx.commit().context("failed to commit manifest database")
+    }
+
+    pub fn write_records(&self, records: &[ManifestRecord]) -> Result<()> {
+        let mut conn = self.open()?;
+        let tx = conn.transaction().context("failed to start manifest transaction")?;
+        tx.execute("DELETE FROM records", [])
+            .context("failed to clear manifest database")?;
+        for record in records {
+            insert(&tx, record)?;
+        }
+        tx.commit().context("failed to commit manifest database")
+    }
+}
+
+// Schema changes in order; the database's user_version counts how many have
+// been applied. Databases from before versioning are at 0 with the table (and
+// possibly some columns) already there, so every step tolerates that.
+const MIGRATIONS: [&str; 5] = [
+    "CREATE TABLE IF NOT EXISTS records (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        ts TEXT NOT NULL,
+        label TEXT NOT NULL,
+        type TEXT NOT NULL,
+        parent TEXT NOT NULL,
+        bytes INTEGER NOT NULL,
+        sha256 TEXT NOT NULL,
+        local_path TEXT NOT NULL,
+        object_key TEXT NOT NULL
+    );",
+    "ALTER TABLE records ADD COLUMN mirror_keys TEXT NOT NULL DEFAULT '';",
+    "ALTER TABLE records ADD COLUMN status TEXT NOT NULL DEFAULT '';",
+    "CREATE INDEX IF NOT EXISTS records_label ON records (label);
+     CREATE INDEX IF NOT EXISTS records_ts ON records (ts);",
+    "ALTER TABLE records ADD COLUMN machine TEXT NOT NULL DEFAULT '';",
+];
+
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+fn user_version(conn: &Connection) -> Result<usize> {
+    let version: i64 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .context("failed to read schema version")?;
+    Ok(usize::try_from(version).unwrap_or(0))
+}
+
+fn migrate(conn: &Connection) -> Result<()> {
+    let version = user_version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!(
+            "schema version {version} is newer than this dev-backup understands ({SCHEMA_VERSION})"
+        ));
+    }
+    for (index, step) in MIGRATIONS.iter().enumerate().skip(version) {
+        if let Some(column) = added_column(step) {
+            let exists = conn
+                .prepare("SELECT 1 FROM pragma_table_info('records') WHERE name = ?1")
+                .and_then(|mut statement| statement.exists([column]))
+                .context("failed to inspect manifest table")?;
+            if exists {
+                conn.pragma_update(None, "user_version", index + 1)?;
+                continue;
+            }
+        }
+        // One transaction per step, so a failed step leaves the version
+        // where it was.
+        conn.execute_batch(&format!(
+            "BEGIN; {step} PRAGMA user_version = {}; COMMIT;",
+            index + 1
+        ))
+        .with_context(|| format!("schema migration {} failed", index + 1))?;
+    }
+    Ok(())
+}
+
+fn added_column(step: &str) -> Option<&str> {
+    let rest = step.strip_prefix("ALTER TABLE records ADD COLUMN ")?;
+    rest.split_whitespace().next()
+}
+
+fn insert(conn: &Connection, record: &ManifestRecord) -> Result<()> {
+    let ts = record.ts.format(&Rfc3339)?;
+    let bytes = i64::try_from(record.bytes).context("artifact size out of range")?;
+    conn.execute(
+        "INSERT INTO records
+             (ts, label, type, parent, bytes, sha256, local_path, object_key, mirror_keys,
+              status, machine)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            ts,
+            record.label,
+            record.record_type,
+            record.parent,
+            bytes,
+            record.sha256,
+            record.local_path,
+            record.object_key,
+            record.mirror_keys,
+            record.status.as_str(),
+            record.machine
+        ],
+    )
+    .with_context(|| format!("failed to write manifest database row for {}", record.label))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unversioned_databases_are_migrated_in_place() {
+        let dir = std::env::temp_dir().join(format!("dev-backup-sqlite-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("legacy.sqlite");
+        let _ = fs::remove_file(&path);
+        // The table as it was before mirror_keys, status and user_version.
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE records (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT, ts TEXT NOT NULL, label TEXT NOT NULL,
+                type TEXT NOT NULL, parent TEXT NOT NULL, bytes INTEGER NOT NULL,
+                sha256 TEXT NOT NULL, local_path TEXT NOT NULL, object_key TEXT NOT NULL
+            );
+            INSERT INTO records (ts, label, type, parent, bytes, sha256, local_path, object_key)
+            VALUES ('2024-01-01T00:00:00Z', '2024-01', 'anchor', '', 5, 'ab', '', 'a/b');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let manifest = SqliteManifest::new(&path);
+        let records = manifest.records_for_label("2024-01").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].status, RecordStatus::Pushed);
+        assert!(manifest.records_for_label("2024-02").unwrap().is_empty());
+        assert_eq!(manifest.schema_version().unwrap(), SCHEMA_VERSION);
+
+        let conn = Connection::open(&path).unwrap();
+        let indexed = conn
+            .prepare("SELECT 1 FROM pragma_index_list('records') WHERE name = 'records_label'")
+            .and_then(|mut statement| statement.exists([]))
+            .unwrap();
+        assert!(indexed);
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+        drop(conn);
+        let err = manifest.read_records().unwrap_err();
+        assert!(format!("{err:#}").contains("newer than this dev-backup understands"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,9 +1,10 @@
 use anyhow::{Context, Result};
+use dev_backup_core::naming::{is_valid_machine_id, NameTemplate};
 use sha2::{Digest, Sha256};
 use std::fs::File;
 use std::io::Read;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ArtifactType {
     Anchor,
     Incremental,
@@ -11,36 +12,75 @@ pub enum ArtifactType {
 
 #[derive(Debug, Clone)]
 pub struct ArtifactInfo {
+    pub stream: String,
     pub label: String,
     pub artifact_type: ArtifactType,
     pub parent: Option<String>,
     pub filename: String,
+    pub machine: Option<String>,
 }
 
-pub fn parse_artifact_filename(filename: &str) -> Option<ArtifactInfo> {
-    if let Some(label) = filename.strip_prefix("dev@").and_then(|name| name.strip_suffix(".full.send.zst.age")) {
+// Artifact files are named after the snapshot they were sent from, behind
+// `{machine}~` when the naming has a machine id.
+pub fn artifact_filename(
+    naming: &NameTemplate,
+    stream: &str,
+    label: &str,
+    parent: Option<&str>,
+) -> String {
+    let name = match naming.machine() {
+        Some(machine) => format!("{machine}~{}", naming.name(stream, label)),
+        None => naming.name(stream, label),
+    };
+    match parent {
+        Some(parent_label) => format!("{name}.incr.from_{parent_label}.send.zst.age"),
+        None => format!("{name}.full.send.zst.age"),
+    }
+}
+
+// Accepts any machine prefix, not just the configured one: an LS registers
+// artifacts from every workstation that shares it.
+pub fn parse_artifact_filename(naming: &NameTemplate, filename: &str) -> Option<ArtifactInfo> {
+    let (machine, rest) = match filename.split_once('~') {
+        Some((machine, rest)) if is_valid_machine_id(machine) => (Some(machine), rest),
+        Some(_) => return None,
+        None => (None, filename),
+    };
+    let mut info = parse_snapshot_artifact(naming, rest)?;
+    info.machine = machine.map(str::to_string);
+    info.filename = filename.to_string();
+    Some(info)
+}
+
+fn parse_snapshot_artifact(naming: &NameTemplate, filename: &str) -> Option<ArtifactInfo> {
+    if let Some(name) = filename.strip_suffix(".full.send.zst.age") {
+        let (stream, label) = naming.parse(name)?;
         return Some(ArtifactInfo {
-            label: label.to_string(),
+            stream,
+            label,
             artifact_type: ArtifactType::Anchor,
             parent: None,
             filename: filename.to_string(),
+            machine: None,
         });
     }
 
-    let trimmed = filename.strip_prefix("dev@")?;
-    let trimmed = trimmed.strip_suffix(".send.zst.age")?;
+    let trimmed = filename.strip_suffix(".send.zst.age")?;
     let mut parts = trimmed.split(".incr.from_");
-    let label = parts.next()?;
+    let name = parts.next()?;
     let parent = parts.next()?;
     if parts.next().is_some() {
         return None;
     }
+    let (stream, label) = naming.parse(name)?;
 
     Some(ArtifactInfo {
-        label: label.to_string(),
+        stream,
+        label,
         artifact_type: ArtifactType::Incremental,
         parent: Some(parent.to_string()),
         filename: filename.to_string(),
+        machine: None,
     })
 }
 
@@ -0,0 +1,263 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Component, Path};
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+
+pub type ObjectReader = Pin<Box<dyn AsyncRead + Send>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub size: u64,
+    pub etag: Option<String>,
+    pub storage_class: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+// Object storage as seen by sync, ls and ws: keys are '/'-separated relative
+// paths and objects move to and from local files.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    async fn put(&self, key: &str, path: &str) -> Result<()>;
+    async fn get(&self, key: &str, path: &str) -> Result<()>;
+    async fn list(&self, prefix: Option<&str>) -> Result<Vec<ObjectInfo>>;
+    async fn delete(&self, key: &str) -> Result<()>;
+    // Metadata only; returns None when the object does not exist.
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>>;
+    // Uploads only if the object is still what `seen` (from `head`) described,
+    // or with None still absent; false means someone else wrote it first.
+    // Backends without conditional writes compare `head` again right before
+    // the upload, which narrows the race rather than closing it.
+    async fn put_if_unchanged(
+        &self,
+        key: &str,
+        path: &str,
+        seen: Option<&ObjectInfo>,
+    ) -> Result<bool> {
+        if self.head(key).await?.as_ref() != seen {
+            return Ok(false);
+        }
+        self.put(key, path).await?;
+        Ok(true)
+    }
+    // Stores the chunks as one object once `Done` arrives; if the sender goes
+    // away without it, nothing is left under `key`.
+    async fn put_stream(&self, key: &str, _chunks: mpsc::Receiver<StreamChunk>) -> Result<()> {
+        Err(anyhow!("this storage backend cannot take streamed uploads ({key})"))
+    }
+    // The object's bytes as they arrive, for consumers that do not want it on
+    // disk first.
+    async fn get_stream(&self, key: &str) -> Result<ObjectReader> {
+        Err(anyhow!("this storage backend cannot stream downloads ({key})"))
+    }
+}
+
+#[derive(Debug)]
+pub enum StreamChunk {
+    Data(Vec<u8>),
+    Done,
+}
+
+// The producing side of `put_stream`: cuts what is written into `chunk_size`
+// pieces (the multipart part size) and hashes it on the way. Writes block
+// while the upload is behind, so it belongs on a blocking thread.
+pub struct ChunkWriter {
+    sender: mpsc::Sender<StreamChunk>,
+    chunk_size: usize,
+    buf: Vec<u8>,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl ChunkWriter {
+    pub fn new(sender: mpsc::Sender<StreamChunk>, chunk_size: usize) -> Self {
+        Self {
+            sender,
+            chunk_size,
+            buf: Vec::with_capacity(chunk_size),
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    // Sends the last chunk and `Done`; returns the sha256 and size of
+    // everything written.
+    pub fn finish(mut self) -> io::Result<(String, u64)> {
+        if !self.buf.is_empty() {
+            self.send_buf()?;
+        }
+        self.send(StreamChunk::Done)?;
+        Ok((format!("{:x}", self.hasher.finalize()), self.bytes))
+    }
+
+    fn send_buf(&mut self) -> io::Result<()> {
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(self.chunk_size));
+        self.send(StreamChunk::Data(chunk))
+    }
+
+    fn send(&self, chunk: StreamChunk) -> io::Result<()> {
+        self.sender
+            .blocking_send(chunk)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "upload stopped"))
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let take = data.len().min(self.chunk_size - self.buf.len());
+        self.buf.extend_from_slice(&data[..take]);
+        self.hasher.update(&data[..take]);
+        self.bytes += take as u64;
+        if self.buf.len() == self.chunk_size {
+            self.send_buf()?;
+        }
+        Ok(take)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Feeds an object into `sender` for a `ChunkReader`, ending with `Done`, and
+// returns the sha256 and size of what went through.
+pub async fn pump_chunks(
+    mut object: ObjectReader,
+    sender: mpsc::Sender<StreamChunk>,
+) -> Result<(String, u64)> {
+    let mut hasher = Sha256::new();
+    let mut bytes = 0;
+    loop {
+        let mut chunk = vec![0; 1024 * 1024];
+        let read = object.read(&mut chunk).await.context("failed to read object stream")?;
+        if read == 0 {
+            break;
+        }
+        chunk.truncate(read);
+        hasher.update(&chunk);
+        bytes += read as u64;
+        if sender.send(StreamChunk::Data(chunk)).await.is_err() {
+            return Err(anyhow!("reader stopped before the end of the object"));
+        }
+    }
+    // The reader may already be gone if it did not need the tail.
+    let _ = sender.send(StreamChunk::Done).await;
+    Ok((format!("{:x}", hasher.finalize()), bytes))
+}
+
+// The consuming side of `pump_chunks`, as a blocking reader. A channel that
+// closes before `Done` is an error rather than a short read.
+pub struct ChunkReader {
+    receiver: mpsc::Receiver<StreamChunk>,
+    chunk: Vec<u8>,
+    offset: usize,
+    done: bool,
+}
+
+impl ChunkReader {
+    pub fn new(receiver: mpsc::Receiver<StreamChunk>) -> Self {
+        Self {
+            receiver,
+            chunk: Vec::new(),
+            offset: 0,
+            done: false,
+        }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for ChunkReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.offset == self.chunk.len() && !self.done {
+            match self.receiver.blocking_recv() {
+                Some(StreamChunk::Data(chunk)) => {
+                    self.chunk = chunk;
+                    self.offset = 0;
+                }
+                Some(StreamChunk::Done) => self.done = true,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "object stream ended early",
+                    ))
+                }
+            }
+        }
+        Ok(&self.chunk[self.offset..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.offset = (self.offset + amount).min(self.chunk.len());
+    }
+}
+
+pub fn validate_key(key: &str) -> Result<()> {
+    if key.is_empty()
+        || key.contains('\\')
+        || !Path::new(key)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(anyhow!("invalid object key: {key:?}"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_writer_cuts_fixed_size_chunks() {
+        let (sender, mut chunks) = mpsc::channel(8);
+        let mut writer = ChunkWriter::new(sender, 4);
+        writer.write_all(b"0123456789").unwrap();
+        let (sha256, bytes) = writer.finish().unwrap();
+
+        let mut received = Vec::new();
+        while let Ok(chunk) = chunks.try_recv() {
+            received.push(chunk);
+        }
+        let data: Vec<&[u8]> = received
+            .iter()
+            .filter_map(|chunk| match chunk {
+                StreamChunk::Data(data) => Some(data.as_slice()),
+                StreamChunk::Done => None,
+            })
+            .collect();
+        assert_eq!(data, [b"0123".as_slice(), b"4567", b"89"]);
+        assert!(matches!(received.last(), Some(StreamChunk::Done)));
+        assert_eq!(bytes, 10);
+        assert_eq!(sha256, format!("{:x}", Sha256::digest(b"0123456789")));
+    }
+
+    #[test]
+    fn chunk_reader_needs_done_to_end_cleanly() {
+        let (sender, receiver) = mpsc::channel(8);
+        sender.try_send(StreamChunk::Data(b"abc".to_vec())).unwrap();
+        sender.try_send(StreamChunk::Data(b"def".to_vec())).unwrap();
+        sender.try_send(StreamChunk::Done).unwrap();
+        let mut data = String::new();
+        ChunkReader::new(receiver).read_to_string(&mut data).unwrap();
+        assert_eq!(data, "abcdef");
+
+        let (sender, receiver) = mpsc::channel(8);
+        sender.try_send(StreamChunk::Data(b"abc".to_vec())).unwrap();
+        drop(sender);
+        let err = ChunkReader::new(receiver).read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
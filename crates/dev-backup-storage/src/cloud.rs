@@ -1,11 +1,25 @@
-use anyhow::{Context, Result};
+use crate::backend::{ObjectInfo, ObjectReader, StorageBackend, StreamChunk};
+use crate::multipart::{part_count, part_range, resume_file_name, SourceStamp, UploadState};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
 use aws_config::BehaviorVersion;
 use aws_credential_types::Credentials;
 use aws_sdk_s3::config::Region;
-use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::primitives::{ByteStream, DateTime, DateTimeFormat, Length};
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
 use aws_sdk_s3::Client;
-use std::path::Path;
+use aws_smithy_http_client::proxy::ProxyConfig;
+use aws_smithy_http_client::tls::{self, rustls_provider::CryptoMode, TlsContext, TrustStore};
+use aws_smithy_http_client::{Builder as HttpClientBuilder, Connector};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::CertificateDer;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
 
 #[derive(Debug, Clone)]
 pub struct R2Config {
@@ -13,14 +27,27 @@ pub struct R2Config {
     pub bucket: String,
     pub access_key: String,
     pub secret_key: String,
+    pub https_proxy: Option<String>,
+    pub ca_bundle_path: Option<String>,
+    pub multipart_threshold: u64,
+    pub multipart_part_size: u64,
+    pub resume_dir: PathBuf,
 }
 
 #[derive(Debug, Clone)]
 pub struct R2Client {
     client: Client,
     bucket: String,
+    multipart_threshold: u64,
+    multipart_part_size: u64,
+    resume_dir: PathBuf,
 }
 
+// Attempts per multipart part and per download before giving up; the SDK's
+// own retries happen inside each attempt.
+const PART_ATTEMPTS: u32 = 4;
+const GET_ATTEMPTS: u32 = 5;
+
 impl R2Client {
     pub async fn new(config: R2Config) -> Result<Self> {
         let creds = Credentials::new(
@@ -31,12 +58,43 @@ impl R2Client {
             "dev-backup",
         );
         let shared = aws_credential_types::provider::SharedCredentialsProvider::new(creds);
-        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+        let mut loader = aws_config::defaults(BehaviorVersion::latest())
             .region(Region::new("auto"))
             .endpoint_url(config.endpoint)
-            .credentials_provider(shared)
-            .load()
-            .await;
+            .credentials_provider(shared);
+        if config.https_proxy.is_some() || config.ca_bundle_path.is_some() {
+            let proxy = config
+                .https_proxy
+                .as_deref()
+                .map(|url| {
+                    ProxyConfig::https(url).map_err(|err| anyhow!("invalid https_proxy {url}: {err}"))
+                })
+                .transpose()?;
+            let trust_store = match config.ca_bundle_path.as_deref() {
+                Some(path) => load_ca_bundle(path)?,
+                None => TrustStore::default(),
+            };
+            let tls_context = TlsContext::builder()
+                .with_trust_store(trust_store)
+                .build()
+                .context("failed to build TLS context")?;
+            let http_client = HttpClientBuilder::new().build_with_connector_fn(
+                move |settings, components| {
+                    let mut builder = Connector::builder();
+                    builder.set_connector_settings(settings.cloned());
+                    builder.set_proxy_config(proxy.clone());
+                    if let Some(components) = components {
+                        builder.set_sleep_impl(components.sleep_impl());
+                    }
+                    builder
+                        .tls_provider(tls::Provider::Rustls(CryptoMode::AwsLc))
+                        .tls_context(tls_context.clone())
+                        .build()
+                },
+            );
+            loader = loader.http_client(http_client);
+        }
+        let sdk_config = loader.load().await;
         let s3_config = aws_sdk_s3::config::Builder::from(&sdk_config)
             .force_path_style(true)
             .build();
@@ -44,10 +102,258 @@ impl R2Client {
         Ok(Self {
             client,
             bucket: config.bucket,
+            multipart_threshold: config.multipart_threshold,
+            multipart_part_size: config.multipart_part_size,
+            resume_dir: config.resume_dir,
+        })
+    }
+
+    // Parts confirmed by an earlier, interrupted run are skipped; the resume
+    // file is removed once the upload completes.
+    async fn put_multipart(&self, key: &str, path: &str, size: u64) -> Result<()> {
+        fs::create_dir_all(&self.resume_dir).with_context(|| {
+            format!("failed to create upload state dir: {}", self.resume_dir.display())
+        })?;
+        let resume_path = self.resume_dir.join(resume_file_name(&self.bucket, key));
+        let part_size = self.multipart_part_size;
+        let source = SourceStamp::of(Path::new(path))?;
+        let mut state = match UploadState::load(&resume_path)? {
+            Some(state) if state.matches(size, part_size, source) => state,
+            stale => {
+                if let Some(stale) = stale {
+                    // Best effort; R2 also expires abandoned uploads on its own.
+                    let _ = self
+                        .client
+                        .abort_multipart_upload()
+                        .bucket(&self.bucket)
+                        .key(key)
+                        .upload_id(&stale.upload_id)
+                        .send()
+                        .await;
+                }
+                let output = self
+                    .client
+                    .create_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .send()
+                    .await
+                    .map_err(|err| cloud_error(err, format!("failed to start upload of {key}")))?;
+                let upload_id = output
+                    .upload_id()
+                    .ok_or_else(|| anyhow!("no upload id returned for {key}"))?;
+                let state = UploadState::new(upload_id.to_string(), size, part_size, source);
+                state.save(&resume_path)?;
+                state
+            }
+        };
+
+        for number in 1..=part_count(size, part_size) {
+            if state.has_part(number) {
+                continue;
+            }
+            let etag = self
+                .upload_part(key, path, &state, number)
+                .await
+                .inspect_err(|_| forget_vanished_upload(&resume_path))?;
+            state.record_part(&resume_path, number, &etag)?;
+        }
+
+        let parts = state
+            .parts
+            .iter()
+            .map(|(number, etag)| CompletedPart::builder().part_number(*number).e_tag(etag).build())
+            .collect();
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(&state.upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+            .send()
+            .await
+            .map_err(|err| upload_error(err, &resume_path, format!("failed to complete {key}")))?;
+        fs::remove_file(&resume_path)
+            .with_context(|| format!("failed to remove upload state: {}", resume_path.display()))
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        path: &str,
+        state: &UploadState,
+        number: i32,
+    ) -> Result<String> {
+        let (offset, length) = part_range(state.size, state.part_size, number);
+        self.send_part(key, &state.upload_id, number, || async move {
+            ByteStream::read_from()
+                .path(path)
+                .offset(offset)
+                .length(Length::Exact(length))
+                .build()
+                .await
+                .with_context(|| format!("failed to read part {number} of {path}"))
         })
+        .await
+    }
+
+    // `body` is called again for every attempt.
+    async fn send_part<F, Fut>(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: i32,
+        body: F,
+    ) -> Result<String>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<ByteStream>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .client
+                .upload_part()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(number)
+                .body(body().await?)
+                .send()
+                .await;
+            match result {
+                Ok(output) => {
+                    return output
+                        .e_tag()
+                        .map(str::to_string)
+                        .ok_or_else(|| anyhow!("no ETag returned for part {number} of {key}"));
+                }
+                Err(err) if attempt < PART_ATTEMPTS && err.code() != Some(NO_SUCH_UPLOAD) => {
+                    tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                    attempt += 1;
+                }
+                Err(err) => {
+                    return Err(cloud_error(err, format!("failed to upload part {number} of {key}")))
+                }
+            }
+        }
+    }
+
+    // A stream cannot be replayed, so unlike `put_multipart` there is no
+    // resume file: an interrupted upload is aborted and has to start over.
+    async fn stream_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        chunks: &mut mpsc::Receiver<StreamChunk>,
+    ) -> Result<Vec<CompletedPart>> {
+        let mut parts = Vec::new();
+        loop {
+            let chunk = match chunks.recv().await {
+                Some(StreamChunk::Data(chunk)) => chunk,
+                Some(StreamChunk::Done) => return Ok(parts),
+                None => return Err(anyhow!("stream for {key} ended before it was complete")),
+            };
+            let number = parts.len() as i32 + 1;
+            let etag = self
+                .send_part(key, upload_id, number, || {
+                    let chunk = chunk.clone();
+                    async move { Ok(ByteStream::from(chunk)) }
+                })
+                .await?;
+            parts.push(CompletedPart::builder().part_number(number).e_tag(etag).build());
+        }
+    }
+
+    // Appends to `partial` from its current length. The ETag of the object is
+    // kept beside it so a resume only continues the same object; if the key
+    // was overwritten meanwhile the download starts over.
+    async fn download_into(&self, key: &str, partial: &str) -> Result<()> {
+        let etag_file = etag_path(partial);
+        let offset = match tokio::fs::metadata(partial).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        let etag = match offset {
+            0 => None,
+            _ => tokio::fs::read_to_string(&etag_file).await.ok(),
+        };
+
+        let mut request = self.client.get_object().bucket(&self.bucket).key(key);
+        if let Some(etag) = &etag {
+            request = request.range(format!("bytes={offset}-")).if_match(etag);
+        }
+        let output = match request.send().await {
+            Ok(output) => output,
+            Err(err) if etag.is_some() && err.code() == Some("InvalidRange") => {
+                // Every byte is already on disk.
+                return Ok(());
+            }
+            Err(err) if etag.is_some() && err.code() == Some("PreconditionFailed") => {
+                let _ = tokio::fs::remove_file(partial).await;
+                return Err(anyhow!("{key} changed since the download started; starting over"));
+            }
+            Err(err) => return Err(cloud_error(err, format!("failed to download {key}"))),
+        };
+
+        // Without a Content-Range the server sent the whole object.
+        let resume = etag.is_some() && output.content_range().is_some();
+        if !resume {
+            if let Some(etag) = output.e_tag() {
+                tokio::fs::write(&etag_file, etag)
+                    .await
+                    .with_context(|| format!("failed to write {}", etag_file.display()))?;
+            }
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume)
+            .truncate(!resume)
+            .open(partial)
+            .await
+            .with_context(|| format!("failed to create download file: {partial}"))?;
+        let mut body = output.body.into_async_read();
+        let copied = tokio::io::copy(&mut body, &mut file).await;
+        file.flush()
+            .await
+            .with_context(|| format!("failed to flush downloaded file: {partial}"))?;
+        copied.with_context(|| format!("failed to download {key}"))?;
+        Ok(())
+    }
+}
+
+const NO_SUCH_UPLOAD: &str = "NoSuchUpload";
+
+fn etag_path(partial: &str) -> PathBuf {
+    PathBuf::from(format!("{partial}.etag"))
+}
+
+// The bucket no longer knows the upload (expired or aborted elsewhere), so
+// the resume file is dropped and the next push starts over.
+fn forget_vanished_upload(resume_path: &Path) {
+    let _ = fs::remove_file(resume_path);
+}
+
+fn upload_error<E>(err: E, resume_path: &Path, action: String) -> anyhow::Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    if err.code() == Some(NO_SUCH_UPLOAD) {
+        forget_vanished_upload(resume_path);
     }
+    cloud_error(err, action)
+}
 
-    pub async fn upload_object(&self, key: &str, path: &str) -> Result<()> {
+#[async_trait]
+impl StorageBackend for R2Client {
+    async fn put(&self, key: &str, path: &str) -> Result<()> {
+        let size = fs::metadata(path)
+            .with_context(|| format!("failed to read file for upload: {path}"))?
+            .len();
+        if size > self.multipart_threshold {
+            return self.put_multipart(key, path, size).await;
+        }
         let body = ByteStream::from_path(Path::new(path))
             .await
             .with_context(|| format!("failed to read file for upload: {path}"))?;
@@ -58,11 +364,124 @@ impl R2Client {
             .body(body)
             .send()
             .await
-            .with_context(|| format!("failed to upload {key}"))?;
+            .map_err(|err| cloud_error(err, format!("failed to upload {key}")))?;
         Ok(())
     }
 
-    pub async fn download_object(&self, key: &str, path: &str) -> Result<()> {
+    // If-Match on the etag `seen` had, or If-None-Match: * when there was no
+    // object, so the bucket itself refuses a write that lost the race.
+    // Objects past the multipart threshold fall back to comparing first.
+    async fn put_if_unchanged(
+        &self,
+        key: &str,
+        path: &str,
+        seen: Option<&ObjectInfo>,
+    ) -> Result<bool> {
+        let size = fs::metadata(path)
+            .with_context(|| format!("failed to read file for upload: {path}"))?
+            .len();
+        let etag = seen.and_then(|info| info.etag.as_deref());
+        if size > self.multipart_threshold || (seen.is_some() && etag.is_none()) {
+            if self.head(key).await?.as_ref() != seen {
+                return Ok(false);
+            }
+            self.put(key, path).await?;
+            return Ok(true);
+        }
+        let body = ByteStream::from_path(Path::new(path))
+            .await
+            .with_context(|| format!("failed to read file for upload: {path}"))?;
+        let request = self.client.put_object().bucket(&self.bucket).key(key).body(body);
+        let request = match etag {
+            Some(etag) => request.if_match(etag),
+            None => request.if_none_match("*"),
+        };
+        match request.send().await {
+            Ok(_) => Ok(true),
+            Err(err)
+                if matches!(
+                    err.code(),
+                    Some("PreconditionFailed" | "ConditionalRequestConflict")
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(cloud_error(err, format!("failed to upload {key}"))),
+        }
+    }
+
+    // Downloads into `<path>.part` and renames it once complete. A dropped
+    // connection, in this run or an earlier one, resumes with a ranged GET from
+    // the bytes already on disk.
+    async fn get(&self, key: &str, path: &str) -> Result<()> {
+        let partial = format!("{path}.part");
+        let mut attempt = 1;
+        while let Err(err) = self.download_into(key, &partial).await {
+            if attempt >= GET_ATTEMPTS {
+                return Err(err);
+            }
+            tracing::warn!("download of {key} interrupted ({err:#}); resuming");
+            tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+            attempt += 1;
+        }
+        let _ = tokio::fs::remove_file(etag_path(&partial)).await;
+        tokio::fs::rename(&partial, path)
+            .await
+            .with_context(|| format!("failed to move {partial} to {path}"))
+    }
+
+    async fn list(&self, prefix: Option<&str>) -> Result<Vec<ObjectInfo>> {
+        let mut objects = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .set_prefix(prefix.map(str::to_string))
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|err| cloud_error(err, "failed to list objects".to_string()))?;
+            for object in page.contents() {
+                objects.push(ObjectInfo {
+                    key: object.key().unwrap_or_default().to_string(),
+                    size: object.size().unwrap_or_default().max(0) as u64,
+                    etag: object.e_tag().map(str::to_string),
+                    storage_class: object.storage_class().map(|class| class.as_str().to_string()),
+                    last_modified: object.last_modified().and_then(format_timestamp),
+                });
+            }
+        }
+        Ok(objects)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|err| cloud_error(err, format!("failed to delete {key}")))?;
+        Ok(())
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        let output = match self.client.head_object().bucket(&self.bucket).key(key).send().await {
+            Ok(output) => output,
+            Err(SdkError::ServiceError(err)) if err.err().is_not_found() => return Ok(None),
+            Err(err) => return Err(cloud_error(err, format!("failed to stat {key}"))),
+        };
+        Ok(Some(ObjectInfo {
+            key: key.to_string(),
+            size: output.content_length().unwrap_or_default().max(0) as u64,
+            etag: output.e_tag().map(str::to_string),
+            storage_class: output.storage_class().map(|class| class.as_str().to_string()),
+            last_modified: output.last_modified().and_then(format_timestamp),
+        }))
+    }
+
+    // No resume here: a dropped connection fails the stream and its consumer.
+    async fn get_stream(&self, key: &str) -> Result<ObjectReader> {
         let output = self
             .client
             .get_object()
@@ -70,18 +489,85 @@ impl R2Client {
             .key(key)
             .send()
             .await
-            .with_context(|| format!("failed to download {key}"))?;
+            .map_err(|err| cloud_error(err, format!("failed to download {key}")))?;
+        Ok(Box::pin(output.body.into_async_read()))
+    }
 
-        let mut file = tokio::fs::File::create(path)
-            .await
-            .with_context(|| format!("failed to create download file: {path}"))?;
-        let mut body = output.body.into_async_read();
-        tokio::io::copy(&mut body, &mut file)
-            .await
-            .with_context(|| format!("failed to write downloaded file: {path}"))?;
-        file.flush()
+    async fn put_stream(&self, key: &str, mut chunks: mpsc::Receiver<StreamChunk>) -> Result<()> {
+        let output = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
             .await
-            .with_context(|| format!("failed to flush downloaded file: {path}"))?;
-        Ok(())
+            .map_err(|err| cloud_error(err, format!("failed to start upload of {key}")))?;
+        let upload_id = output
+            .upload_id()
+            .ok_or_else(|| anyhow!("no upload id returned for {key}"))?;
+
+        let completed = match self.stream_parts(key, upload_id, &mut chunks).await {
+            Ok(parts) => self
+                .client
+                .complete_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(|err| cloud_error(err, format!("failed to complete {key}"))),
+            Err(err) => Err(err),
+        };
+        if completed.is_err() {
+            // Dropping the receiver stops the producer; the abort is best
+            // effort since R2 also expires abandoned uploads.
+            drop(chunks);
+            let _ = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+        }
+        completed
     }
 }
+
+fn format_timestamp(value: &DateTime) -> Option<String> {
+    value.fmt(DateTimeFormat::DateTime).ok()
+}
+
+fn load_ca_bundle(path: &str) -> Result<TrustStore> {
+    let pem = fs::read(path).with_context(|| format!("failed to read CA bundle: {path}"))?;
+    let certs = CertificateDer::pem_slice_iter(&pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| anyhow!("invalid PEM in CA bundle {path}: {err}"))?;
+    if certs.is_empty() {
+        return Err(anyhow!("CA bundle contains no certificates: {path}"));
+    }
+    Ok(TrustStore::default().with_pem_certificate(pem))
+}
+
+fn cloud_error<E>(err: E, action: String) -> anyhow::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let detail = DisplayErrorContext(&err).to_string();
+    if is_tls_validation_failure(&detail) {
+        return anyhow::Error::new(err).context(format!(
+            "{action}: TLS certificate validation failed; if a proxy intercepts TLS, set cloud.ca_bundle_path to its CA bundle"
+        ));
+    }
+    anyhow::Error::new(err).context(action)
+}
+
+fn is_tls_validation_failure(detail: &str) -> bool {
+    let detail = detail.to_ascii_lowercase();
+    ["invalid peer certificate", "unknownissuer", "certificate verify failed", "self signed certificate"]
+        .iter()
+        .any(|needle| detail.contains(needle))
+}
@@ -1,4 +1,11 @@
+use crate::header::open_artifact;
+use age::secrecy::ExposeSecret;
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::stream::{StreamReader, StreamWriter};
+use age::x25519;
 use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
 use std::process::Command;
 
 pub fn encrypt_to_age(public_key: &str, input_path: &str, output_path: &str) -> Result<()> {
@@ -22,3 +29,145 @@ pub fn decrypt_from_age(private_key_path: &str, input_path: &str, output_path: &
     }
     Ok(())
 }
+
+// `public_key` is either a literal age1... recipient or the path of a
+// recipients file with one recipient per line.
+pub fn parse_recipients(public_key: &str) -> Result<Vec<x25519::Recipient>> {
+    let contents = if public_key.starts_with("age1") {
+        public_key.to_string()
+    } else {
+        fs::read_to_string(public_key)
+            .with_context(|| format!("failed to read age recipients: {public_key}"))?
+    };
+    let recipients = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.parse::<x25519::Recipient>()
+                .map_err(|err| anyhow!("unsupported age recipient {line:?}: {err}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if recipients.is_empty() {
+        return Err(anyhow!("no age recipients in {public_key}"));
+    }
+    Ok(recipients)
+}
+
+pub type AgeWriter<W> = StreamWriter<ArmoredWriter<W>>;
+pub type AgeReader<R> = StreamReader<ArmoredReader<BufReader<R>>>;
+
+// With `armor` the output is the PEM-style ASCII encoding (`age -a`), for
+// small files that have to survive email, pastes or other text-only stores.
+pub fn encrypt_writer<W: Write>(public_key: &str, output: W, armor: bool) -> Result<AgeWriter<W>> {
+    let format = if armor { Format::AsciiArmor } else { Format::Binary };
+    let output = ArmoredWriter::wrap_output(output, format).context("failed to start age armor")?;
+    let recipients = parse_recipients(public_key)?;
+    let encryptor = age::Encryptor::with_recipients(
+        recipients.iter().map(|recipient| recipient as &dyn age::Recipient),
+    )
+    .map_err(|err| anyhow!("failed to set up age encryption: {err}"))?;
+    encryptor
+        .wrap_output(output)
+        .context("failed to start age encryption")
+}
+
+pub fn finish_writer<W: Write>(writer: AgeWriter<W>) -> Result<W> {
+    let mut output = writer
+        .finish()
+        .and_then(ArmoredWriter::finish)
+        .context("failed to finish age encryption")?;
+    output.flush().context("failed to flush age output")?;
+    Ok(output)
+}
+
+// Armored and binary input are both accepted; the format is detected from the
+// first bytes.
+pub fn decrypt_reader<R: Read>(private_key_path: &str, input: R) -> Result<AgeReader<R>> {
+    let identities = read_identities(private_key_path)?;
+    let decryptor = age::Decryptor::new(ArmoredReader::new(input))
+        .map_err(|err| anyhow!("failed to read age header: {err}"))?;
+    decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref() as &dyn age::Identity))
+        .map_err(|err| anyhow!("age decryption failed: {err}"))
+}
+
+fn read_identities(private_key_path: &str) -> Result<Vec<Box<dyn age::Identity>>> {
+    age::IdentityFile::from_file(private_key_path.to_string())
+        .with_context(|| format!("failed to read age identity: {private_key_path}"))?
+        .into_identities()
+        .map_err(|err| anyhow!("invalid age identity {private_key_path}: {err}"))
+}
+
+// Tags of the recipient stanzas in an artifact's age header ("X25519",
+// "scrypt", ...), one per stanza. X25519 stanzas do not name their recipient,
+// so which key opens a file can only be learned by trying identities.
+pub fn header_stanzas(path: &str) -> Result<Vec<String>> {
+    let (_, payload) = open_artifact(path)?;
+    let mut reader = BufReader::new(ArmoredReader::new(payload));
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .with_context(|| format!("failed to read age header: {path}"))?;
+    if line.trim_end() != "age-encryption.org/v1" {
+        return Err(anyhow!("not an age v1 file: {path}"));
+    }
+    let mut stanzas = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_line(&mut line)
+            .with_context(|| format!("failed to read age header: {path}"))?;
+        if read == 0 {
+            return Err(anyhow!("truncated age header: {path}"));
+        }
+        if line.starts_with("---") {
+            return Ok(stanzas);
+        }
+        if let Some(stanza) = line.strip_prefix("-> ") {
+            // The age crate adds a random "<x>-grease" stanza to every header.
+            let tag = stanza.split_whitespace().next().unwrap_or_default();
+            if !tag.ends_with("-grease") {
+                stanzas.push(tag.to_string());
+            }
+        }
+    }
+}
+
+// Whether any identity in `private_key_path` can unwrap the file key of the
+// artifact; only the header is read.
+pub fn identity_unwraps(private_key_path: &str, path: &str) -> Result<bool> {
+    let identities = read_identities(private_key_path)?;
+    let (_, payload) = open_artifact(path)?;
+    let decryptor = age::Decryptor::new(ArmoredReader::new(payload))
+        .map_err(|err| anyhow!("failed to read age header of {path}: {err}"))?;
+    let identities = identities.iter().map(|identity| identity.as_ref() as &dyn age::Identity);
+    match decryptor.decrypt(identities) {
+        Ok(_) => Ok(true),
+        Err(age::DecryptError::NoMatchingKeys) => Ok(false),
+        Err(err) => Err(anyhow!("failed to unwrap {path}: {err}")),
+    }
+}
+
+// Contents of a new identity file, in the same layout age-keygen writes.
+pub fn generate_identity() -> String {
+    let identity = x25519::Identity::generate();
+    format!(
+        "# public key: {}\n{}\n",
+        identity.to_public(),
+        identity.to_string().expose_secret()
+    )
+}
+
+pub fn identity_public_key(private_key_path: &str) -> Result<String> {
+    let contents = fs::read_to_string(private_key_path)
+        .with_context(|| format!("failed to read age identity: {private_key_path}"))?;
+    let identity = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or_else(|| anyhow!("no age identity in {private_key_path}"))?
+        .parse::<x25519::Identity>()
+        .map_err(|err| anyhow!("invalid age identity {private_key_path}: {err}"))?;
+    Ok(identity.to_public().to_string())
+}
@@ -0,0 +1,216 @@
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+pub const MAGIC: &str = "dev-backup-artifact/v2";
+const NO_PARENT: &str = "-";
+const PENDING_SHA256: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+// Cleartext `key: value` lines in front of the age payload of a v2 artifact,
+// so a file can be identified without the private key. The closing
+// `header-sha256` line covers every line before it; a header that does not
+// match is rejected instead of being misread. Files that do not start with
+// MAGIC are headerless v1 artifacts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactHeader {
+    pub label: String,
+    pub parent: Option<String>,
+    pub compression: String,
+    pub created: OffsetDateTime,
+    pub payload_sha256: String,
+}
+
+impl ArtifactHeader {
+    pub fn new(
+        label: &str,
+        parent: Option<&str>,
+        compression: &str,
+        created: OffsetDateTime,
+    ) -> Self {
+        Self {
+            label: label.to_string(),
+            parent: parent.map(str::to_string),
+            compression: compression.to_string(),
+            created,
+            payload_sha256: PENDING_SHA256.to_string(),
+        }
+    }
+
+    // False for a header `ArtifactWriter::finish` never rewrote: the build
+    // stopped partway and the payload is incomplete.
+    pub fn is_finished(&self) -> bool {
+        self.payload_sha256 != PENDING_SHA256
+    }
+
+    pub fn encode(&self) -> Result<String> {
+        let created = self
+            .created
+            .format(&Rfc3339)
+            .context("failed to format header timestamp")?;
+        let body = format!(
+            "{MAGIC}\nlabel: {}\nparent: {}\ncompression: {}\ncreated: {created}\n\
+             payload-sha256: {}\n",
+            self.label,
+            self.parent.as_deref().unwrap_or(NO_PARENT),
+            self.compression,
+            self.payload_sha256,
+        );
+        let digest = Sha256::digest(body.as_bytes());
+        Ok(format!("{body}header-sha256: {digest:x}\n"))
+    }
+}
+
+// Consumes the header when there is one, leaving `reader` at the first byte
+// of the age payload either way.
+pub fn read_header<R: BufRead>(reader: &mut R) -> Result<Option<ArtifactHeader>> {
+    if !reader
+        .fill_buf()
+        .context("failed to read artifact")?
+        .starts_with(MAGIC.as_bytes())
+    {
+        return Ok(None);
+    }
+    let mut body = String::new();
+    let mut fields = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).context("failed to read artifact header")? == 0 {
+            return Err(anyhow!("truncated artifact header"));
+        }
+        if let Some(digest) = line.trim_end().strip_prefix("header-sha256: ") {
+            if format!("{:x}", Sha256::digest(body.as_bytes())) != digest {
+                return Err(anyhow!("artifact header checksum mismatch"));
+            }
+            break;
+        }
+        if let Some((key, value)) = line.trim_end().split_once(": ") {
+            fields.push((key.to_string(), value.to_string()));
+        }
+        body.push_str(&line);
+    }
+
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| anyhow!("artifact header is missing {name}"))
+    };
+    let parent = field("parent")?;
+    let created = field("created")?;
+    Ok(Some(ArtifactHeader {
+        label: field("label")?,
+        parent: (parent != NO_PARENT).then_some(parent),
+        compression: field("compression")?,
+        created: OffsetDateTime::parse(&created, &Rfc3339)
+            .map_err(|err| anyhow!("invalid created time in artifact header: {err}"))?,
+        payload_sha256: field("payload-sha256")?,
+    }))
+}
+
+pub fn open_artifact(path: &str) -> Result<(Option<ArtifactHeader>, BufReader<File>)> {
+    let file = File::open(path).with_context(|| format!("failed to open artifact: {path}"))?;
+    let mut reader = BufReader::new(file);
+    let header = read_header(&mut reader).with_context(|| format!("invalid artifact: {path}"))?;
+    Ok((header, reader))
+}
+
+// Hashes the rest of `reader`: after `open_artifact`, the age payload.
+pub fn payload_sha256(reader: &mut impl Read) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(reader, &mut hasher).context("failed to read artifact payload")?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Writes a v2 artifact. The header goes out first with a placeholder payload
+// checksum and is rewritten in place by `finish`; both checksums are
+// fixed-width, so the header keeps its length.
+pub struct ArtifactWriter {
+    file: BufWriter<File>,
+    header: ArtifactHeader,
+    hasher: Sha256,
+}
+
+impl ArtifactWriter {
+    pub fn create(path: &str, header: ArtifactHeader) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("failed to create output: {path}"))?;
+        let mut file = BufWriter::new(file);
+        file.write_all(header.encode()?.as_bytes())
+            .with_context(|| format!("failed to write artifact header: {path}"))?;
+        Ok(Self {
+            file,
+            header,
+            hasher: Sha256::new(),
+        })
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.header.payload_sha256 = format!("{:x}", self.hasher.finalize());
+        let mut file = self
+            .file
+            .into_inner()
+            .map_err(|err| anyhow!("failed to flush artifact: {}", err.error()))?;
+        file.seek(SeekFrom::Start(0))
+            .context("failed to rewind artifact")?;
+        file.write_all(self.header.encode()?.as_bytes())
+            .context("failed to finish artifact header")?;
+        file.flush().context("failed to flush artifact")?;
+        file.sync_all().context("failed to sync artifact")
+    }
+}
+
+impl Write for ArtifactWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn header_round_trips_and_leaves_the_payload() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("artifact");
+        let path = path.to_str().unwrap();
+        let created = OffsetDateTime::from_unix_timestamp(1_706_745_600).unwrap();
+        let header = ArtifactHeader::new("2024-02", Some("2024-01"), "zstd-3", created);
+        let mut writer = ArtifactWriter::create(path, header).unwrap();
+        writer.write_all(b"payload").unwrap();
+        writer.finish().unwrap();
+
+        let (header, mut reader) = open_artifact(path).unwrap();
+        let header = header.unwrap();
+        assert_eq!(header.label, "2024-02");
+        assert_eq!(header.parent.as_deref(), Some("2024-01"));
+        assert_eq!(header.created, created);
+        assert_eq!(header.payload_sha256, format!("{:x}", Sha256::digest(b"payload")));
+        let mut payload = Vec::new();
+        reader.read_to_end(&mut payload).unwrap();
+        assert_eq!(payload, b"payload");
+    }
+
+    #[test]
+    fn headerless_files_are_v1_and_damaged_headers_are_rejected() {
+        let mut v1 = io::Cursor::new(b"age-encryption.org/v1\n".to_vec());
+        assert!(read_header(&mut v1).unwrap().is_none());
+        assert_eq!(v1.position(), 0);
+
+        let header = ArtifactHeader::new("2024-02", None, "zstd-3", OffsetDateTime::UNIX_EPOCH);
+        let tampered = header.encode().unwrap().replace("2024-02", "2024-03");
+        let err = read_header(&mut io::Cursor::new(tampered.into_bytes())).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::backend::{validate_key, ObjectInfo, StorageBackend};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use std::fs;
+use std::process::Command;
+
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    // Base URL of the mirror; a query string (e.g. a CDN access token) is
+    // sent with every request.
+    pub url: String,
+    pub https_proxy: Option<String>,
+    pub ca_bundle_path: Option<String>,
+}
+
+// A static HTTP(S) copy of the bucket, such as a CDN in front of it, read
+// through the `curl` binary. Objects are fetched from `<url>/<key>`; nothing
+// can be listed, written or deleted.
+#[derive(Debug, Clone)]
+pub struct HttpBackend {
+    config: HttpConfig,
+}
+
+impl HttpBackend {
+    pub fn new(config: HttpConfig) -> Self {
+        Self { config }
+    }
+
+    fn object_url(&self, key: &str) -> Result<String> {
+        validate_key(key)?;
+        let (base, query) = match self.config.url.split_once('?') {
+            Some((base, query)) => (base, Some(query)),
+            None => (self.config.url.as_str(), None),
+        };
+        let mut url = format!("{}/{}", base.trim_end_matches('/'), encode_path(key));
+        if let Some(query) = query {
+            url.push('?');
+            url.push_str(query);
+        }
+        Ok(url)
+    }
+
+    fn curl(&self) -> Command {
+        let mut cmd = Command::new("curl");
+        cmd.args(["--silent", "--show-error", "--location", "--proto", "=http,https"]);
+        if let Some(proxy) = &self.config.https_proxy {
+            cmd.arg("--proxy").arg(proxy);
+        }
+        if let Some(ca_bundle) = &self.config.ca_bundle_path {
+            cmd.arg("--cacert").arg(ca_bundle);
+        }
+        cmd
+    }
+
+    async fn run(&self, cmd: Command) -> Result<String> {
+        tokio::task::spawn_blocking(move || run_curl(cmd))
+            .await
+            .context("curl task panicked")?
+    }
+}
+
+fn run_curl(mut cmd: Command) -> Result<String> {
+    let output = cmd.output().context("failed to start curl")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("curl failed: {}", stderr.trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn read_only(key: &str) -> anyhow::Error {
+    anyhow!("http remotes are read-only; cannot write {key}")
+}
+
+#[async_trait]
+impl StorageBackend for HttpBackend {
+    async fn put(&self, key: &str, _path: &str) -> Result<()> {
+        Err(read_only(key))
+    }
+
+    async fn get(&self, key: &str, path: &str) -> Result<()> {
+        let url = self.object_url(key)?;
+        let mut cmd = self.curl();
+        cmd.arg("--fail").arg("--output").arg(path).arg("--").arg(&url);
+        if let Err(err) = self.run(cmd).await {
+            let _ = fs::remove_file(path);
+            return Err(err.context(format!("failed to download {key}")));
+        }
+        Ok(())
+    }
+
+    async fn list(&self, _prefix: Option<&str>) -> Result<Vec<ObjectInfo>> {
+        Err(anyhow!("http remotes cannot be listed; fetch objects by the keys in the manifest"))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        Err(read_only(key))
+    }
+
+    // A HEAD request; 404 and 410 mean the object is not there.
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        let url = self.object_url(key)?;
+        let mut cmd = self.curl();
+        cmd.args(["--head", "--write-out", "%{http_code}"]).arg("--").arg(&url);
+        let output = self
+            .run(cmd)
+            .await
+            .with_context(|| format!("failed to stat {key}"))?;
+        let (headers, status) = output.rsplit_once('\n').unwrap_or(("", output.as_str()));
+        match status.trim() {
+            "404" | "410" => Ok(None),
+            code if code.starts_with('2') => Ok(Some(parse_head(key, headers))),
+            code => Err(anyhow!("failed to stat {key}: HTTP {code}")),
+        }
+    }
+
+    async fn put_if_unchanged(
+        &self,
+        key: &str,
+        _path: &str,
+        _seen: Option<&ObjectInfo>,
+    ) -> Result<bool> {
+        Err(read_only(key))
+    }
+}
+
+// Headers of the last response, as --location prints every hop's.
+fn parse_head(key: &str, headers: &str) -> ObjectInfo {
+    let last = headers
+        .split("\r\n\r\n")
+        .map(str::trim)
+        .filter(|block| !block.is_empty())
+        .last()
+        .unwrap_or_default();
+    let mut info = ObjectInfo {
+        key: key.to_string(),
+        size: 0,
+        etag: None,
+        storage_class: None,
+        last_modified: None,
+    };
+    for line in last.lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => info.size = value.parse().unwrap_or(0),
+            "etag" => info.etag = Some(value.trim_matches('"').to_string()),
+            "last-modified" => info.last_modified = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    info
+}
+
+// Percent-encodes everything in a key but unreserved characters, '/', '@'
+// and '~', which artifact names use.
+fn encode_path(key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len());
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b'@' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(url: &str) -> HttpBackend {
+        HttpBackend::new(HttpConfig {
+            url: url.to_string(),
+            https_proxy: None,
+            ca_bundle_path: None,
+        })
+    }
+
+    #[test]
+    fn object_urls_keep_the_query_string() {
+        let url = backend("https://cdn.example.com/dev/?token=abc")
+            .object_url("manifests/dev@2024 01.tsv")
+            .unwrap();
+        assert_eq!(url, "https://cdn.example.com/dev/manifests/dev@2024%2001.tsv?token=abc");
+        assert!(backend("https://cdn.example.com").object_url("../x").is_err());
+    }
+
+    #[test]
+    fn head_reads_the_last_response() {
+        let headers = "HTTP/1.1 302 Found\r\nLocation: /b\r\nContent-Length: 0\r\n\r\n\
+                       HTTP/1.1 200 OK\r\nContent-Length: 42\r\nETag: \"abc\"\r\n\r\n";
+        let info = parse_head("k", headers);
+        assert_eq!(info.size, 42);
+        assert_eq!(info.etag.as_deref(), Some("abc"));
+    }
+}
@@ -1,3 +1,10 @@
 pub mod artifact;
+pub mod backend;
+#[cfg(feature = "cloud")]
 pub mod cloud;
 pub mod crypto;
+pub mod header;
+pub mod http;
+pub mod local;
+pub mod multipart;
+pub mod sftp;
@@ -0,0 +1,184 @@
+use crate::backend::{validate_key, ObjectInfo, ObjectReader, StorageBackend, StreamChunk};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use std::io;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+// Objects stored as plain files under `root`, laid out by key. Useful for a
+// second disk or NFS mount, and for exercising sync without cloud credentials.
+#[derive(Debug, Clone)]
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn object_path(&self, key: &str) -> Result<PathBuf> {
+        validate_key(key)?;
+        Ok(self.root.join(key))
+    }
+
+    // Writes go next to the destination and are renamed into place so readers
+    // never see a partially written object.
+    async fn partial_path(&self, key: &str) -> Result<(PathBuf, PathBuf)> {
+        let dest = self.object_path(key)?;
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("failed to create directory: {}", parent.display()))?;
+        }
+        let partial = dest.with_file_name(format!(
+            ".{}.partial",
+            dest.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        Ok((partial, dest))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalBackend {
+    async fn put(&self, key: &str, path: &str) -> Result<()> {
+        let (partial, dest) = self.partial_path(key).await?;
+        tokio::fs::copy(path, &partial)
+            .await
+            .with_context(|| format!("failed to upload {key}"))?;
+        tokio::fs::rename(&partial, &dest)
+            .await
+            .with_context(|| format!("failed to upload {key}"))?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, path: &str) -> Result<()> {
+        let source = self.object_path(key)?;
+        tokio::fs::copy(&source, path)
+            .await
+            .with_context(|| format!("failed to download {key}"))?;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: Option<&str>) -> Result<Vec<ObjectInfo>> {
+        let mut objects = Vec::new();
+        let mut pending = vec![self.root.clone()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("failed to list {}", dir.display()));
+                }
+            };
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .with_context(|| format!("failed to list {}", dir.display()))?
+            {
+                let path = entry.path();
+                let metadata = entry
+                    .metadata()
+                    .await
+                    .with_context(|| format!("failed to stat {}", path.display()))?;
+                if metadata.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                if entry.file_name().to_string_lossy().ends_with(".partial") {
+                    continue;
+                }
+                let key = object_key(&self.root, &path);
+                if prefix.is_some_and(|prefix| !key.starts_with(prefix)) {
+                    continue;
+                }
+                objects.push(object_info(key, &metadata));
+            }
+        }
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let path = self.object_path(key)?;
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).with_context(|| format!("failed to delete {key}")),
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        let path = self.object_path(key)?;
+        match tokio::fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => Ok(Some(object_info(key.to_string(), &metadata))),
+            Ok(_) => Ok(None),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("failed to stat {key}")),
+        }
+    }
+
+    async fn get_stream(&self, key: &str) -> Result<ObjectReader> {
+        let source = self.object_path(key)?;
+        let file = tokio::fs::File::open(&source)
+            .await
+            .with_context(|| format!("failed to download {key}"))?;
+        Ok(Box::pin(file))
+    }
+
+    async fn put_stream(&self, key: &str, mut chunks: mpsc::Receiver<StreamChunk>) -> Result<()> {
+        let (partial, dest) = self.partial_path(key).await?;
+        let mut file = tokio::fs::File::create(&partial)
+            .await
+            .with_context(|| format!("failed to upload {key}"))?;
+        loop {
+            match chunks.recv().await {
+                Some(StreamChunk::Data(chunk)) => {
+                    file.write_all(&chunk)
+                        .await
+                        .with_context(|| format!("failed to upload {key}"))?;
+                }
+                Some(StreamChunk::Done) => break,
+                None => {
+                    drop(file);
+                    let _ = tokio::fs::remove_file(&partial).await;
+                    return Err(anyhow!("stream for {key} ended before it was complete"));
+                }
+            }
+        }
+        file.flush()
+            .await
+            .with_context(|| format!("failed to upload {key}"))?;
+        tokio::fs::rename(&partial, &dest)
+            .await
+            .with_context(|| format!("failed to upload {key}"))?;
+        Ok(())
+    }
+}
+
+fn object_key(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn object_info(key: String, metadata: &std::fs::Metadata) -> ObjectInfo {
+    ObjectInfo {
+        key,
+        size: metadata.len(),
+        etag: None,
+        storage_class: None,
+        last_modified: metadata
+            .modified()
+            .ok()
+            .and_then(|modified| OffsetDateTime::from(modified).replace_nanosecond(0).ok())
+            .and_then(|modified| modified.format(&Rfc3339).ok()),
+    }
+}
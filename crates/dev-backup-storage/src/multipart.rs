@@ -0,0 +1,199 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+// Progress of one multipart upload, kept on disk so an interrupted push can
+// continue with the parts the bucket does not have yet. One `field\tvalue`
+// line per setting, then one `part\t<number>\t<etag>` line per finished part.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadState {
+    pub upload_id: String,
+    pub size: u64,
+    pub part_size: u64,
+    // None in a state written before the source was recorded.
+    pub source: Option<SourceStamp>,
+    pub parts: Vec<(i32, String)>,
+}
+
+// Which file the parts were read from. A file rebuilt under the same name,
+// even at the same size, has a new inode or modification time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceStamp {
+    pub inode: u64,
+    pub mtime_ns: i128,
+}
+
+impl SourceStamp {
+    pub fn of(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)
+            .with_context(|| format!("failed to stat upload source: {}", path.display()))?;
+        let mtime_ns =
+            i128::from(metadata.mtime()) * 1_000_000_000 + i128::from(metadata.mtime_nsec());
+        Ok(Self { inode: metadata.ino(), mtime_ns })
+    }
+}
+
+impl UploadState {
+    pub fn new(upload_id: String, size: u64, part_size: u64, source: SourceStamp) -> Self {
+        Self {
+            upload_id,
+            size,
+            part_size,
+            source: Some(source),
+            parts: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read upload state: {}", path.display()))?;
+        parse_state(&contents)
+            .map(Some)
+            .with_context(|| format!("invalid upload state: {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut contents = format!(
+            "upload_id\t{}\nsize\t{}\npart_size\t{}\n",
+            self.upload_id, self.size, self.part_size
+        );
+        if let Some(source) = &self.source {
+            contents.push_str(&format!("inode\t{}\nmtime_ns\t{}\n", source.inode, source.mtime_ns));
+        }
+        for (number, etag) in &self.parts {
+            contents.push_str(&format!("part\t{number}\t{etag}\n"));
+        }
+        let partial = path.with_extension("partial");
+        fs::write(&partial, contents)
+            .with_context(|| format!("failed to write upload state: {}", partial.display()))?;
+        fs::rename(&partial, path)
+            .with_context(|| format!("failed to write upload state: {}", path.display()))
+    }
+
+    pub fn record_part(&mut self, path: &Path, number: i32, etag: &str) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open upload state: {}", path.display()))?;
+        writeln!(file, "part\t{number}\t{etag}")
+            .and_then(|_| file.sync_data())
+            .with_context(|| format!("failed to update upload state: {}", path.display()))?;
+        self.parts.push((number, etag.to_string()));
+        Ok(())
+    }
+
+    // A state left by a different file or part size cannot be resumed.
+    pub fn matches(&self, size: u64, part_size: u64, source: SourceStamp) -> bool {
+        self.size == size && self.part_size == part_size && self.source == Some(source)
+    }
+
+    pub fn has_part(&self, number: i32) -> bool {
+        self.parts.iter().any(|(done, _)| *done == number)
+    }
+}
+
+fn parse_state(contents: &str) -> Result<UploadState> {
+    let mut upload_id = None;
+    let mut size = None;
+    let mut part_size = None;
+    let mut inode = None;
+    let mut mtime_ns = None;
+    let mut parts = Vec::new();
+    for line in contents.lines().filter(|line| !line.is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            ["upload_id", value] if !value.is_empty() => upload_id = Some(value.to_string()),
+            ["size", value] => size = Some(value.parse::<u64>()?),
+            ["part_size", value] => part_size = Some(value.parse::<u64>()?),
+            ["inode", value] => inode = Some(value.parse::<u64>()?),
+            ["mtime_ns", value] => mtime_ns = Some(value.parse::<i128>()?),
+            ["part", number, etag] if !etag.is_empty() => {
+                parts.push((number.parse::<i32>()?, etag.to_string()))
+            }
+            _ => return Err(anyhow!("unexpected line {line:?}")),
+        }
+    }
+    Ok(UploadState {
+        upload_id: upload_id.ok_or_else(|| anyhow!("missing upload_id"))?,
+        size: size.ok_or_else(|| anyhow!("missing size"))?,
+        part_size: part_size.ok_or_else(|| anyhow!("missing part_size"))?,
+        source: inode.zip(mtime_ns).map(|(inode, mtime_ns)| SourceStamp { inode, mtime_ns }),
+        parts,
+    })
+}
+
+pub fn part_count(size: u64, part_size: u64) -> i32 {
+    size.div_ceil(part_size).max(1) as i32
+}
+
+// Byte offset and length of 1-based part `number`.
+pub fn part_range(size: u64, part_size: u64, number: i32) -> (u64, u64) {
+    let offset = (number as u64 - 1) * part_size;
+    (offset, part_size.min(size - offset))
+}
+
+pub fn resume_file_name(bucket: &str, key: &str) -> String {
+    format!("{bucket}/{key}.upload").replace('/', "%2F")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn parts_cover_the_file() {
+        assert_eq!(part_count(10, 4), 3);
+        assert_eq!(part_range(10, 4, 1), (0, 4));
+        assert_eq!(part_range(10, 4, 3), (8, 2));
+        assert_eq!(part_count(8, 4), 2);
+        assert_eq!(part_range(8, 4, 2), (4, 4));
+    }
+
+    #[test]
+    fn state_round_trips_through_the_resume_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(resume_file_name("bucket", "artifacts/anchors/a.age"));
+        let source = SourceStamp { inode: 7, mtime_ns: 1_700_000_000_000_000_001 };
+        let mut state = UploadState::new("abc".to_string(), 10, 4, source);
+        state.save(&path).unwrap();
+        state.record_part(&path, 1, "\"etag-1\"").unwrap();
+        state.record_part(&path, 2, "\"etag-2\"").unwrap();
+
+        let loaded = UploadState::load(&path).unwrap().unwrap();
+        assert_eq!(loaded, state);
+        assert!(loaded.has_part(2) && !loaded.has_part(3));
+        assert!(loaded.matches(10, 4, source) && !loaded.matches(11, 4, source));
+    }
+
+    #[test]
+    fn a_rebuilt_file_does_not_match() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("a.age");
+        fs::write(&file, b"first").unwrap();
+        let state = UploadState::new("abc".to_string(), 5, 4, SourceStamp::of(&file).unwrap());
+
+        let rebuilt = dir.path().join("a.age.partial");
+        fs::write(&rebuilt, b"other").unwrap();
+        fs::rename(&rebuilt, &file).unwrap();
+        assert!(!state.matches(5, 4, SourceStamp::of(&file).unwrap()));
+    }
+
+    #[test]
+    fn a_state_without_its_source_never_matches() {
+        let state = parse_state("upload_id\tabc\nsize\t10\npart_size\t4\n").unwrap();
+        assert!(!state.matches(10, 4, SourceStamp { inode: 7, mtime_ns: 0 }));
+    }
+
+    #[test]
+    fn truncated_state_is_rejected() {
+        assert!(parse_state("upload_id\tabc\nsize\t10\n").is_err());
+        assert!(parse_state("upload_id\tabc\nsize\t10\npart_size\t4\ninode\tx\n").is_err());
+        assert!(parse_state("upload_id\tabc\nsize\t10\npart_size\t4\npart\tx\te\n").is_err());
+    }
+}
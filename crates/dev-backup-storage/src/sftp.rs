@@ -0,0 +1,259 @@
+use crate::backend::{validate_key, ObjectInfo, StorageBackend};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone)]
+pub struct SftpConfig {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    pub root: String,
+    // Passed to sftp as `-o` options, e.g. multiplexing or ConnectTimeout.
+    pub ssh_options: Vec<String>,
+}
+
+// Objects stored as files under `root` on an SSH server, driven through the
+// OpenSSH `sftp` client in batch mode so keys, agents, ~/.ssh/config and
+// known_hosts behave exactly as they do for `ssh`. Works with sftp-only
+// (chrooted, no shell) accounts.
+#[derive(Debug, Clone)]
+pub struct SftpBackend {
+    config: SftpConfig,
+}
+
+impl SftpBackend {
+    pub fn new(config: SftpConfig) -> Self {
+        Self { config }
+    }
+
+    fn object_path(&self, key: &str) -> Result<String> {
+        validate_key(key)?;
+        Ok(format!("{}/{key}", self.config.root))
+    }
+
+    fn destination(&self) -> String {
+        let host = if self.config.host.contains(':') {
+            format!("[{}]", self.config.host)
+        } else {
+            self.config.host.clone()
+        };
+        match &self.config.user {
+            Some(user) => format!("{user}@{host}"),
+            None => host,
+        }
+    }
+
+    // Runs one batch; a command prefixed with '-' may fail without aborting.
+    async fn batch(&self, commands: Vec<String>) -> Result<String> {
+        let mut cmd = Command::new("sftp");
+        cmd.arg("-q").arg("-b").arg("-");
+        if let Some(port) = self.config.port {
+            cmd.arg("-P").arg(port.to_string());
+        }
+        for option in &self.config.ssh_options {
+            cmd.arg("-o").arg(option);
+        }
+        cmd.arg("--").arg(self.destination());
+        tokio::task::spawn_blocking(move || run_batch(cmd, &commands))
+            .await
+            .context("sftp task panicked")?
+    }
+}
+
+fn run_batch(mut cmd: Command, commands: &[String]) -> Result<String> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to start sftp")?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("failed to capture sftp stdin"))?;
+    for command in commands {
+        writeln!(stdin, "{command}").context("failed to write sftp batch")?;
+    }
+    drop(stdin);
+    let output = child.wait_with_output().context("failed to wait on sftp")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("sftp failed: {}", stderr.trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[async_trait]
+impl StorageBackend for SftpBackend {
+    async fn put(&self, key: &str, path: &str) -> Result<()> {
+        let dest = self.object_path(key)?;
+        let (parent, name) = dest.rsplit_once('/').unwrap_or_default();
+        let partial = format!("{parent}/.{name}.partial");
+        let mut commands = Vec::new();
+        let mut dir = self.config.root.clone();
+        commands.push(format!("-mkdir {}", quote(&dir)));
+        for component in key.split('/').take(key.split('/').count() - 1) {
+            dir = format!("{dir}/{component}");
+            commands.push(format!("-mkdir {}", quote(&dir)));
+        }
+        commands.push(format!("put {} {}", quote(path), quote(&partial)));
+        commands.push(format!("rename {} {}", quote(&partial), quote(&dest)));
+        self.batch(commands)
+            .await
+            .with_context(|| format!("failed to upload {key}"))?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, path: &str) -> Result<()> {
+        let source = self.object_path(key)?;
+        self.batch(vec![format!("get {} {}", quote(&source), quote(path))])
+            .await
+            .with_context(|| format!("failed to download {key}"))?;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: Option<&str>) -> Result<Vec<ObjectInfo>> {
+        let root = self.config.root.clone();
+        let mut objects = Vec::new();
+        let mut dirs = vec![root.clone()];
+        while !dirs.is_empty() {
+            let commands = dirs.iter().map(|dir| format!("-ls -ln {}", quote(dir))).collect();
+            let output = self.batch(commands).await.context("failed to list objects")?;
+            dirs.clear();
+            for entry in parse_listing(&output) {
+                let Some(key) = entry.path.strip_prefix(&format!("{root}/")) else {
+                    continue;
+                };
+                if entry.is_dir {
+                    dirs.push(entry.path.clone());
+                    continue;
+                }
+                if key.rsplit('/').next().is_some_and(|name| name.ends_with(".partial")) {
+                    continue;
+                }
+                if prefix.is_some_and(|prefix| !key.starts_with(prefix)) {
+                    continue;
+                }
+                objects.push(ObjectInfo {
+                    key: key.to_string(),
+                    size: entry.size,
+                    etag: None,
+                    storage_class: None,
+                    last_modified: None,
+                });
+            }
+        }
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let path = self.object_path(key)?;
+        self.batch(vec![format!("-rm {}", quote(&path))])
+            .await
+            .with_context(|| format!("failed to delete {key}"))?;
+        Ok(())
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        let path = self.object_path(key)?;
+        let output = self
+            .batch(vec![format!("-ls -ln {}", quote(&path))])
+            .await
+            .with_context(|| format!("failed to stat {key}"))?;
+        let entries: HashMap<String, ListEntry> = parse_listing(&output)
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        Ok(entries
+            .get(&path)
+            .filter(|entry| !entry.is_dir)
+            .map(|entry| ObjectInfo {
+                key: key.to_string(),
+                size: entry.size,
+                etag: None,
+                storage_class: None,
+                last_modified: None,
+            }))
+    }
+}
+
+// sftp batch arguments are split like a shell would; double quotes keep
+// spaces together and backslashes escape quotes inside them.
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ListEntry {
+    path: String,
+    size: u64,
+    is_dir: bool,
+}
+
+// Parses `ls -ln` output: mode, links, uid, gid, size, month, day, time or
+// year, then the path, which is absolute because absolute paths were listed.
+fn parse_listing(output: &str) -> Vec<ListEntry> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with("sftp>"))
+        .filter_map(|line| {
+            let mut rest = line.trim_start();
+            let mut fields = Vec::with_capacity(8);
+            for _ in 0..8 {
+                let end = rest.find(char::is_whitespace)?;
+                fields.push(&rest[..end]);
+                rest = rest[end..].trim_start();
+            }
+            if rest.is_empty() {
+                return None;
+            }
+            Some(ListEntry {
+                path: rest.to_string(),
+                size: fields[4].parse().ok()?,
+                is_dir: fields[0].starts_with('d'),
+            })
+        })
+        .filter(|entry| {
+            let name = entry.path.rsplit('/').next().unwrap_or_default();
+            name != "." && name != ".."
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_long_listing() {
+        let output = "sftp> -ls -ln \"/srv/backup/artifacts\"\n\
+            drwxr-xr-x    ? 1000     1000         4096 Jan  1 12:00 /srv/backup/artifacts/anchors\n\
+            -rw-r--r--    ? 1000     1000      1048576 Mar 14  2024 /srv/backup/my dir/x.age\n\
+            drwxr-xr-x    ? 1000     1000         4096 Jan  1 12:00 /srv/backup/artifacts/.\n";
+        assert_eq!(
+            parse_listing(output),
+            vec![
+                ListEntry {
+                    path: "/srv/backup/artifacts/anchors".to_string(),
+                    size: 4096,
+                    is_dir: true,
+                },
+                ListEntry {
+                    path: "/srv/backup/my dir/x.age".to_string(),
+                    size: 1_048_576,
+                    is_dir: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes_batch_arguments() {
+        assert_eq!(quote("/srv/a b"), "\"/srv/a b\"");
+        assert_eq!(quote("x\"y\\z"), "\"x\\\"y\\\\z\"");
+    }
+}